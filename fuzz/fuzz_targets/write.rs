@@ -0,0 +1,20 @@
+#![no_main]
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    fasta: Vec<u8>,
+    // Offsets are kept small, so that the fuzzer does not spend its time
+    // allocating gigabytes of padding
+    writes: Vec<(u8, u16, Vec<u8>)>,
+}
+
+fuzz_target!(|input: Input| {
+    let writes = input
+        .writes
+        .iter()
+        .map(|(i, offset, data)| (*i as usize, *offset as usize, &data[..]))
+        .collect::<Vec<_>>();
+    fusta::fuzzing::write(&input.fasta, &writes);
+});
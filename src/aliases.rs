@@ -0,0 +1,134 @@
+//! The names a sequence goes by in the different naming conventions, e.g.
+//! `chr1`, `1` and `NC_000001.11`, as listed in a UCSC chromAlias.txt file.
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+
+/// Groups of names designating the same sequence
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Aliases {
+    groups: Vec<Vec<String>>,
+    index: HashMap<String, usize>, // The group of every name
+}
+impl Aliases {
+    pub fn load(filename: &str) -> Result<Aliases> {
+        let content = std::fs::read_to_string(filename)
+            .with_context(|| format!("failed to open `{}`", filename))?;
+        Aliases::parse(&content).with_context(|| format!("while parsing `{}`", filename))
+    }
+
+    /// Parse a tab-separated chromAlias.txt, where every line lists the
+    /// names of a sequence, some of them possibly empty. A `#` line names the
+    /// columns, and a column named `source`, as in the older `alias chrom
+    /// source` tables, is ignored; lines sharing a name are merged.
+    pub fn parse(content: &str) -> Result<Aliases> {
+        let mut r = Aliases::default();
+        let mut source = None;
+        for (i, line) in content.lines().enumerate() {
+            if let Some(header) = line.strip_prefix('#') {
+                source = header.split('\t').position(|c| c.trim() == "source");
+                continue;
+            }
+            let names = line
+                .split('\t')
+                .enumerate()
+                .filter(|(j, _)| Some(*j) != source)
+                .map(|(_, name)| name.trim())
+                .filter(|name| !name.is_empty())
+                .collect::<Vec<_>>();
+            if names.iter().any(|name| name.contains(char::is_whitespace)) {
+                bail!("line {}: the names should be separated by tabs", i + 1)
+            }
+            r.add(&names);
+        }
+        Ok(r)
+    }
+
+    /// Record that all the `names` designate the same sequence
+    fn add(&mut self, names: &[&str]) {
+        let mut groups = names
+            .iter()
+            .filter_map(|name| self.index.get(*name).copied())
+            .collect::<Vec<_>>();
+        groups.sort_unstable();
+        groups.dedup();
+        let group = match groups.first() {
+            Some(&group) => group,
+            None => {
+                self.groups.push(Vec::new());
+                self.groups.len() - 1
+            }
+        };
+        // The other groups are emptied in the one kept
+        for &other in groups.iter().skip(1) {
+            for name in std::mem::take(&mut self.groups[other]) {
+                self.index.insert(name.clone(), group);
+                self.groups[group].push(name);
+            }
+        }
+        for name in names {
+            if !self.index.contains_key(*name) {
+                self.index.insert(name.to_string(), group);
+                self.groups[group].push(name.to_string());
+            }
+        }
+    }
+
+    /// All the other names of `name`, in the order they were listed
+    pub fn aliases_of<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.index
+            .get(name)
+            .map(|&group| self.groups[group].as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(String::as_str)
+            .filter(move |alias| *alias != name)
+    }
+
+    /// The first of the other names of `name` that `exists`
+    pub fn resolve<'a>(&'a self, name: &'a str, exists: impl Fn(&str) -> bool) -> Option<&'a str> {
+        self.aliases_of(name).find(|alias| exists(alias))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases() {
+        let aliases = Aliases::parse(
+            "# ucsc\tassembly\tgenbank\trefseq\n\
+             chr1\t1\tCM000663.2\tNC_000001.11\n\
+             chrM\tMT\t\tNC_012920.1\n",
+        )
+        .unwrap();
+        assert_eq!(
+            aliases.aliases_of("1").collect::<Vec<_>>(),
+            ["chr1", "CM000663.2", "NC_000001.11"]
+        );
+        assert_eq!(
+            aliases.resolve("MT", |n| n.starts_with("NC_")),
+            Some("NC_012920.1")
+        );
+        assert_eq!(aliases.resolve("chr2", |_| true), None);
+
+        // The older tables list one alias per line, with its source
+        let aliases = Aliases::parse(
+            "#alias\tchrom\tsource\n\
+             1\tchr1\tensembl\n\
+             NC_000001.11\tchr1\trefseq\n",
+        )
+        .unwrap();
+        assert_eq!(
+            aliases.aliases_of("NC_000001.11").collect::<Vec<_>>(),
+            ["1", "chr1"]
+        );
+        assert!(aliases.aliases_of("refseq").next().is_none());
+
+        // Lines sharing a name are merged
+        let aliases = Aliases::parse("a\tb\nc\td\nd\ta\n").unwrap();
+        assert_eq!(aliases.aliases_of("a").collect::<Vec<_>>(), ["b", "c", "d"]);
+
+        assert!(Aliases::parse("chr1 1\n").is_err());
+    }
+}
@@ -0,0 +1,443 @@
+//! The features of a GFF3 annotation of the mounted sequences, and the
+//! translation of their coding sequences.
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strand {
+    Forward,
+    Reverse,
+    Unknown,
+}
+
+/// The region flanking a feature, in its direction of transcription
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flank {
+    Upstream(usize),   // That many bases before its start
+    Downstream(usize), // That many bases after its end
+}
+impl Flank {
+    /// Parse a flank named as `upstreamN` or `downstreamN`
+    pub fn parse(name: &str) -> Option<Flank> {
+        let (make, n): (fn(usize) -> Flank, _) = if let Some(n) = name.strip_prefix("upstream") {
+            (Flank::Upstream, n)
+        } else if let Some(n) = name.strip_prefix("downstream") {
+            (Flank::Downstream, n)
+        } else {
+            return None;
+        };
+        // Only accept the canonical writing of N, so that a flank has a
+        // single name
+        n.parse::<usize>()
+            .ok()
+            .filter(|x| x.to_string() == n)
+            .map(make)
+    }
+}
+impl std::fmt::Display for Flank {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Flank::Upstream(n) => write!(f, "upstream{}", n),
+            Flank::Downstream(n) => write!(f, "downstream{}", n),
+        }
+    }
+}
+
+/// A line of a GFF3 file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Feature {
+    pub seqid: String,
+    pub kind: String,
+    /// 1-based, fully closed
+    pub start: usize,
+    pub end: usize,
+    pub strand: Strand,
+    /// How many bases to skip from the start of a CDS to reach a codon
+    pub phase: Option<usize>,
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub parents: Vec<String>,
+}
+impl Feature {
+    /// The range covered by `flank` on a sequence of `len` bases, 0-based and
+    /// half-open, clipped at the ends of the sequence; features of unknown
+    /// strand are considered as forward
+    pub fn flank(&self, flank: Flank, len: usize) -> Range<usize> {
+        let before = |n: usize| (self.start - 1).saturating_sub(n)..self.start - 1;
+        let after = |n: usize| self.end..self.end.saturating_add(n);
+        let r = match (flank, self.strand == Strand::Reverse) {
+            (Flank::Upstream(n), false) | (Flank::Downstream(n), true) => before(n),
+            (Flank::Downstream(n), false) | (Flank::Upstream(n), true) => after(n),
+        };
+        r.start.min(len)..r.end.min(len)
+    }
+
+    fn parse(line: &str) -> Result<Feature> {
+        let columns = line.split('\t').collect::<Vec<_>>();
+        if columns.len() != 9 {
+            bail!("expected 9 tab-separated columns, found {}", columns.len())
+        }
+        let position = |s: &str| {
+            s.parse::<usize>()
+                .ok()
+                .filter(|&x| x > 0)
+                .ok_or_else(|| anyhow!("`{}` is not a valid position", s))
+        };
+        let (start, end) = (position(columns[3])?, position(columns[4])?);
+        if end < start {
+            bail!("the feature ends at {}, before its start at {}", end, start)
+        }
+
+        let mut r = Feature {
+            seqid: decode(columns[0]),
+            kind: columns[2].to_owned(),
+            start,
+            end,
+            strand: match columns[6] {
+                "+" => Strand::Forward,
+                "-" => Strand::Reverse,
+                "." | "?" => Strand::Unknown,
+                x => bail!("`{}` is not a valid strand", x),
+            },
+            phase: match columns[7] {
+                "." => None,
+                x => Some(
+                    x.parse::<usize>()
+                        .ok()
+                        .filter(|&x| x < 3)
+                        .ok_or_else(|| anyhow!("`{}` is not a valid phase", x))?,
+                ),
+            },
+            id: None,
+            name: None,
+            parents: Vec::new(),
+        };
+        for attribute in columns[8].trim_end_matches(';').split(';') {
+            match attribute.trim().split_once('=') {
+                Some(("ID", id)) => r.id = Some(decode(id)),
+                Some(("Name", name)) => r.name = Some(decode(name)),
+                Some(("Parent", parents)) => r.parents = parents.split(',').map(decode).collect(),
+                _ => {}
+            }
+        }
+        Ok(r)
+    }
+}
+
+/// Decode the percent-encoded characters of a GFF3 field
+fn decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut r = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], s.get(i + 1..i + 3)) {
+            (b'%', Some(hex)) if u8::from_str_radix(hex, 16).is_ok() => {
+                r.push(u8::from_str_radix(hex, 16).unwrap());
+                i += 3;
+            }
+            (c, _) => {
+                r.push(c);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&r).into_owned()
+}
+
+/// The coding sequence of a transcript, made of the CDS features sharing the
+/// same parent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transcript {
+    pub id: String,
+    pub seqid: String,
+    pub strand: Strand,
+    /// The (start, end) of the CDS features, 1-based and fully closed, in
+    /// increasing order
+    pub cds: Vec<(usize, usize)>,
+    /// The phase of the first CDS in the direction of transcription
+    pub phase: usize,
+}
+impl Transcript {
+    /// The length of the spliced coding sequence, in bases
+    pub fn len(&self) -> usize {
+        self.cds.iter().map(|(start, end)| end - start + 1).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cds.is_empty()
+    }
+
+    /// How many codons the coding sequence contains, once its phase is
+    /// skipped
+    pub fn codons(&self) -> usize {
+        self.len().saturating_sub(self.phase) / 3
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    pub features: Vec<Feature>,
+}
+impl Annotation {
+    pub fn parse<R: BufRead>(reader: R) -> Result<Annotation> {
+        let mut features = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line == "##FASTA" {
+                break;
+            }
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            features.push(Feature::parse(&line).with_context(|| format!("line {}", i + 1))?);
+        }
+        Ok(Annotation { features })
+    }
+
+    /// The genes of the annotation having an ID
+    pub fn genes(&self) -> impl Iterator<Item = &Feature> {
+        self.features
+            .iter()
+            .filter(|f| f.kind == "gene" && f.id.is_some())
+    }
+
+    pub fn load(filename: &str) -> Result<Annotation> {
+        let file = std::fs::File::open(filename)
+            .with_context(|| format!("failed to open `{}`", filename))?;
+        Annotation::parse(std::io::BufReader::new(file))
+            .with_context(|| format!("while parsing `{}`", filename))
+    }
+
+    /// The transcripts of the annotation, in the order of their first CDS;
+    /// the CDS without a parent make a transcript of their own
+    pub fn transcripts(&self) -> Result<Vec<Transcript>> {
+        let mut r: Vec<Transcript> = Vec::new();
+        let mut by_id = HashMap::new();
+        for (i, cds) in self
+            .features
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| f.kind == "CDS")
+        {
+            let own_id = [cds.id.clone().unwrap_or_else(|| format!("cds{}", i + 1))];
+            let parents = if cds.parents.is_empty() {
+                &own_id[..]
+            } else {
+                &cds.parents[..]
+            };
+            for parent in parents {
+                let transcript = *by_id.entry(parent.clone()).or_insert_with(|| {
+                    r.push(Transcript {
+                        id: parent.clone(),
+                        seqid: cds.seqid.clone(),
+                        strand: cds.strand,
+                        cds: Vec::new(),
+                        phase: 0,
+                    });
+                    r.len() - 1
+                });
+                let transcript = &mut r[transcript];
+                if transcript.seqid != cds.seqid || transcript.strand != cds.strand {
+                    bail!(
+                        "the CDS of `{}` are not all on the same sequence and strand",
+                        parent
+                    )
+                }
+                transcript.cds.push((cds.start, cds.end));
+                // The phase of the first CDS is the only one needed once
+                // they are spliced
+                let first = match cds.strand {
+                    Strand::Reverse => transcript.cds.iter().all(|c| c.1 <= cds.end),
+                    _ => transcript.cds.iter().all(|c| c.0 >= cds.start),
+                };
+                if first {
+                    transcript.phase = cds.phase.unwrap_or(0);
+                }
+            }
+        }
+        for transcript in r.iter_mut() {
+            transcript.cds.sort_unstable();
+        }
+        Ok(r)
+    }
+}
+
+/// One of the NCBI genetic codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GeneticCode {
+    pub table: u8,
+    /// The amino acids coded by the 64 codons, in the TCAG order
+    amino_acids: &'static [u8; 64],
+}
+impl GeneticCode {
+    pub fn new(table: u8) -> Result<GeneticCode> {
+        let amino_acids = match table {
+            1 | 11 => b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+            2 => b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSS**VVVVAAAADDEEGGGG",
+            3 => b"FFLLSSSSYY**CCWWTTTTPPPPHHQQRRRRIIMMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+            4 => b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+            5 => b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSSSVVVVAAAADDEEGGGG",
+            6 => b"FFLLSSSSYYQQCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+            9 => b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+            10 => b"FFLLSSSSYY**CCCWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+            12 => b"FFLLSSSSYY**CC*WLLLSPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+            13 => b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSGGVVVVAAAADDEEGGGG",
+            14 => b"FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+            _ => bail!(
+                "unsupported genetic code {}; expected one of 1-6 and 9-14",
+                table
+            ),
+        };
+        Ok(GeneticCode { table, amino_acids })
+    }
+
+    /// The codon at `index` in the TCAG order
+    pub fn codon(index: usize) -> [u8; 3] {
+        const BASES: &[u8; 4] = b"TCAG";
+        [
+            BASES[(index >> 4) & 3],
+            BASES[(index >> 2) & 3],
+            BASES[index & 3],
+        ]
+    }
+
+    /// The amino acid coded by the codon at `index` in the TCAG order
+    pub fn amino_acid(&self, index: usize) -> u8 {
+        self.amino_acids[index]
+    }
+
+    /// The index of `codon` in the TCAG order, if it only contains
+    /// unambiguous bases
+    pub fn index(codon: &[u8]) -> Option<usize> {
+        codon.iter().try_fold(0, |index, base| {
+            let base = match base.to_ascii_uppercase() {
+                b'T' | b'U' => 0,
+                b'C' => 1,
+                b'A' => 2,
+                b'G' => 3,
+                _ => return None,
+            };
+            Some(4 * index + base)
+        })
+    }
+
+    /// The amino acid coded by `codon`; `X` if it is ambiguous
+    pub fn translate_codon(&self, codon: &[u8]) -> u8 {
+        GeneticCode::index(codon)
+            .map(|i| self.amino_acids[i])
+            .unwrap_or(b'X')
+    }
+
+    /// Translate `seq` codon by codon, stops included as `*`; trailing
+    /// incomplete codons are dropped
+    pub fn translate(&self, seq: &[u8]) -> Vec<u8> {
+        seq.chunks_exact(3)
+            .map(|codon| self.translate_codon(codon))
+            .collect()
+    }
+}
+impl Default for GeneticCode {
+    fn default() -> Self {
+        GeneticCode::new(1).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transcripts() {
+        let gff = "##gff-version 3\n\
+                   chr1\t.\tgene\t1\t30\t.\t+\t.\tID=g1\n\
+                   chr1\t.\tmRNA\t1\t30\t.\t+\t.\tID=t1;Parent=g1\n\
+                   chr1\t.\tCDS\t2\t7\t.\t+\t1\tID=c1;Parent=t1,t2\n\
+                   chr1\t.\tCDS\t20\t25\t.\t+\t0\tID=c2;Parent=t1\n\
+                   chr2\t.\tCDS\t10\t21\t.\t-\t2\tParent=t%3B3\n\
+                   chr2\t.\tCDS\t1\t5\t.\t-\t0\tParent=t%3B3\n\
+                   chr3\t.\tCDS\t1\t6\t.\t-\t.\t\n\
+                   ##FASTA\n\
+                   >chr1\n";
+        let annotation = Annotation::parse(gff.as_bytes()).unwrap();
+        assert_eq!(annotation.features.len(), 7);
+        assert_eq!(annotation.features[1].parents, ["g1"]);
+
+        let transcripts = annotation.transcripts().unwrap();
+        let summary = transcripts
+            .iter()
+            .map(|t| (t.id.as_str(), t.cds.clone(), t.phase, t.codons()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("t1", vec![(2, 7), (20, 25)], 1, 3),
+                ("t2", vec![(2, 7)], 1, 1),
+                ("t;3", vec![(1, 5), (10, 21)], 2, 5),
+                ("cds7", vec![(1, 6)], 0, 2),
+            ]
+        );
+
+        for invalid in [
+            "chr1\t.\tCDS\t2\t7\t.\t+\t0",
+            "chr1\t.\tCDS\t7\t2\t.\t+\t0\t",
+            "chr1\t.\tCDS\t0\t2\t.\t+\t0\t",
+            "chr1\t.\tCDS\t2\t7\t.\tx\t0\t",
+            "chr1\t.\tCDS\t2\t7\t.\t+\t3\t",
+        ] {
+            assert!(
+                Annotation::parse(invalid.as_bytes()).is_err(),
+                "{}",
+                invalid
+            );
+        }
+        let split = "chr1\t.\tCDS\t2\t7\t.\t+\t0\tParent=t\n\
+                     chr2\t.\tCDS\t2\t7\t.\t+\t0\tParent=t\n";
+        assert!(Annotation::parse(split.as_bytes())
+            .unwrap()
+            .transcripts()
+            .is_err());
+    }
+
+    #[test]
+    fn flanks() {
+        let gff = "chr1\t.\tgene\t11\t20\t.\t+\t.\tID=g1\n\
+                   chr1\t.\tgene\t11\t20\t.\t-\t.\tID=g2\n\
+                   chr1\t.\tmRNA\t11\t20\t.\t-\t.\tID=t2;Parent=g2\n";
+        let annotation = Annotation::parse(gff.as_bytes()).unwrap();
+        let genes = annotation.genes().collect::<Vec<_>>();
+        assert_eq!(genes.len(), 2);
+        let upstream = Flank::parse("upstream5").unwrap();
+        let downstream = Flank::parse("downstream100").unwrap();
+        assert_eq!(upstream, Flank::Upstream(5));
+        assert_eq!(downstream.to_string(), "downstream100");
+        for invalid in ["upstream", "upstream05", "upstream-5", "sideways5"] {
+            assert_eq!(Flank::parse(invalid), None, "{}", invalid);
+        }
+
+        assert_eq!(genes[0].flank(upstream, 30), 5..10);
+        assert_eq!(genes[0].flank(downstream, 30), 20..30);
+        assert_eq!(genes[1].flank(upstream, 30), 20..25);
+        assert_eq!(genes[1].flank(downstream, 30), 0..10);
+        // The fragment is shorter than annotated
+        assert_eq!(genes[0].flank(downstream, 15), 15..15);
+        assert_eq!(genes[1].flank(upstream, 8), 8..8);
+    }
+
+    #[test]
+    fn translation() {
+        let standard = GeneticCode::default();
+        assert_eq!(standard.translate(b"ATGgcuTAAtgANNNTG"), b"MA**X");
+        let mitochondrial = GeneticCode::new(2).unwrap();
+        assert_eq!(mitochondrial.translate(b"ATGAGATGAATA"), b"M*WM");
+        assert!(GeneticCode::new(7).is_err());
+        for i in 0..64 {
+            assert_eq!(GeneticCode::index(&GeneticCode::codon(i)), Some(i));
+        }
+        assert_eq!(
+            standard.amino_acid(GeneticCode::index(b"TGG").unwrap()),
+            b'W'
+        );
+    }
+}
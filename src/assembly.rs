@@ -0,0 +1,164 @@
+//! Statistics over the sequences of a FASTA file, computed incrementally so
+//! that sequences can be fed chunk by chunk.
+
+/// The base composition of one or several sequences
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Composition {
+    /// The number of residues, whitespace excluded
+    pub length: usize,
+    pub gc: usize,
+    pub at: usize,
+    /// Unknown bases, i.e. `N`
+    pub n: usize,
+}
+impl Composition {
+    /// Account for `chunk`, a part of a raw sequence; newlines and other
+    /// whitespace are skipped
+    pub fn consume(&mut self, chunk: &[u8]) {
+        for &c in chunk {
+            match c.to_ascii_uppercase() {
+                b'G' | b'C' | b'S' => self.gc += 1,
+                b'A' | b'T' | b'U' | b'W' => self.at += 1,
+                b'N' => self.n += 1,
+                c if c.is_ascii_whitespace() => continue,
+                _ => {}
+            }
+            self.length += 1;
+        }
+    }
+
+    pub fn add(&mut self, other: &Composition) {
+        self.length += other.length;
+        self.gc += other.gc;
+        self.at += other.at;
+        self.n += other.n;
+    }
+
+    /// The proportion of G/C among the known bases
+    pub fn gc_content(&self) -> f64 {
+        if self.gc + self.at == 0 {
+            0.
+        } else {
+            self.gc as f64 / (self.gc + self.at) as f64
+        }
+    }
+
+    /// The proportion of unknown bases
+    pub fn n_content(&self) -> f64 {
+        if self.length == 0 {
+            0.
+        } else {
+            self.n as f64 / self.length as f64
+        }
+    }
+}
+
+/// The usual contiguity statistics of an assembly
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AssemblyStats {
+    pub count: usize,
+    pub total: usize,
+    pub shortest: usize,
+    pub longest: usize,
+    pub mean: f64,
+    /// The length of the shortest sequence among the longest ones covering
+    /// half of the assembly
+    pub n50: usize,
+    /// How many of the longest sequences are needed to cover half of the
+    /// assembly
+    pub l50: usize,
+    pub n90: usize,
+    pub l90: usize,
+    pub composition: Composition,
+}
+impl AssemblyStats {
+    /// Compute the statistics of the sequences of composition `sequences`
+    pub fn new(sequences: &[Composition]) -> AssemblyStats {
+        let mut lengths = sequences.iter().map(|c| c.length).collect::<Vec<_>>();
+        lengths.sort_unstable_by(|a, b| b.cmp(a));
+        let total = lengths.iter().sum::<usize>();
+        let mut composition = Composition::default();
+        for c in sequences {
+            composition.add(c);
+        }
+
+        // The (Nx, Lx) pair for a fraction x of the total length
+        let nx = |x: usize| {
+            let mut covered = 0;
+            for (i, &l) in lengths.iter().enumerate() {
+                covered += l;
+                if covered * 100 >= total * x {
+                    return (l, i + 1);
+                }
+            }
+            (0, 0)
+        };
+        let (n50, l50) = nx(50);
+        let (n90, l90) = nx(90);
+
+        AssemblyStats {
+            count: lengths.len(),
+            total,
+            shortest: lengths.last().copied().unwrap_or_default(),
+            longest: lengths.first().copied().unwrap_or_default(),
+            mean: if lengths.is_empty() {
+                0.
+            } else {
+                total as f64 / lengths.len() as f64
+            },
+            n50,
+            l50,
+            n90,
+            l90,
+            composition,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn composition(seq: &[u8]) -> Composition {
+        let mut r = Composition::default();
+        r.consume(seq);
+        r
+    }
+
+    #[test]
+    fn chunked_composition() {
+        let seq = b"ACGTN\nacgtn\r\nSWRY\n";
+        let whole = composition(seq);
+        assert_eq!(
+            whole,
+            Composition {
+                length: 14,
+                gc: 5,
+                at: 5,
+                n: 2
+            }
+        );
+        for split in 0..seq.len() {
+            let mut chunked = composition(&seq[..split]);
+            chunked.consume(&seq[split..]);
+            assert_eq!(chunked, whole);
+        }
+        assert_eq!(whole.gc_content(), 0.5);
+    }
+
+    #[test]
+    fn contiguity() {
+        let sequences = [80, 70, 50, 40, 30, 20, 10]
+            .iter()
+            .map(|&l| composition(&b"G".repeat(l)))
+            .collect::<Vec<_>>();
+        let stats = AssemblyStats::new(&sequences);
+        assert_eq!(stats.total, 300);
+        assert_eq!((stats.shortest, stats.longest), (10, 80));
+        assert_eq!((stats.n50, stats.l50), (70, 2));
+        assert_eq!((stats.n90, stats.l90), (30, 5));
+        assert_eq!(stats.composition.gc_content(), 1.);
+
+        assert_eq!(AssemblyStats::new(&[]), AssemblyStats::default());
+    }
+}
@@ -0,0 +1,948 @@
+use crate::bgzf::{self, BgzfReader};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::FileExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const BLOCK_SIZE: usize = 1 << 20;
+// How much of an edited sequence is compressed at once, so that reads only
+// decompress the blocks they overlap
+const EDIT_BLOCK_SIZE: usize = 256 * 1024;
+// Spilling is on the way of the writes, so speed is favoured over ratio
+const EDIT_COMPRESSION_LEVEL: i32 = 1;
+
+/// Whether `c` is part of a line ending, be it LF or CRLF; the CRs are thus
+/// never counted as bases, so that the files written on Windows have the
+/// same sequences as the others
+fn is_newline(c: u8) -> bool {
+    c == b'\n' || c == b'\r'
+}
+
+/// Returns up to `size` bytes of `data`, starting at `offset`, skipping the
+/// newlines both in the result and when counting `offset`
+fn pure_slice(data: &[u8], offset: usize, size: usize) -> std::io::Result<Box<[u8]>> {
+    Ok(data
+        .iter()
+        .cloned()
+        .filter(|&c| !is_newline(c))
+        .skip(offset)
+        .take(size)
+        .collect::<Vec<_>>()
+        .into())
+}
+
+fn bounded_slice(data: &[u8], offset: usize, size: usize) -> std::io::Result<Box<[u8]>> {
+    let start = std::cmp::min(offset, data.len());
+    let end = std::cmp::min(start.saturating_add(size), data.len());
+    Ok(data[start..end].into())
+}
+
+/// Where the sequence of a fragment is stored. Offsets and sizes are
+/// expressed in bytes of the stored sequence, newlines included; reads
+/// extending past its end are truncated. The sequences that are not held
+/// in memory may fail to be read, e.g. when the storage of the FASTA file
+/// goes away; this is reported to the caller, that can then report it to
+/// the client, rather than bringing down the whole mount.
+pub trait SequenceSource: std::fmt::Debug + Send + Sync {
+    /// The size of the sequence, newlines included
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns up to `size` bytes starting at `offset`
+    fn chunk(&self, offset: usize, size: usize) -> std::io::Result<Box<[u8]>>;
+
+    /// Whether the underlying storage failed to be read even after retrying
+    fn is_degraded(&self) -> bool {
+        false
+    }
+
+    /// Returns the whole sequence
+    fn data(&self) -> std::io::Result<Box<[u8]>> {
+        self.chunk(0, self.len())
+    }
+
+    /// The same as `chunk`, but skipping new lines, CRLF included
+    fn pure_chunk(&self, offset: usize, size: usize) -> std::io::Result<Box<[u8]>> {
+        let mut r = Vec::with_capacity(std::cmp::min(size, self.len()));
+        let mut skipped = 0;
+        let mut position = 0;
+        while position < self.len() && r.len() < size {
+            let block = self.chunk(position, BLOCK_SIZE)?;
+            position += block.len();
+            for &c in block.iter().filter(|&&c| !is_newline(c)) {
+                if skipped < offset {
+                    skipped += 1;
+                } else if r.len() < size {
+                    r.push(c);
+                }
+            }
+        }
+        Ok(r.into())
+    }
+
+    /// The length of the sequence, newlines (CRLF included) excluded
+    fn sequence_len(&self) -> std::io::Result<usize> {
+        let mut newlines = 0;
+        let mut position = 0;
+        while position < self.len() {
+            let block = self.chunk(position, BLOCK_SIZE)?;
+            position += block.len();
+            newlines += block.iter().filter(|&&c| is_newline(c)).count();
+        }
+        Ok(self.len() - newlines)
+    }
+
+    /// How many bytes of the sequence are held in memory
+    fn in_memory(&self) -> usize {
+        0
+    }
+
+    /// The underlying buffer, if the sequence can be edited in place
+    fn as_mut_buffer(&mut self) -> Option<&mut Vec<u8>> {
+        None
+    }
+}
+
+/// How the reads failing on a flaky storage, e.g. NFS or Lustre, are retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    pub attempts: usize, // How many times a failed read is tried again
+    pub delay: Duration, // How long to wait before the first retry, doubled at every retry
+}
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            attempts: 3,
+            delay: Duration::from_millis(100),
+        }
+    }
+}
+impl Retry {
+    /// Whether `e` may vanish if the operation is tried again
+    fn is_transient(e: &std::io::Error) -> bool {
+        matches!(
+            e.raw_os_error(),
+            Some(libc::EIO | libc::ESTALE | libc::EAGAIN | libc::EINTR | libc::ETIMEDOUT)
+        )
+    }
+
+    /// Run `f` until it succeeds, fails with a persistent error, or has been
+    /// retried `attempts` times; `what` describes it in the logs
+    pub fn run<T>(
+        &self,
+        what: impl Fn() -> String,
+        mut f: impl FnMut() -> std::io::Result<T>,
+    ) -> std::io::Result<T> {
+        let mut delay = self.delay;
+        for _ in 0..self.attempts {
+            match f() {
+                Err(e) if Retry::is_transient(&e) => {
+                    log::warn!("{} failed ({}); retrying in {:?}", what(), e, delay);
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                }
+                r => return r,
+            }
+        }
+        f()
+    }
+}
+
+/// A FASTA file, read at the offsets of its content
+#[derive(Debug, Clone)]
+pub enum Backing {
+    Plain(Arc<fs::File>),
+    /// A bgzip-compressed file, whose content is inflated block by block
+    Bgzf(Arc<BgzfReader>),
+}
+impl Backing {
+    /// Open the FASTA file at `path`, compressed with bgzip or not; the
+    /// blocks of a compressed one are located from its `.gzi` index if any
+    pub fn open(path: &str) -> std::io::Result<Backing> {
+        let file = fs::File::open(path)?;
+        let mut header = [0; 16];
+        if file.read_exact_at(&mut header, 0).is_ok() && bgzf::is_bgzf(&header) {
+            let gzi = std::path::PathBuf::from(format!("{}.gzi", path));
+            Ok(Backing::Bgzf(Arc::new(BgzfReader::new(file, &gzi)?)))
+        } else {
+            Ok(Backing::Plain(Arc::new(file)))
+        }
+    }
+
+    /// The file itself, compressed or not
+    pub fn file(&self) -> &fs::File {
+        match self {
+            Backing::Plain(file) => file,
+            Backing::Bgzf(reader) => reader.file(),
+        }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        matches!(self, Backing::Bgzf(_))
+    }
+
+    /// The size of the content of the file, once decompressed if need be
+    pub fn size(&self) -> std::io::Result<u64> {
+        match self {
+            Backing::Plain(file) => Ok(file.metadata()?.len()),
+            Backing::Bgzf(reader) => Ok(reader.len()),
+        }
+    }
+
+    pub fn read_exact_at(&self, buffer: &mut [u8], offset: u64) -> std::io::Result<()> {
+        match self {
+            Backing::Plain(file) => file.read_exact_at(buffer, offset),
+            Backing::Bgzf(reader) => reader.read_exact_at(buffer, offset),
+        }
+    }
+
+    /// Read the content of the file from its beginning
+    pub fn reader(&self) -> BackingReader {
+        BackingReader {
+            backing: self.clone(),
+            position: 0,
+        }
+    }
+}
+
+/// Reads a `Backing` sequentially, e.g. to parse it
+#[derive(Debug)]
+pub struct BackingReader {
+    backing: Backing,
+    position: u64,
+}
+impl Read for BackingReader {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let read = match &self.backing {
+            Backing::Plain(file) => file.read_at(buffer, self.position)?,
+            Backing::Bgzf(reader) => {
+                let size = std::cmp::min(
+                    buffer.len() as u64,
+                    reader.len().saturating_sub(self.position),
+                ) as usize;
+                reader.read_exact_at(&mut buffer[..size], self.position)?;
+                size
+            }
+        };
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+/// A start, end pair in the content of a file shared between fragments, read
+/// with pread(2)
+#[derive(Debug)]
+pub struct FileSlice {
+    file: Backing,
+    path: Arc<str>, // Where `file` is, for the logs
+    start: usize,
+    end: usize,
+    retry: Retry,
+    degraded: AtomicBool, // Whether the last read failed even after retrying
+}
+impl FileSlice {
+    pub fn new(file: Backing, path: Arc<str>, start: usize, end: usize, retry: Retry) -> FileSlice {
+        FileSlice {
+            file,
+            path,
+            start,
+            end,
+            retry,
+            degraded: AtomicBool::new(false),
+        }
+    }
+}
+impl SequenceSource for FileSlice {
+    fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Once degraded, the reads are only tried once, so as not to stall the
+    /// clients for the whole backoff every time, until one succeeds again
+    fn chunk(&self, offset: usize, size: usize) -> std::io::Result<Box<[u8]>> {
+        let from = std::cmp::min(self.start.saturating_add(offset), self.end);
+        let to = std::cmp::min(from.saturating_add(size), self.end);
+        let mut buffer = vec![0u8; to - from];
+        let degraded = self.degraded.load(Ordering::Relaxed);
+        let retry = if degraded {
+            Retry {
+                attempts: 0,
+                ..self.retry
+            }
+        } else {
+            self.retry
+        };
+        let what = || format!("Reading {}:{} from `{}`", from, to, self.path);
+        match retry.run(what, || self.file.read_exact_at(&mut buffer, from as u64)) {
+            Ok(()) => {
+                if degraded {
+                    log::info!("`{}` can be read again at {}:{}", self.path, from, to);
+                    self.degraded.store(false, Ordering::Relaxed);
+                }
+                Ok(buffer.into_boxed_slice())
+            }
+            Err(e) => {
+                if !degraded {
+                    log::error!(
+                        "Unable to read {}:{} from `{}` after {} retries: {}",
+                        from,
+                        to,
+                        self.path,
+                        self.retry.attempts,
+                        e
+                    );
+                    self.degraded.store(true, Ordering::Relaxed);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+}
+
+/// A memmapped chunk of memory
+#[derive(Debug)]
+pub struct Mmapped(pub memmap2::Mmap);
+impl SequenceSource for Mmapped {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn chunk(&self, offset: usize, size: usize) -> std::io::Result<Box<[u8]>> {
+        bounded_slice(&self.0, offset, size)
+    }
+
+    fn pure_chunk(&self, offset: usize, size: usize) -> std::io::Result<Box<[u8]>> {
+        pure_slice(&self.0, offset, size)
+    }
+}
+
+/// A chunk of memory, that can be edited
+#[derive(Debug, Default)]
+pub struct Buffer(pub Vec<u8>);
+impl SequenceSource for Buffer {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn chunk(&self, offset: usize, size: usize) -> std::io::Result<Box<[u8]>> {
+        bounded_slice(&self.0, offset, size)
+    }
+
+    fn pure_chunk(&self, offset: usize, size: usize) -> std::io::Result<Box<[u8]>> {
+        pure_slice(&self.0, offset, size)
+    }
+
+    fn in_memory(&self) -> usize {
+        self.0.len()
+    }
+
+    fn as_mut_buffer(&mut self) -> Option<&mut Vec<u8>> {
+        Some(&mut self.0)
+    }
+}
+
+/// A chunk of memory guaranteed to be pure (i.e. without newlines), that can
+/// thus be accessed directly
+#[derive(Debug, Default)]
+pub struct PureBuffer(pub Vec<u8>);
+impl SequenceSource for PureBuffer {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn chunk(&self, offset: usize, size: usize) -> std::io::Result<Box<[u8]>> {
+        bounded_slice(&self.0, offset, size)
+    }
+
+    fn pure_chunk(&self, offset: usize, size: usize) -> std::io::Result<Box<[u8]>> {
+        bounded_slice(&self.0, offset, size)
+    }
+
+    fn sequence_len(&self) -> std::io::Result<usize> {
+        Ok(self.0.len())
+    }
+}
+
+/// The sequences of the memory cache: they are kept in memory up to a
+/// budget, beyond which the least recently used ones are spilled to an
+/// anonymous temporary file, and read back from there when accessed
+#[derive(Debug)]
+pub struct SpillCache(Mutex<Spill>);
+
+#[derive(Debug, Default)]
+struct Spill {
+    budget: usize,
+    resident: usize,        // How many bytes are in memory
+    file: Option<fs::File>, // Created on the first spill
+    end: u64,               // Where the next spilled sequence is written
+    clock: u64,
+    next_slot: u64,
+    slots: HashMap<u64, Slot>,
+    lru: BTreeMap<u64, u64>, // The slots in memory, by their last use
+}
+
+#[derive(Debug)]
+struct Slot {
+    data: Option<Vec<u8>>, // The sequence, if in memory
+    spilled: Option<u64>,  // Where it has been written in the file, if ever
+    len: usize,
+    last_used: u64,
+}
+
+impl Spill {
+    fn touch(&mut self, slot: u64) {
+        self.clock += 1;
+        let s = self.slots.get_mut(&slot).unwrap();
+        self.lru.remove(&s.last_used);
+        s.last_used = self.clock;
+        self.lru.insert(self.clock, slot);
+    }
+
+    /// Spill the least recently used sequences, except `keep` if any, until
+    /// the budget is respected
+    fn evict(&mut self, keep: Option<u64>) {
+        let candidates = self.lru.values().copied().collect::<Vec<_>>();
+        for slot in candidates {
+            if self.resident <= self.budget {
+                break;
+            }
+            if Some(slot) == keep {
+                continue;
+            }
+            if let Err(e) = self.spill(slot) {
+                log::warn!("Unable to spill a sequence to disk: {}", e);
+                return;
+            }
+        }
+    }
+
+    fn spill(&mut self, slot: u64) -> std::io::Result<()> {
+        if self.file.is_none() {
+            self.file = Some(tempfile::tempfile()?);
+        }
+        let s = self.slots.get_mut(&slot).unwrap();
+        // The sequences never change, so they are only written once
+        if s.spilled.is_none() {
+            let data = s.data.as_ref().unwrap();
+            self.file.as_ref().unwrap().write_all_at(data, self.end)?;
+            s.spilled = Some(self.end);
+            self.end += data.len() as u64;
+        }
+        s.data = None;
+        self.resident -= s.len;
+        self.lru.remove(&s.last_used);
+        Ok(())
+    }
+}
+
+impl SpillCache {
+    /// Keep up to `budget` bytes in memory
+    pub fn new(budget: usize) -> Arc<SpillCache> {
+        Arc::new(SpillCache(Mutex::new(Spill {
+            budget,
+            ..Default::default()
+        })))
+    }
+
+    /// Keep up to `budget` bytes in memory from now on, spilling the excess
+    pub fn set_budget(&self, budget: usize) {
+        let mut spill = self.0.lock().unwrap();
+        spill.budget = budget;
+        spill.evict(None);
+    }
+
+    /// Store the sequence `data`, which must not contain newlines
+    pub fn hold(self: &Arc<Self>, data: Vec<u8>) -> Spilled {
+        let mut spill = self.0.lock().unwrap();
+        let slot = spill.next_slot;
+        spill.next_slot += 1;
+        let len = data.len();
+        spill.resident += len;
+        spill.slots.insert(
+            slot,
+            Slot {
+                data: Some(data),
+                spilled: None,
+                len,
+                last_used: 0,
+            },
+        );
+        spill.touch(slot);
+        spill.evict(None);
+        Spilled {
+            cache: self.clone(),
+            slot,
+            len,
+        }
+    }
+
+    /// Call `f` on the sequence in `slot`, first reading it back from the
+    /// disk if it has been spilled
+    fn with<R>(&self, slot: u64, f: impl FnOnce(&[u8]) -> R) -> std::io::Result<R> {
+        let mut spill = self.0.lock().unwrap();
+        let s = &spill.slots[&slot];
+        if s.data.is_none() {
+            let mut data = vec![0; s.len];
+            spill
+                .file
+                .as_ref()
+                .unwrap()
+                .read_exact_at(&mut data, s.spilled.unwrap())?;
+            spill.resident += data.len();
+            spill.slots.get_mut(&slot).unwrap().data = Some(data);
+        }
+        spill.touch(slot);
+        spill.evict(Some(slot));
+        Ok(f(spill.slots[&slot].data.as_ref().unwrap()))
+    }
+
+    fn release(&self, slot: u64) {
+        let mut spill = self.0.lock().unwrap();
+        if let Some(s) = spill.slots.remove(&slot) {
+            if s.data.is_some() {
+                spill.resident -= s.len;
+                spill.lru.remove(&s.last_used);
+            }
+        }
+    }
+
+    /// How many bytes of the sequences are in memory
+    pub fn resident(&self) -> usize {
+        self.0.lock().unwrap().resident
+    }
+}
+
+/// A sequence held by a `SpillCache`; as a `PureBuffer`, it does not contain
+/// newlines
+#[derive(Debug)]
+pub struct Spilled {
+    cache: Arc<SpillCache>,
+    slot: u64,
+    len: usize,
+}
+impl SequenceSource for Spilled {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn chunk(&self, offset: usize, size: usize) -> std::io::Result<Box<[u8]>> {
+        self.cache
+            .with(self.slot, |data| bounded_slice(data, offset, size))?
+    }
+
+    fn pure_chunk(&self, offset: usize, size: usize) -> std::io::Result<Box<[u8]>> {
+        self.chunk(offset, size)
+    }
+
+    fn sequence_len(&self) -> std::io::Result<usize> {
+        Ok(self.len)
+    }
+}
+impl Drop for Spilled {
+    fn drop(&mut self) {
+        self.cache.release(self.slot);
+    }
+}
+
+/// Where the edited sequences are set aside once the edits held in memory
+/// exceed the budget: they are compressed with zstd, block by block, to an
+/// anonymous temporary file, which is dropped once none of them is in use
+#[derive(Debug, Default)]
+pub struct EditSpill(Mutex<EditSpillFile>);
+
+#[derive(Debug, Default)]
+struct EditSpillFile {
+    file: Option<fs::File>, // Created on the first spill
+    end: u64,               // Where the next block is written
+    live: usize,            // How many of the sequences written are still in use
+}
+
+impl EditSpill {
+    pub fn new() -> Arc<EditSpill> {
+        Arc::new(EditSpill::default())
+    }
+
+    /// Compress `data` to the spill file, where it is read back from
+    pub fn store(self: &Arc<Self>, data: &[u8]) -> std::io::Result<Compressed> {
+        let mut spill = self.0.lock().unwrap();
+        if spill.file.is_none() {
+            spill.file = Some(tempfile::tempfile()?);
+        }
+        let mut blocks = Vec::with_capacity(data.len().div_ceil(EDIT_BLOCK_SIZE));
+        for block in data.chunks(EDIT_BLOCK_SIZE) {
+            let compressed = zstd::bulk::compress(block, EDIT_COMPRESSION_LEVEL)?;
+            spill
+                .file
+                .as_ref()
+                .unwrap()
+                .write_all_at(&compressed, spill.end)?;
+            blocks.push((spill.end, compressed.len()));
+            spill.end += compressed.len() as u64;
+        }
+        spill.live += 1;
+        Ok(Compressed {
+            spill: self.clone(),
+            blocks,
+            len: data.len(),
+            sequence_len: data.iter().filter(|&&c| !is_newline(c)).count(),
+            last: Mutex::new(None),
+        })
+    }
+
+    fn read(&self, (offset, size): (u64, usize), len: usize) -> std::io::Result<Vec<u8>> {
+        let mut compressed = vec![0; size];
+        self.0
+            .lock()
+            .unwrap()
+            .file
+            .as_ref()
+            .unwrap()
+            .read_exact_at(&mut compressed, offset)?;
+        zstd::bulk::decompress(&compressed, len)
+    }
+
+    fn release(&self) {
+        let mut spill = self.0.lock().unwrap();
+        spill.live -= 1;
+        if spill.live == 0 {
+            spill.file = None;
+            spill.end = 0;
+        }
+    }
+
+    /// How many bytes the spill file takes
+    pub fn size(&self) -> u64 {
+        self.0.lock().unwrap().end
+    }
+}
+
+/// An edited sequence set aside by an `EditSpill`; as a `Buffer`, it may
+/// contain newlines
+#[derive(Debug)]
+pub struct Compressed {
+    spill: Arc<EditSpill>,
+    blocks: Vec<(u64, usize)>, // Where every block lies in the spill file
+    len: usize,
+    sequence_len: usize,
+    last: Mutex<Option<(usize, Arc<[u8]>)>>, // The last block read, reads being mostly sequential
+}
+impl Compressed {
+    fn block(&self, i: usize) -> std::io::Result<Arc<[u8]>> {
+        let mut last = self.last.lock().unwrap();
+        if let Some((j, block)) = last.as_ref() {
+            if *j == i {
+                return Ok(block.clone());
+            }
+        }
+        let len = std::cmp::min(EDIT_BLOCK_SIZE, self.len - i * EDIT_BLOCK_SIZE);
+        let block: Arc<[u8]> = self.spill.read(self.blocks[i], len)?.into();
+        *last = Some((i, block.clone()));
+        Ok(block)
+    }
+}
+impl SequenceSource for Compressed {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn chunk(&self, offset: usize, size: usize) -> std::io::Result<Box<[u8]>> {
+        let start = std::cmp::min(offset, self.len);
+        let end = std::cmp::min(start.saturating_add(size), self.len);
+        let mut r = Vec::with_capacity(end - start);
+        let mut position = start;
+        while position < end {
+            let block = self.block(position / EDIT_BLOCK_SIZE)?;
+            let from = position % EDIT_BLOCK_SIZE;
+            let to = std::cmp::min(block.len(), from + end - position);
+            r.extend_from_slice(&block[from..to]);
+            position += to - from;
+        }
+        Ok(r.into())
+    }
+
+    fn sequence_len(&self) -> std::io::Result<usize> {
+        Ok(self.sequence_len)
+    }
+}
+impl Drop for Compressed {
+    fn drop(&mut self) {
+        self.spill.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Wrap `seq` in lines of `width` characters, as found in a FASTA file
+    fn wrap(seq: &[u8], width: usize, final_newline: bool, newline: &[u8]) -> Vec<u8> {
+        let mut r = seq.chunks(width).collect::<Vec<_>>().join(newline).to_vec();
+        if final_newline && !seq.is_empty() {
+            r.extend_from_slice(newline);
+        }
+        r
+    }
+
+    /// Synthetic sequences, of various lengths, line widths and line endings
+    fn sequences() -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut r = Vec::new();
+        for len in [0, 1, 7, 60, 61, 1000, BLOCK_SIZE + 17] {
+            let seq = (0..len).map(|i| b"ACGTN"[i * 7 % 5]).collect::<Vec<_>>();
+            for width in [1, 3, 60, 80] {
+                for final_newline in [true, false] {
+                    r.push((seq.clone(), wrap(&seq, width, final_newline, b"\n")));
+                }
+                // Windows line endings
+                if width == 60 {
+                    r.push((seq.clone(), wrap(&seq, width, true, b"\r\n")));
+                }
+            }
+        }
+        r
+    }
+
+    /// All the sources storing `raw`, along with the file backing them
+    fn sources(raw: &[u8]) -> (tempfile::NamedTempFile, Vec<Box<dyn SequenceSource>>) {
+        // Put the sequence in the middle of the file, as it would be in a
+        // multi-FASTA file
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b">header\n").unwrap();
+        file.write_all(raw).unwrap();
+        file.write_all(b">next\nAAAA\n").unwrap();
+        file.flush().unwrap();
+
+        let handle = Arc::new(file.reopen().unwrap());
+        let mut sources: Vec<Box<dyn SequenceSource>> = vec![
+            Box::new(FileSlice::new(
+                Backing::Plain(handle.clone()),
+                "test.fa".into(),
+                8,
+                8 + raw.len(),
+                Retry::default(),
+            )),
+            Box::new(Buffer(raw.to_vec())),
+        ];
+        let content = std::fs::read(file.path()).unwrap();
+        let compressed = tempfile::NamedTempFile::new().unwrap();
+        let blocks = content
+            .chunks(bgzf::BLOCK_INPUT_SIZE)
+            .flat_map(bgzf::block)
+            .collect::<Vec<_>>();
+        std::fs::write(compressed.path(), blocks).unwrap();
+        sources.push(Box::new(FileSlice::new(
+            Backing::open(compressed.path().to_str().unwrap()).unwrap(),
+            "test.fa.gz".into(),
+            8,
+            8 + raw.len(),
+            Retry::default(),
+        )));
+        sources.push(Box::new(EditSpill::new().store(raw).unwrap()));
+        if !raw.is_empty() {
+            sources.push(Box::new(Mmapped(unsafe {
+                memmap2::MmapOptions::new()
+                    .offset(8)
+                    .len(raw.len())
+                    .map(&*handle)
+                    .unwrap()
+            })));
+        }
+        (file, sources)
+    }
+
+    /// The (offset, size) windows to check for a sequence of `len` bytes
+    fn windows(len: usize) -> Vec<(usize, usize)> {
+        let mut r = Vec::new();
+        for offset in [
+            0,
+            1,
+            len / 2,
+            len.saturating_sub(1),
+            len,
+            len + 1,
+            len + 100,
+        ] {
+            for size in [0, 1, 59, 4096, len, len + 1, usize::MAX / 2] {
+                r.push((offset, size));
+            }
+        }
+        r
+    }
+
+    fn expected(data: &[u8], offset: usize, size: usize) -> &[u8] {
+        let start = std::cmp::min(offset, data.len());
+        &data[start..std::cmp::min(start.saturating_add(size), data.len())]
+    }
+
+    #[test]
+    fn sources_agree() {
+        for (seq, raw) in sequences() {
+            let (_file, sources) = sources(&raw);
+            for source in sources.iter() {
+                let name = format!("{:?} ({} bp)", source, seq.len());
+                assert_eq!(source.len(), raw.len(), "{}", name);
+                assert_eq!(source.is_empty(), raw.is_empty(), "{}", name);
+                assert_eq!(&*source.data().unwrap(), &raw[..], "{}", name);
+                assert_eq!(source.sequence_len().unwrap(), seq.len(), "{}", name);
+                for (offset, size) in windows(raw.len()) {
+                    assert_eq!(
+                        &*source.chunk(offset, size).unwrap(),
+                        expected(&raw, offset, size),
+                        "{}: chunk({}, {})",
+                        name,
+                        offset,
+                        size
+                    );
+                }
+                for (offset, size) in windows(seq.len()) {
+                    assert_eq!(
+                        &*source.pure_chunk(offset, size).unwrap(),
+                        expected(&seq, offset, size),
+                        "{}: pure_chunk({}, {})",
+                        name,
+                        offset,
+                        size
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unreadable() {
+        // The sequence extends past the end of a file truncated since then
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b">header\nACGT\n").unwrap();
+        file.flush().unwrap();
+        let source = FileSlice::new(
+            Backing::Plain(Arc::new(file.reopen().unwrap())),
+            "test.fa".into(),
+            8,
+            1000,
+            Retry::default(),
+        );
+        assert!(source.chunk(0, 4).is_ok());
+        assert!(source.chunk(0, 100).is_err());
+        assert!(source.is_degraded());
+        assert!(source.data().is_err());
+        assert!(source.pure_chunk(0, 100).is_err());
+        assert!(source.sequence_len().is_err());
+    }
+
+    #[test]
+    fn pure_buffer() {
+        for (seq, _) in sequences() {
+            let source = PureBuffer(seq.clone());
+            assert_eq!(source.sequence_len().unwrap(), seq.len());
+            for (offset, size) in windows(seq.len()) {
+                assert_eq!(
+                    &*source.chunk(offset, size).unwrap(),
+                    expected(&seq, offset, size)
+                );
+                assert_eq!(
+                    &*source.pure_chunk(offset, size).unwrap(),
+                    expected(&seq, offset, size)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn edit_spill() {
+        let spill = EditSpill::new();
+        let seq = b"ACGTACGTAC\n".repeat(EDIT_BLOCK_SIZE / 4);
+        let a = spill.store(&seq).unwrap();
+        let b = spill.store(b"").unwrap();
+        assert!(spill.size() < seq.len() as u64 / 10);
+        assert_eq!(
+            a.chunk(EDIT_BLOCK_SIZE - 2, 4).unwrap(),
+            seq[EDIT_BLOCK_SIZE - 2..][..4].into()
+        );
+
+        // The file is dropped with the last sequence set aside in it
+        drop(a);
+        assert!(spill.size() > 0);
+        drop(b);
+        assert_eq!(spill.size(), 0);
+    }
+
+    #[test]
+    fn only_buffers_are_editable() {
+        let (_file, mut sources) = sources(b"ACGT\nAC\n");
+        let editable = sources
+            .iter_mut()
+            .map(|s| s.as_mut_buffer().is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(editable, [false, true, false, false, false]);
+        assert_eq!(sources[1].in_memory(), 8);
+        assert_eq!(sources[0].in_memory(), 0);
+    }
+
+    #[test]
+    fn spilling() {
+        let cache = SpillCache::new(10);
+        let seqs = sequences()
+            .into_iter()
+            .map(|(seq, _)| seq)
+            .filter(|seq| seq.len() < 100)
+            .collect::<Vec<_>>();
+        let held = seqs
+            .iter()
+            .map(|seq| cache.hold(seq.clone()))
+            .collect::<Vec<_>>();
+        assert!(cache.resident() <= 10);
+        // Reading them back, in any order, promotes them
+        for (seq, source) in seqs.iter().zip(held.iter()).rev() {
+            assert_eq!(&*source.data().unwrap(), &seq[..]);
+            assert_eq!(source.sequence_len().unwrap(), seq.len());
+            for (offset, size) in windows(seq.len()) {
+                assert_eq!(
+                    &*source.pure_chunk(offset, size).unwrap(),
+                    expected(seq, offset, size)
+                );
+            }
+        }
+        // A sequence larger than the budget is still served
+        let large = vec![b'A'; 50];
+        let source = cache.hold(large.clone());
+        assert_eq!(&*source.data().unwrap(), &large[..]);
+        drop(source);
+        drop(held);
+        assert_eq!(cache.resident(), 0);
+    }
+
+    #[test]
+    fn retries() {
+        let retry = Retry {
+            attempts: 3,
+            delay: Duration::from_millis(1),
+        };
+        let failing = |failures: usize, errno: i32| {
+            let mut calls = 0;
+            let r = retry.run(String::new, || {
+                calls += 1;
+                if calls <= failures {
+                    Err(std::io::Error::from_raw_os_error(errno))
+                } else {
+                    Ok(calls)
+                }
+            });
+            (r.ok(), calls)
+        };
+        assert_eq!(failing(0, libc::EIO), (Some(1), 1));
+        assert_eq!(failing(3, libc::ESTALE), (Some(4), 4));
+        assert_eq!(failing(4, libc::EIO), (None, 4));
+        // Persistent errors are not retried
+        assert_eq!(failing(1, libc::ENOENT), (None, 1));
+    }
+}
@@ -0,0 +1,382 @@
+use flate2::{Compress, Compression, Crc, FlushCompress};
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// The largest amount of data compressed in a single block, chosen so that
+/// a block always fits in the 64kB BGZF limit, even if incompressible
+pub const BLOCK_INPUT_SIZE: usize = 0xff00;
+
+/// The empty block terminating every BGZF file
+const EOF_BLOCK: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Compress `data` in a single BGZF block, i.e. a gzip member carrying its
+/// own size in an extra field
+pub fn block(data: &[u8]) -> Vec<u8> {
+    assert!(data.len() <= BLOCK_INPUT_SIZE);
+    let mut deflated = Vec::with_capacity(BLOCK_INPUT_SIZE + 1024);
+    Compress::new(Compression::default(), false)
+        .compress_vec(data, &mut deflated, FlushCompress::Finish)
+        .expect("deflating to memory can not fail");
+    let mut crc = Crc::new();
+    crc.update(data);
+
+    let size = 18 + deflated.len() + 8;
+    let mut r = Vec::with_capacity(size);
+    r.extend_from_slice(&[0x1f, 0x8b, 0x08, 0x04, 0, 0, 0, 0, 0, 0xff]);
+    r.extend_from_slice(&[0x06, 0x00, b'B', b'C', 0x02, 0x00]);
+    r.extend_from_slice(&((size - 1) as u16).to_le_bytes());
+    r.extend_from_slice(&deflated);
+    r.extend_from_slice(&crc.sum().to_le_bytes());
+    r.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    r
+}
+
+/// How many decompressed blocks a reader keeps, so that the successive small
+/// reads of a sequence do not inflate the same block over and over
+const CACHED_BLOCKS: usize = 16;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Whether `header`, the beginning of a file, is that of a BGZF file
+pub fn is_bgzf(header: &[u8]) -> bool {
+    header.len() >= 16 && header[..4] == [0x1f, 0x8b, 0x08, 0x04] && &header[12..14] == b"BC"
+}
+
+/// Whether the file at `path` is BGZF-compressed
+pub fn is_bgzf_file(path: &Path) -> bool {
+    let mut header = [0; 16];
+    fs::File::open(path).is_ok_and(|f| f.read_exact_at(&mut header, 0).is_ok()) && is_bgzf(&header)
+}
+
+/// The size of the header of the BGZF block starting with `bytes`, and the
+/// size of the whole block, as stored in its `BC` extra field
+fn block_header(bytes: &[u8]) -> io::Result<(usize, usize)> {
+    if bytes.len() < 12 || bytes[..3] != [0x1f, 0x8b, 0x08] || bytes[3] & 0x04 == 0 {
+        return Err(invalid("not a BGZF block".into()));
+    }
+    let xlen = u16::from_le_bytes([bytes[10], bytes[11]]) as usize;
+    let mut extra = bytes
+        .get(12..12 + xlen)
+        .ok_or_else(|| invalid("truncated BGZF block header".into()))?;
+    while extra.len() >= 4 {
+        let len = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        if &extra[..2] == b"BC" && len == 2 && extra.len() >= 6 {
+            let size = u16::from_le_bytes([extra[4], extra[5]]) as usize + 1;
+            if size < 12 + xlen + 8 {
+                return Err(invalid("invalid BGZF block size".into()));
+            }
+            return Ok((12 + xlen, size));
+        }
+        extra = &extra[std::cmp::min(4 + len, extra.len())..];
+    }
+    Err(invalid("not a BGZF block".into()))
+}
+
+/// Random access to the content of a BGZF file, i.e. a bgzip-compressed
+/// file, reading and inflating only the blocks covering each read
+#[derive(Debug)]
+pub struct BgzfReader {
+    file: fs::File,
+    /// Where every block starts, in the compressed file and in its content,
+    /// followed by where both end
+    blocks: Vec<(u64, u64)>,
+    /// The blocks last inflated, by index
+    cache: Mutex<VecDeque<(usize, Arc<[u8]>)>>,
+}
+impl BgzfReader {
+    /// Locate the blocks of `file` from its `.gzi` index at `gzi`, as written
+    /// by `bgzip -i`, or from their headers if there is none
+    pub fn new(file: fs::File, gzi: &Path) -> io::Result<BgzfReader> {
+        let size = file.metadata()?.len();
+        let mut blocks = match fs::read(gzi) {
+            Ok(index) => match BgzfReader::parse_gzi(&index) {
+                Ok(blocks) => blocks,
+                Err(e) => {
+                    log::warn!("Ignoring {}: {}", gzi.display(), e);
+                    vec![(0, 0)]
+                }
+            },
+            Err(_) => vec![(0, 0)],
+        };
+        // The index does not tell where the last block ends
+        let last = blocks.pop().unwrap();
+        match BgzfReader::walk(&file, last, size) {
+            Ok(rest) => blocks.extend(rest),
+            Err(e) if last != (0, 0) => {
+                log::warn!("Ignoring {}: {}", gzi.display(), e);
+                blocks = BgzfReader::walk(&file, (0, 0), size)?;
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(BgzfReader {
+            file,
+            blocks,
+            cache: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    /// The blocks listed in a `.gzi` index: their count, then where each but
+    /// the first one starts in the compressed file and in its content
+    fn parse_gzi(index: &[u8]) -> io::Result<Vec<(u64, u64)>> {
+        let u64_at = |i: usize| u64::from_le_bytes(index[i..i + 8].try_into().unwrap());
+        if index.len() < 8 || !(index.len() - 8).is_multiple_of(16) {
+            return Err(invalid("truncated index".into()));
+        }
+        let count = u64_at(0) as usize;
+        if count != (index.len() - 8) / 16 {
+            return Err(invalid(format!("{} blocks expected", count)));
+        }
+        let mut blocks = vec![(0, 0)];
+        for i in 0..count {
+            let block = (u64_at(8 + 16 * i), u64_at(16 + 16 * i));
+            if block.0 <= blocks.last().unwrap().0 || block.1 < blocks.last().unwrap().1 {
+                return Err(invalid("unordered blocks".into()));
+            }
+            blocks.push(block);
+        }
+        Ok(blocks)
+    }
+
+    /// Locate the blocks of `file`, of `size` bytes, from the one starting at
+    /// `from`, by reading their headers and their trailing content size
+    fn walk(file: &fs::File, from: (u64, u64), size: u64) -> io::Result<Vec<(u64, u64)>> {
+        let (mut start, mut offset) = from;
+        let mut blocks = Vec::new();
+        while start < size {
+            blocks.push((start, offset));
+            let mut header = vec![0; 12];
+            file.read_exact_at(&mut header, start)?;
+            let xlen = u16::from_le_bytes([header[10], header[11]]) as usize;
+            header.resize(12 + xlen, 0);
+            file.read_exact_at(&mut header, start)?;
+            let (_, block_size) = block_header(&header)?;
+            let mut isize = [0; 4];
+            file.read_exact_at(&mut isize, start + block_size as u64 - 4)?;
+            start += block_size as u64;
+            offset += u32::from_le_bytes(isize) as u64;
+        }
+        if start != size {
+            return Err(invalid("truncated BGZF file".into()));
+        }
+        blocks.push((start, offset));
+        Ok(blocks)
+    }
+
+    /// The compressed file
+    pub fn file(&self) -> &fs::File {
+        &self.file
+    }
+
+    /// The size of the content of the file
+    pub fn len(&self) -> u64 {
+        self.blocks.last().unwrap().1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The content of the block `i`
+    fn block(&self, i: usize) -> io::Result<Arc<[u8]>> {
+        if let Some((_, block)) = self.cache.lock().unwrap().iter().find(|b| b.0 == i) {
+            return Ok(block.clone());
+        }
+        let ((start, offset), (end, next)) = (self.blocks[i], self.blocks[i + 1]);
+        let mut compressed = vec![0; (end - start) as usize];
+        self.file.read_exact_at(&mut compressed, start)?;
+        let (header_size, _) = block_header(&compressed)?;
+        let mut block = Vec::with_capacity((next - offset) as usize);
+        flate2::read::DeflateDecoder::new(
+            compressed
+                .get(header_size..compressed.len().saturating_sub(8))
+                .ok_or_else(|| invalid(format!("truncated BGZF block at {}", start)))?,
+        )
+        .read_to_end(&mut block)?;
+        if block.len() as u64 != next - offset {
+            return Err(invalid(format!("corrupted BGZF block at {}", start)));
+        }
+        let block: Arc<[u8]> = block.into();
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= CACHED_BLOCKS {
+            cache.pop_front();
+        }
+        cache.push_back((i, block.clone()));
+        Ok(block)
+    }
+
+    /// Fill `buffer` with the content of the file starting at `offset`
+    pub fn read_exact_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+        if offset + buffer.len() as u64 > self.len() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let mut done = 0;
+        while done < buffer.len() {
+            let position = offset + done as u64;
+            // Empty blocks start where the next one does, and are skipped
+            let i = self.blocks.partition_point(|b| b.1 <= position) - 1;
+            let block = self.block(i)?;
+            let start = (position - self.blocks[i].1) as usize;
+            let len = std::cmp::min(block.len() - start, buffer.len() - done);
+            buffer[done..done + len].copy_from_slice(&block[start..start + len]);
+            done += len;
+        }
+        Ok(())
+    }
+}
+
+/// Compresses a file block after block as it is being read, so that only
+/// the blocks covering the current read are kept in memory. Reads are
+/// expected to be sequential; going backward restarts the compression from
+/// the beginning of the file.
+#[derive(Debug, Default)]
+pub struct BgzfStream {
+    /// How much of the uncompressed file has been consumed
+    consumed: usize,
+    /// The offset of `buffer` in the compressed file
+    start: u64,
+    /// Compressed data, not yet entirely read
+    buffer: Vec<u8>,
+    finished: bool,
+}
+impl BgzfStream {
+    /// Returns up to `size` bytes of the compressed file, starting at
+    /// `offset`; `source(offset, size)` returns up to `size` bytes of the
+    /// uncompressed file, starting at `offset`, and nothing past its end.
+    pub fn read<E>(
+        &mut self,
+        offset: u64,
+        size: usize,
+        mut source: impl FnMut(usize, usize) -> Result<Vec<u8>, E>,
+    ) -> Result<Vec<u8>, E> {
+        if offset < self.start {
+            *self = BgzfStream::default();
+        }
+        let end = offset + size as u64;
+        while !self.finished && self.start + (self.buffer.len() as u64) < end {
+            let mut data = Vec::with_capacity(BLOCK_INPUT_SIZE);
+            while data.len() < BLOCK_INPUT_SIZE {
+                let chunk = source(self.consumed, BLOCK_INPUT_SIZE - data.len())?;
+                if chunk.is_empty() {
+                    break;
+                }
+                self.consumed += chunk.len();
+                data.extend_from_slice(&chunk);
+            }
+            if data.is_empty() {
+                self.buffer.extend_from_slice(&EOF_BLOCK);
+                self.finished = true;
+            } else {
+                self.buffer.extend(block(&data));
+            }
+        }
+
+        // Drop what precedes this read, as the next one should follow it
+        let skip = std::cmp::min(offset - self.start, self.buffer.len() as u64) as usize;
+        self.buffer.drain(..skip);
+        self.start += skip as u64;
+        let len = std::cmp::min(size, self.buffer.len());
+        Ok(self.buffer[..len].to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streamed_compression() {
+        let data = (0..300_000)
+            .map(|i| b"ACGT"[(i * i / 7) % 4])
+            .collect::<Vec<_>>();
+        let source = |offset: usize, size: usize| -> Result<Vec<u8>, ()> {
+            // Short reads, to exercise the refilling of the blocks
+            let end = std::cmp::min(offset + std::cmp::min(size, 10_000), data.len());
+            Ok(data[std::cmp::min(offset, end)..end].to_vec())
+        };
+
+        let mut stream = BgzfStream::default();
+        let mut compressed = Vec::new();
+        loop {
+            let chunk = stream.read(compressed.len() as u64, 4096, source).unwrap();
+            if chunk.is_empty() {
+                break;
+            }
+            compressed.extend(chunk);
+        }
+        assert!(compressed.ends_with(&EOF_BLOCK));
+
+        let mut decompressed = Vec::new();
+        flate2::read::MultiGzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+
+        // Going backward restarts the compression
+        assert_eq!(
+            stream.read(10, 100, source).unwrap(),
+            compressed[10..110].to_vec()
+        );
+    }
+
+    #[test]
+    fn random_access() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ref.fa.gz");
+        let data = (0..200_000)
+            .map(|i| b"ACGT\n"[(i * i / 7) % 5])
+            .collect::<Vec<_>>();
+        let mut compressed = Vec::new();
+        let mut gzi = Vec::new();
+        for (i, chunk) in data.chunks(BLOCK_INPUT_SIZE).enumerate() {
+            if i > 0 {
+                gzi.push((compressed.len() as u64, (i * BLOCK_INPUT_SIZE) as u64));
+            }
+            compressed.extend(block(chunk));
+        }
+        compressed.extend_from_slice(&EOF_BLOCK);
+        fs::write(&path, &compressed).unwrap();
+        assert!(is_bgzf_file(&path));
+
+        let index = dir.path().join("ref.fa.gz.gzi");
+        let check = |reader: &BgzfReader| {
+            assert_eq!(reader.len(), data.len() as u64);
+            for (offset, size) in [(0, 10), (BLOCK_INPUT_SIZE - 5, 10), (150_000, 50_000)] {
+                let mut buffer = vec![0; size];
+                reader.read_exact_at(&mut buffer, offset as u64).unwrap();
+                assert_eq!(buffer, data[offset..offset + size]);
+            }
+            let mut buffer = vec![0; 2];
+            assert!(reader
+                .read_exact_at(&mut buffer, data.len() as u64 - 1)
+                .is_err());
+        };
+        // Without the index, the blocks are found from their headers
+        check(&BgzfReader::new(fs::File::open(&path).unwrap(), &index).unwrap());
+
+        let mut content = (gzi.len() as u64).to_le_bytes().to_vec();
+        for (c, u) in gzi.iter() {
+            content.extend_from_slice(&c.to_le_bytes());
+            content.extend_from_slice(&u.to_le_bytes());
+        }
+        fs::write(&index, &content).unwrap();
+        let reader = BgzfReader::new(fs::File::open(&path).unwrap(), &index).unwrap();
+        assert_eq!(reader.blocks[..gzi.len() + 1][1..], gzi[..]);
+        check(&reader);
+
+        // A broken index is ignored
+        fs::write(&index, &content[..20]).unwrap();
+        check(&BgzfReader::new(fs::File::open(&path).unwrap(), &index).unwrap());
+        fs::write(&index, [&1u64.to_le_bytes()[..], &[7; 16]].concat()).unwrap();
+        check(&BgzfReader::new(fs::File::open(&path).unwrap(), &index).unwrap());
+    }
+}
@@ -0,0 +1,462 @@
+//! `fusta browse`: a terminal interface to look through the sequences of a
+//! FASTA file, and to rename, delete or extract them without mounting it.
+use crate::fs::{FustaFS, FustaSettings};
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::path::Path;
+use std::time::Duration;
+
+// How long to wait for a key before checking whether stats.csv is ready
+const TICK: Duration = Duration::from_millis(250);
+const HELP: &str = "↑↓ select  ←→ scroll  r rename  d delete  x extract  q quit";
+
+#[derive(Debug, PartialEq)]
+enum Mode {
+    Browse,
+    Rename(String),  // The new ID, as typed so far
+    Extract(String), // The file to extract to, as typed so far
+    ConfirmDelete,
+    ConfirmQuit,
+}
+
+/// Apply `key` to a line being typed
+fn edit(input: &mut String, key: KeyCode) {
+    match key {
+        KeyCode::Char(c) => input.push(c),
+        KeyCode::Backspace => {
+            input.pop();
+        }
+        _ => {}
+    }
+}
+
+struct Entry {
+    id: String,
+    length: usize,
+    gc: Option<f64>, // Known once stats.csv is computed
+}
+
+struct App {
+    fusta: FustaFS,
+    filename: String,
+    entries: Vec<Entry>,
+    table: TableState,
+    offset: usize,       // Where the preview starts in the selected sequence
+    preview_size: usize, // How many bases fit in the preview
+    mode: Mode,
+    message: Option<String>, // The outcome of the last action
+    stats_pending: bool,
+    done: bool,
+}
+impl App {
+    fn new(fusta: FustaFS, filename: &str) -> App {
+        let mut r = App {
+            fusta,
+            filename: filename.to_owned(),
+            entries: Vec::new(),
+            table: TableState::default(),
+            offset: 0,
+            preview_size: 0,
+            mode: Mode::Browse,
+            message: None,
+            stats_pending: false,
+            done: false,
+        };
+        r.refresh();
+        r
+    }
+
+    /// List the sequences again after they changed
+    fn refresh(&mut self) {
+        let sequences = self.fusta.sequences().unwrap_or_else(|e| {
+            self.message = Some(e.to_string());
+            Vec::new()
+        });
+        self.entries = sequences
+            .into_iter()
+            .map(|(id, length)| Entry {
+                id,
+                length,
+                gc: None,
+            })
+            .collect();
+        self.stats_pending = true;
+        self.table.select(if self.entries.is_empty() {
+            None
+        } else {
+            Some(
+                self.table
+                    .selected()
+                    .unwrap_or(0)
+                    .min(self.entries.len() - 1),
+            )
+        });
+        self.offset = 0;
+    }
+
+    /// Fill in the GC contents if stats.csv has been computed since
+    fn poll_stats(&mut self) {
+        if !self.stats_pending {
+            return;
+        }
+        match self.fusta.stats() {
+            Ok(Some(csv)) => {
+                // id, length, gc_content, n_content, gaps, md5
+                let separator = self.fusta.csv_separator();
+                let gcs = csv
+                    .lines()
+                    .skip(1)
+                    .filter_map(|l| {
+                        let fields = l.rsplitn(6, separator).collect::<Vec<_>>();
+                        Some((fields.get(5)?.to_string(), fields[3].parse::<f64>().ok()?))
+                    })
+                    .collect::<std::collections::HashMap<_, _>>();
+                for e in self.entries.iter_mut() {
+                    e.gc = gcs.get(&e.id).copied();
+                }
+                self.stats_pending = false;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                self.message = Some(e.to_string());
+                self.stats_pending = false;
+            }
+        }
+    }
+
+    fn selected(&self) -> Option<&Entry> {
+        self.table.selected().and_then(|i| self.entries.get(i))
+    }
+
+    fn select(&mut self, i: usize) {
+        if !self.entries.is_empty() {
+            self.table.select(Some(i.min(self.entries.len() - 1)));
+            self.offset = 0;
+        }
+    }
+
+    /// Report the outcome of an action, refreshing the list if it succeeded
+    fn report<E: std::fmt::Display>(&mut self, r: Result<String, E>) {
+        match r {
+            Ok(msg) => {
+                self.refresh();
+                self.message = Some(msg);
+            }
+            Err(e) => self.message = Some(e.to_string()),
+        }
+    }
+
+    fn handle(&mut self, key: KeyCode) {
+        let current = self.selected().map(|e| e.id.clone());
+        let selected = self.table.selected().unwrap_or(0);
+        let mode = std::mem::replace(&mut self.mode, Mode::Browse);
+        self.message = None;
+        match (mode, key) {
+            (Mode::Browse, KeyCode::Char('q')) => {
+                if self.fusta.is_dirty() {
+                    self.mode = Mode::ConfirmQuit;
+                } else {
+                    self.done = true;
+                }
+            }
+            (Mode::Browse, KeyCode::Down | KeyCode::Char('j')) => self.select(selected + 1),
+            (Mode::Browse, KeyCode::Up | KeyCode::Char('k')) => {
+                self.select(selected.saturating_sub(1))
+            }
+            (Mode::Browse, KeyCode::PageDown) => self.select(selected + 20),
+            (Mode::Browse, KeyCode::PageUp) => self.select(selected.saturating_sub(20)),
+            (Mode::Browse, KeyCode::Home) => self.select(0),
+            (Mode::Browse, KeyCode::End) => self.select(usize::MAX),
+            (Mode::Browse, KeyCode::Right | KeyCode::Char('l')) => {
+                let length = self.selected().map(|e| e.length).unwrap_or(0);
+                if self.offset + self.preview_size < length {
+                    self.offset += self.preview_size;
+                }
+            }
+            (Mode::Browse, KeyCode::Left | KeyCode::Char('h')) => {
+                self.offset = self.offset.saturating_sub(self.preview_size)
+            }
+            (Mode::Browse, KeyCode::Char('r')) => {
+                if let Some(id) = current {
+                    self.mode = Mode::Rename(id);
+                }
+            }
+            (Mode::Browse, KeyCode::Char('d')) => {
+                if current.is_some() {
+                    self.mode = Mode::ConfirmDelete;
+                }
+            }
+            (Mode::Browse, KeyCode::Char('x')) => {
+                if let Some(id) = current {
+                    let path = Path::new(&self.filename).with_file_name(format!("{}.fa", id));
+                    self.mode = Mode::Extract(path.to_string_lossy().into_owned());
+                }
+            }
+            (Mode::Browse, _) => {}
+
+            (Mode::Rename(new_id), KeyCode::Enter) => {
+                let id = current.unwrap();
+                let r = self
+                    .fusta
+                    .rename_fragment(&id, &new_id)
+                    .map(|_| format!("`{}` renamed to `{}`", id, new_id));
+                self.report(r);
+            }
+            (Mode::Extract(path), KeyCode::Enter) => {
+                let id = current.unwrap();
+                let r = self
+                    .fusta
+                    .extract(&id, Path::new(&path))
+                    .map(|_| format!("`{}` extracted to {}", id, path));
+                // The sequences are left unchanged
+                self.message = Some(r.unwrap_or_else(|e| e.to_string()));
+            }
+            (Mode::Rename(_) | Mode::Extract(_), KeyCode::Esc) => {}
+            (Mode::Rename(mut input), key) => {
+                edit(&mut input, key);
+                self.mode = Mode::Rename(input);
+            }
+            (Mode::Extract(mut input), key) => {
+                edit(&mut input, key);
+                self.mode = Mode::Extract(input);
+            }
+
+            (Mode::ConfirmDelete, KeyCode::Char('y')) => {
+                let id = current.unwrap();
+                let r = self
+                    .fusta
+                    .delete_fragment(&id)
+                    .map(|_| format!("`{}` deleted", id));
+                self.report(r);
+            }
+            (Mode::ConfirmDelete, _) => {}
+
+            (Mode::ConfirmQuit, KeyCode::Char(c @ ('y' | 'n'))) => {
+                match self.fusta.write_changes(c == 'y') {
+                    Ok(_) => self.done = true,
+                    Err(e) => self.message = Some(e.to_string()),
+                }
+            }
+            (Mode::ConfirmQuit, KeyCode::Char('c') | KeyCode::Esc) => {}
+            (Mode::ConfirmQuit, _) => self.mode = Mode::ConfirmQuit,
+        }
+    }
+
+    fn status(&self) -> String {
+        match &self.mode {
+            Mode::Browse => self.message.clone().unwrap_or_else(|| HELP.to_owned()),
+            Mode::Rename(input) => format!("Rename to: {}▏", input),
+            Mode::Extract(input) => format!("Extract to: {}▏", input),
+            Mode::ConfirmDelete => format!(
+                "Delete `{}`? [y/n]",
+                self.selected().map(|e| e.id.as_str()).unwrap_or_default()
+            ),
+            Mode::ConfirmQuit => format!(
+                "Write the changes back to {}? [y]es, [n]o, [c]ancel",
+                self.filename
+            ),
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).areas(frame.area());
+        let [list, preview] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(main);
+
+        let rows = self.entries.iter().map(|e| {
+            Row::new(vec![
+                e.id.clone(),
+                e.length.to_string(),
+                e.gc.map(|gc| format!("{:.1}%", 100. * gc))
+                    .unwrap_or_else(|| "…".to_owned()),
+            ])
+        });
+        let title = format!(
+            " {}{} ",
+            self.filename,
+            if self.fusta.is_dirty() {
+                " [modified]"
+            } else {
+                ""
+            }
+        );
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(1),
+                Constraint::Length(12),
+                Constraint::Length(6),
+            ],
+        )
+        .header(
+            Row::new(vec!["ID", "Length", "GC"]).style(Style::new().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::bordered().title(title))
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, list, &mut self.table);
+
+        let block = Block::bordered();
+        let inner = block.inner(preview);
+        let width = std::cmp::max(inner.width as usize, 1);
+        self.preview_size = width * inner.height as usize;
+        let (title, lines) = match self.selected() {
+            Some(e) => match self
+                .fusta
+                .sequence_window(&e.id, self.offset, self.preview_size)
+            {
+                // e.g. when the storage of the FASTA file went away
+                Err(err) => (format!(" {} ", e.id), vec![Line::raw(err.to_string())]),
+                Ok(window) => (
+                    format!(
+                        " {} [{}-{}/{}] ",
+                        e.id,
+                        std::cmp::min(self.offset + 1, e.length),
+                        self.offset + window.len(),
+                        e.length
+                    ),
+                    window
+                        .chunks(width)
+                        .map(|l| Line::raw(String::from_utf8_lossy(l).into_owned()))
+                        .collect::<Vec<_>>(),
+                ),
+            },
+            None => (String::new(), Vec::new()),
+        };
+        frame.render_widget(Paragraph::new(lines).block(block.title(title)), preview);
+        frame.render_widget(Paragraph::new(self.status()), status);
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        while !self.done {
+            terminal.draw(|frame| self.draw(frame))?;
+            if event::poll(TICK)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        self.handle(key.code);
+                    }
+                }
+            }
+            self.poll_stats();
+        }
+        Ok(())
+    }
+}
+
+pub fn run(filename: &str) -> Result<()> {
+    let fusta = FustaFS::new(FustaSettings::default(), filename)?;
+    let mut app = App::new(fusta, filename);
+    let mut terminal = ratatui::init();
+    let r = app.run(&mut terminal);
+    ratatui::restore();
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+    use std::fs;
+
+    #[test]
+    fn browse() {
+        let dir = tempfile::tempdir().unwrap();
+        let fasta = dir.path().join("ref.fa");
+        fs::write(&fasta, ">a\nACGT\nAC\n>b\nGGGG\n>c\nTTTT\n").unwrap();
+        let filename = fasta.to_str().unwrap();
+        let mut app = App::new(
+            FustaFS::new(FustaSettings::default(), filename).unwrap(),
+            filename,
+        );
+        let keys = |app: &mut App, keys: &str| {
+            for c in keys.chars() {
+                app.handle(match c {
+                    '\n' => KeyCode::Enter,
+                    '\x08' => KeyCode::Backspace,
+                    c => KeyCode::Char(c),
+                });
+            }
+        };
+        for _ in 0..100 {
+            app.poll_stats();
+            if !app.stats_pending {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(app.entries[0].length, 6);
+        assert_eq!(app.entries[1].gc, Some(1.));
+
+        let mut terminal = Terminal::new(TestBackend::new(60, 8)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let screen = terminal
+            .backend()
+            .buffer()
+            .content
+            .iter()
+            .map(|c| c.symbol())
+            .collect::<String>();
+        assert!(screen.contains("ACGTAC"), "{}", screen);
+        assert!(screen.contains("100.0%"), "{}", screen);
+
+        // Actions go through the same operations as the mount
+        keys(&mut app, "jr\x08z\n");
+        assert_eq!(app.entries[1].id, "z");
+        keys(&mut app, "jdn");
+        assert_eq!(app.entries.len(), 3);
+        keys(&mut app, "dy");
+        assert_eq!(app.entries.len(), 2);
+        keys(&mut app, "kr\x08c\n");
+        assert!(app.message.as_ref().unwrap().contains("renamed"));
+        keys(&mut app, "r\x08z\n");
+        assert!(app.message.as_ref().unwrap().contains("already existing"));
+        keys(&mut app, "x\n");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("c.fa")).unwrap(),
+            ">c\nACGT\nAC\n"
+        );
+
+        // Nothing is written back unless confirmed
+        keys(&mut app, "qc");
+        assert!(!app.done);
+        assert_eq!(
+            fs::read_to_string(&fasta).unwrap(),
+            ">a\nACGT\nAC\n>b\nGGGG\n>c\nTTTT\n"
+        );
+        keys(&mut app, "qy");
+        assert!(app.done);
+        assert_eq!(
+            fs::read_to_string(&fasta).unwrap(),
+            ">c\nACGT\nAC\n>z\nGGGG\n"
+        );
+    }
+
+    #[test]
+    fn csv_separator() {
+        let dir = tempfile::tempdir().unwrap();
+        let fasta = dir.path().join("ref.fa");
+        fs::write(&fasta, ">a\nACGT\n>b\nGGGG\n").unwrap();
+        let filename = fasta.to_str().unwrap();
+        let settings = FustaSettings {
+            csv_separator: ";".into(),
+            ..FustaSettings::default()
+        };
+        let mut app = App::new(FustaFS::new(settings, filename).unwrap(), filename);
+        for _ in 0..100 {
+            app.poll_stats();
+            if !app.stats_pending {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(app.entries[0].gc, Some(0.5));
+        assert_eq!(app.entries[1].gc, Some(1.));
+    }
+}
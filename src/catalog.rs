@@ -0,0 +1,446 @@
+//! The catalog of a FASTA file, kept with --catalog in an SQLite database
+//! next to its session. It holds the records found in the file, so that it
+//! is remounted without being scanned again, and the state of its fragments
+//! (inodes, digests, tags) in place of the session, so that the recorded
+//! digests stay in the database rather than being held in memory. Meant for
+//! the files of millions of sequences, e.g. nt or SILVA.
+use crate::session::Session;
+use anyhow::{Context, Result};
+use fusta::fasta::Fragment as Record;
+use fusta::index::Source;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const CATALOG_EXT: &str = "fusta-catalog";
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS sources (
+        file TEXT PRIMARY KEY,
+        size INTEGER NOT NULL,
+        mtime INTEGER NOT NULL,
+        sample BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS records (
+        file TEXT NOT NULL,
+        rank INTEGER NOT NULL,
+        id TEXT NOT NULL,
+        name TEXT,
+        header TEXT NOT NULL,
+        seq_start INTEGER NOT NULL,
+        seq_end INTEGER NOT NULL,
+        line_width INTEGER NOT NULL,
+        PRIMARY KEY (file, rank)
+    );
+    CREATE TABLE IF NOT EXISTS fragments (
+        id TEXT PRIMARY KEY,
+        fasta_ino INTEGER NOT NULL,
+        seq_ino INTEGER NOT NULL,
+        generation INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS digests (
+        id TEXT PRIMARY KEY,
+        digest TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS tags (
+        id TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (id, key)
+    );
+    CREATE TABLE IF NOT EXISTS saved (
+        state TEXT NOT NULL
+    );
+";
+
+/// The state of a fragment, as saved at the end of a mount
+pub struct State<'a> {
+    pub id: &'a str,
+    pub original_id: &'a str, // The ID it had when mounted, that its reference digest is found under
+    pub inos: [u64; 2],
+    pub generation: u64,
+    pub digest: Option<&'a str>, // Its digest, if computed during this mount
+    pub tags: &'a BTreeMap<String, String>,
+}
+
+pub struct Catalog {
+    db: Connection,
+}
+impl Catalog {
+    /// Where the catalog of the FASTA file whose session is stored in
+    /// `session` is kept
+    pub fn path_for(session: &Path) -> PathBuf {
+        session.with_extension(CATALOG_EXT)
+    }
+
+    /// Open the catalog stored in `path`, creating it if need be. The
+    /// digests it holds are set aside as the reference ones for the whole
+    /// mount, as those of the session are.
+    pub fn open(path: &Path) -> Result<Catalog> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).context(format!("while creating {:?}", dir))?;
+        }
+        let db = Connection::open(path).context(format!("while opening {:?}", path))?;
+        db.execute_batch(SCHEMA)
+            .and_then(|_| {
+                db.execute_batch(
+                    "CREATE TEMP TABLE reference AS SELECT id, digest FROM digests;
+                     CREATE UNIQUE INDEX temp.reference_id ON reference (id);",
+                )
+            })
+            .context(format!("while reading {:?}", path))?;
+        Ok(Catalog { db })
+    }
+
+    /// The records of `file`, provided that they have been stored for its
+    /// current state, `source`
+    pub fn records(&self, file: &str, source: &Source) -> Result<Option<Vec<Record>>> {
+        let stored = self
+            .db
+            .query_row(
+                "SELECT size, mtime, sample FROM sources WHERE file = ?1",
+                [file],
+                |row| {
+                    Ok(Source {
+                        size: row.get::<_, i64>(0)? as u64,
+                        mtime: row.get::<_, i64>(1)? as u64,
+                        sample: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?;
+        if stored.as_ref() != Some(source) {
+            return Ok(None);
+        }
+        let mut statement = self.db.prepare(
+            "SELECT id, name, header, seq_start, seq_end, line_width
+             FROM records WHERE file = ?1 ORDER BY rank",
+        )?;
+        let records = statement
+            .query_map([file], |row| {
+                let (start, end) = (
+                    row.get::<_, i64>(3)? as usize,
+                    row.get::<_, i64>(4)? as usize,
+                );
+                Ok(Record {
+                    id: row.get::<_, String>(0)?.into(),
+                    name: row.get(1)?,
+                    header: row.get(2)?,
+                    pos: (start, end),
+                    len: end - start,
+                    line_width: row.get::<_, i64>(5)? as usize,
+                    seq: None,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(Some(records))
+    }
+
+    /// Replace the records of `file` by `records`, as found in its current
+    /// state, `source`
+    pub fn set_records(&self, file: &str, source: &Source, records: &[Record]) -> Result<()> {
+        let tx = self.db.unchecked_transaction()?;
+        tx.execute("DELETE FROM records WHERE file = ?1", [file])?;
+        tx.execute(
+            "INSERT OR REPLACE INTO sources (file, size, mtime, sample) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                file,
+                source.size as i64,
+                source.mtime as i64,
+                &source.sample[..]
+            ],
+        )?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO records (file, rank, id, name, header, seq_start, seq_end, line_width)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for (rank, r) in records.iter().enumerate() {
+                insert.execute(params![
+                    file,
+                    rank as i64,
+                    r.id.as_str(),
+                    r.name,
+                    r.header,
+                    r.pos.0 as i64,
+                    r.pos.1 as i64,
+                    r.line_width as i64
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Fill in the inodes, generations and tags of the fragments in
+    /// `session`, which the mount needs from the start; the previous
+    /// session is imported first into a new catalog.
+    pub fn load(&self, session: &mut Session) -> Result<()> {
+        let new = self
+            .db
+            .query_row("SELECT NOT EXISTS (SELECT 1 FROM fragments)", [], |row| {
+                row.get::<_, bool>(0)
+            })?;
+        if new && !session.inodes.is_empty() {
+            self.import(session)?;
+        }
+        let mut inodes = BTreeMap::new();
+        let mut generations = BTreeMap::new();
+        let mut tags = BTreeMap::<String, BTreeMap<String, String>>::new();
+        let mut statement = self
+            .db
+            .prepare("SELECT id, fasta_ino, seq_ino, generation FROM fragments")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let id: String = row.get(0)?;
+            let inos = [row.get::<_, i64>(1)? as u64, row.get::<_, i64>(2)? as u64];
+            generations.insert(id.clone(), row.get::<_, i64>(3)? as u64);
+            inodes.insert(id, inos);
+        }
+        let mut statement = self.db.prepare("SELECT id, key, value FROM tags")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            tags.entry(row.get(0)?)
+                .or_default()
+                .insert(row.get(1)?, row.get(2)?);
+        }
+        session.inodes = inodes;
+        session.generations = generations;
+        session.tags = tags;
+        session.digests.clear();
+        Ok(())
+    }
+
+    fn import(&self, session: &Session) -> Result<()> {
+        let tx = self.db.unchecked_transaction()?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO fragments (id, fasta_ino, seq_ino, generation) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for (id, [fasta_ino, seq_ino]) in session.inodes.iter() {
+                let generation = session.generations.get(id).copied().unwrap_or(1);
+                insert.execute(params![
+                    id,
+                    *fasta_ino as i64,
+                    *seq_ino as i64,
+                    generation as i64
+                ])?;
+            }
+            let mut insert = tx.prepare("INSERT INTO digests (id, digest) VALUES (?1, ?2)")?;
+            for (id, digest) in session.digests.iter() {
+                insert.execute([id, digest])?;
+            }
+            let mut insert = tx.prepare("INSERT INTO tags (id, key, value) VALUES (?1, ?2, ?3)")?;
+            for (id, tags) in session.tags.iter() {
+                for (key, value) in tags.iter() {
+                    insert.execute([id, key, value])?;
+                }
+            }
+            tx.execute(
+                "INSERT OR REPLACE INTO temp.reference SELECT id, digest FROM digests",
+                [],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// All the digests as of the start of the mount, by fragment ID
+    pub fn reference_digests(&self) -> Result<BTreeMap<String, String>> {
+        let mut statement = self.db.prepare("SELECT id, digest FROM temp.reference")?;
+        let digests = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(digests)
+    }
+
+    /// Replace the tags of fragment `id`
+    pub fn set_tags(&self, id: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        let tx = self.db.unchecked_transaction()?;
+        tx.execute("DELETE FROM saved", [])?;
+        tx.execute("DELETE FROM tags WHERE id = ?1", [id])?;
+        {
+            let mut insert = tx.prepare("INSERT INTO tags (id, key, value) VALUES (?1, ?2, ?3)")?;
+            for (key, value) in tags.iter() {
+                insert.execute([id, key, value])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Replace the state of the fragments by the one listed by `fragments`;
+    /// their digests are left untouched if `keep_digests`, e.g. when writing
+    /// elsewhere than to the mounted file
+    pub fn save<'a, I: Iterator<Item = State<'a>>>(
+        &self,
+        fragments: impl Fn() -> I,
+        keep_digests: bool,
+    ) -> Result<()> {
+        // Saving millions of fragments takes seconds, while most of the
+        // saves, e.g. when remounting, do not change anything
+        let mut state = md5::Context::new();
+        state.consume([u8::from(keep_digests)]);
+        for f in fragments() {
+            state.consume(format!(
+                "{:?}\n",
+                (f.id, f.original_id, f.inos, f.generation, f.digest, f.tags)
+            ));
+        }
+        let state = format!("{:x}", state.compute());
+        let saved = self
+            .db
+            .query_row("SELECT state FROM saved", [], |row| row.get::<_, String>(0))
+            .optional()?;
+        if saved.as_deref() == Some(&state) {
+            return Ok(());
+        }
+
+        let tx = self.db.unchecked_transaction()?;
+        tx.execute_batch("DELETE FROM fragments; DELETE FROM tags; DELETE FROM saved;")?;
+        tx.execute("INSERT INTO saved (state) VALUES (?1)", [&state])?;
+        if !keep_digests {
+            tx.execute("DELETE FROM digests", [])?;
+        }
+        {
+            let mut insert_fragment = tx.prepare(
+                "INSERT INTO fragments (id, fasta_ino, seq_ino, generation) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            // The fragments whose digest has not been computed keep the one
+            // they had when mounted
+            let mut insert_digest = tx.prepare(
+                "INSERT INTO digests (id, digest)
+                 SELECT ?1, digest FROM (
+                     SELECT COALESCE(?2, (SELECT digest FROM temp.reference WHERE id = ?3)) AS digest
+                 ) WHERE digest IS NOT NULL",
+            )?;
+            let mut insert_tag =
+                tx.prepare("INSERT INTO tags (id, key, value) VALUES (?1, ?2, ?3)")?;
+            for f in fragments() {
+                insert_fragment.execute(params![
+                    f.id,
+                    f.inos[0] as i64,
+                    f.inos[1] as i64,
+                    f.generation as i64
+                ])?;
+                if !keep_digests {
+                    insert_digest.execute(params![f.id, f.digest, f.original_id])?;
+                }
+                for (key, value) in f.tags.iter() {
+                    insert_tag.execute([f.id, key, value])?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fusta::fasta::FastaReader;
+    use std::fs;
+
+    #[test]
+    fn catalog() {
+        let dir = tempfile::tempdir().unwrap();
+        let fasta = dir.path().join("ref.fa");
+        fs::write(&fasta, ">a desc  here\nACGT\nAC\n>b\r\nGG\r\n>c\n").unwrap();
+        let file = fs::File::open(&fasta).unwrap();
+        let source = Source::of(&file).unwrap();
+        let path = Catalog::path_for(&dir.path().join("ref.fa-0123.fusta-session"));
+        assert_eq!(path, dir.path().join("ref.fa-0123.fusta-catalog"));
+
+        // The records are kept as long as the file does not change
+        let catalog = Catalog::open(&path).unwrap();
+        assert!(catalog.records("ref.fa", &source).unwrap().is_none());
+        let scanned = FastaReader::new(&file, false).collect::<Vec<_>>();
+        catalog.set_records("ref.fa", &source, &scanned).unwrap();
+        let records = catalog.records("ref.fa", &source).unwrap().unwrap();
+        assert_eq!(records.len(), 3);
+        for (r, s) in records.iter().zip(scanned.iter()) {
+            assert_eq!(
+                (&r.id, &r.name, &r.header, r.pos, r.len, r.line_width),
+                (&s.id, &s.name, &s.header, s.pos, s.len, s.line_width)
+            );
+        }
+        let changed = Source { size: 1, ..source };
+        assert!(catalog.records("ref.fa", &changed).unwrap().is_none());
+        assert!(catalog.records("other.fa", &source).unwrap().is_none());
+
+        // The previous session is imported...
+        let mut session = Session {
+            inodes: [("a".to_owned(), [10, 11]), ("b".to_owned(), [12, 13])].into(),
+            generations: [("a".to_owned(), 2)].into(),
+            digests: [("a".to_owned(), "0a".to_owned())].into(),
+            tags: [("b".to_owned(), [("k".to_owned(), "v".to_owned())].into())].into(),
+            ..Session::default()
+        };
+        catalog.load(&mut session).unwrap();
+        assert_eq!(session.inodes["b"], [12, 13]);
+        assert_eq!(session.generations["a"], 2);
+        assert_eq!(session.generations["b"], 1);
+        assert_eq!(session.tags["b"]["k"], "v");
+        assert!(session.digests.is_empty());
+        assert_eq!(catalog.reference_digests().unwrap()["a"], "0a");
+
+        // ...then replaced by the state of the fragments
+        let tags = [("keep".to_owned(), "yes".to_owned())].into();
+        let none = BTreeMap::new();
+        let states = || {
+            vec![
+                State {
+                    id: "z",
+                    original_id: "a",
+                    inos: [10, 11],
+                    generation: 2,
+                    digest: None,
+                    tags: &tags,
+                },
+                State {
+                    id: "b",
+                    original_id: "b",
+                    inos: [12, 13],
+                    generation: 1,
+                    digest: Some("0b"),
+                    tags: &none,
+                },
+            ]
+            .into_iter()
+        };
+        catalog.save(states, false).unwrap();
+        drop(catalog);
+        let catalog = Catalog::open(&path).unwrap();
+        let mut session = Session::default();
+        catalog.load(&mut session).unwrap();
+        assert_eq!(session.inodes.keys().collect::<Vec<_>>(), ["b", "z"]);
+        assert_eq!(session.tags.len(), 1);
+        assert_eq!(session.tags["z"]["keep"], "yes");
+        assert_eq!(
+            catalog.reference_digests().unwrap(),
+            [
+                ("b".to_owned(), "0b".to_owned()),
+                ("z".to_owned(), "0a".to_owned())
+            ]
+            .into()
+        );
+        catalog.set_tags("z", &none).unwrap();
+        catalog.load(&mut session).unwrap();
+        assert!(session.tags.is_empty());
+
+        // Saving the same state again is skipped
+        catalog.save(states, false).unwrap();
+        catalog.db.execute("DELETE FROM fragments", []).unwrap();
+        catalog.save(states, false).unwrap();
+        let mut session = Session::default();
+        catalog.load(&mut session).unwrap();
+        assert!(session.inodes.is_empty());
+        catalog.save(states, true).unwrap();
+        catalog.load(&mut session).unwrap();
+        assert_eq!(session.inodes.len(), 2);
+        assert_eq!(
+            catalog.records("ref.fa", &source).unwrap().unwrap().len(),
+            3
+        );
+    }
+}
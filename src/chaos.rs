@@ -0,0 +1,154 @@
+use crate::error::FustaError;
+use anyhow::{anyhow, bail, Context, Result};
+use std::cell::Cell;
+use std::time::Duration;
+
+/// Faults injected in the accesses to the backing file, to check how the
+/// tools working on a mount behave when their storage degrades.
+#[derive(Clone, Debug, Default)]
+pub struct Chaos {
+    pub read_delay: Duration,
+    pub write_delay: Duration,
+    pub read_error: f64,  // The probability for a read to fail
+    pub write_error: f64, // The probability for a write to fail
+    state: Cell<u64>,
+}
+impl Chaos {
+    /// Parse a comma-separated list of `key=value` pairs, e.g.
+    /// `read-delay=50ms,write-error=0.01`; the same `seed` makes the same
+    /// accesses fail
+    pub fn parse(spec: &str, seed: u64) -> Result<Chaos> {
+        let mut r = Chaos {
+            state: Cell::new(seed),
+            ..Default::default()
+        };
+        for pair in spec.split(',').filter(|s| !s.trim().is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("`{}` should be formatted as KEY=VALUE", pair))?;
+            let value = value.trim();
+            match key.trim() {
+                "read-delay" => r.read_delay = parse_duration(value)?,
+                "write-delay" => r.write_delay = parse_duration(value)?,
+                "read-error" => r.read_error = parse_probability(value)?,
+                "write-error" => r.write_error = parse_probability(value)?,
+                _ => bail!(
+                    "unknown key `{}`; expected one of read-delay, write-delay, read-error, write-error",
+                    key
+                ),
+            }
+        }
+        Ok(r)
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.read_delay.is_zero()
+            || !self.write_delay.is_zero()
+            || self.read_error > 0.
+            || self.write_error > 0.
+    }
+
+    /// Called before reading `what` from the backing storage
+    pub fn read(&self, what: impl FnOnce() -> String) -> Result<(), FustaError> {
+        self.strike(self.read_delay, self.read_error, || {
+            format!("Unable to read {}", what())
+        })
+    }
+
+    /// Called before writing `what` to the backing storage
+    pub fn write(&self, what: impl FnOnce() -> String) -> Result<(), FustaError> {
+        self.strike(self.write_delay, self.write_error, || {
+            format!("Unable to write {}", what())
+        })
+    }
+
+    fn strike(
+        &self,
+        delay: Duration,
+        probability: f64,
+        msg: impl FnOnce() -> String,
+    ) -> Result<(), FustaError> {
+        if !delay.is_zero() {
+            std::thread::sleep(delay);
+        }
+        if probability > 0. && self.draw() < probability {
+            Err(FustaError::BackingIo(
+                msg(),
+                std::io::Error::other("simulated fault"),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// A uniform draw in [0, 1)
+    fn draw(&self) -> f64 {
+        let state = self
+            .state
+            .get()
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        self.state.set(state);
+        (state >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl std::fmt::Display for Chaos {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "reads delayed by {:?} and failing with p={}, writes delayed by {:?} and failing with p={}",
+            self.read_delay, self.read_error, self.write_delay, self.write_error
+        )
+    }
+}
+
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value = value
+        .parse::<u64>()
+        .with_context(|| format!("`{}` is not a duration", s))?;
+    Ok(match unit {
+        "us" => Duration::from_micros(value),
+        "ms" | "" => Duration::from_millis(value),
+        "s" => Duration::from_secs(value),
+        _ => bail!("unknown unit `{}` in `{}`; expected us, ms or s", unit, s),
+    })
+}
+
+/// A seed for the randomized features when none is given
+pub fn random_seed() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+fn parse_probability(s: &str) -> Result<f64> {
+    let p = s
+        .parse::<f64>()
+        .with_context(|| format!("`{}` is not a probability", s))?;
+    if !(0. ..=1.).contains(&p) {
+        bail!("`{}` is not between 0 and 1", s);
+    }
+    Ok(p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded() {
+        let outcomes = |seed| {
+            let chaos = Chaos::parse("read-error=0.5", seed).unwrap();
+            (0..64)
+                .map(|_| chaos.read(|| "x".into()).is_ok())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(outcomes(42), outcomes(42));
+        assert_ne!(outcomes(42), outcomes(43));
+        assert!(outcomes(42).contains(&true) && outcomes(42).contains(&false));
+    }
+}
@@ -0,0 +1,217 @@
+use crate::output::Output;
+use anyhow::{Context, Result};
+use fusta::fasta::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::UNIX_EPOCH;
+
+const CACHE_EXT: &str = "fusta-digests";
+
+/// Where a sequence lies in a file, `(offset, length)`, and when the file
+/// was last modified, in ns since the epoch
+type Key = (usize, usize, u128);
+
+/// The digests of the sequences of a FASTA file, as computed by `fusta
+/// checksum`, stored as `OFFSET LENGTH MTIME DIGEST` lines appended as they
+/// are computed, so that an interrupted run can be resumed
+#[derive(Debug, Default)]
+pub struct DigestCache {
+    digests: HashMap<Key, String>,
+}
+impl DigestCache {
+    pub fn path_for(fasta_file: &str) -> PathBuf {
+        PathBuf::from(format!("{}.{}", fasta_file, CACHE_EXT))
+    }
+
+    /// The modification time of `metadata`, as used in the keys
+    pub fn mtime(metadata: &fs::Metadata) -> u128 {
+        metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos())
+            .unwrap_or_default()
+    }
+
+    /// Load the digests stored in `path`, skipping the malformed lines, e.g.
+    /// one cut short by an interruption; a missing cache is an empty one
+    pub fn load(path: &Path) -> DigestCache {
+        let content = fs::read_to_string(path).unwrap_or_default();
+        let digests = content
+            .lines()
+            .filter_map(|l| match l.split('\t').collect::<Vec<_>>()[..] {
+                [offset, length, mtime, digest] if digest.len() == 32 => Some((
+                    (
+                        offset.parse().ok()?,
+                        length.parse().ok()?,
+                        mtime.parse().ok()?,
+                    ),
+                    digest.to_owned(),
+                )),
+                _ => None,
+            })
+            .collect();
+        DigestCache { digests }
+    }
+
+    /// The digest of the sequence found in `range` of a file modified at
+    /// `mtime`, if known
+    pub fn get(&self, range: (usize, usize), mtime: u128) -> Option<&str> {
+        self.digests
+            .get(&(range.0, range.1 - range.0, mtime))
+            .map(String::as_str)
+    }
+
+    fn line(key: &Key, digest: &str) -> String {
+        format!("{}\t{}\t{}\t{}\n", key.0, key.1, key.2, digest)
+    }
+}
+
+#[derive(Serialize)]
+struct Checksum {
+    id: String,
+    md5: String,
+    cached: bool,
+}
+
+/// Compute the digests of all the sequences of `filename` on `threads`
+/// threads, reusing and completing the ones stored in `cache`
+pub fn run(filename: &str, threads: usize, cache: &Path, output: Output) -> Result<()> {
+    let file =
+        fs::File::open(filename).with_context(|| format!("Failed to open `{}`", filename))?;
+    let mtime = DigestCache::mtime(&file.metadata()?);
+    let fragments = FastaReader::new(&file, false).collect::<Vec<_>>();
+    let mut known = DigestCache::load(cache);
+
+    // The digests of the previous versions of the file are dropped, as well
+    // as the line an interruption may have cut short
+    let cut_short = fs::read(cache).is_ok_and(|c| !c.is_empty() && !c.ends_with(b"\n"));
+    let stale = known.digests.keys().any(|k| k.2 != mtime);
+    known.digests.retain(|k, _| k.2 == mtime);
+    let mut cache_file = if stale || cut_short {
+        let mut content = String::new();
+        for (key, digest) in known.digests.iter() {
+            content.push_str(&DigestCache::line(key, digest));
+        }
+        fs::write(cache, content)
+            .with_context(|| format!("Unable to write `{}`", cache.display()))?;
+        fs::OpenOptions::new().append(true).open(cache)
+    } else {
+        fs::OpenOptions::new().create(true).append(true).open(cache)
+    }
+    .with_context(|| format!("Unable to open `{}`", cache.display()))?;
+
+    let mut digests = fragments
+        .iter()
+        .map(|f| known.get(f.pos, mtime).map(str::to_owned))
+        .collect::<Vec<_>>();
+    let todo = (0..fragments.len())
+        .filter(|&i| digests[i].is_none())
+        .collect::<Vec<_>>();
+    let cached = digests.iter().map(Option::is_some).collect::<Vec<_>>();
+    if !todo.is_empty() {
+        let mmap = unsafe { memmap2::Mmap::map(&file) }
+            .with_context(|| format!("Failed to map `{}`", filename))?;
+        let next = AtomicUsize::new(0);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::scope(|s| -> Result<()> {
+            for _ in 0..threads.max(1).min(todo.len()) {
+                let sender = sender.clone();
+                let (next, todo, fragments, mmap) = (&next, &todo, &fragments, &mmap);
+                s.spawn(move || {
+                    while let Some(&i) = todo.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let pos = fragments[i].pos;
+                        if sender
+                            .send((i, sequence_digest(&mmap[pos.0..pos.1])))
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(sender);
+            // Recorded as they come, so that an interrupted run is resumed
+            for (i, digest) in receiver {
+                let pos = fragments[i].pos;
+                let key = (pos.0, pos.1 - pos.0, mtime);
+                cache_file
+                    .write_all(DigestCache::line(&key, &digest).as_bytes())
+                    .with_context(|| format!("Unable to write `{}`", cache.display()))?;
+                digests[i] = Some(digest);
+            }
+            Ok(())
+        })?;
+    }
+
+    let checksums = fragments
+        .iter()
+        .zip(digests)
+        .zip(cached)
+        .map(|((f, digest), cached)| Checksum {
+            id: f.id.to_string(),
+            md5: digest.unwrap(),
+            cached,
+        })
+        .collect::<Vec<_>>();
+    log::info!(
+        "{} digests computed, {} found in {}",
+        todo.len(),
+        checksums.len() - todo.len(),
+        cache.display()
+    );
+    output.emit(&checksums, |checksums| {
+        for c in checksums.iter() {
+            println!("{}  {}", c.md5, c.id);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        let dir = tempfile::tempdir().unwrap();
+        let fasta = dir.path().join("ref.fa");
+        fs::write(&fasta, ">a\nACGT\nac\n>b\n>c\nGG\n").unwrap();
+        let filename = fasta.to_str().unwrap();
+        let cache = DigestCache::path_for(filename);
+        run(filename, 2, &cache, Output::Quiet).unwrap();
+
+        let mtime = DigestCache::mtime(&fs::metadata(&fasta).unwrap());
+        let known = DigestCache::load(&cache);
+        assert_eq!(known.digests.len(), 3);
+        assert_eq!(
+            known.get((3, 11), mtime),
+            Some(&*sequence_digest(b"ACGTAC"))
+        );
+        assert_eq!(known.get((3, 11), mtime + 1), None);
+
+        // Interrupted runs are resumed, skipping the partial lines
+        let content = fs::read_to_string(&cache).unwrap();
+        let first = content.lines().next().unwrap();
+        fs::write(&cache, format!("{}\n{}", first, &first[..10])).unwrap();
+        run(filename, 1, &cache, Output::Quiet).unwrap();
+        assert_eq!(DigestCache::load(&cache).digests.len(), 3);
+
+        // ...while the digests of a previous version of the file are dropped
+        fs::write(&fasta, ">a\nACGT\n").unwrap();
+        let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1);
+        fs::File::options()
+            .write(true)
+            .open(&fasta)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        run(filename, 4, &cache, Output::Quiet).unwrap();
+        let content = fs::read_to_string(&cache).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        assert!(content.starts_with("3\t5\t1000000000\t"));
+    }
+}
@@ -0,0 +1,335 @@
+//! The mount of a directory of FASTA files, every one of them in its own
+//! subtree of the mount point; with --watch, the subtrees follow the files
+//! written to or deleted from the directory while mounted.
+use crate::fs::{FustaFS, FustaSettings};
+use anyhow::{bail, Context, Result};
+use fusta::sanity::Alphabet;
+use log::*;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const EXTENSIONS: &[&str] = &[".fa", ".fasta", ".fa.gz", ".fasta.gz"];
+
+/// The name of the subtree of the file `name`, i.e. its name stripped of its
+/// extension, if it is a FASTA file
+pub fn subtree_name(name: &str) -> Option<&str> {
+    EXTENSIONS
+        .iter()
+        .find_map(|e| name.strip_suffix(e))
+        .filter(|stem| !stem.is_empty() && !stem.starts_with('.'))
+}
+
+/// The FASTA files in `dir` to mount as a collection, sorted by the names of
+/// their subtrees
+pub fn files(dir: &str) -> Result<Vec<(String, String)>> {
+    let mut files = BTreeMap::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Unable to read `{}`", dir))? {
+        let path = entry?.path();
+        let stem = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(subtree_name);
+        if let Some(stem) = stem {
+            if !path.is_file() {
+                continue;
+            }
+            let file = path.to_string_lossy().to_string();
+            if let Some(other) = files.insert(stem.to_owned(), file) {
+                bail!(
+                    "`{}` and `{}` would both be mounted as `{}`; please rename one of them",
+                    other,
+                    path.display(),
+                    stem
+                );
+            }
+        }
+    }
+    if files.is_empty() {
+        bail!(
+            "`{}` holds no *.fa, *.fasta, *.fa.gz or *.fasta.gz file to mount",
+            dir
+        );
+    }
+    Ok(files.into_iter().collect())
+}
+
+/// The subtrees currently mounted in `root`, to unmount them all
+pub fn mountpoints(root: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(root)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.is_dir())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// A FASTA file mounted in its own subtree, served by its own thread
+struct Subtree {
+    file: String,
+    mountpoint: PathBuf,
+    thread: JoinHandle<std::io::Result<()>>,
+}
+
+/// The FASTA files of a directory, mounted side by side
+pub struct Collection {
+    dir: PathBuf,  // Where the FASTA files are
+    root: PathBuf, // Where the subtrees are mounted
+    settings: FustaSettings,
+    options: Vec<fuser::MountOption>,
+    expected: Option<Alphabet>, // What the files should contain, as set by --type
+    subtrees: BTreeMap<String, Subtree>,
+}
+impl Collection {
+    pub fn new(
+        dir: &Path,
+        root: &Path,
+        settings: FustaSettings,
+        options: Vec<fuser::MountOption>,
+        expected: Option<Alphabet>,
+    ) -> Collection {
+        Collection {
+            dir: dir.to_owned(),
+            root: root.to_owned(),
+            settings,
+            options,
+            expected,
+            subtrees: BTreeMap::new(),
+        }
+    }
+
+    /// Read `file`, that can then be mounted with the returned options
+    pub fn prepare(&self, file: &str) -> Result<(FustaFS, Vec<fuser::MountOption>)> {
+        crate::check_sanity(file, self.expected)?;
+        let mut options = self.options.clone();
+        // The compressed files are read-only on their own
+        if crate::bgzf::is_bgzf_file(Path::new(file)) {
+            info!("`{}` is bgzip-compressed; mounting it read-only", file);
+            options.push(fuser::MountOption::RO);
+        }
+        let fs = FustaFS::new(self.settings.clone(), file)
+            .with_context(|| format!("while mounting {}", file))?;
+        Ok((fs, options))
+    }
+
+    /// Mount `fs`, read from `file`, as the subtree `name`
+    pub fn mount(
+        &mut self,
+        name: &str,
+        file: &str,
+        fs: FustaFS,
+        options: &[fuser::MountOption],
+    ) -> Result<()> {
+        let mountpoint = self.root.join(name);
+        if !mountpoint.exists() {
+            std::fs::create_dir(&mountpoint)?;
+        }
+        let mut session = fuser::Session::new(fs, &mountpoint, options)
+            .with_context(|| format!("Unable to mount {} on {:?}", file, mountpoint))?;
+        self.subtrees.insert(
+            name.to_owned(),
+            Subtree {
+                file: file.to_owned(),
+                mountpoint,
+                thread: std::thread::spawn(move || session.run()),
+            },
+        );
+        Ok(())
+    }
+
+    /// Forget about the subtrees that have been unmounted, every file being
+    /// written back as its own subtree is unmounted; with `wait`, until they
+    /// all are
+    fn reap(&mut self, wait: bool) {
+        let done = self
+            .subtrees
+            .iter()
+            .filter(|(_, s)| wait || s.thread.is_finished())
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        for name in done {
+            let subtree = self.subtrees.remove(&name).unwrap();
+            if let Ok(Err(e)) = subtree.thread.join() {
+                error!("The FUSE filesystem of {} failed: {}", subtree.file, e);
+            }
+            if let Err(e) = std::fs::remove_dir(&subtree.mountpoint) {
+                warn!("Unable to remove {:?}: {}", subtree.mountpoint, e);
+            }
+        }
+    }
+
+    /// Follow the change of the file `name` of the directory, that has been
+    /// written or moved in if `present`, deleted or moved out otherwise
+    fn follow(&mut self, name: &str, present: bool) {
+        let subtree = match subtree_name(name) {
+            Some(subtree) => subtree,
+            None => return,
+        };
+        let file = self.dir.join(name).to_string_lossy().to_string();
+        match self.subtrees.get(subtree) {
+            // Also seen when the file is written back
+            Some(mounted) if present && mounted.file == file => {}
+            Some(mounted) if present => warn!(
+                "`{}` would be mounted as `{}`, as `{}` already is; please rename it",
+                file, subtree, mounted.file
+            ),
+            None if present && Path::new(&file).is_file() => {
+                info!("Mounting `{}`, added to {:?}", file, self.dir);
+                let mounted = self
+                    .prepare(&file)
+                    .and_then(|(fs, options)| self.mount(subtree, &file, fs, &options));
+                if let Err(e) = mounted {
+                    warn!("{:#}; skipping it", e);
+                }
+            }
+            Some(mounted) if mounted.file == file && !Path::new(&file).exists() => {
+                info!(
+                    "Unmounting {:?}, as `{}` has been removed",
+                    mounted.mountpoint, file
+                );
+                if let Err(e) = crate::umount::detach(&mounted.mountpoint) {
+                    error!("Unable to unmount {:?}: {:?}", mounted.mountpoint, e);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Serve the subtrees until they are all unmounted, following the changes
+    /// of the directory reported by `watcher`, if any
+    pub fn run(mut self, watcher: Option<Watcher>) {
+        match watcher {
+            None => self.reap(true),
+            Some(watcher) => {
+                while !self.subtrees.is_empty() {
+                    for (name, present) in watcher.changes(Duration::from_millis(500)) {
+                        self.follow(&name, present);
+                    }
+                    self.reap(false);
+                }
+            }
+        }
+    }
+}
+
+/// Reports the files written to, moved to, deleted from or moved out of a
+/// directory, through inotify
+#[cfg(target_os = "linux")]
+pub struct Watcher {
+    fd: std::os::fd::OwnedFd,
+}
+#[cfg(target_os = "linux")]
+impl Watcher {
+    pub fn new(dir: &Path) -> Result<Watcher> {
+        use std::os::fd::FromRawFd;
+        use std::os::unix::ffi::OsStrExt;
+
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).context("Unable to set up inotify");
+        }
+        let fd = unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) };
+        let path = std::ffi::CString::new(dir.as_os_str().as_bytes())?;
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_DELETE | libc::IN_MOVED_FROM;
+        if unsafe {
+            libc::inotify_add_watch(std::os::fd::AsRawFd::as_raw_fd(&fd), path.as_ptr(), mask)
+        } < 0
+        {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Unable to watch {:?}", dir));
+        }
+        Ok(Watcher { fd })
+    }
+
+    /// The names of the files changed within `timeout`, and whether they
+    /// have been written or moved in (true), or deleted or moved out (false)
+    pub fn changes(&self, timeout: Duration) -> Vec<(String, bool)> {
+        use std::os::fd::AsRawFd;
+
+        let mut poll = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut poll, 1, timeout.as_millis() as i32) } <= 0 {
+            return Vec::new();
+        }
+        let mut r = Vec::new();
+        let mut buffer = [0u8; 4096];
+        loop {
+            let read = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                )
+            };
+            if read <= 0 {
+                break;
+            }
+            // Every event is a struct inotify_event: wd, mask, cookie and
+            // len, then the NUL-padded name on len bytes
+            let mut events = &buffer[..read as usize];
+            while events.len() >= 16 {
+                let field = |i: usize| u32::from_ne_bytes(events[i..i + 4].try_into().unwrap());
+                let (mask, len) = (field(4), field(12) as usize);
+                let name = &events[16..(16 + len).min(events.len())];
+                let name = name.split(|&c| c == 0).next().unwrap_or_default();
+                if let Ok(name) = std::str::from_utf8(name) {
+                    if !name.is_empty() {
+                        let present = mask & (libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO) != 0;
+                        r.push((name.to_owned(), present));
+                    }
+                }
+                events = &events[(16 + len).min(events.len())..];
+            }
+        }
+        r
+    }
+}
+#[cfg(not(target_os = "linux"))]
+pub struct Watcher;
+#[cfg(not(target_os = "linux"))]
+impl Watcher {
+    pub fn new(_dir: &Path) -> Result<Watcher> {
+        bail!("--watch is only supported on Linux")
+    }
+
+    pub fn changes(&self, timeout: Duration) -> Vec<(String, bool)> {
+        std::thread::sleep(timeout);
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subtrees() {
+        assert_eq!(subtree_name("hg38.fa"), Some("hg38"));
+        assert_eq!(subtree_name("hg38.fasta.gz"), Some("hg38"));
+        assert_eq!(subtree_name("hg38.fa.fusta-session"), None);
+        assert_eq!(subtree_name(".fa"), None);
+        assert_eq!(subtree_name(".hidden.fa"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        std::fs::write(path("b.fasta"), ">b\nA\n").unwrap();
+        std::fs::write(path("a.fa"), ">a\nA\n").unwrap();
+        std::fs::write(path("notes.txt"), "").unwrap();
+        std::fs::create_dir(path("c.fa")).unwrap();
+        let dir_name = dir.path().to_str().unwrap();
+        assert_eq!(
+            files(dir_name).unwrap(),
+            [("a".into(), path("a.fa")), ("b".into(), path("b.fasta"))]
+        );
+        std::fs::write(path("a.fasta.gz"), "").unwrap();
+        assert!(files(dir_name).is_err());
+    }
+}
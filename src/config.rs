@@ -0,0 +1,181 @@
+use crate::notifications::Thresholds;
+use crate::throttle::{Client, Limits};
+use anyhow::{anyhow, bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use fusta::transform::Pipeline;
+
+/// The settings read from the configuration file, one `key = value` pair per
+/// line, `#` starting a comment, e.g.
+///
+/// ```text
+/// notify-rewrite-size = 500M
+/// notify-append-count = 100
+/// notify-summary-interval = 30m
+/// view.rc = revcomp
+/// read-rate = 50M
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub notifications: Thresholds,
+    pub views: Vec<(String, Pipeline)>, // The directories exposing the transformed fragments
+    pub reads: Limits,                  // How fast every client may read
+}
+impl Config {
+    /// Where the configuration file is looked for when none is given
+    pub fn default_path() -> Option<PathBuf> {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .map(|dir| dir.join("fusta").join("config"))
+    }
+
+    /// Read the given configuration file, or the default one if it exists
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => match Config::default_path().filter(|p| p.exists()) {
+                Some(path) => path,
+                None => return Ok(Config::default()),
+            },
+        };
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Unable to read {}", path.display()))?;
+        Config::parse(&content)
+            .with_context(|| format!("Invalid configuration in {}", path.display()))
+    }
+
+    pub fn parse(content: &str) -> Result<Config> {
+        let mut r = Config::default();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("line {}: expected KEY = VALUE", i + 1))?;
+            let value = value.trim();
+            let error = || format!("line {}: invalid value `{}`", i + 1, value);
+            match key.trim() {
+                "notify-rewrite-size" => {
+                    r.notifications.rewrite_size = parse_size(value).with_context(error)?
+                }
+                "notify-append-count" => {
+                    r.notifications.append_count = value.parse().with_context(error)?
+                }
+                "notify-summary-interval" => {
+                    r.notifications.summary_interval = parse_duration(value).with_context(error)?
+                }
+                "read-rate" => r.reads.rate = parse_size(value).with_context(error)?,
+                "read-burst" => r.reads.burst = parse_size(value).with_context(error)?,
+                "read-rate-per" => {
+                    r.reads.per = match value {
+                        "pid" => Client::Pid,
+                        "uid" => Client::Uid,
+                        _ => return Err(anyhow!("expected pid or uid")).with_context(error),
+                    }
+                }
+                key if key.starts_with("view.") => {
+                    let name = &key["view.".len()..];
+                    if name.is_empty() || name.starts_with('.') || name.contains('/') {
+                        bail!("line {}: `{}` is not a valid directory name", i + 1, name)
+                    }
+                    if r.views.iter().any(|(other, _)| other == name) {
+                        bail!("line {}: view `{}` is already defined", i + 1, name)
+                    }
+                    r.views
+                        .push((name.to_owned(), Pipeline::parse(value).with_context(error)?))
+                }
+                key => bail!(
+                    "line {}: unknown key `{}`; expected one of notify-rewrite-size, notify-append-count, notify-summary-interval, read-rate, read-burst, read-rate-per, view.NAME",
+                    i + 1,
+                    key
+                ),
+            }
+        }
+        Ok(r)
+    }
+}
+
+/// A number of bytes, optionally suffixed with K, M or G
+fn parse_size(s: &str) -> Result<u64> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value = value.parse::<u64>()?;
+    Ok(match unit.trim() {
+        "" => value,
+        "K" => value << 10,
+        "M" => value << 20,
+        "G" => value << 30,
+        unit => bail!("unknown unit `{}`; expected K, M or G", unit),
+    })
+}
+
+/// A number of seconds, optionally suffixed with s, m or h
+fn parse_duration(s: &str) -> Result<Duration> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value = value.parse::<u64>()?;
+    Ok(Duration::from_secs(match unit.trim() {
+        "" | "s" => value,
+        "m" => value * 60,
+        "h" => value * 3600,
+        unit => bail!("unknown unit `{}`; expected s, m or h", unit),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configuration() {
+        let config = Config::parse(
+            "# Only notify the really large operations\n\
+             notify-rewrite-size = 2G\n\
+             \n\
+             notify-summary-interval=1h # hourly\n\
+             view.rc = revcomp, strip-newlines\n\
+             read-rate = 20M\n\
+             read-rate-per = uid\n",
+        )
+        .unwrap();
+        assert_eq!(config.notifications.rewrite_size, 2 << 30);
+        assert_eq!(config.notifications.append_count, 1000);
+        assert_eq!(
+            config.notifications.summary_interval,
+            Duration::from_secs(3600)
+        );
+        assert_eq!(
+            config.reads,
+            Limits {
+                rate: 20 << 20,
+                burst: 16 << 20,
+                per: Client::Uid
+            }
+        );
+        assert_eq!(
+            config.views,
+            [(
+                "rc".to_owned(),
+                Pipeline::parse("revcomp,strip-newlines").unwrap()
+            )]
+        );
+
+        for invalid in [
+            "notify-rewrite-size = 2T",
+            "notify-append-count = many",
+            "notify-summary-interval",
+            "notify-everything = 1",
+            "read-rate = fast",
+            "read-rate-per = gid",
+            "view.rc = reverse",
+            "view. = revcomp",
+            "view.rc = revcomp\nview.rc = mask",
+        ] {
+            assert!(Config::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use crate::output::Output;
+use anyhow::{bail, Context, Result};
+use fusta::fasta::*;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The companion files that can be derived from a FASTA file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Companion {
+    Fai,        // The samtools index, as `FILE.fa.fai`
+    Dict,       // The sequence dictionary, as `FILE.dict`
+    ChromSizes, // The length of every sequence, as `FILE.chrom.sizes`
+    Checksums,  // The digest of every sequence, as `FILE.checksums.md5`, as read by --verify
+}
+impl Companion {
+    pub const ALL: [Companion; 4] = [
+        Companion::Fai,
+        Companion::Dict,
+        Companion::ChromSizes,
+        Companion::Checksums,
+    ];
+
+    /// Where the companion of `fasta` is written
+    pub fn path(&self, fasta: &Path) -> PathBuf {
+        let with_extension = |ext: &str| fasta.with_extension(ext);
+        match self {
+            Companion::Fai => {
+                let mut name = fasta.as_os_str().to_owned();
+                name.push(".fai");
+                PathBuf::from(name)
+            }
+            Companion::Dict => with_extension("dict"),
+            Companion::ChromSizes => with_extension("chrom.sizes"),
+            Companion::Checksums => with_extension("checksums.md5"),
+        }
+    }
+
+    fn needs_digests(&self) -> bool {
+        matches!(self, Companion::Dict | Companion::Checksums)
+    }
+}
+
+/// What the companion files say about a sequence
+struct Entry {
+    id: String,
+    length: usize,
+    offset: usize,     // Where its sequence starts in the file
+    line_bases: usize, // How many bases its lines hold
+    line_width: usize, // ...and how many bytes, newline included
+    digest: Option<String>,
+}
+
+/// Describe all the sequences of `filename`, their digests being only
+/// computed if `with_digests`
+fn scan(filename: &str, with_digests: bool) -> Result<Vec<Entry>> {
+    let file =
+        fs::File::open(filename).with_context(|| format!("Failed to open `{}`", filename))?;
+    let fragments = FastaReader::new(&file, false).collect::<Vec<_>>();
+    if fragments.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mmap = unsafe { memmap2::Mmap::map(&file) }
+        .with_context(|| format!("Failed to map `{}`", filename))?;
+    fragments
+        .into_iter()
+        .map(|f| {
+            let raw = &mmap[f.pos.0..f.pos.1];
+            let (length, line_bases, line_width) =
+                line_layout(raw).with_context(|| format!("`{}` can not be indexed", f.id))?;
+            Ok(Entry {
+                id: f.id.to_string(),
+                length,
+                offset: f.pos.0,
+                line_bases,
+                line_width,
+                digest: with_digests.then(|| sequence_digest(raw)),
+            })
+        })
+        .collect()
+}
+
+/// The number of bases of the sequence `raw`, as found in the file, and of
+/// bases and bytes in its lines, which should all be the same but for the
+/// last one
+fn line_layout(raw: &[u8]) -> Result<(usize, usize, usize)> {
+    let raw = raw.strip_suffix(b"\n").unwrap_or(raw);
+    if raw.is_empty() {
+        return Ok((0, 0, 0));
+    }
+    let lines = raw
+        .split(|&c| c == b'\n')
+        .map(|l| (l.strip_suffix(b"\r").unwrap_or(l).len(), l.ends_with(b"\r")))
+        .collect::<Vec<_>>();
+    let (bases, crlf) = lines[0];
+    let (last, others) = lines.split_last().unwrap();
+    if bases == 0 || others.iter().any(|&l| l != (bases, crlf)) || last.0 == 0 || last.0 > bases {
+        bail!("its lines are not all of the same length")
+    }
+    let length = others.len() * bases + last.0;
+    Ok((length, bases, bases + 1 + usize::from(crlf)))
+}
+
+fn render(companion: Companion, fasta: &Path, entries: &[Entry]) -> String {
+    let mut r = String::new();
+    match companion {
+        Companion::Fai => {
+            for e in entries {
+                r += &format!(
+                    "{}\t{}\t{}\t{}\t{}\n",
+                    e.id, e.length, e.offset, e.line_bases, e.line_width
+                );
+            }
+        }
+        Companion::Dict => {
+            let url = fs::canonicalize(fasta).unwrap_or_else(|_| fasta.to_owned());
+            r += "@HD\tVN:1.0\tSO:unsorted\n";
+            for e in entries {
+                r += &format!(
+                    "@SQ\tSN:{}\tLN:{}\tM5:{}\tUR:file:{}\n",
+                    e.id,
+                    e.length,
+                    e.digest.as_ref().unwrap(),
+                    url.display()
+                );
+            }
+        }
+        Companion::ChromSizes => {
+            for e in entries {
+                r += &format!("{}\t{}\n", e.id, e.length);
+            }
+        }
+        Companion::Checksums => {
+            for e in entries {
+                r += &format!("{}  {}\n", e.digest.as_ref().unwrap(), e.id);
+            }
+        }
+    }
+    r
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    file: &'a str,
+    sequences: usize,
+    written: Vec<String>,
+}
+
+/// Write the `companions` of `filename` next to it
+pub fn run(filename: &str, companions: &[Companion], output: Output) -> Result<()> {
+    let entries = scan(filename, companions.iter().any(Companion::needs_digests))?;
+    let fasta = Path::new(filename);
+    let mut written = Vec::new();
+    for companion in companions {
+        let path = companion.path(fasta);
+        fs::write(&path, render(*companion, fasta, &entries))
+            .with_context(|| format!("Unable to write `{}`", path.display()))?;
+        written.push(path.display().to_string());
+    }
+    let report = Report {
+        file: filename,
+        sequences: entries.len(),
+        written,
+    };
+    output.emit(&report, |report| {
+        for path in report.written.iter() {
+            println!("{} sequences described in {}", report.sequences, path);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn companions() {
+        let dir = tempfile::tempdir().unwrap();
+        let fasta = dir.path().join("ref.fa");
+        fs::write(&fasta, ">a desc\nACGT\nAC\n>b\r\nGG\r\n>c\n>d\nAC\nACGT\n").unwrap();
+        let filename = fasta.to_str().unwrap();
+        assert!(scan(filename, false).is_err());
+
+        fs::write(&fasta, ">a desc\nACGT\nAC\n>b\r\nGG\r\n>c\n>d\nacgt").unwrap();
+        run(filename, &Companion::ALL, Output::Quiet).unwrap();
+        let read = |ext| fs::read_to_string(dir.path().join(ext)).unwrap();
+        assert_eq!(
+            read("ref.fa.fai"),
+            "a\t6\t8\t4\t5\nb\t2\t20\t2\t4\nc\t0\t27\t0\t0\nd\t4\t30\t4\t5\n"
+        );
+        assert_eq!(read("ref.chrom.sizes"), "a\t6\nb\t2\nc\t0\nd\t4\n");
+        let checksums = read("ref.checksums.md5");
+        assert_eq!(
+            checksums.lines().nth(3).unwrap(),
+            format!("{}  d", sequence_digest(b"ACGT"))
+        );
+        let dict = read("ref.dict");
+        assert!(dict.starts_with("@HD\tVN:1.0\tSO:unsorted\n@SQ\tSN:a\tLN:6\tM5:"));
+        assert_eq!(dict.lines().count(), 5);
+    }
+}
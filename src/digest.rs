@@ -0,0 +1,177 @@
+//! The simulation of restriction digests: where enzymes cut the sequences,
+//! and the length of the resulting pieces.
+use crate::fasta::IupacPattern;
+use anyhow::{bail, Result};
+
+/// Some common restriction enzymes, with their site; `^` marks where the
+/// forward strand is cut
+const ENZYMES: &[(&str, &str)] = &[
+    ("AluI", "AG^CT"),
+    ("ApaI", "GGGCC^C"),
+    ("BamHI", "G^GATCC"),
+    ("BglII", "A^GATCT"),
+    ("DpnII", "^GATC"),
+    ("EcoRI", "G^AATTC"),
+    ("EcoRV", "GAT^ATC"),
+    ("HaeIII", "GG^CC"),
+    ("HindIII", "A^AGCTT"),
+    ("HinfI", "G^ANTC"),
+    ("KpnI", "GGTAC^C"),
+    ("MboI", "^GATC"),
+    ("MspI", "C^CGG"),
+    ("NcoI", "C^CATGG"),
+    ("NdeI", "CA^TATG"),
+    ("NheI", "G^CTAGC"),
+    ("NlaIII", "CATG^"),
+    ("NotI", "GC^GGCCGC"),
+    ("PstI", "CTGCA^G"),
+    ("SacI", "GAGCT^C"),
+    ("SalI", "G^TCGAC"),
+    ("SfiI", "GGCCNNNN^NGGCC"),
+    ("SmaI", "CCC^GGG"),
+    ("SpeI", "A^CTAGT"),
+    ("XbaI", "T^CTAGA"),
+    ("XhoI", "C^TCGAG"),
+];
+
+/// Where an enzyme recognizes and cuts a sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Enzyme {
+    pub name: String,
+    /// The recognition site, in IUPAC codes
+    pub site: Vec<u8>,
+    pattern: IupacPattern,
+    /// Where the forward strand is cut, from the start of the site
+    pub cut: usize,
+}
+impl Enzyme {
+    /// Either one of the known enzymes, e.g. `EcoRI`, or a site in IUPAC
+    /// codes, where `^` may mark the cut, e.g. `G^AATTC`; by default, the
+    /// sequence is cut before the site
+    pub fn parse(name: &str) -> Result<Enzyme> {
+        let site = ENZYMES
+            .iter()
+            .find(|(enzyme, _)| enzyme.eq_ignore_ascii_case(name))
+            .map(|(_, site)| *site)
+            .unwrap_or(name);
+        let cut = site.find('^').unwrap_or(0);
+        let site = site.replacen('^', "", 1).into_bytes();
+        let pattern = match IupacPattern::new(&site) {
+            Some(pattern) => pattern,
+            None => bail!(
+                "`{}` is neither a known enzyme nor a site made of IUPAC codes",
+                name
+            ),
+        };
+        Ok(Enzyme {
+            name: name.to_owned(),
+            site: site.to_ascii_uppercase(),
+            pattern,
+            cut,
+        })
+    }
+
+    /// Whether the site is its own reverse complement, as most are
+    pub fn is_palindromic(&self) -> bool {
+        self.pattern.reverse_complement() == self.pattern
+    }
+
+    /// The sites recognized in `seq`, on either strand, in increasing order
+    pub fn sites(&self, seq: &[u8]) -> Vec<Site> {
+        let len = self.site.len();
+        let mut r = self
+            .pattern
+            .find_all(seq)
+            .into_iter()
+            .map(|start| Site {
+                start,
+                end: start + len,
+                reverse: false,
+                cut: start + self.cut,
+            })
+            .collect::<Vec<_>>();
+        if !self.is_palindromic() {
+            r.extend(
+                self.pattern
+                    .reverse_complement()
+                    .find_all(seq)
+                    .into_iter()
+                    .map(|start| Site {
+                        start,
+                        end: start + len,
+                        reverse: true,
+                        cut: start + len - self.cut,
+                    }),
+            );
+            r.sort_by_key(|s| s.start);
+        }
+        r
+    }
+
+    /// The lengths of the pieces of a sequence of `len` bases cut at `sites`
+    pub fn pieces(sites: &[Site], len: usize) -> Vec<usize> {
+        let mut cuts = sites.iter().map(|s| s.cut).collect::<Vec<_>>();
+        cuts.sort_unstable();
+        cuts.dedup();
+        let mut r = Vec::with_capacity(cuts.len() + 1);
+        let mut last = 0;
+        for cut in cuts.into_iter().filter(|&c| c > 0 && c < len) {
+            r.push(cut - last);
+            last = cut;
+        }
+        r.push(len - last);
+        r
+    }
+}
+
+/// An occurrence of the site of an enzyme; positions are 0-based, and the
+/// range half-open
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Site {
+    pub start: usize,
+    pub end: usize,
+    /// Whether the site has been found on the reverse strand
+    pub reverse: bool,
+    /// Where the forward strand is cut
+    pub cut: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digestion() {
+        let ecori = Enzyme::parse("EcoRI").unwrap();
+        let site = Enzyme::parse("G^AATTC").unwrap();
+        assert_eq!((&ecori.site, ecori.cut), (&site.site, site.cut));
+        assert!(ecori.is_palindromic());
+        assert_eq!(Enzyme::parse("ecori").unwrap().site, b"GAATTC");
+        for invalid in ["", "^", "GAAXTC", "NotAnEnzyme"] {
+            assert!(Enzyme::parse(invalid).is_err(), "{}", invalid);
+        }
+
+        let seq = b"AAGAATTCAAgaattcNAGAANTCA";
+        let sites = ecori.sites(seq);
+        assert_eq!(
+            sites.iter().map(|s| (s.start, s.cut)).collect::<Vec<_>>(),
+            [(2, 3), (10, 11)]
+        );
+        assert_eq!(Enzyme::pieces(&sites, seq.len()), [3, 8, 14]);
+        assert_eq!(Enzyme::pieces(&[], 10), [10]);
+
+        // Non-palindromic sites are looked for on both strands
+        let site = Enzyme::parse("GA^TG").unwrap();
+        let sites = site.sites(b"GATGCCATCAA");
+        assert_eq!(
+            sites
+                .iter()
+                .map(|s| (s.start, s.reverse, s.cut))
+                .collect::<Vec<_>>(),
+            [(0, false, 2), (5, true, 7)]
+        );
+        assert_eq!(Enzyme::pieces(&sites, 11), [2, 5, 4]);
+        let hinfi = Enzyme::parse("HinfI").unwrap();
+        assert_eq!(hinfi.sites(b"GACTCGAGTC").len(), 2);
+    }
+}
@@ -0,0 +1,335 @@
+use crate::fs::{FustaFS, FustaSettings};
+use crate::output::Output;
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::fs;
+use std::io::prelude::*;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// The outcome of a single diagnostic
+#[derive(Serialize)]
+struct Check {
+    name: &'static str,
+    ok: bool,
+    details: String,
+    advice: Option<String>,
+}
+impl Check {
+    fn ok<S: AsRef<str>>(name: &'static str, details: S) -> Check {
+        Check {
+            name,
+            ok: true,
+            details: details.as_ref().to_owned(),
+            advice: None,
+        }
+    }
+
+    fn fail<S: AsRef<str>, T: AsRef<str>>(name: &'static str, details: S, advice: T) -> Check {
+        Check {
+            name,
+            ok: false,
+            details: details.as_ref().to_owned(),
+            advice: Some(advice.as_ref().to_owned()),
+        }
+    }
+}
+
+fn check_device() -> Check {
+    let dev = Path::new("/dev/fuse");
+    if !dev.exists() {
+        return Check::fail(
+            "FUSE device",
+            "/dev/fuse does not exist",
+            "load the FUSE kernel module (`modprobe fuse`) or ask your administrator to do so",
+        );
+    }
+    let path = std::ffi::CString::new("/dev/fuse").unwrap();
+    if unsafe { libc::access(path.as_ptr(), libc::R_OK | libc::W_OK) } != 0 {
+        return Check::fail(
+            "FUSE device",
+            "/dev/fuse is not readable and writeable by the current user",
+            "ask your administrator to add you to the `fuse` group or to relax /dev/fuse permissions",
+        );
+    }
+    Check::ok("FUSE device", "/dev/fuse is available")
+}
+
+/// The version of libfuse, as reported by `helper -V`, e.g. `fusermount3
+/// version: 3.14.0`
+fn libfuse_version(helper: &str) -> Option<(String, Vec<u32>)> {
+    let output = std::process::Command::new(helper).arg("-V").output().ok()?;
+    let output = [output.stdout, output.stderr].concat();
+    let output = String::from_utf8_lossy(&output);
+    let version = output.split("version:").nth(1)?.trim().to_owned();
+    let numbers = version
+        .split('.')
+        .map_while(|x| x.parse::<u32>().ok())
+        .collect::<Vec<_>>();
+    if numbers.is_empty() {
+        None
+    } else {
+        Some((version, numbers))
+    }
+}
+
+fn check_fuse_version() -> Check {
+    match libfuse_version("fusermount3").or_else(|| libfuse_version("fusermount")) {
+        Some((version, numbers)) if numbers < vec![2, 6] => Check::fail(
+            "FUSE version",
+            format!("libfuse {} is too old", version),
+            "FUSTA requires libfuse 2.6 or later; install the fuse3 package of your distribution",
+        ),
+        Some((version, _)) => Check::ok("FUSE version", format!("libfuse {}", version)),
+        None if unsafe { libc::geteuid() } == 0 => Check::ok(
+            "FUSE version",
+            "fusermount is not available, but is not needed to mount as root",
+        ),
+        None => Check::fail(
+            "FUSE version",
+            "unable to run `fusermount3 -V` nor `fusermount -V`",
+            "install the fuse3 package of your distribution, which provides the setuid fusermount3 needed to mount without being root",
+        ),
+    }
+}
+
+fn check_fusermount() -> Check {
+    let found = std::env::var_os("PATH")
+        .map(|paths| {
+            std::env::split_paths(&paths).any(|p| {
+                p.join("fusermount").is_file()
+                    || p.join("fusermount3").is_file()
+                    || p.join("umount").is_file()
+            })
+        })
+        .unwrap_or(false);
+    if found {
+        Check::ok("unmount helper", "fusermount or umount is available")
+    } else {
+        Check::fail(
+            "unmount helper",
+            "neither fusermount nor umount could be found in $PATH",
+            "install the fuse or fuse3 package of your distribution",
+        )
+    }
+}
+
+fn check_mountpoint(mountpoint: &Path) -> Check {
+    if crate::fusta_mountpoints().is_empty() && cfg!(target_os = "linux") {
+        return Check::fail(
+            "mount point",
+            "no FUSTA instance is currently mounted",
+            "start FUSTA first, e.g. `fusta file.fa -o mountpoint`",
+        );
+    }
+    let canonical = match mountpoint.canonicalize() {
+        Ok(c) => c,
+        Err(e) => {
+            return Check::fail(
+                "mount point",
+                format!("{:?} is not accessible: {}", mountpoint, e),
+                "check that the mount point exists and that FUSTA is still running",
+            )
+        }
+    };
+    if cfg!(target_os = "linux") && !crate::fusta_mountpoints().contains(&canonical) {
+        return Check::fail(
+            "mount point",
+            format!("{:?} is not a FUSTA mount point", canonical),
+            "check the path, or look at the logs of the FUSTA instance for mounting errors",
+        );
+    }
+    Check::ok("mount point", format!("{:?} is a FUSTA mount", canonical))
+}
+
+/// Reads the settings of the FUSE connection serving `mountpoint`
+fn check_connection(mountpoint: &Path) -> Check {
+    let dev = match fs::metadata(mountpoint) {
+        Ok(m) => m.dev(),
+        Err(e) => {
+            return Check::fail(
+                "FUSE connection",
+                format!("unable to stat {:?}: {}", mountpoint, e),
+                "check that FUSTA is still running",
+            )
+        }
+    };
+    let minor = ((dev & 0xff) | ((dev >> 12) & 0xfff00)) as u32;
+    let connection = PathBuf::from(format!("/sys/fs/fuse/connections/{}", minor));
+    let read = |f: &str| {
+        fs::read_to_string(connection.join(f))
+            .ok()
+            .and_then(|x| x.trim().parse::<u32>().ok())
+    };
+    match (read("max_background"), read("congestion_threshold")) {
+        (Some(max_background), Some(congestion)) => {
+            if max_background < 12 {
+                Check::fail(
+                    "FUSE connection",
+                    format!(
+                        "max_background = {}, congestion_threshold = {}",
+                        max_background, congestion
+                    ),
+                    format!(
+                        "parallel readers may stall; raise it with `echo 64 > {}`",
+                        connection.join("max_background").display()
+                    ),
+                )
+            } else {
+                Check::ok(
+                    "FUSE connection",
+                    format!(
+                        "max_background = {}, congestion_threshold = {}",
+                        max_background, congestion
+                    ),
+                )
+            }
+        }
+        _ => Check::ok(
+            "FUSE connection",
+            "connection settings unavailable (is fusectl mounted?); skipping",
+        ),
+    }
+}
+
+fn check_permissions(mountpoint: &Path) -> Check {
+    for (dir, mode, what) in [
+        ("fasta", libc::R_OK | libc::X_OK, "readable"),
+        ("seqs", libc::R_OK | libc::W_OK | libc::X_OK, "writeable"),
+        ("append", libc::W_OK | libc::X_OK, "writeable"),
+    ] {
+        let path =
+            std::ffi::CString::new(mountpoint.join(dir).to_string_lossy().as_bytes()).unwrap();
+        if unsafe { libc::access(path.as_ptr(), mode) } != 0 {
+            return Check::fail(
+                "permissions",
+                format!("{}/ is not {} by the current user", dir, what),
+                "check that FUSTA has been started by the current user, or with allow_other",
+            );
+        }
+    }
+    Check::ok(
+        "permissions",
+        "virtual directories have the expected permissions",
+    )
+}
+
+/// Exercise the lookup, read and write path on a scratch FASTA file,
+/// mounted in a temporary directory so as to leave the data of the checked
+/// instance untouched
+fn check_roundtrip() -> Check {
+    fn exercise(mountpoint: &Path, id: &str) -> Result<()> {
+        let append = mountpoint.join("append").join(format!("{}.fa", id));
+        let seq = mountpoint.join("seqs").join(format!("{}.seq", id));
+
+        fs::File::create(&append)?.write_all(format!(">{}\nACGT\n", id).as_bytes())?;
+        if !seq.exists() {
+            bail!("the appended fragment did not show up in seqs/");
+        }
+        let content = fs::read(&seq)?;
+        if !content.starts_with(b"ACGT") {
+            bail!(
+                "read {:?} instead of `ACGT`",
+                String::from_utf8_lossy(&content)
+            );
+        }
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&seq)?
+            .write_all(b"TTTT")?;
+        let content = fs::read(&seq)?;
+        if !content.starts_with(b"TTTT") {
+            bail!(
+                "read {:?} instead of `TTTT`",
+                String::from_utf8_lossy(&content)
+            );
+        }
+        let range = fs::read(mountpoint.join("get").join(format!("{}:2-3", id)))?;
+        if range != b"TT" {
+            bail!(
+                "get/ returned {:?} instead of `TT`",
+                String::from_utf8_lossy(&range)
+            );
+        }
+        fs::remove_file(&seq)?;
+        Ok(())
+    }
+
+    fn roundtrip(dir: &Path) -> Result<()> {
+        const CONTENT: &str = ">scratch\nACGT\n";
+        let fasta = dir.join("scratch.fa");
+        fs::write(&fasta, CONTENT)?;
+        let mountpoint = dir.join("mnt");
+        fs::create_dir(&mountpoint)?;
+        let fs = FustaFS::new(FustaSettings::default(), &fasta.to_string_lossy())?;
+        let session = fuser::spawn_mount2(
+            fs,
+            &mountpoint,
+            &[fuser::MountOption::FSName("fusta".into())],
+        )?;
+        let exercised = exercise(&mountpoint, "doctor");
+        // Unmount, writing the changes back
+        session.join();
+        exercised?;
+        let content = fs::read_to_string(&fasta)?;
+        if content != CONTENT {
+            bail!("wrote back {:?} instead of {:?}", content, CONTENT);
+        }
+        Ok(())
+    }
+
+    match tempfile::tempdir()
+        .map_err(anyhow::Error::from)
+        .and_then(|dir| roundtrip(dir.path()))
+    {
+        Ok(_) => Check::ok(
+            "roundtrip",
+            "mount, append, lookup, read, write, delete and write-back succeeded on a scratch file",
+        ),
+        Err(e) => Check::fail(
+            "roundtrip",
+            format!("{:#}", e),
+            "run FUSTA with -D -vv on a small file to inspect the failing operation in the logs",
+        ),
+    }
+}
+
+/// Run all the diagnostics against the FUSTA instance mounted on
+/// `mountpoint`, returning an error if any of them failed.
+pub fn run(mountpoint: &str, output: Output) -> Result<()> {
+    let mountpoint = Path::new(mountpoint);
+    let mut checks = vec![check_device(), check_fuse_version(), check_fusermount()];
+    // Mounting anything requires a working FUSE
+    if checks.iter().all(|c| c.ok) {
+        checks.push(check_roundtrip());
+    }
+    checks.push(check_mountpoint(mountpoint));
+    // Live checks only make sense on a working mount
+    if checks.last().unwrap().ok {
+        checks.push(check_connection(mountpoint));
+        checks.push(check_permissions(mountpoint));
+    }
+
+    output.emit(&checks, |checks| {
+        for check in checks.iter() {
+            println!(
+                "[{}] {:<16} {}",
+                if check.ok { " OK " } else { "FAIL" },
+                check.name,
+                check.details
+            );
+            if let Some(advice) = check.advice.as_ref() {
+                println!("       {:<16} -> {}", "", advice);
+            }
+        }
+    })?;
+
+    let failed = checks.iter().filter(|c| !c.ok).count();
+    if failed > 0 {
+        bail!("{} check(s) failed", failed)
+    }
+    if output.is_human() {
+        println!("Everything looks fine");
+    }
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use libc::*;
+use std::fmt;
+
+/// Why a filesystem operation failed; every failure is mapped here to the
+/// errno replied to the calling process and to the message logged.
+#[derive(Debug)]
+pub enum FustaError {
+    /// Some user-provided content (region, rename map, …) is malformed
+    Parse(String),
+    /// The FASTA file, or one of its companion files, could not be accessed
+    BackingIo(String, std::io::Error),
+    /// The operation would replace an existing file
+    Conflict(String),
+    /// The operation would grow a file past what can be stored
+    Quota(String),
+    /// The file can not be modified, at least not by this user
+    ReadOnly(String),
+    /// The file, directory or fragment does not exist
+    NotFound(String),
+    /// The request is well-formed, but falls outside of what it targets
+    OutOfRange(String),
+    /// The operation is held back until the user confirms it
+    Unconfirmed(String),
+    /// The result is still being computed; the operation should be retried
+    Busy(String),
+    /// The content is not available in this mount, e.g. with --headers-only
+    Unavailable(String),
+}
+impl FustaError {
+    pub fn errno(&self) -> c_int {
+        match self {
+            FustaError::Parse(_) => EINVAL,
+            FustaError::BackingIo(..) => EIO,
+            FustaError::Conflict(_) => EEXIST,
+            FustaError::Quota(_) => EFBIG,
+            FustaError::ReadOnly(_) => EACCES,
+            FustaError::NotFound(_) => ENOENT,
+            FustaError::OutOfRange(_) => ERANGE,
+            FustaError::Unconfirmed(_) => ECANCELED,
+            FustaError::Busy(_) => EAGAIN,
+            FustaError::Unavailable(_) => EIO,
+        }
+    }
+
+    /// How loudly the error should be reported; missing files are routinely
+    /// looked up by shells and tools, and are thus not worth a warning.
+    pub fn level(&self) -> log::Level {
+        match self {
+            FustaError::NotFound(_) => log::Level::Debug,
+            FustaError::Busy(_) => log::Level::Info,
+            FustaError::BackingIo(..) => log::Level::Error,
+            _ => log::Level::Warn,
+        }
+    }
+}
+impl fmt::Display for FustaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FustaError::BackingIo(msg, e) => write!(f, "{}: {}", msg, e),
+            FustaError::Parse(msg)
+            | FustaError::Conflict(msg)
+            | FustaError::Quota(msg)
+            | FustaError::ReadOnly(msg)
+            | FustaError::NotFound(msg)
+            | FustaError::OutOfRange(msg)
+            | FustaError::Unconfirmed(msg)
+            | FustaError::Busy(msg)
+            | FustaError::Unavailable(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+impl std::error::Error for FustaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FustaError::BackingIo(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Reading the samtools index of a FASTA file, `FASTA.fai`, so that the
+//! sequences are located without scanning the whole file.
+//!
+//! Every line describes a sequence as `NAME LENGTH OFFSET LINEBASES
+//! LINEWIDTH`, separated by tabs; the headers, which it lacks, are read
+//! right before the offsets of their sequences.
+use crate::fasta::{split_header, Fragment};
+use anyhow::{bail, ensure, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// How much is read at once before the sequences, looking for their headers
+const HEADER_WINDOW: u64 = 4096;
+const MAX_HEADER_SIZE: u64 = 1 << 20;
+
+/// Where samtools writes the index of `fasta`
+pub fn path_for(fasta: &Path) -> PathBuf {
+    let mut name = fasta.as_os_str().to_owned();
+    name.push(".fai");
+    PathBuf::from(name)
+}
+
+/// A line of a `.fai` file
+#[derive(Debug)]
+struct Entry<'a> {
+    name: &'a str,
+    length: u64,
+    offset: u64,
+    line_bases: u64,
+    line_width: u64,
+}
+impl Entry<'_> {
+    /// How many bytes the sequence takes in the file, up to its last base
+    fn span(&self) -> u64 {
+        if self.length == 0 || self.line_bases == 0 {
+            return self.length;
+        }
+        let lines = (self.length - 1) / self.line_bases;
+        self.length + lines * (self.line_width.saturating_sub(self.line_bases))
+    }
+}
+
+fn parse(content: &str) -> Result<Vec<Entry<'_>>> {
+    let mut r: Vec<Entry> = Vec::new();
+    for (i, line) in content.lines().enumerate().filter(|(_, l)| !l.is_empty()) {
+        let fields = line.split('\t').collect::<Vec<_>>();
+        let entry = match fields[..] {
+            [name, length, offset, line_bases, line_width] => {
+                let number = |x: &str| {
+                    x.parse::<u64>()
+                        .with_context(|| format!("line {}: `{}` is not a valid number", i + 1, x))
+                };
+                Entry {
+                    name,
+                    length: number(length)?,
+                    offset: number(offset)?,
+                    line_bases: number(line_bases)?,
+                    line_width: number(line_width)?,
+                }
+            }
+            _ => bail!(
+                "line {}: expected 5 tab-separated fields, found {}",
+                i + 1,
+                fields.len()
+            ),
+        };
+        ensure!(
+            entry.line_width >= entry.line_bases,
+            "line {}: lines are shorter than their bases",
+            i + 1
+        );
+        if let Some(last) = r.last() {
+            ensure!(
+                entry.offset >= last.offset + last.span(),
+                "line {}: `{}` overlaps `{}`",
+                i + 1,
+                entry.name,
+                last.name
+            );
+        }
+        r.push(entry);
+    }
+    Ok(r)
+}
+
+/// Where the header line of the sequence starting at `offset` starts
+fn header_start(
+    offset: u64,
+    read_at: &impl Fn(&mut [u8], u64) -> std::io::Result<()>,
+) -> Result<u64> {
+    ensure!(offset > 0, "no header before the first sequence");
+    let end = offset - 1; // The newline ending the header
+    let mut window = HEADER_WINDOW;
+    loop {
+        let start = end.saturating_sub(window);
+        let mut buffer = vec![0; (end - start) as usize];
+        read_at(&mut buffer, start)?;
+        if let Some(i) = buffer.iter().rposition(|&c| c == b'\n') {
+            return Ok(start + i as u64 + 1);
+        }
+        if start == 0 {
+            return Ok(0);
+        }
+        ensure!(window < MAX_HEADER_SIZE, "header too long");
+        window *= 4;
+    }
+}
+
+/// The header line right before the sequence starting at `offset`, without
+/// its leading '>'
+pub fn header_before(
+    offset: u64,
+    read_at: &impl Fn(&mut [u8], u64) -> std::io::Result<()>,
+) -> Result<(u64, String)> {
+    let start = header_start(offset, read_at)?;
+    let mut header = vec![0; (offset - 1 - start) as usize];
+    read_at(&mut header, start)?;
+    let mut newline = [0];
+    read_at(&mut newline, offset - 1)?;
+    ensure!(
+        header.first() == Some(&b'>') && newline[0] == b'\n',
+        "no header found"
+    );
+    Ok((start, String::from_utf8_lossy(&header[1..]).to_string()))
+}
+
+/// The fragments of `fasta`, whose content is `size` bytes long, as indexed
+/// in `path`, reading their headers through `read_at`; fails if the index
+/// is older than `fasta` or does not match its content
+pub fn load(
+    path: &Path,
+    fasta: &fs::File,
+    size: u64,
+    read_at: impl Fn(&mut [u8], u64) -> std::io::Result<()>,
+) -> Result<Vec<Fragment>> {
+    let modified = |m: fs::Metadata| m.modified().ok();
+    if modified(fs::metadata(path)?) < modified(fasta.metadata()?) {
+        bail!("the file was modified since it was indexed")
+    }
+    let content = fs::read_to_string(path)?;
+    let entries = parse(&content)?;
+
+    let mut starts = Vec::with_capacity(entries.len());
+    for entry in entries.iter() {
+        ensure!(
+            entry.offset <= size,
+            "`{}` lies past the end of the file",
+            entry.name
+        );
+        let (start, header) = header_before(entry.offset, &read_at)
+            .with_context(|| format!("before `{}`", entry.name))?;
+        ensure!(
+            split_header(&header).0 == entry.name,
+            "`{}` is not found where indexed",
+            entry.name
+        );
+        starts.push((start, header));
+    }
+
+    // Nor anything but blank lines before the first one
+    let first = starts.first().map(|s| s.0).unwrap_or(size);
+    let mut leading = vec![0; std::cmp::min(first, HEADER_WINDOW) as usize];
+    read_at(&mut leading, 0)?;
+    ensure!(
+        first <= HEADER_WINDOW && leading.iter().all(u8::is_ascii_whitespace),
+        "unindexed content before the first sequence"
+    );
+
+    let mut fragments = Vec::with_capacity(entries.len());
+    for (i, (entry, (_, header))) in entries.iter().zip(starts.iter()).enumerate() {
+        let end = starts.get(i + 1).map(|s| s.0).unwrap_or(size);
+        // Only blank lines may lie between a sequence and the next header
+        let gap = end
+            .checked_sub(entry.offset + entry.span())
+            .with_context(|| format!("`{}` is longer than indexed", entry.name))?;
+        let mut trailing = vec![0; std::cmp::min(gap, HEADER_WINDOW) as usize];
+        read_at(&mut trailing, end - gap)?;
+        ensure!(
+            gap <= HEADER_WINDOW && trailing.iter().all(u8::is_ascii_whitespace),
+            "unindexed content after `{}`",
+            entry.name
+        );
+        let (id, name) = split_header(header);
+        fragments.push(Fragment {
+            id: id.into(),
+            name,
+            header: header.clone(),
+            pos: (entry.offset as usize, end as usize),
+            len: (end - entry.offset) as usize,
+            line_width: std::cmp::min(entry.line_bases, entry.length) as usize,
+            seq: None,
+        });
+    }
+    Ok(fragments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fasta::FastaReader;
+    use std::os::unix::fs::FileExt;
+
+    #[test]
+    fn fragments() {
+        let dir = tempfile::tempdir().unwrap();
+        let fasta = dir.path().join("ref.fa");
+        let content = ">a desc  here\nACGT\nAC\n\n>b\nGGGGG\n>c\n>d x\nacgt";
+        fs::write(&fasta, content).unwrap();
+        let fai = path_for(&fasta);
+        assert_eq!(fai, dir.path().join("ref.fa.fai"));
+        fs::write(
+            &fai,
+            "a\t6\t14\t4\t5\nb\t5\t26\t5\t6\nc\t0\t35\t0\t0\nd\t4\t40\t4\t4\n",
+        )
+        .unwrap();
+        let file = fs::File::open(&fasta).unwrap();
+        let read_at = |buffer: &mut [u8], offset| file.read_exact_at(buffer, offset);
+
+        let loaded = load(&fai, &file, content.len() as u64, read_at).unwrap();
+        let scanned = FastaReader::new(&file, false).collect::<Vec<_>>();
+        assert_eq!(loaded.len(), 4);
+        for (l, s) in loaded.iter().zip(scanned.iter()) {
+            assert_eq!(
+                (&l.id, &l.name, &l.header, l.pos, l.len, l.line_width),
+                (&s.id, &s.name, &s.header, s.pos, s.len, s.line_width)
+            );
+        }
+
+        // An index not matching the file is refused
+        for bad in [
+            "a\t6\t14\t4\t5\nb\t5\t27\t5\t6\n",
+            "a\t6\t14\t4\t5\nz\t5\t26\t5\t6\n",
+            "a\t60\t14\t4\t5\n",
+            "a\t6\t14\t4\t5\nc\t0\t35\t0\t0\nd\t4\t40\t4\t4\n",
+            "b\t5\t26\t5\t6\nc\t0\t35\t0\t0\nd\t4\t40\t4\t4\n",
+            "",
+            "a\t6\t14\t4\n",
+            "a\t6\t1400\t4\t5\n",
+        ] {
+            fs::write(&fai, bad).unwrap();
+            assert!(
+                load(&fai, &file, content.len() as u64, read_at).is_err(),
+                "{}",
+                bad
+            );
+        }
+    }
+}
@@ -1,20 +1,26 @@
 use std::io::prelude::*;
-use std::io::{BufReader, Lines};
+use std::io::BufReader;
 
+/// A record of a FASTA file. Lines may end with CRLF rather than LF: the CR
+/// is then kept in `header`, so that the header line is written back as it
+/// was, but it is part of neither `id`, `name`, `line_width` nor `seq`.
 #[derive(Debug)]
 pub struct Fragment {
-    pub id: smartstring::SmartString<smartstring::LazyCompact>,
+    pub id: smartstring::SmartString<smartstring::LazyCompact>, // Empty for a bare `>`, which is not a valid ID
     pub name: Option<String>,
+    pub header: String, // The header line as found in the file, without its leading '>' and newline
     pub pos: (usize, usize),
     pub len: usize,
+    pub line_width: usize, // The length of the first sequence line, without the newline
     pub seq: Option<Vec<u8>>,
 }
 
 pub struct FastaReader<T> {
-    buffer_lines: Lines<BufReader<T>>,
+    reader: BufReader<T>,
     current_header: Option<String>,
     current_start: usize,
     current_offset: usize,
+    current_width: Option<usize>,
 
     with_seq: bool,
 }
@@ -22,14 +28,54 @@ pub struct FastaReader<T> {
 impl<T: Read> FastaReader<T> {
     pub fn new(file: T, with_seq: bool) -> FastaReader<T> {
         FastaReader {
-            buffer_lines: BufReader::new(file).lines(),
+            reader: BufReader::new(file),
             current_header: None,
             current_start: 0,
             current_offset: 0,
+            current_width: None,
 
             with_seq,
         }
     }
+
+    /// Feed the next line to `sink` chunk by chunk, without its newline, and
+    /// return the number of bytes consumed
+    fn consume_line(&mut self, mut sink: impl FnMut(&[u8])) -> usize {
+        let mut consumed = 0;
+        loop {
+            let available = self.reader.fill_buf().unwrap();
+            if available.is_empty() {
+                return consumed;
+            }
+            match available.iter().position(|&c| c == b'\n') {
+                Some(i) => {
+                    sink(&available[..i]);
+                    self.reader.consume(i + 1);
+                    return consumed + i + 1;
+                }
+                None => {
+                    let len = available.len();
+                    sink(available);
+                    self.reader.consume(len);
+                    consumed += len;
+                }
+            }
+        }
+    }
+
+    /// Build the fragment described by `header`, whose sequence ends at `end`
+    fn make_fragment(&mut self, header: String, end: usize, seq: Vec<u8>) -> Fragment {
+        let (id, name) = split_header(&header);
+        Fragment {
+            id: id.into(),
+            name,
+            header,
+            pos: (self.current_start, end),
+            len: end - self.current_start,
+            line_width: self.current_width.take().unwrap_or(0),
+            seq: if self.with_seq { Some(seq) } else { None },
+        }
+    }
 }
 
 impl<T: Read> Iterator for FastaReader<T> {
@@ -37,69 +83,247 @@ impl<T: Read> Iterator for FastaReader<T> {
 
     fn next(&mut self) -> Option<Fragment> {
         let mut current_seq: Vec<u8> = Vec::new();
-        for l in self.buffer_lines.by_ref() {
-            let line = l.unwrap();
-            let len = line.len() + 1;
-            self.current_offset += len;
-
-            if let Some(name) = line.strip_prefix('>') {
-                if let Some(ref current_header) = self.current_header {
-                    let split = current_header
-                        .split(' ')
-                        .filter(|s| !s.is_empty())
-                        .collect::<Vec<_>>();
-                    let r = Fragment {
-                        id: split[0].into(),
-                        name: if split.len() > 1 {
-                            Some(split[1..].join(" "))
-                        } else {
-                            None
-                        },
-
-                        pos: (self.current_start, self.current_offset - len),
-                        len: self.current_offset - self.current_start - len,
-                        seq: if self.with_seq {
-                            Some(current_seq)
-                        } else {
-                            None
-                        },
-                    };
-                    self.current_header = Some(String::from(name));
+        loop {
+            let is_header = match self.reader.fill_buf().unwrap() {
+                [] => break,
+                available => available[0] == b'>',
+            };
 
+            if is_header {
+                let mut header = Vec::new();
+                let len = self.consume_line(|chunk| header.extend_from_slice(chunk));
+                self.current_offset += len;
+                let name = String::from_utf8_lossy(&header[1..]).to_string();
+                if let Some(current_header) = self.current_header.replace(name) {
+                    let r =
+                        self.make_fragment(current_header, self.current_offset - len, current_seq);
                     self.current_start = self.current_offset;
-
                     return Some(r);
                 } else {
-                    self.current_header = Some(String::from(name));
+                    // Whatever preceded the first header is not a sequence
+                    current_seq.clear();
                     self.current_start = self.current_offset;
+                    self.current_width = None;
                 }
                 continue;
-            } else if self.with_seq {
-                current_seq.extend(line.trim_end().as_bytes());
+            }
+
+            // Sequence lines are never buffered as a whole, so that they may
+            // be arbitrarily long
+            let line_start = current_seq.len();
+            let mut line_len = 0;
+            let mut last = None;
+            let with_seq = self.with_seq;
+            let len = self.consume_line(|chunk| {
+                line_len += chunk.len();
+                last = chunk.last().copied().or(last);
+                if with_seq {
+                    current_seq.extend_from_slice(chunk);
+                }
+            });
+            self.current_offset += len;
+            if self.current_width.is_none() {
+                self.current_width = Some(line_len - usize::from(last == Some(b'\r')));
+            }
+            while current_seq.len() > line_start
+                && current_seq.last().unwrap().is_ascii_whitespace()
+            {
+                current_seq.pop();
             }
         }
 
-        if let Some(ref current_header) = self.current_header {
-            let split = current_header.split(' ').collect::<Vec<_>>();
-            let r = Fragment {
-                id: split[0].into(),
-                name: if split.len() > 1 {
-                    Some(split[1..].join(" "))
-                } else {
-                    None
-                },
-                pos: (self.current_start, self.current_offset),
-                len: self.current_offset - self.current_start,
-                seq: if self.with_seq {
-                    Some(current_seq)
-                } else {
-                    None
-                },
-            };
-            self.current_header = None;
-            return Some(r);
+        if let Some(current_header) = self.current_header.take() {
+            return Some(self.make_fragment(current_header, self.current_offset, current_seq));
         }
 
         None
     }
 }
+
+/// The ID and the description found in a header line, given without its
+/// leading '>'
+pub fn split_header(header: &str) -> (&str, Option<String>) {
+    let split = header
+        .trim_end()
+        .split(' ')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+    (
+        split.first().copied().unwrap_or_default(),
+        if split.len() > 1 {
+            Some(split[1..].join(" "))
+        } else {
+            None
+        },
+    )
+}
+
+/// Returns the header line of a fragment
+pub fn make_label(id: &str, name: &Option<String>) -> String {
+    format!(
+        ">{}{}\n",
+        id,
+        name.as_ref().map(|n| format!(" {}", n)).unwrap_or_default()
+    )
+}
+
+/// Writes `data` at `offset` in `buffer`, padding it with NULs if it is too
+/// short; returns `None`, leaving `buffer` untouched, if it can not grow enough
+pub fn write_at(buffer: &mut Vec<u8>, offset: usize, data: &[u8]) -> Option<usize> {
+    let end = offset.checked_add(data.len())?;
+    if end > buffer.len() {
+        resize(buffer, end)?;
+    }
+    buffer[offset..end].copy_from_slice(data);
+    Some(data.len())
+}
+
+/// Truncates `buffer` or pads it with NULs to `size`; returns `None`, leaving
+/// `buffer` untouched, if it can not grow enough
+pub fn resize(buffer: &mut Vec<u8>, size: usize) -> Option<()> {
+    if size > buffer.len() {
+        buffer.try_reserve(size - buffer.len()).ok()?;
+    }
+    buffer.resize(size, 0);
+    Some(())
+}
+
+/// Returns the complement of an IUPAC nucleotide code, preserving its case;
+/// unknown symbols are returned as-is.
+pub fn complement(n: u8) -> u8 {
+    let c = match n.to_ascii_uppercase() {
+        b'A' => b'T',
+        b'T' | b'U' => b'A',
+        b'G' => b'C',
+        b'C' => b'G',
+        b'R' => b'Y',
+        b'Y' => b'R',
+        b'K' => b'M',
+        b'M' => b'K',
+        b'B' => b'V',
+        b'V' => b'B',
+        b'D' => b'H',
+        b'H' => b'D',
+        _ => return n,
+    };
+    if n.is_ascii_lowercase() {
+        c.to_ascii_lowercase()
+    } else {
+        c
+    }
+}
+
+/// Reverse-complements a sequence in place
+pub fn reverse_complement(seq: &mut [u8]) {
+    seq.reverse();
+    for n in seq.iter_mut() {
+        *n = complement(*n);
+    }
+}
+
+/// The bases an IUPAC nucleotide code stands for, as a bit set of ACGT
+fn iupac_bases(code: u8) -> Option<u8> {
+    const A: u8 = 1;
+    const C: u8 = 2;
+    const G: u8 = 4;
+    const T: u8 = 8;
+    Some(match code.to_ascii_uppercase() {
+        b'A' => A,
+        b'C' => C,
+        b'G' => G,
+        b'T' | b'U' => T,
+        b'R' => A | G,
+        b'Y' => C | T,
+        b'S' => C | G,
+        b'W' => A | T,
+        b'K' => G | T,
+        b'M' => A | C,
+        b'B' => C | G | T,
+        b'D' => A | G | T,
+        b'H' => A | C | T,
+        b'V' => A | C | G,
+        b'N' => A | C | G | T,
+        _ => return None,
+    })
+}
+
+/// A sequence of IUPAC nucleotide codes, e.g. a primer or a restriction site,
+/// to be looked for in sequences
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IupacPattern(Vec<u8>);
+impl IupacPattern {
+    /// `None` if `codes` is empty or contains anything else than IUPAC codes
+    pub fn new(codes: &[u8]) -> Option<IupacPattern> {
+        let bases = codes
+            .iter()
+            .map(|&c| iupac_bases(c))
+            .collect::<Option<Vec<_>>>()?;
+        (!bases.is_empty()).then_some(IupacPattern(bases))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn reverse_complement(&self) -> IupacPattern {
+        // Swapping A and T, and C and G, amounts to reversing the bit set
+        IupacPattern(self.0.iter().rev().map(|b| b.reverse_bits() >> 4).collect())
+    }
+
+    /// Whether `window` matches the pattern; unlike those of the pattern, the
+    /// ambiguous bases of `window`, e.g. N, match nothing
+    pub fn matches(&self, window: &[u8]) -> bool {
+        window.len() == self.0.len()
+            && self
+                .0
+                .iter()
+                .zip(window)
+                .all(|(&p, &b)| match iupac_bases(b) {
+                    Some(b) if b.count_ones() == 1 => p & b != 0,
+                    _ => false,
+                })
+    }
+
+    /// The start of every match of the pattern in `seq`, in increasing order
+    pub fn find_all(&self, seq: &[u8]) -> Vec<usize> {
+        seq.windows(self.0.len())
+            .enumerate()
+            .filter(|(_, window)| self.matches(window))
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+/// Incrementally computes the MD5 digest of a sequence, ignoring newlines and
+/// case, as done by e.g. `samtools dict`.
+pub struct SequenceDigest(md5::Context);
+impl Default for SequenceDigest {
+    fn default() -> Self {
+        SequenceDigest(md5::Context::new())
+    }
+}
+impl SequenceDigest {
+    pub fn consume(&mut self, chunk: &[u8]) {
+        self.0.consume(
+            chunk
+                .iter()
+                .filter(|c| !c.is_ascii_whitespace())
+                .map(u8::to_ascii_uppercase)
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    pub fn finalize(self) -> String {
+        format!("{:x}", self.0.compute())
+    }
+}
+
+pub fn sequence_digest(seq: &[u8]) -> String {
+    let mut digest = SequenceDigest::default();
+    digest.consume(seq);
+    digest.finalize()
+}
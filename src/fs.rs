@@ -1,5 +1,18 @@
 #![allow(clippy::redundant_field_names)]
+use crate::backing::*;
+use crate::bgzf::BgzfStream;
+#[cfg(feature = "catalog")]
+use crate::catalog::{self, Catalog};
+use crate::chaos::Chaos;
+use crate::checksum::DigestCache;
+use crate::derive::Companion;
+use crate::error::FustaError;
+use crate::notifications::{Notifier, Operation, Thresholds};
 use crate::notify;
+use crate::pool::{Pending, Pool};
+use crate::session::{DirOwnership, Session};
+use crate::throttle::{Limits, Throttle};
+use crate::trace::op;
 use anyhow::{Context, Result};
 use fuser::*;
 use libc::*;
@@ -8,29 +21,43 @@ use maplit::*;
 use multi_map::MultiMap;
 use regex::Regex;
 use smartstring::SmartString;
-use std::cell::RefCell;
+use std::cell::Cell;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::convert::TryInto;
+use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::ffi::OsStr;
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 type SString = SmartString<smartstring::LazyCompact>;
 
 use std::io::prelude::*;
-use std::io::SeekFrom;
 
+use fusta::aliases::Aliases;
+use fusta::annotation::{Annotation, Feature, Flank, GeneticCode, Strand, Transcript};
+use fusta::assembly::Composition;
+use fusta::digest::Enzyme;
+use fusta::fai;
 use fusta::fasta::*;
+use fusta::ids::{Codec, IdRules};
+use fusta::index::{self, Index};
+use fusta::pcr::Primers;
+use fusta::repeats::{SimpleRepeats, TelomereMotifs, TELOMERE_WINDOW};
+use fusta::tags::TagFilter;
+use fusta::transform::Pipeline;
 
-#[cfg(unix)]
-const FORBIDDEN_CHARS: [char; 2] = ['\\', '\0'];
-#[cfg(windows)]
-const FORBIDDEN_CHARS: [char; 8] = ['\\', '/', ':', '*', '?', '|', '<', '>'];
+// How long the kernel may cache the attributes and entries, until changed
+// through ctl/config
 const TTL: Duration = Duration::from_secs(1);
+// Largest read & write requests negotiated with the kernel, so that
+// sequential accesses to large sequences are not split in 128kB chunks
+pub const MAX_IO_SIZE: u32 = 1 << 20;
 
 const FASTA_EXT: &str = ".fa";
 const SEQ_EXT: &str = ".seq";
-const DOTLESS_SEQ_EXT: &str = ".seq";
+const TMP_SUFFIX: &str = "#fusta#"; // Appended to the files being rewritten
 
 // Virtual directories
 const ROOT_DIR: u64 = 1;
@@ -38,25 +65,128 @@ const FASTA_DIR: u64 = 2;
 const SEQ_DIR: u64 = 3;
 const APPEND_DIR: u64 = 4;
 const SUBFRAGMENTS_DIR: u64 = 5;
+const CTL_DIR: u64 = 6;
+const SNAPSHOT_DIR: u64 = 7;
+const META_DIR: u64 = 8;
+const META_DIR_NAME: &str = ".fusta";
+const DOWNLOAD_DIR: u64 = 9;
+const DOWNLOAD_EXT: &str = ".fa.gz";
+const DIGEST_DIR: u64 = 28;
+const DIGEST_DIR_NAME: &str = "digest";
+const BED_EXT: &str = ".bed";
+const SIZES_EXT: &str = ".sizes";
+const PCR_DIR: u64 = 29;
+const PCR_DIR_NAME: &str = "pcr";
+const ENDS_DIR: u64 = 31;
+const ENDS_DIR_NAME: &str = "ends";
+const HEAD_EXT: &str = ".head.fa";
+const TAIL_EXT: &str = ".tail.fa";
+const TAGS_DIR: u64 = 33;
+const TAGS_DIR_NAME: &str = "tags";
+const TAGS_EXT: &str = ".tags";
+const TAXA_DIR: u64 = 44;
+const TAXA_DIR_NAME: &str = "taxa";
+const TAXON_COUNT_FILE_NAME: &str = "count.txt";
+// Only present when aliases are provided
+const ALIASES_DIR: u64 = 35;
+const ALIASES_DIR_NAME: &str = "aliases";
+// Only present when an annotation is provided
+const PROTEINS_DIR: u64 = 25;
+const PROTEINS_DIR_NAME: &str = "proteins";
+const PROTEIN_EXT: &str = ".faa";
+const FEATURES_DIR: u64 = 26;
+const FEATURES_DIR_NAME: &str = "features";
+const FEATURE_EXT: &str = ".fa";
+// How many residues per line in the files of proteins/, features/ and ends/
+const LINE_WIDTH: usize = 60;
 
-// First free ino
-const FIRST_INO: u64 = 20;
+// First free ino, leaving some room for new virtual files
+const FIRST_INO: u64 = 64;
+// Inos derived from fragment IDs live above this one, so that they never
+// collide with the sequentially allocated ones.
+const FIRST_STABLE_INO: u64 = 1 << 32;
 
 // Pure virtual files
 const INFO_FILE: u64 = 10;
 const INFO_FILE_NAME: &str = "infos.txt";
+const SUMMARY_FILE: u64 = 38;
+const SUMMARY_FILE_NAME: &str = "summary.txt";
 const LABELS_FILE: u64 = 11;
 const LABELS_FILE_NAME: &str = "labels.txt";
 const INFO_CSV_FILE: u64 = 12;
 const INFO_CSV_FILE_NAME: &str = "infos.csv";
+const INFO_JSON_FILE: u64 = 32;
+const INFO_JSON_FILE_NAME: &str = "infos.json";
+const OFFSETS_FILE: u64 = 15;
+const OFFSETS_FILE_NAME: &str = "offsets.csv";
+const FAI_FILE: u64 = 42;
+const FAI_FILE_NAME: &str = "index.fai";
+const APPENDS_FILE: u64 = 16;
+const APPENDS_FILE_NAME: &str = "appends.csv";
+const LAST_ERROR_FILE: u64 = 17;
+const LAST_ERROR_FILE_NAME: &str = "last_error";
+const COLLISIONS_FILE: u64 = 41;
+const COLLISIONS_FILE_NAME: &str = "collisions";
+const COMMIT_REPORT_FILE: u64 = 43;
+const COMMIT_REPORT_FILE_NAME: &str = "commit-report.txt";
+const STATUS_FILE: u64 = 21;
+const STATUS_FILE_NAME: &str = "status";
+const MANIFEST_FILE: u64 = 37;
+const MANIFEST_FILE_NAME: &str = "manifest.json";
 
-fn is_fasta_char(c: u8) -> bool {
-    c.is_ascii_alphanumeric() || [b'\n', b'-', b'_', b'.', b'+', b'='].contains(&c)
-}
+// Control files
+const RENAME_MAP_FILE: u64 = 13;
+const RENAME_MAP_FILE_NAME: &str = "rename-map";
+const REGIONS_FILE: u64 = 14;
+const REGIONS_FILE_NAME: &str = "regions";
+const EXPORT_FILE: u64 = 18;
+const EXPORT_FILE_NAME: &str = "export";
+const EXPORT_SPLIT_FILE: u64 = 34;
+const EXPORT_SPLIT_FILE_NAME: &str = "export-split";
+const COMMIT_FILE: u64 = 19;
+const COMMIT_FILE_NAME: &str = "commit";
+const REVERT_FILE: u64 = 22;
+const REVERT_FILE_NAME: &str = "revert";
+const UNDO_FILE: u64 = 23;
+const UNDO_FILE_NAME: &str = "undo";
+const DELETE_FILE: u64 = 36;
+const DELETE_FILE_NAME: &str = "delete";
+const CONFIG_FILE: u64 = 40;
+const CONFIG_FILE_NAME: &str = "config";
+const STATS_FILE: u64 = 20;
+const STATS_FILE_NAME: &str = "stats.csv";
+const DUPLICATES_FILE: u64 = 24;
+const DUPLICATES_FILE_NAME: &str = "duplicates.txt";
+const REPEATS_FILE: u64 = 30;
+const REPEATS_FILE_NAME: &str = "repeats.txt";
+const FORMAT_FILE: u64 = 39;
+const FORMAT_FILE_NAME: &str = "format.txt";
+// Only present when an annotation is provided
+const CODON_USAGE_FILE: u64 = 27;
+const CODON_USAGE_FILE_NAME: &str = "codon_usage.csv";
+// The files appended with this prefix overwrite the existing sequences,
+// whatever --on-collision
+const FORCE_APPEND_PREFIX: &str = "force_";
+// How long a read of the statistics or of the duplicates may wait for them
+// to be computed before being asked to retry
+const STATS_WAIT: Duration = Duration::from_secs(2);
+// How old the access time of a file may grow while it is read
+const RELATIME_DELAY: Duration = Duration::from_secs(24 * 3600);
+const CTL_FILES: [u64; 9] = [
+    RENAME_MAP_FILE,
+    REGIONS_FILE,
+    EXPORT_FILE,
+    EXPORT_SPLIT_FILE,
+    COMMIT_FILE,
+    REVERT_FILE,
+    UNDO_FILE,
+    DELETE_FILE,
+    CONFIG_FILE,
+];
 
 #[derive(Debug, PartialEq, Clone)]
 enum FileClass {
-    Fasta(std::cell::RefCell<Vec<u8>>),
+    Fasta,
     Seq,
     Text,
 }
@@ -134,41 +264,25 @@ impl VirtualFile for FragmentFile {
     }
 }
 
-#[derive(Debug)]
-enum Backing {
-    File(SString, usize, usize), // A start, end pair in a file
-    Buffer(Vec<u8>),             // A chunk of memory
-    PureBuffer(Vec<u8>), // The same, but guaranteed pure (i.e. no newlines) - can be accessed directly
-    MMap(memmap2::Mmap), // A memmapped chunk of memory
-}
-impl Backing {
-    fn len(&self) -> usize {
-        match self {
-            Backing::File(_, start, end) => end - start,
-            Backing::Buffer(ref b) => b.len(),
-            Backing::PureBuffer(ref b) => b.len(),
-            Backing::MMap(ref mmap) => mmap.len(),
-        }
-    }
-}
-
 #[derive(Debug)]
 struct Fragment {
     id: SString,
+    original_id: SString,   // The ID this fragment had when mounted or appended
+    generation: u64,        // The generation of its inos
+    header: Option<String>, // The header read from the file, if it differs from the one `id` and `name` would give
+    offsets: Option<(usize, usize, usize)>, // (start, end, line width) of the sequence in the FASTA file, if written
+    origin: Option<usize>, // The --merge file it is written back to, if not the mounted one
+    digest: Option<String>, // The digest of the current sequence, if already computed
+    sequence_len: Cell<Option<usize>>, // Its length, newlines excluded, if already computed
     name: Option<String>,
-    data: Backing,
+    data: Arc<dyn SequenceSource>,
+    codec: Codec, // How the ID is turned into the names of the files
     fasta_file: FragmentFile,
     seq_file: FragmentFile,
+    tags: BTreeMap<String, String>, // The key=value pairs set in tags/
+    edited: bool, // Whether its sequence has been edited since it was last written back
 }
 impl Fragment {
-    fn make_label(id: &str, name: &Option<String>) -> String {
-        format!(
-            ">{}{}\n",
-            id,
-            name.as_ref().map(|n| format!(" {}", n)).unwrap_or_default()
-        )
-    }
-
     fn make_virtual_file(
         ino: u64,
         name: &str,
@@ -191,7 +305,7 @@ impl Fragment {
                 ctime: modified,
                 crtime: modified,
                 kind: FileType::RegularFile,
-                perm: permissions,
+                perm: permissions & !*UMASK,
                 nlink: 0,
                 uid: unsafe { libc::geteuid() },
                 gid: unsafe { libc::getgid() },
@@ -205,164 +319,166 @@ impl Fragment {
     fn new(
         id: &str,
         name: &Option<String>,
-        data: Backing,
-        fasta_ino: u64,
-        seq_ino: u64,
+        data: Arc<dyn SequenceSource>,
+        (fasta_ino, seq_ino): (u64, u64),
+        codec: Codec,
         accessed: SystemTime,
         modified: SystemTime,
     ) -> Fragment {
-        let label = Fragment::make_label(id, name);
+        let label = make_label(id, name);
+        let stem = codec.encode(id);
         let data_size = data.len();
         Fragment {
             id: id.into(),
+            original_id: id.into(),
+            generation: 0,
+            header: None,
+            offsets: None,
+            origin: None,
+            digest: None,
+            sequence_len: Cell::new(None),
             name: name.clone(),
             data: data,
+            edited: false,
+            codec,
             fasta_file: Fragment::make_virtual_file(
                 fasta_ino,
-                &format!("{}{}", id, FASTA_EXT),
+                &format!("{}{}", stem, FASTA_EXT),
                 0o444,
                 label.as_bytes().len() + data_size,
-                FileClass::Fasta(RefCell::new(Vec::new())),
+                FileClass::Fasta,
                 accessed,
                 modified,
             ),
             seq_file: Fragment::make_virtual_file(
                 seq_ino,
-                &format!("{}{}", id, SEQ_EXT),
+                &format!("{}{}", stem, SEQ_EXT),
                 0o664,
                 data_size,
                 FileClass::Seq,
                 accessed,
                 modified,
             ),
+            tags: BTreeMap::new(),
         }
     }
 
     fn rename(&mut self, new_id: &str) {
         self.id = new_id.into();
+        self.header = None;
+        self.refresh_virtual_files();
+    }
+
+    /// Keep `header`, as read from a file, rather than the one regenerated
+    /// from the ID and description; e.g. to preserve tabs and repeated
+    /// spaces, or an ID that has been normalized
+    fn set_header(&mut self, header: &str) {
+        self.header = (format!(">{}\n", header) != make_label(&self.id, &self.name))
+            .then(|| header.to_owned());
         self.refresh_virtual_files();
     }
 
     fn refresh_virtual_files(&mut self) {
-        self.fasta_file.name = format!("{}{}", self.id, FASTA_EXT).into();
+        let stem = self.codec.encode(&self.id);
+        self.fasta_file.name = format!("{}{}", stem, FASTA_EXT).into();
         self.fasta_file.attrs.size = (self.label_size() + self.data_size()) as u64;
 
-        self.seq_file.name = format!("{}{}", self.id, SEQ_EXT).into();
+        self.seq_file.name = format!("{}{}", stem, SEQ_EXT).into();
         self.seq_file.attrs.size = self.data_size() as u64;
     }
 
+    /// Record that the sequence has been modified, as seen from both of its
+    /// files
+    fn touch_modified(&mut self, now: SystemTime) {
+        for attrs in [&mut self.fasta_file.attrs, &mut self.seq_file.attrs] {
+            attrs.mtime = now;
+            attrs.ctime = now;
+        }
+    }
+
     fn label_size(&self) -> usize {
         self.label().len()
     }
 
     fn data_size(&self) -> usize {
-        match &self.data {
-            Backing::File(_, start, end) => end - start,
-            Backing::Buffer(ref b) | Backing::PureBuffer(ref b) => b.len(),
-            Backing::MMap(ref mmap) => mmap.len(),
-        }
+        self.data.len()
     }
 
     fn label(&self) -> String {
-        Fragment::make_label(&self.id, &self.name)
-    }
-
-    fn data(&self) -> Box<[u8]> {
-        match &self.data {
-            Backing::File(filename, start, _) => {
-                let mut buffer = vec![0u8; self.data_size()];
-                let mut f = fs::File::open(filename.as_str())
-                    .unwrap_or_else(|_| panic!("Unable to open `{}`", filename));
-                f.seek(SeekFrom::Start(*start as u64))
-                    .unwrap_or_else(|_| panic!("Unable to seek in `{}`", filename));
-                f.read_exact(&mut buffer)
-                    .unwrap_or_else(|_| panic!("Unable to read from `{}`", filename));
-                buffer.into_boxed_slice()
-            }
-            Backing::Buffer(ref b) | Backing::PureBuffer(ref b) => b[..].into(),
-            Backing::MMap(ref mmap) => mmap[..].into(),
-        }
-    }
-
-    fn chunk(&self, offset: i64, size: u32) -> Box<[u8]> {
-        match &self.data {
-            Backing::File(filename, start, _) => {
-                let size = size as usize;
-                let offset = offset as usize;
-                let mut buffer = vec![0u8; size];
-                let mut f = fs::File::open(filename.as_str())
-                    .unwrap_or_else(|_| panic!("Unable to open `{}`", filename));
-                f.seek(SeekFrom::Start(*start as u64 + offset as u64))
-                    .unwrap_or_else(|_| panic!("Unable to seek in `{}`", filename));
-
-                f.read_exact(&mut buffer).unwrap_or_else(|e| {
-                    panic!(
-                        "Unable to read {}:{} from `{}` ({}): {}",
-                        start + offset,
-                        start + offset + size,
-                        filename,
-                        f.metadata().unwrap().len(),
-                        e
-                    )
-                });
-                buffer.into_boxed_slice()
-            }
-            Backing::Buffer(ref b) | Backing::PureBuffer(ref b) => {
-                let start = offset as usize;
-                let end = std::cmp::min(b.len() as i64, offset + size as i64) as usize;
-                b[start..end].into()
-            }
-            Backing::MMap(ref mmap) => {
-                let start = offset as usize;
-                let end = std::cmp::min(mmap.len() as i64, offset + size as i64) as usize;
-                mmap[start..end].into()
-            }
+        match &self.header {
+            Some(header) => format!(">{}\n", header),
+            None => make_label(&self.id, &self.name),
         }
     }
 
+    /// The sequence; as all the accessors of the sequence, this fails if
+    /// the FASTA file can not be read
+    fn data(&self) -> std::io::Result<Box<[u8]>> {
+        self.data.data()
+    }
+
+    fn chunk(&self, offset: usize, size: usize) -> std::io::Result<Box<[u8]>> {
+        self.data.chunk(offset, size)
+    }
+
     // The same as `chunk`, but skipping new lines.
-    fn pure_chunk(&self, offset: i64, size: u32) -> Box<[u8]> {
-        let offset = offset as usize;
-        let size = size as usize;
-        match &self.data {
-            Backing::File(filename, start, _) => {
-                let mut f = fs::File::open(filename.as_str())
-                    .unwrap_or_else(|_| panic!("Unable to open `{}`", filename));
-                f.seek(SeekFrom::Start(*start as u64 + offset as u64))
-                    .unwrap_or_else(|_| panic!("Unable to seek in `{}`", filename));
-                f.bytes()
-                    .filter_map(Result::ok)
-                    .filter(|&c| c != b'\n')
-                    .skip(offset)
-                    .take(size)
-                    .collect::<Vec<_>>()
-                    .into()
-            }
-            Backing::Buffer(ref b) => b
-                .iter()
-                .cloned()
-                .filter(|&c| c != b'\n')
-                .skip(offset)
-                .take(size)
-                .collect::<Vec<_>>()
-                .into(),
-            Backing::PureBuffer(ref b) => b[offset..offset + size].to_vec().into(),
-            Backing::MMap(ref mmap) => mmap
-                .iter()
-                .cloned()
-                .filter(|&c| c != b'\n')
-                .skip(offset)
-                .take(size)
-                .collect::<Vec<_>>()
-                .into(),
+    fn pure_chunk(&self, offset: usize, size: usize) -> std::io::Result<Box<[u8]>> {
+        self.data.pure_chunk(offset, size)
+    }
+
+    /// The length of the sequence, newlines excluded
+    fn sequence_len(&self) -> std::io::Result<usize> {
+        self.data.sequence_len()
+    }
+
+    /// The same as `sequence_len`, but kept until the sequence changes, for
+    /// the figures that should stay cheap; unlike the former, it does not
+    /// tell a sequence that became unreadable
+    fn cached_sequence_len(&self) -> std::io::Result<usize> {
+        if let Some(len) = self.sequence_len.get() {
+            return Ok(len);
         }
+        let len = self.data.sequence_len()?;
+        self.sequence_len.set(Some(len));
+        Ok(len)
     }
 
-    fn extend(&mut self, size: usize) {
-        match &mut self.data {
-            Backing::Buffer(ref mut b) => b.resize_with(size, Default::default),
-            _ => unreachable!(),
+    /// Forget what was derived from the sequence, once it changed
+    fn forget_derived(&mut self) {
+        self.digest = None;
+        self.sequence_len.set(None);
+    }
+
+    /// How a failure to read the sequence is reported to the clients
+    fn unreadable(&self, e: std::io::Error) -> FustaError {
+        FustaError::BackingIo(format!("unable to read the sequence of `{}`", self.id), e)
+    }
+
+    /// Move the sequence to memory, where it can be edited; it is copied
+    /// if a delayed read still holds it
+    fn make_editable(&mut self) -> Result<&mut Vec<u8>, FustaError> {
+        if Arc::get_mut(&mut self.data)
+            .and_then(|data| data.as_mut_buffer())
+            .is_none()
+        {
+            let data = self.data().map_err(|e| self.unreadable(e))?;
+            self.data = Arc::new(Buffer(data.into_vec()));
+        }
+        Ok(Arc::get_mut(&mut self.data)
+            .and_then(|data| data.as_mut_buffer())
+            .unwrap())
+    }
+
+    fn compute_digest(&self) -> std::io::Result<String> {
+        const CHUNK_SIZE: usize = 1 << 20;
+        let mut digest = SequenceDigest::default();
+        let mut offset = 0;
+        while offset < self.data_size() {
+            let size = std::cmp::min(CHUNK_SIZE, self.data_size() - offset);
+            digest.consume(&self.chunk(offset, size)?);
+            offset += size;
         }
+        Ok(digest.finalize())
     }
 
     fn file_from_filename(&self, name: &str) -> Option<&dyn VirtualFile> {
@@ -396,1298 +512,11168 @@ impl Fragment {
     }
 }
 
-#[derive(PartialEq, Debug)]
-pub enum Cache {
-    Mmap, // Store fragments as mmapped-memory
-    File, // ...or as filename:start-end pairs
-    RAM,  // Buffer all fragments in cache
-}
-pub struct FustaSettings {
-    pub cache: Cache,
-    pub concretize_threshold: usize, // How much leeway do we have in memory consumption (in B)
-    pub csv_separator: String,
-    pub no_overwrite: bool,
-}
-
-#[derive(Debug)]
-struct PendingAppend {
-    data: Vec<u8>,
-    attrs: FileAttr,
-    fasta_ino: u64,
-    seq_ino: u64,
+/// A row of infos.csv, and what it was rendered from
+struct InfoRow {
+    name: Option<String>,
+    length: usize,
+    line: Box<str>, // Starting with the newline ending the previous one
 }
 
-/// A Subfragment represents a portion of a fragment (chr:start-end)
-#[derive(Debug)]
-struct SubFragment {
-    /// The parent fragment
-    fragment: String,
-    /// Starting offset in the parent fragment
-    start: isize,
-    /// The attributes of this subfragment inode. NOTE: that the subfragment
-    /// size is encoded in those.
-    attrs: FileAttr,
+/// infos.csv, rendered row by row: the rows of the fragments left unchanged
+/// are kept as they are, and reads only copy the rows they overlap, so that
+/// huge mounts do not render the whole report on every change
+#[derive(Default)]
+struct InfoCsv {
+    header: String,
+    rows: HashMap<SString, InfoRow>, // By fragment ID
+    order: Vec<SString>,             // The IDs of the rows, in order
+    ends: Vec<usize>,                // Where every row ends in the file
+    stale: bool,                     // Whether the fragments changed since the last update
 }
-impl SubFragment {
-    fn new(fragment: &str, start: isize, attrs: FileAttr) -> SubFragment {
-        SubFragment {
-            fragment: fragment.to_owned(),
-            start,
-            attrs,
+impl InfoCsv {
+    fn new(sep: &str) -> InfoCsv {
+        InfoCsv {
+            header: format!("id{}name{}length", sep, sep),
+            stale: true,
+            ..Default::default()
         }
     }
-}
-lazy_static! {
-    static ref SUBFRAGMENT_RE: Regex = Regex::new(r"(.+):(\d+)-(\d+)").unwrap();
-}
 
-pub struct FustaFS {
-    fragments: Vec<Fragment>,
-    name2fragment: HashMap<String, usize>,
-    ino2fragment: HashMap<u64, usize>,
+    fn len(&self) -> usize {
+        self.ends.last().copied().unwrap_or(self.header.len())
+    }
 
-    metadata: fs::Metadata,
-    dir_attrs: BTreeMap<u64, FileAttr>,
-    files: Vec<Box<dyn VirtualFile + Send>>,
-    filename: String,
-    settings: FustaSettings,
-    current_ino: u64,
+    /// Render the rows of the fragments that changed since the last update
+    fn update(&mut self, fragments: &[Fragment], sep: &str) {
+        let mut rows = HashMap::with_capacity(fragments.len());
+        let mut end = self.header.len();
+        self.order.clear();
+        self.ends.clear();
+        for f in fragments {
+            let row = match self.rows.remove(&f.id) {
+                Some(row) if row.name == f.name && row.length == f.data_size() => row,
+                _ => InfoRow {
+                    name: f.name.clone(),
+                    length: f.data_size(),
+                    line: format!(
+                        "\n{}{}\"{}\"{}{}",
+                        f.id,
+                        sep,
+                        f.name.as_deref().unwrap_or_default(),
+                        sep,
+                        f.data_size()
+                    )
+                    .into(),
+                },
+            };
+            end += row.line.len();
+            self.ends.push(end);
+            self.order.push(f.id.clone());
+            rows.insert(f.id.clone(), row);
+        }
+        self.rows = rows;
+        self.stale = false;
+    }
 
-    pending_appends: BTreeMap<String, PendingAppend>,
+    /// The bytes of the file in `range`
+    fn read(&self, range: std::ops::Range<usize>) -> Vec<u8> {
+        let mut r = Vec::with_capacity(range.len());
+        let header = self.header.as_bytes();
+        if range.start < header.len() {
+            r.extend_from_slice(&header[range.start..range.end.min(header.len())]);
+        }
+        let first = self.ends.partition_point(|&end| end <= range.start);
+        for i in first..self.order.len() {
+            let start = if i == 0 {
+                header.len()
+            } else {
+                self.ends[i - 1]
+            };
+            if start >= range.end {
+                break;
+            }
+            let line = self.rows[&self.order[i]].line.as_bytes();
+            r.extend_from_slice(
+                &line[range.start.saturating_sub(start)..line.len().min(range.end - start)],
+            );
+        }
+        r
+    }
+}
 
-    subfragments: MultiMap<String, u64, SubFragment>, // name -> inode -> SubFragment
+/// Where the workers computing stats.csv and duplicates.txt read a sequence
+/// from
+enum StatsSource {
+    File(usize, usize), // A range of the FASTA file
+    Memory(Box<[u8]>),
+}
 
-    dirty: bool,
+impl StatsSource {
+    /// Feed the sequence to `consume`, chunk by chunk
+    fn read(&self, file: &Backing, mut consume: impl FnMut(&[u8])) -> std::io::Result<()> {
+        const CHUNK_SIZE: usize = 1 << 20;
+        match self {
+            StatsSource::File(start, end) => {
+                let mut offset = *start;
+                while offset < *end {
+                    let mut chunk = vec![0; std::cmp::min(CHUNK_SIZE, end - offset)];
+                    file.read_exact_at(&mut chunk, offset as u64)?;
+                    consume(&chunk);
+                    offset += chunk.len();
+                }
+            }
+            StatsSource::Memory(data) => data.chunks(CHUNK_SIZE).for_each(consume),
+        }
+        Ok(())
+    }
 }
 
-impl FustaFS {
-    pub fn new(settings: FustaSettings, filename: &str) -> Result<FustaFS> {
-        let metadata = fs::metadata(filename).context(format!("while opening `{}`", filename))?;
-        let mut r = FustaFS {
-            fragments: Vec::new(),
-            name2fragment: HashMap::new(),
-            ino2fragment: HashMap::new(),
+/// The content of stats.csv: the length, composition, number of gaps (i.e.
+/// runs of N) and digest of every sequence
+fn compute_stats(
+    file: &Backing,
+    sources: Vec<(String, Option<String>, StatsSource)>,
+    sep: &str,
+) -> std::io::Result<String> {
+    let mut r = format!(
+        "id{}length{}gc_content{}n_content{}gaps{}md5\n",
+        sep, sep, sep, sep, sep
+    );
+    for (id, known_digest, source) in sources {
+        let mut composition = Composition::default();
+        let mut digest = SequenceDigest::default();
+        let mut gaps = 0;
+        let mut in_gap = false;
+        source.read(file, |chunk: &[u8]| {
+            composition.consume(chunk);
+            if known_digest.is_none() {
+                digest.consume(chunk);
+            }
+            for c in chunk.iter().filter(|c| !c.is_ascii_whitespace()) {
+                let is_n = c.eq_ignore_ascii_case(&b'N');
+                if is_n && !in_gap {
+                    gaps += 1;
+                }
+                in_gap = is_n;
+            }
+        })?;
+        r.push_str(&format!(
+            "{}{}{}{}{:.4}{}{:.4}{}{}{}{}\n",
+            id,
+            sep,
+            composition.length,
+            sep,
+            composition.gc_content(),
+            sep,
+            composition.n_content(),
+            sep,
+            gaps,
+            sep,
+            known_digest.unwrap_or_else(|| digest.finalize())
+        ));
+    }
+    Ok(r)
+}
 
-            filename: String::new(),
-            dir_attrs: btreemap! {
-                // Virtual folders
-                ROOT_DIR         => FustaFS::make_dir_attrs(ROOT_DIR, 0o775),
-                SEQ_DIR          => FustaFS::make_dir_attrs(SEQ_DIR, 0o775),
-                FASTA_DIR        => FustaFS::make_dir_attrs(FASTA_DIR, 0o555),
-                APPEND_DIR       => FustaFS::make_dir_attrs(APPEND_DIR, 0o775),
-                SUBFRAGMENTS_DIR => FustaFS::make_dir_attrs(SUBFRAGMENTS_DIR, 0o555),
-            },
-            files: vec![
-                Box::new(BufferFile {
-                    name: INFO_FILE_NAME.into(),
-                    ino: INFO_FILE,
-                    attrs: FustaFS::make_file_attrs(INFO_FILE, 0o444),
-                    class: FileClass::Text,
-                    _data: Vec::new(),
-                }),
-                Box::new(BufferFile {
-                    name: INFO_CSV_FILE_NAME.into(),
-                    ino: INFO_CSV_FILE,
-                    attrs: FustaFS::make_file_attrs(INFO_CSV_FILE, 0o444),
-                    class: FileClass::Text,
-                    _data: Vec::new(),
-                }),
-                Box::new(BufferFile {
-                    name: LABELS_FILE_NAME.into(),
-                    ino: LABELS_FILE,
-                    attrs: FustaFS::make_file_attrs(LABELS_FILE, 0o444),
-                    class: FileClass::Text,
-                    _data: Vec::new(),
-                }),
-            ],
-            metadata,
-            settings,
-            current_ino: FIRST_INO,
-            pending_appends: Default::default(),
-            subfragments: Default::default(),
-            dirty: false,
+/// Suffix the IDs of the `records` of `filename` colliding with a previous
+/// one once normalized with `__2`, `__3`, …, so that both stay reachable;
+/// all the records of a collision are reported in `collisions`
+fn suffix_collisions(filename: &str, records: Records, collisions: &mut Vec<String>) -> Records {
+    let mut counts = HashMap::<String, usize>::new();
+    for (id, _) in records.iter() {
+        *counts.entry(id.clone()).or_default() += 1;
+    }
+    let mut taken = counts.keys().cloned().collect::<HashSet<_>>();
+    let mut seen = HashSet::new();
+    let mut r = Vec::with_capacity(records.len());
+    for (id, record) in records {
+        if counts[&id] == 1 {
+            r.push((id, record));
+            continue;
+        }
+        let mounted = if seen.insert(id.clone()) {
+            id.clone()
+        } else {
+            let renamed = (2..)
+                .map(|n| format!("{}__{}", id, n))
+                .find(|id| !taken.contains(id))
+                .unwrap();
+            taken.insert(renamed.clone());
+            warn!(
+                "`{}` from `{}` collides with a previous sequence; mounted as `{}`",
+                record.header, filename, renamed
+            );
+            renamed
         };
-
-        r.read_fasta(filename)
-            .context(format!("while parsing {}", filename))?;
-        Ok(r)
+        collisions.push(format!(
+            "{}\t{}\t{}\t{}",
+            mounted, id, filename, record.header
+        ));
+        r.push((mounted, record));
     }
+    r
+}
 
-    fn make_dir_attrs(ino: u64, perms: u16) -> FileAttr {
-        FileAttr {
-            ino,
-            size: 0,
-            blocks: 0,
-            atime: std::time::SystemTime::now(),
-            mtime: std::time::SystemTime::now(),
-            ctime: std::time::SystemTime::now(),
-            crtime: std::time::SystemTime::now(),
-            kind: FileType::Directory,
-            perm: perms,
-            nlink: 1,
-            uid: unsafe { libc::geteuid() },
-            gid: unsafe { libc::getgid() },
-            rdev: 0,
-            flags: 0,
-            blksize: 0,
+/// The content of duplicates.txt: the groups of fragments sharing the same
+/// sequence, either exactly or only once case-folded. The sequences are first
+/// grouped by their case-insensitive digest, reusing the `digests` already
+/// computed, then only the candidates are compared exactly.
+fn compute_duplicates(
+    file: &Backing,
+    sources: Vec<(String, Option<String>, StatsSource)>,
+) -> std::io::Result<String> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut by_digest: HashMap<String, usize> = HashMap::new();
+    for (i, (_, digest, source)) in sources.iter().enumerate() {
+        let digest = match digest {
+            Some(digest) => digest.clone(),
+            None => {
+                let mut digest = SequenceDigest::default();
+                source.read(file, |chunk| digest.consume(chunk))?;
+                digest.finalize()
+            }
+        };
+        match by_digest.get(&digest) {
+            Some(&group) => groups[group].push(i),
+            None => {
+                by_digest.insert(digest, groups.len());
+                groups.push(vec![i]);
+            }
         }
     }
 
-    fn make_file_attrs(ino: u64, perms: u16) -> FileAttr {
-        FileAttr {
-            ino,
-            size: 0,
-            blocks: 0,
-            atime: std::time::SystemTime::now(),
-            mtime: std::time::SystemTime::now(),
-            ctime: std::time::SystemTime::now(),
-            crtime: std::time::SystemTime::now(),
-            kind: FileType::RegularFile,
-            perm: perms,
-            nlink: 1,
-            uid: unsafe { libc::geteuid() },
-            gid: unsafe { libc::getgid() },
-            rdev: 0,
-            flags: 0,
-            blksize: 512,
+    let mut r = String::new();
+    for members in groups.iter().filter(|g| g.len() > 1) {
+        let mut exact: Vec<(md5::Digest, Vec<usize>)> = Vec::new();
+        for &i in members {
+            let mut context = md5::Context::new();
+            sources[i].2.read(file, |chunk| {
+                context.consume(
+                    chunk
+                        .iter()
+                        .filter(|c| !c.is_ascii_whitespace())
+                        .copied()
+                        .collect::<Vec<_>>(),
+                )
+            })?;
+            let digest = context.compute();
+            match exact.iter_mut().find(|e| e.0 == digest) {
+                Some(e) => e.1.push(i),
+                None => exact.push((digest, vec![i])),
+            }
+        }
+        let ids = |members: &[usize]| {
+            members
+                .iter()
+                .map(|&i| sources[i].0.as_str())
+                .collect::<Vec<_>>()
+                .join("\t")
+        };
+        for (_, members) in exact.iter().filter(|e| e.1.len() > 1) {
+            r.push_str(&format!("identical\t{}\n", ids(members)));
+        }
+        if exact.len() > 1 {
+            r.push_str(&format!("case-insensitive\t{}\n", ids(members)));
         }
     }
+    Ok(r)
+}
 
-    fn make_file_attrs_with_size(ino: u64, perms: u16, size: u64) -> FileAttr {
-        let mut attrs = Self::make_file_attrs(ino, perms);
-        attrs.size = size;
-        attrs
+/// The content of repeats.txt: for every fragment, the telomeric motif
+/// found at each of its ends, if any, and the fraction of its sequence lying
+/// in simple repeats
+fn compute_repeats(
+    file: &Backing,
+    sources: Vec<(String, StatsSource)>,
+    motifs: &TelomereMotifs,
+) -> std::io::Result<String> {
+    let mut r = "id\tlength\thead\ttail\tsimple_repeats\n".to_string();
+    for (id, source) in sources {
+        // Only the ends of the sequence are kept, for the telomeres
+        let mut head = Vec::with_capacity(TELOMERE_WINDOW);
+        let mut tail = VecDeque::with_capacity(TELOMERE_WINDOW);
+        let mut repeats = SimpleRepeats::default();
+        let mut len = 0;
+        source.read(file, |chunk| {
+            for &c in chunk.iter().filter(|c| !c.is_ascii_whitespace()) {
+                if head.len() < TELOMERE_WINDOW {
+                    head.push(c);
+                }
+                if tail.len() == TELOMERE_WINDOW {
+                    tail.pop_front();
+                }
+                tail.push_back(c);
+                repeats.push(c);
+                len += 1;
+            }
+        })?;
+        let telomere = |end: &[u8]| {
+            motifs
+                .find(end)
+                .map(|(motif, density)| format!("{}:{:.2}", motif, density))
+                .unwrap_or_else(|| "-".into())
+        };
+        r.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{:.4}\n",
+            id,
+            len,
+            telomere(&head),
+            telomere(tail.make_contiguous()),
+            if len == 0 {
+                0.
+            } else {
+                repeats.finish() as f64 / len as f64
+            }
+        ));
     }
+    Ok(r)
+}
 
-    fn new_ino(&mut self) -> u64 {
-        let r = self.current_ino;
-        self.current_ino += 1;
-        r
+/// How the lines of a sequence are laid out, as fed chunk by chunk
+#[derive(Default)]
+struct LineLayout {
+    widths: BTreeMap<usize, usize>, // Width, CR excluded -> how many lines have it
+    last: Option<usize>,            // The width of the last line
+    current: usize,                 // The width of the line being read
+    terminated: usize,              // How many lines end with a newline
+    crlf: usize,                    // ...and how many with a CRLF
+    trailing_blanks: usize,         // How many lines end with spaces or tabs
+    cr: bool,                       // Whether the last byte seen is a CR
+    blank: bool,                    // Whether the last byte seen, CR excluded, is a blank
+}
+impl LineLayout {
+    fn consume(&mut self, chunk: &[u8]) {
+        for &c in chunk {
+            if c == b'\n' {
+                self.end_line(true);
+                continue;
+            }
+            self.current += 1;
+            self.cr = c == b'\r';
+            if !self.cr {
+                self.blank = c == b' ' || c == b'\t';
+            }
+        }
     }
 
-    fn get_file(&mut self, ino: u64) -> Option<&mut Box<dyn VirtualFile + Send>> {
-        self.files.iter_mut().find(|f| f.ino() == ino)
+    fn end_line(&mut self, terminated: bool) {
+        let width = self.current - usize::from(self.cr);
+        *self.widths.entry(width).or_default() += 1;
+        self.last = Some(width);
+        self.terminated += usize::from(terminated);
+        self.crlf += usize::from(terminated && self.cr);
+        self.trailing_blanks += usize::from(self.blank);
+        self.current = 0;
+        self.cr = false;
+        self.blank = false;
     }
 
-    fn read_fasta(&mut self, filename: &str) -> Result<()> {
-        info!("Reading {}...", filename);
-        let fasta_file =
-            fs::File::open(filename).context(format!("failed to open file `{}`", filename))?;
-        let fragments =
-            FastaReader::new(fasta_file, self.settings.cache == Cache::RAM).collect::<Vec<_>>();
-        let mut keys = fragments.iter().map(|f| &f.id).collect::<Vec<_>>();
-        keys.sort();
-        keys.dedup();
-        if keys.len() != fragments.len() {
-            anyhow::bail!("`{}` contains duplicated keys", filename)
-        }
-
-        let file = fs::File::open(filename).context(format!("Failed to open `{}`", filename))?;
-        self.filename = filename.to_owned();
+    fn finish(&mut self) {
+        if self.current > 0 {
+            self.end_line(false);
+        }
+    }
 
-        self.fragments = fragments
-            .into_iter()
-            .map(|fragment| {
-                if fragment.id.chars().any(|c| FORBIDDEN_CHARS.contains(&c)) {
-                    Err(anyhow::anyhow!(format!(
-                        "Fragment ID `{}` contains a forbidden character",
-                        fragment.id
-                    )))
-                } else {
-                    Ok(Fragment::new(
-                        &fragment.id,
-                        &fragment.name,
-                        match self.settings.cache {
-                            Cache::Mmap => Backing::MMap(unsafe {
-                                memmap2::MmapOptions::new()
-                                    .offset(fragment.pos.0 as u64)
-                                    .len(fragment.len)
-                                    .map(&file)
-                                    .unwrap()
-                            }),
-                            Cache::File => {
-                                Backing::File(filename.into(), fragment.pos.0, fragment.pos.1)
-                            }
-                            Cache::RAM => Backing::PureBuffer(fragment.seq.unwrap()),
-                        },
-                        self.new_ino(),
-                        self.new_ino(),
-                        self.metadata.accessed().unwrap(),
-                        self.metadata.modified().unwrap(),
-                    ))
-                }
-            })
-            .collect::<Result<Vec<_>>>()?;
-        self.refresh_metadata(true);
-        info!("Done.");
-        Ok(())
-    }
-
-    fn concretize(&mut self, force: bool) {
-        if !self.dirty {
-            debug!("CONCRETIZE: nothing to do");
-            return;
+    /// Whether all the lines but the last one have the same, non-zero,
+    /// width, the last one being no wider, and end the same way, as expected
+    /// by samtools faidx
+    fn is_strict(&self) -> bool {
+        let last = match self.last {
+            Some(last) => last,
+            None => return true,
+        };
+        let mut others = self.widths.clone();
+        let count = others.get_mut(&last).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            others.remove(&last);
         }
+        let uniform = match others.keys().collect::<Vec<_>>()[..] {
+            [] => last > 0,
+            [&width] => width > 0 && last > 0 && last <= width,
+            _ => false,
+        };
+        uniform && self.trailing_blanks == 0 && (self.crlf == 0 || self.crlf == self.terminated)
+    }
 
-        let in_memory = self.fragments.iter().fold(0, |ax, f| {
-            ax + match &f.data {
-                Backing::Buffer(b) => b.len(),
-                _ => 0,
-            }
-        });
-
-        // We only concretize if the call is not forced and
-        // 1. the allowed cache is not yet used
-        // or
-        // 2. the user wants to cache everything anyway
-        if !force
-            && (in_memory < self.settings.concretize_threshold
-                || !(self.settings.cache == Cache::RAM))
-        {
-            trace!(
-                "Using {:.2}MB out of {}; skipping concretization",
-                in_memory / (1024 * 1024),
-                self.settings.concretize_threshold / (1024 * 1024)
-            );
-            return;
+    /// The length of the sequence, and how many bases and bytes its lines
+    /// hold, as written in a .fai index, if strict
+    fn fai(&self) -> Option<(usize, usize, usize)> {
+        if !self.is_strict() {
+            return None;
         }
+        let length = self.widths.iter().map(|(w, n)| w * n).sum::<usize>();
+        let bases = self.widths.keys().next_back().copied().unwrap_or(0);
+        let width = if bases == 0 {
+            0
+        } else {
+            bases + 1 + usize::from(self.crlf > 0)
+        };
+        Some((length, bases, width))
+    }
+}
 
-        trace!("========== CONCRETIZING ========");
-        notify(format!("Updating {}", &self.filename));
-        let mut index = 0;
-        let mut last_start;
-        trace!("Writing fragments");
-        let tmp_filename = format!("{}#fusta#", &self.filename);
-        {
-            // Scope to ensure the tmp file is correctly closed
-            let mut tmp_file = fs::File::create(&tmp_filename)
-                .unwrap_or_else(|_| panic!("Unable to create `{}`", tmp_filename));
-            for fragment in self.fragments.iter_mut() {
-                trace!("Writing {}", fragment.id);
-                tmp_file
-                    .write_all(fragment.label().as_bytes())
-                    .unwrap_or_else(|_| panic!("Unable to write to `{}`", tmp_filename));
-                index += fragment.label().as_bytes().len();
-                last_start = index;
-                tmp_file
-                    .write_all(&fragment.data())
-                    .unwrap_or_else(|_| panic!("Unable to write to `{}`", tmp_filename));
-                index += fragment.data().len();
-                if let Some(c) = fragment.data().last() {
-                    if *c != b'\n' {
-                        tmp_file
-                            .write_all(&[b'\n'])
-                            .unwrap_or_else(|_| panic!("Unable to write to `{}`", tmp_filename));
-                        index += 1;
-                    }
-                }
+/// The content of format.txt: the line widths of every fragment, as
+/// `WIDTH:COUNT`, whether they end with CRLF or blanks, and whether they are
+/// uniformly wrapped, as samtools faidx requires
+fn compute_format(file: &Backing, sources: Vec<(String, StatsSource)>) -> std::io::Result<String> {
+    let mut table = String::new();
+    let mut loose = 0;
+    for (id, source) in sources {
+        let mut layout = LineLayout::default();
+        source.read(file, |chunk| layout.consume(chunk))?;
+        layout.finish();
+        let mut widths = layout.widths.iter().collect::<Vec<_>>();
+        widths.sort_by_key(|(width, count)| (std::cmp::Reverse(**count), **width));
+        let strict = layout.is_strict();
+        loose += usize::from(!strict);
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        table.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            id,
+            widths
+                .iter()
+                .map(|(width, count)| format!("{}:{}", width, count))
+                .collect::<Vec<_>>()
+                .join(","),
+            yes_no(layout.crlf > 0),
+            yes_no(layout.trailing_blanks > 0),
+            yes_no(strict),
+        ));
+    }
+    let verdict = if loose == 0 {
+        "# strict: yes\n".to_string()
+    } else {
+        format!(
+            "# strict: no, {} fragments are not uniformly wrapped\n",
+            loose
+        )
+    };
+    Ok(format!(
+        "{}id\tline_widths\tcrlf\ttrailing_blanks\tstrict\n{}",
+        verdict, table
+    ))
+}
 
-                fragment.data = Backing::File(self.filename.clone().into(), last_start, index);
-                fragment.refresh_virtual_files();
+/// What becomes of a fragment at a write-back, since the previous one
+struct Change {
+    kinds: Vec<&'static str>, // added, deleted, renamed and/or edited
+    id: String,
+    previous_id: Option<String>,
+    previous_length: Option<usize>,
+    length: Option<usize>,
+}
+impl Change {
+    /// The share of its length the fragment loses, if edited
+    fn shrinkage(&self) -> f64 {
+        match (self.previous_length, self.length) {
+            (Some(before), Some(after)) if before > after => {
+                (before - after) as f64 / before as f64
             }
+            _ => 0.,
         }
-        trace!("Renaming {} to {}", tmp_filename, &self.filename);
-        fs::rename(&tmp_filename, &self.filename).unwrap_or_else(|_| {
-            panic!(
-                "Unable to rename `{}` to `{}`",
-                &tmp_filename, &self.filename
-            )
-        });
-        trace!("========== DONE ========");
-        notify(format!("{} has been updated", &self.filename));
-        self.dirty = false;
     }
+}
 
-    fn fragment_from_id(&self, id: &str) -> Option<&Fragment> {
-        self.name2fragment.get(id).map(|&i| &self.fragments[i])
+/// How many sequences may be memory-mapped: half of the mappings the kernel
+/// allows a process (vm.max_map_count), the rest being left to the allocator
+/// and the libraries
+fn mapping_budget() -> usize {
+    fs::read_to_string("/proc/sys/vm/max_map_count")
+        .ok()
+        .and_then(|n| n.trim().parse::<usize>().ok())
+        .map_or(usize::MAX, |n| n / 2)
+}
+
+/// How many bases `chunk` holds, line endings excluded
+fn count_bases(chunk: &[u8]) -> usize {
+    chunk.iter().filter(|c| !c.is_ascii_whitespace()).count()
+}
+
+/// The content of commit-report.txt: a first line telling what became of
+/// the `changes`, then one line per changed fragment
+fn render_changes(summary: &str, changes: &[Change]) -> String {
+    let number = |x: Option<usize>| x.map(|x| x.to_string()).unwrap_or_default();
+    let mut r = format!(
+        "# {}\nchange\tid\tprevious_id\tprevious_length\tlength\tdelta\n",
+        summary
+    );
+    for c in changes {
+        let delta = match (c.previous_length, c.length) {
+            (Some(before), Some(after)) => format!("{:+}", after as i64 - before as i64),
+            _ => String::new(),
+        };
+        r.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\n",
+            c.kinds.join(","),
+            c.id,
+            c.previous_id.as_deref().unwrap_or_default(),
+            number(c.previous_length),
+            number(c.length),
+            delta
+        ));
     }
+    r
+}
 
-    fn fragment_from_ino(&self, ino: u64) -> Option<&Fragment> {
-        self.ino2fragment
-            .get(&ino)
-            .and_then(|&i| self.fragments.get(i))
+/// The content of index.fai: the samtools index of the sequences lying in
+/// `file` between the given offsets, named as in their headers
+fn compute_fai(file: &Backing, sources: Vec<(usize, usize)>) -> std::io::Result<String> {
+    let mut r = String::new();
+    for (start, end) in sources {
+        let (_, header) = fai::header_before(start as u64, &|buffer, offset| {
+            file.read_exact_at(buffer, offset)
+        })
+        .map_err(|e| std::io::Error::other(format!("{:#} at byte {}", e, start)))?;
+        let (id, _) = split_header(&header);
+        let mut layout = LineLayout::default();
+        StatsSource::File(start, end).read(file, |chunk| layout.consume(chunk))?;
+        layout.finish();
+        let (length, bases, width) = layout.fai().ok_or_else(|| {
+            std::io::Error::other(format!(
+                "`{}` is not uniformly wrapped, as samtools faidx requires; see {}",
+                id, FORMAT_FILE_NAME
+            ))
+        })?;
+        r.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            id, length, start, bases, width
+        ));
     }
+    Ok(r)
+}
 
-    fn mut_fragment_from_ino(&mut self, ino: u64) -> Option<&mut Fragment> {
-        if let Some(i) = self.ino2fragment.get(&ino) {
-            self.fragments.get_mut(*i)
-        } else {
-            None
+/// The content of codon_usage.csv: how often every codon appears in the
+/// coding sequences of the `transcripts` lying on each fragment, and among
+/// the codons of the same amino acid. Transcripts that do not fit in their
+/// fragment, e.g. after it was shortened, are left out.
+fn compute_codon_usage(
+    file: &Backing,
+    sources: Vec<(StatsSource, Vec<Transcript>)>,
+    code: GeneticCode,
+    sep: &str,
+) -> std::io::Result<String> {
+    let mut counts = [0usize; 64];
+    for (source, transcripts) in sources {
+        let mut seq = Vec::new();
+        source.read(file, |chunk| {
+            seq.extend(chunk.iter().filter(|c| !c.is_ascii_whitespace()))
+        })?;
+        for transcript in transcripts {
+            if transcript.cds.iter().any(|&(_, end)| end > seq.len()) {
+                continue;
+            }
+            let mut cds = Vec::with_capacity(transcript.len());
+            for &(start, end) in transcript.cds.iter() {
+                cds.extend_from_slice(&seq[start - 1..end]);
+            }
+            if transcript.strand == Strand::Reverse {
+                reverse_complement(&mut cds);
+            }
+            for codon in cds[transcript.phase.min(cds.len())..].chunks_exact(3) {
+                if let Some(i) = GeneticCode::index(codon) {
+                    counts[i] += 1;
+                }
+            }
         }
     }
 
-    fn fragment_from_fasta_filename(&self, name: &str) -> Option<&Fragment> {
-        name.strip_suffix(FASTA_EXT)
-            .and_then(|id| self.name2fragment.get(id))
-            .and_then(|i| self.fragments.get(*i))
+    let total = counts.iter().sum::<usize>();
+    let mut synonymous = HashMap::<u8, usize>::new();
+    for (i, count) in counts.iter().enumerate() {
+        *synonymous.entry(code.amino_acid(i)).or_default() += count;
     }
-
-    fn fragment_from_seq_filename(&self, name: &str) -> Option<&Fragment> {
-        name.strip_suffix(SEQ_EXT)
-            .and_then(|id| self.name2fragment.get(id))
-            .and_then(|i| self.fragments.get(*i))
+    let mut r = format!(
+        "codon{}amino_acid{}count{}per_thousand{}fraction\n",
+        sep, sep, sep, sep
+    );
+    for (i, &count) in counts.iter().enumerate() {
+        let ratio = |total: usize| {
+            if total > 0 {
+                count as f64 / total as f64
+            } else {
+                0.
+            }
+        };
+        r.push_str(&format!(
+            "{}{}{}{}{}{}{:.2}{}{:.4}\n",
+            String::from_utf8_lossy(&GeneticCode::codon(i)),
+            sep,
+            code.amino_acid(i) as char,
+            sep,
+            count,
+            sep,
+            1000. * ratio(total),
+            sep,
+            ratio(synonymous[&code.amino_acid(i)])
+        ));
     }
+    Ok(r)
+}
 
-    fn mut_fragment_from_seq_filename(&mut self, name: &str) -> Option<&mut Fragment> {
-        let id = name.strip_suffix(SEQ_EXT).unwrap();
-        if let Some(i) = self.name2fragment.get(id) {
-            Some(&mut self.fragments[*i])
-        } else {
-            None
+/// The content of a file of digest/ or pcr/, scanning every fragment
+fn compute_scan(
+    file: &Backing,
+    sources: Vec<(String, StatsSource)>,
+    scan: &Scan,
+    max_amplicon: usize,
+) -> std::io::Result<String> {
+    let mut r = String::new();
+    for (id, source) in sources {
+        let mut seq = Vec::new();
+        source.read(file, |chunk| {
+            seq.extend(chunk.iter().filter(|c| !c.is_ascii_whitespace()))
+        })?;
+        match scan {
+            Scan::Sites(enzyme) => {
+                for site in enzyme.sites(&seq) {
+                    r.push_str(&format!(
+                        "{}\t{}\t{}\t{}\t0\t{}\n",
+                        id,
+                        site.start,
+                        site.end,
+                        enzyme.name,
+                        if site.reverse { '-' } else { '+' }
+                    ));
+                }
+            }
+            Scan::Pieces(enzyme) => {
+                for length in Enzyme::pieces(&enzyme.sites(&seq), seq.len()) {
+                    r.push_str(&format!("{}\t{}\n", id, length));
+                }
+            }
+            Scan::Amplicons(primers) => {
+                for amplicon in primers.amplicons(&seq, max_amplicon) {
+                    let mut product = seq[amplicon.start..amplicon.end].to_vec();
+                    if amplicon.reverse {
+                        reverse_complement(&mut product);
+                    }
+                    r.push_str(&format!(
+                        ">{}:{}-{}:{} {}bp\n",
+                        id,
+                        amplicon.start + 1,
+                        amplicon.end,
+                        if amplicon.reverse { '-' } else { '+' },
+                        amplicon.len()
+                    ));
+                    for line in product.chunks(LINE_WIDTH) {
+                        r.push_str(&String::from_utf8_lossy(line));
+                        r.push('\n');
+                    }
+                }
+            }
         }
     }
+    Ok(r)
+}
 
-    fn mut_fragment_from_fasta_filename(&mut self, name: &str) -> Option<&mut Fragment> {
-        let id = name.strip_suffix(FASTA_EXT).unwrap();
-        if let Some(i) = self.name2fragment.get(id) {
-            Some(&mut self.fragments[*i])
-        } else {
-            None
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Cache {
+    Mmap, // Store fragments as mmapped-memory
+    File, // ...or as filename:start-end pairs
+    RAM,  // Buffer all fragments in cache
+}
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Collision {
+    Skip,      // Keep the existing fragment and drop the appended one
+    Overwrite, // Replace the existing fragment with the appended one
+    Suffix,    // Keep both, suffixing the ID of the appended one
+}
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum MergeCollision {
+    Refuse, // Do not mount files sharing IDs
+    Suffix, // Keep both, suffixing the ID of the latter one
+    Prefix, // Keep both, prefixing the ID of the latter one with the stem of its file
+}
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Prefetch {
+    None,  // Only read the sequences when they are accessed
+    Index, // Compute the statistics of all the sequences once mounted
+    All,   // ...and first read the whole file, to warm the page cache
+}
+#[derive(Clone, PartialEq, Debug)]
+pub enum Verify {
+    Off,
+    AgainstSession,      // Check the digests recorded in the previous session
+    AgainstFile(String), // Check the digests listed in a `DIGEST ID` file
+}
+#[derive(Clone)]
+pub struct FustaSettings {
+    pub cache: Cache,
+    pub concretize_threshold: usize, // How much leeway do we have in memory consumption (in B)
+    pub memory_budget: usize, // How much of the sequences --cache memory keeps in memory, the rest being spilled to disk (in B)
+    pub csv_separator: String,
+    pub no_overwrite: bool,
+    pub on_append_collision: Collision,
+    pub on_edit_conflict: Collision, // What becomes of an appended fragment that would overwrite unsaved edits
+    pub verify: Verify,
+    pub refuse_mismatches: bool, // Whether a failed verification should prevent mounting
+    pub writers: Writers,
+    pub append_id_template: String, // How to name sequences appended without a header
+    pub writable_fasta: bool,       // Whether the files in fasta/ can be edited
+    pub defer_deletions: bool,      // Whether deleting fragments waits for the next write-back
+    pub headers_only: bool, // Whether only the headers and the sizes of the sequences may be read
+    pub noatime: bool,      // Whether reading the sequences leaves their access times untouched
+    pub no_index: bool,     // Whether FASTA.fusta-idx is left alone after scanning or writing back
+    pub catalog: bool,      // Whether to keep the records and states in an SQLite catalog
+    pub seed: u64,          // What the randomized features draw from, for reproducible runs
+    pub chaos: Chaos,       // Faults to inject in the backing storage
+    pub retry: Retry,       // How the failed reads of the backing storage are retried
+    pub id_rules: IdRules,  // How to derive file names from the IDs in the headers
+    pub codec: Codec,       // How to encode the IDs into file names
+    pub allow_empty: bool,  // Whether the file may be written back whatever its shrinkage
+    pub max_loss: f64, // The share of its size the file may lose before write-backs need a confirmation
+    pub max_shrink: Option<f64>, // The share of its length an edited sequence may lose before write-backs need a confirmation
+    pub notifications: Thresholds, // How large an operation should be to be notified right away
+    pub prefetch: Prefetch,      // What to read in the background once mounted
+    pub undo_budget: usize, // How much memory the previous states of the edited fragments may use (in B)
+    pub annotation: Option<String>, // The GFF3 file annotating the sequences
+    pub genetic_code: GeneticCode, // How to translate the CDS of the annotation
+    pub max_amplicon: usize, // The longest product reported in pcr/ (in bp)
+    pub telomere_motifs: TelomereMotifs, // What repeats.txt looks for at the ends of the fragments
+    pub end_length: usize,  // How many bases the files of ends/ expose
+    pub taxa_delimiter: String, // What separates the ranks of the taxonomies grouped in taxa/
+    pub write_filter: Option<TagFilter>, // Which fragments are written back, by their tags
+    pub output: Option<String>, // Where to write the fragments back, leaving the mounted file untouched
+    pub merge: Vec<String>,     // The other FASTA files whose fragments are mounted alongside
+    pub on_merge_collision: MergeCollision,
+    pub aliases: Option<Aliases>, // The other names of the fragments, resolved in lookups and regions
+    pub views: Vec<(String, Pipeline)>, // The directories exposing the fragments transformed, by name
+    pub session_dir: Option<std::path::PathBuf>, // Where the sessions are saved, if anywhere
+    pub reads: Limits,                  // How fast every client may read from the mount point
+    pub write_transform: Pipeline,      // How the edited and appended sequences are normalized
+}
+
+/// The settings of a mount with the default options, as used by `fusta
+/// browse`
+impl Default for FustaSettings {
+    fn default() -> Self {
+        FustaSettings {
+            cache: Cache::Mmap,
+            concretize_threshold: 500 * 1024 * 1024,
+            memory_budget: 500 * 1024 * 1024,
+            csv_separator: ",".into(),
+            no_overwrite: true,
+            on_append_collision: Collision::Skip,
+            on_edit_conflict: Collision::Skip,
+            verify: Verify::Off,
+            refuse_mismatches: true,
+            writers: Writers::default(),
+            append_id_template: "{filename}".into(),
+            writable_fasta: false,
+            defer_deletions: false,
+            headers_only: false,
+            noatime: false,
+            no_index: false,
+            catalog: false,
+            seed: 0,
+            chaos: Chaos::default(),
+            retry: Retry::default(),
+            id_rules: IdRules::default(),
+            codec: Codec::Plain,
+            allow_empty: false,
+            max_loss: 0.5,
+            max_shrink: None,
+            notifications: Thresholds::default(),
+            prefetch: Prefetch::None,
+            undo_budget: 100 * 1024 * 1024,
+            annotation: None,
+            genetic_code: GeneticCode::default(),
+            max_amplicon: 4000,
+            telomere_motifs: TelomereMotifs::new(&["TTAGGG"]).unwrap(),
+            end_length: 1000,
+            taxa_delimiter: ";".into(),
+            write_filter: None,
+            output: None,
+            merge: Vec::new(),
+            on_merge_collision: MergeCollision::Refuse,
+            aliases: None,
+            views: Vec::new(),
+            session_dir: Session::default_dir(),
+            reads: Limits::default(),
+            write_transform: Pipeline::default(),
         }
     }
+}
 
-    fn subfragment_from_ino(&self, ino: u64) -> Option<&SubFragment> {
-        self.subfragments.get_alt(&ino)
+/// The users allowed to modify the mounted file, besides the one running
+/// FUSTA. If empty, write access is only governed by the file modes.
+#[derive(Clone, Debug, Default)]
+pub struct Writers {
+    pub uids: Vec<u32>,
+    pub gids: Vec<u32>,
+}
+impl Writers {
+    pub fn is_empty(&self) -> bool {
+        self.uids.is_empty() && self.gids.is_empty()
     }
+}
 
-    fn make_info_buffer(&mut self) {
-        use ascii_table::*;
-        use num_format::*;
+/// The range of a file of `len` bytes covered by a read of `size` bytes at
+/// `offset`, capped to `MAX_IO_SIZE`
+fn window(offset: i64, size: u32, len: usize) -> std::ops::Range<usize> {
+    let start = std::cmp::min(std::cmp::max(offset, 0) as usize, len);
+    let size = std::cmp::min(size, MAX_IO_SIZE) as usize;
+    start..std::cmp::min(start.saturating_add(size), len)
+}
 
-        let mut table = AsciiTable::default();
-        table.column(0).set_header("ID");
-        table.column(1).set_header("Infos");
-        table.column(2).set_header("Length (bp)");
+/// The bytes in `range` of the concatenation of `head` and of a body of
+/// `body_len` bytes, e.g. of the label and the sequence of a fragment; the
+/// body is only read, through `read_body(offset, size)`, if `range` reaches
+/// it
+fn read_concatenated<E>(
+    head: &[u8],
+    body_len: usize,
+    range: std::ops::Range<usize>,
+    read_body: impl FnOnce(usize, usize) -> Result<Box<[u8]>, E>,
+) -> Result<Vec<u8>, E> {
+    let end = std::cmp::min(range.end, head.len() + body_len);
+    let mut r = Vec::with_capacity(end.saturating_sub(range.start));
+    if range.start < head.len() {
+        r.extend_from_slice(&head[range.start..std::cmp::min(end, head.len())]);
+    }
+    let body = range.start.saturating_sub(head.len())..end.saturating_sub(head.len());
+    if !body.is_empty() {
+        r.extend_from_slice(&read_body(body.start, body.len())?);
+    }
+    Ok(r)
+}
 
-        trace!("Making INFO BUFFER");
-        let header = format!("{} - {} sequences", &self.filename, self.fragments.len());
-        let infos = self
-            .fragments
-            .iter()
-            .map(|f| {
-                vec![
-                    f.id.to_owned(),
-                    f.name.as_ref().unwrap_or(&"".to_string()).into(),
-                    f.data_size().to_formatted_string(&Locale::en).into(),
-                ]
-            })
-            .collect::<Vec<_>>();
-        let content = format!(
-            "{}\n{}\n{}",
-            header,
-            "=".repeat(header.len()),
-            &table.format(&infos)
-        );
-        let size = content.as_bytes().len() as u64;
-        if let Some(x) = self.get_file(INFO_FILE) {
-            x.set_data(content.as_bytes());
-            x.mut_attrs().size = size;
+/// A read of the file of a fragment in fasta/ or seqs/, holding on to its
+/// sequence so that it can be run later, e.g. once a throttled client may
+/// read again
+struct SequenceRead {
+    id: SString,
+    label: Option<String>, // The header preceding the sequence, in fasta/
+    data: Arc<dyn SequenceSource>,
+    range: std::ops::Range<usize>,
+}
+impl SequenceRead {
+    fn run(&self) -> Result<Vec<u8>, FustaError> {
+        let read_error = |e| {
+            FustaError::BackingIo(
+                format!("READ: unable to read the sequence of `{}`", self.id),
+                e,
+            )
+        };
+        match &self.label {
+            // The read may straddle the header and the sequence
+            Some(label) => read_concatenated(
+                label.as_bytes(),
+                self.data.len(),
+                self.range.clone(),
+                |start, len| self.data.chunk(start, len),
+            )
+            .map_err(read_error),
+            None => Ok(self
+                .data
+                .chunk(self.range.start, self.range.len())
+                .map_err(read_error)?
+                .into_vec()),
         }
     }
+}
 
-    fn make_info_csv_buffer(&mut self) {
-        trace!("Making INFO_CSV BUFFER");
-        let header = format!(
-            "id{}name{}length",
-            self.settings.csv_separator, self.settings.csv_separator
-        );
-        let infos = self
-            .fragments
-            .iter()
-            .map(|f| {
-                format!(
-                    "{}{}\"{}\"{}{}",
-                    f.id.to_owned(),
-                    self.settings.csv_separator,
-                    f.name.as_ref().unwrap_or(&"".to_string()),
-                    self.settings.csv_separator,
-                    f.data_size()
-                )
-            })
-            .collect::<Vec<_>>();
-        let content = format!("{}\n{}", header, infos.join("\n"));
-        let size = content.as_bytes().len() as u64;
-        if let Some(x) = self.get_file(INFO_CSV_FILE) {
-            x.set_data(content.as_bytes());
-            x.mut_attrs().size = size;
-        }
+/// Most filesystems limit file names to 255 bytes, extension included
+const MAX_ID_SIZE: usize = 255 - FASTA_EXT.len();
+
+/// Check that `id` can be used both as a FASTA ID and, once encoded by
+/// `codec`, as a file name
+fn check_id(id: &str, codec: Codec) -> Result<(), String> {
+    if id.is_empty() {
+        Err(format!("`{}` is not a valid ID", id))
+    } else if id.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        Err(format!(
+            "{:?} contains whitespace or control characters",
+            id
+        ))
+    } else if codec.encode(id).len() > MAX_ID_SIZE {
+        Err(format!(
+            "file names can not be longer than {} bytes",
+            MAX_ID_SIZE
+        ))
+    } else {
+        codec.check(id)
     }
+}
 
-    fn make_labels_buffer(&mut self) {
-        trace!("Making LABELS BUFFER");
-        let content = self
-            .fragments
-            .iter()
-            .map(Fragment::label)
-            .collect::<Vec<_>>()
-            .join("");
-        let size = content.as_bytes().len() as u64;
-        if let Some(x) = self.get_file(LABELS_FILE) {
-            x.set_data(content.as_bytes());
-            x.mut_attrs().size = size;
+/// Whether `data` is empty or starts with a FASTA header
+fn starts_with_header(data: &[u8]) -> bool {
+    data.iter()
+        .find(|c| !c.is_ascii_whitespace())
+        .is_none_or(|&c| c == b'>')
+}
+
+/// Returns all the groups `uid` belongs to, starting with its primary group `gid`
+fn user_groups(uid: u32, gid: u32) -> Vec<u32> {
+    unsafe {
+        let mut pwd: passwd = std::mem::zeroed();
+        let mut buffer = vec![0 as c_char; 16 * 1024];
+        let mut entry = std::ptr::null_mut();
+        if getpwuid_r(uid, &mut pwd, buffer.as_mut_ptr(), buffer.len(), &mut entry) != 0
+            || entry.is_null()
+        {
+            return vec![gid];
         }
-    }
 
-    fn refresh_metadata(&mut self, force: bool) {
-        if self.dirty || force {
-            debug!("Refreshing metadata...");
-            self.make_info_buffer();
-            self.make_info_csv_buffer();
-            self.make_labels_buffer();
-            self.update_indices();
-            debug!("Done.")
+        let mut count: c_int = 64;
+        let mut groups = vec![0 as gid_t; count as usize];
+        if getgrouplist(pwd.pw_name, gid, groups.as_mut_ptr(), &mut count) < 0 {
+            // `count` now holds the required size
+            groups.resize(count as usize, 0);
+            if getgrouplist(pwd.pw_name, gid, groups.as_mut_ptr(), &mut count) < 0 {
+                return vec![gid];
+            }
         }
+        groups.truncate(count as usize);
+        groups
     }
+}
 
-    fn update_indices(&mut self) {
-        self.name2fragment = self
-            .fragments
-            .iter()
-            .enumerate()
-            .map(|(i, f)| (f.id.clone().into(), i))
-            .collect::<HashMap<_, _>>();
+#[derive(Debug)]
+struct PendingAppend {
+    data: Vec<u8>,
+    attrs: FileAttr,
+}
 
-        self.ino2fragment = self
-            .fragments
-            .iter()
-            .enumerate()
-            .flat_map(|(i, f)| {
-                std::iter::IntoIterator::into_iter([(f.fasta_file.ino, i), (f.seq_file.ino, i)])
-            })
-            .collect::<HashMap<_, _>>();
+/// Allocates the inos of the transient files – subfragments, pending
+/// appends, … – recycling the ones the kernel has forgotten. The generation
+/// of an ino is bumped every time it is freed, so that an (ino, generation)
+/// pair never designates two different files.
+struct InoAllocator {
+    next: u64,
+    free: Vec<u64>,
+    generations: HashMap<u64, u64>, // Only for the inos recycled at least once
+}
+impl InoAllocator {
+    fn new() -> InoAllocator {
+        InoAllocator {
+            next: FIRST_INO,
+            free: Vec::new(),
+            generations: HashMap::new(),
+        }
     }
 
-    fn is_fasta_file(&self, ino: u64) -> bool {
-        self.ino2fragment
-            .get(&ino)
-            .and_then(|i| self.fragments.get(*i))
-            .map(|f| f.fasta_file.ino == ino)
-            .unwrap_or(false)
+    fn allocate(&mut self) -> u64 {
+        self.free.pop().unwrap_or_else(|| {
+            let r = self.next;
+            self.next += 1;
+            assert!(self.next < FIRST_STABLE_INO, "Out of inos");
+            r
+        })
     }
 
-    fn is_seq_file(&self, ino: u64) -> bool {
-        self.ino2fragment
-            .get(&ino)
-            .and_then(|i| self.fragments.get(*i))
-            .map(|f| f.seq_file.ino == ino)
-            .unwrap_or(false)
+    /// Whether `ino` is managed by this allocator
+    fn owns(&self, ino: u64) -> bool {
+        (FIRST_INO..self.next).contains(&ino)
     }
 
-    fn is_append_file(&self, ino: u64) -> bool {
-        self.pending_appends.iter().any(|p| p.1.attrs.ino == ino)
+    fn release(&mut self, ino: u64) {
+        *self.generations.entry(ino).or_default() += 1;
+        self.free.push(ino);
     }
 
-    fn is_writeable(&self, ino: u64) -> bool {
-        self.is_append_file(ino) || self.is_seq_file(ino)
+    fn generation(&self, ino: u64) -> u64 {
+        self.generations.get(&ino).copied().unwrap_or_default()
     }
+}
 
-    fn create_subfragment(&mut self, name: &str) -> Result<FileAttr, String> {
-        fn clear_coordinates(start: isize, end: isize) -> (isize, isize) {
-            let start = if start < 0 {
-                warn!("Invalid start position {}; using 1 instead", start);
-                0
-            } else {
-                start
-            };
-            let end = if end < start {
-                warn!(
-                    "{}-{}: {} < {}; using {} instead",
-                    start,
-                    end,
-                    end,
-                    start,
-                    std::cmp::max(start, end)
-                );
-                std::cmp::max(start, end)
-            } else {
-                end
-            };
-            (start, end)
+/// Allocates inos derived from a stable hash of the fragments IDs, so that a
+/// fragment keeps the same inos across remounts.
+#[derive(Default)]
+struct StableInos {
+    known: HashMap<String, [u64; 2]>, // Fragment ID -> inos, as stored in the session
+    reserved: HashSet<u64>,           // All the inos in `known`
+    used: HashSet<u64>,
+}
+impl StableInos {
+    fn new(known: HashMap<String, [u64; 2]>) -> StableInos {
+        let reserved = known.values().flatten().cloned().collect();
+        StableInos {
+            known,
+            reserved,
+            used: HashSet::new(),
         }
+    }
 
-        let error_message = format!("`{}` is not a valid subfragment scheme", name);
-        if name.contains(':') && name.contains('-') {
-            let caps = SUBFRAGMENT_RE.captures(name).ok_or_else(|| {
-                format!("{}: it should be of the form ID:START-END", error_message)
-            })?;
-            if caps.len() == 4 {
-                let ino = self.new_ino();
-                let fragment_id = self
-                    .fragment_from_id(&caps[1])
-                    .ok_or_else(|| format!("`{}` is not a fragment", &caps[1]))?
-                    .id
-                    .clone();
-                let start = str::parse::<isize>(&caps[2])
-                    .map_err(|_| format!("{}: `{}` is not an integer", &error_message, &caps[1]))?
-                    - 1;
-                let end = str::parse::<isize>(&caps[3])
-                    .map_err(|_| format!("{}: `{}` is not an integer", &error_message, &caps[2]))?;
-                let (start, end) = clear_coordinates(start, end);
-                let key = format!("{}:{}-{}", fragment_id, start, end);
-
-                self.subfragments
-                    .get(&key)
-                    .map(|sf| sf.attrs)
-                    .or_else(|| {
-                        let size = (end - start) as u64;
-                        let attrs = FustaFS::make_file_attrs_with_size(ino, 0o444, size);
-                        let sf = SubFragment::new(&fragment_id, start, attrs);
-                        self.subfragments.insert(key, ino, sf);
-                        Some(attrs)
-                    })
-                    .ok_or_else(|| unimplemented!())
-            } else {
-                Err(error_message)
-            }
-        } else {
-            let ino = self.new_ino();
-            let fragment = self
-                .fragment_from_id(name)
-                .ok_or_else(|| format!("`{}` is not a fragment", name))?;
-            let fragment_id = fragment.id.clone();
-            let fragment_len = fragment.data.len();
-            let attrs =
-                FustaFS::make_file_attrs_with_size(ino, 0o444, fragment_len.try_into().unwrap());
-            let sf = SubFragment::new(&fragment_id, 0, attrs);
-            self.subfragments.insert(name.to_owned(), ino, sf);
-            Ok(attrs)
+    // 64-bits FNV-1a, that is stable across platforms and versions
+    fn hash(key: &str) -> u64 {
+        key.bytes().fold(0xcbf29ce484222325, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+
+    fn derive(&mut self, key: &str) -> u64 {
+        let span = (u64::MAX >> 1) - FIRST_STABLE_INO;
+        let mut ino = FIRST_STABLE_INO + Self::hash(key) % span;
+        // Linear probing on collisions
+        while self.used.contains(&ino) || self.reserved.contains(&ino) {
+            ino = FIRST_STABLE_INO + (ino - FIRST_STABLE_INO + 1) % span;
         }
+        ino
     }
-}
 
-impl Drop for FustaFS {
+    /// Returns the ino to use for the file identified by `key`
+    fn ino_for(&mut self, key: &str) -> u64 {
+        let ino = self.derive(key);
+        self.used.insert(ino);
+        ino
+    }
+
+    /// Whether `inos` are the ones given to fragment `id` in the session
+    fn is_known(&self, id: &str, (fasta_ino, seq_ino): (u64, u64)) -> bool {
+        self.known.get(id) == Some(&[fasta_ino, seq_ino])
+    }
+
+    /// Returns the (FASTA file, sequence file) inos to use for fragment `id`
+    fn inos_for(&mut self, id: &str) -> (u64, u64) {
+        if let Some(&[fasta_ino, seq_ino]) = self.known.get(id) {
+            if !self.used.contains(&fasta_ino) && !self.used.contains(&seq_ino) {
+                self.used.insert(fasta_ino);
+                self.used.insert(seq_ino);
+                return (fasta_ino, seq_ino);
+            }
+        }
+        let fasta_ino = self.derive(&format!("fasta/{}", id));
+        self.used.insert(fasta_ino);
+        let seq_ino = self.derive(&format!("seqs/{}", id));
+        self.used.insert(seq_ino);
+        (fasta_ino, seq_ino)
+    }
+}
+
+/// A BGZF-compressed rendering of a fasta/ file, generated while it is read
+struct Download {
+    fasta_ino: u64,
+    attrs: FileAttr,
+    stream: BgzfStream,
+}
+
+/// The first or last bases of a fragment, exposed in ends/
+struct End {
+    fasta_ino: u64,
+    tail: bool,
+    attrs: FileAttr,
+}
+
+/// A directory set in the configuration file, exposing every fragment
+/// transformed by a pipeline
+struct ViewDir {
+    name: String,
+    pipeline: Pipeline,
+    ino: u64,
+}
+
+/// A fragment, exposed in a view directory
+struct ViewFile {
+    dir: usize,
+    fasta_ino: u64,
+    attrs: FileAttr,
+}
+
+/// The tags of a fragment, exposed in tags/
+struct TagFile {
+    fasta_ino: u64,
+    attrs: FileAttr,
+}
+
+/// A directory of taxa/, grouping the fragments whose description lists the
+/// same ranks down to it
+struct Taxon {
+    path: Vec<String>, // The names of the directories leading to it, e.g. [Bacteria, Firmicutes]
+    parent: u64,
+    attrs: FileAttr,
+    count: FileAttr,                 // Its count.txt
+    children: BTreeMap<String, u64>, // name -> ino of the directories of the next rank
+    members: Vec<u64>,               // The fasta/ inos of the fragments at or below it
+}
+
+/// The ranks listed in `description`, e.g. `Bacteria;Firmicutes;Bacilli` in
+/// a SILVA header, if it is a taxonomy, i.e. if it holds `delimiter`
+fn taxonomy<'a>(description: &'a str, delimiter: &str) -> Vec<&'a str> {
+    if delimiter.is_empty() || !description.contains(delimiter) {
+        return Vec::new();
+    }
+    description
+        .split(delimiter)
+        .map(str::trim)
+        .filter(|rank| !rank.is_empty())
+        .collect()
+}
+
+/// The content of a file of tags/: one `key=value` pair per line
+fn format_tags(tags: &BTreeMap<String, String>) -> String {
+    tags.iter()
+        .map(|(key, value)| format!("{}={}\n", key, value))
+        .collect()
+}
+
+/// Parse the content written to a file of tags/; empty lines and lines
+/// starting with `#` are ignored
+fn parse_tags(content: &str) -> Result<BTreeMap<String, String>, FustaError> {
+    let mut r = BTreeMap::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once('=') {
+            Some((key, value))
+                if !key.trim().is_empty() && !key.trim().contains(char::is_whitespace) =>
+            {
+                r.insert(key.trim().to_owned(), value.trim().to_owned());
+            }
+            _ => {
+                return Err(FustaError::Parse(format!(
+                    "tags, line {}: expected `key=value`, found `{}`",
+                    i + 1,
+                    line
+                )))
+            }
+        }
+    }
+    Ok(r)
+}
+
+/// A Subfragment represents a portion of a fragment (chr:start-end)
+#[derive(Debug)]
+struct SubFragment {
+    /// The parent fragment
+    fragment: String,
+    /// Starting offset in the parent fragment
+    start: isize,
+    /// Whether the subfragment is read on the reverse strand
+    reverse: bool,
+    /// Whether the subfragment shows up when listing its directory
+    listed: bool,
+    /// The attributes of this subfragment inode. NOTE: that the subfragment
+    /// size is encoded in those.
+    attrs: FileAttr,
+}
+impl SubFragment {
+    fn new(fragment: &str, start: isize, reverse: bool, attrs: FileAttr) -> SubFragment {
+        SubFragment {
+            fragment: fragment.to_owned(),
+            start,
+            reverse,
+            listed: false,
+            attrs,
+        }
+    }
+}
+/// A change to the set of fragments. Mutations are applied one at a time
+/// through `FustaFS::apply`, each of them being complete – fragments,
+/// indices and FASTA file – before the next one starts.
+enum Mutation {
+    /// Add the fragments written to a file of the append directory
+    Append {
+        file: String,
+        data: Vec<u8>,
+        atime: SystemTime,
+        mtime: SystemTime,
+    },
+    /// Remove a fragment
+    Delete(SString),
+    /// Rename a fragment, replacing any other one bearing the new ID
+    Rename { from: SString, to: SString },
+}
+
+/// The records of a FASTA file, by their normalized ID
+type Records = Vec<(String, fusta::fasta::Fragment)>;
+/// A fragment as last written back: its ID, the --merge file it went to, if
+/// any, and the range of its sequence there
+type Committed = (SString, Option<usize>, (usize, usize));
+
+/// A fragment sequence, as it was when the FASTA file was mounted
+struct SnapshotEntry {
+    name: SString,
+    source: usize, // The file it is read from, among snapshot_sources
+    start: u64,    // Offset of the sequence in this file
+    attrs: FileAttr,
+}
+
+/// A fragment as it was before an edit, to be restored through ctl/undo
+struct Edit {
+    ino: u64,    // The ino of the seqs/ file of the fragment, which survives renamings
+    serial: u64, // Tells the states apart, e.g. when exposed in seqs/ as ID.seq@N
+    id: SString,
+    name: Option<String>,
+    header: Option<String>,
+    data: Vec<u8>,
+}
+
+/// A previous state of a fragment, exposed in seqs/ as `ID.seq@N`
+struct Version {
+    serial: u64, // The serial of its Edit, as long as it is kept in the history
+    attrs: FileAttr,
+}
+
+/// The translation of the coding sequence of a transcript, in proteins/
+struct Protein {
+    transcript: Transcript,
+    name: SString,
+    header: String,
+    attrs: FileAttr,
+}
+
+/// A directory of features/, e.g. upstream1000/, exposing the same flank of
+/// every gene
+struct FlankDir {
+    flank: Flank,
+    name: String,
+    attrs: FileAttr,
+    files: Vec<FileAttr>, // One per gene, in the same order
+}
+
+/// What a file of digest/ or pcr/ looks for in the fragments
+#[derive(Clone)]
+enum Scan {
+    Sites(Enzyme),      // digest/ENZYME.bed
+    Pieces(Enzyme),     // digest/ENZYME.sizes
+    Amplicons(Primers), // pcr/FORWARD__REVERSE
+}
+
+/// A file of digest/ or pcr/, computed in the background from all the
+/// fragments
+struct ScanFile {
+    dir: u64,
+    name: String,
+    scan: Scan,
+    attrs: FileAttr,
+    content: Option<Pending<Result<Arc<String>, String>>>, // Once computed
+}
+
+/// The entries of a directory, as replied to readdir
+type Listing = Arc<Vec<(u64, FileType, SString)>>;
+
+lazy_static! {
+    static ref SUBFRAGMENT_RE: Regex = Regex::new(r"^(.+):([\d,]+)-([\d,]+)(?::([+-]))?$").unwrap();
+    static ref WINDOW_RE: Regex = Regex::new(r"^(.+):([\d,]+)([~+])([\d,]+)(?::([+-]))?$").unwrap();
+    /// The umask of the daemon, applied to the default modes of all the files
+    static ref UMASK: u16 = unsafe {
+        let mask = umask(0);
+        umask(mask);
+        mask as u16
+    };
+}
+
+pub struct FustaFS {
+    fragments: Vec<Fragment>,
+    name2fragment: HashMap<String, usize>,
+    ino2fragment: HashMap<u64, usize>,
+
+    metadata: fs::Metadata,
+    dir_attrs: BTreeMap<u64, FileAttr>,
+    files: Vec<Box<dyn VirtualFile + Send>>,
+    filename: String,
+    settings: FustaSettings,
+    inos: InoAllocator,
+    stable_inos: StableInos,
+    session_file: Option<std::path::PathBuf>, // Where the session is saved, if anywhere
+    session_unsaved: std::cell::Cell<bool>, // Whether saving the session failed, to only warn once
+    #[cfg(feature = "catalog")]
+    catalog: Option<Catalog>, // Where the records and the state of the fragments are kept, with --catalog
+    reference_digests: BTreeMap<String, String>, // Fragment ID -> digest, from the previous session
+    reference_generations: BTreeMap<String, u64>, // Fragment ID -> inodes generation, from the previous session
+    epoch: u64, // How many times the file has been mounted, this time included
+
+    pending_appends: BTreeMap<String, PendingAppend>,
+    pending_controls: BTreeMap<u64, Vec<u8>>, // ino -> content written to a control file
+    pending_fastas: BTreeMap<u64, Vec<u8>>,   // ino -> edited content of a fasta/ file
+
+    history: VecDeque<Edit>, // The previous states of the edited fragments, oldest first
+    editing: HashSet<u64>, // The seqs/ inos of the fragments whose ongoing edit is already in `history`
+    edits: u64,            // How many states have been recorded in `history`, numbering them
+    versions: HashMap<u64, Version>, // ino -> previous state of a fragment
+
+    subfragments: MultiMap<String, u64, SubFragment>, // name -> inode -> SubFragment
+    region_dirs: BTreeMap<String, u64>, // Directories created in get/ from ctl/regions
+    bookmarks: BTreeMap<String, String>, // Name -> region, created in get/ as NAME=REGION
+    directories: BTreeMap<u64, DirOwnership>, // ino -> mode and owner of the chmod-ed or chown-ed directories
+
+    // The mounted file is kept open, so that its original content remains
+    // available even once it has been replaced by a rewrite.
+    snapshot_sources: Vec<Backing>, // The mounted file, then the --merge ones
+    spill: Arc<SpillCache>,         // Where --cache memory keeps the sequences
+    mappings: usize,                // How many more sequences --cache mmap may map
+    edit_spill: Arc<EditSpill>,     // Where the edits beyond --max-cache are set aside
+    snapshot: Vec<SnapshotEntry>,
+    snapshot_names: HashMap<String, usize>,
+    snapshot_inos: HashMap<u64, usize>,
+
+    downloads: HashMap<u64, Download>, // ino -> compressed fasta/ file
+    download_inos: HashMap<u64, u64>,  // fasta/ file ino -> download ino
+
+    ends: HashMap<u64, End>,              // ino -> file of ends/
+    end_inos: HashMap<(u64, bool), u64>,  // (fasta/ file ino, tail) -> file of ends/ ino
+    tag_files: HashMap<u64, TagFile>,     // ino -> file of tags/
+    tag_inos: HashMap<u64, u64>,          // fasta/ file ino -> file of tags/ ino
+    pending_tags: BTreeMap<u64, Vec<u8>>, // ino -> content written to a file of tags/
+    view_dirs: Vec<ViewDir>,
+    view_files: HashMap<u64, ViewFile>, // ino -> file of a view directory
+    view_inos: HashMap<(usize, u64), u64>, // (view directory, fasta/ file ino) -> file ino
+    taxa: HashMap<u64, Taxon>,          // ino -> directory of taxa/, built on access
+    taxon_counts: HashMap<u64, u64>,    // ino -> directory of taxa/ of a count.txt
+    taxa_stale: bool,                   // Whether taxa/ should be rebuilt from the descriptions
+    taxon_inos: HashMap<String, u64>,   // path -> ino of a directory or count.txt of taxa/
+
+    listings: HashMap<u64, Listing>, // dir ino -> entries of fasta/, seqs/, download/, ends/ or tags/, until the fragments change
+
+    proteins: Vec<Protein>,
+    protein_names: HashMap<String, usize>, // file name -> protein
+    protein_inos: HashMap<u64, usize>,     // ino -> protein
+    genes: Vec<Feature>,
+    gene_names: HashMap<String, usize>,       // file name -> gene
+    flank_dirs: Vec<FlankDir>,                // The directories of features/, created on lookup
+    flank_inos: HashMap<u64, (usize, usize)>, // ino -> (flank directory, gene)
+
+    scans: Vec<ScanFile>, // The files of digest/ and pcr/, created on lookup
+    scan_inos: HashMap<u64, usize>, // ino -> file of digest/ or pcr/
+
+    notifier: Notifier,
+    throttle: Option<Throttle>, // Delays the reads of the clients exceeding their rate, if limited
+    pool: Pool,                 // Workers computing the derived files, e.g. stats.csv
+    stats: Option<Pending<Result<Arc<String>, String>>>, // The content of stats.csv, once computed
+    infos_stale: bool,          // Whether infos.txt should be rebuilt before being read
+    info_csv: InfoCsv,
+    duplicates: Option<Pending<Result<Arc<String>, String>>>, // The content of duplicates.txt, once computed
+    codon_usage: Option<Pending<Result<Arc<String>, String>>>, // The content of codon_usage.csv, once computed
+    repeats: Option<Pending<Result<Arc<String>, String>>>, // The content of repeats.txt, once computed
+    format: Option<Pending<Result<Arc<String>, String>>>, // The content of format.txt, once computed
+    fai: Option<Pending<Result<Arc<String>, String>>>,    // The content of index.fai, once computed
+
+    user_groups: HashMap<u32, Vec<u32>>, // uid -> groups, cached for writers checks
+    headerless_appends: usize,           // How many sequences were named from `append_id_template`
+    reference_size: u64, // The size of the file when mounted, or when a write-back was last confirmed
+    committed: HashMap<u64, Committed>, // The fragments as of the last write-back, by seq ino
+    held_back: bool, // Whether the last write-back was held back for shrinking some sequences too much
+    ttl: Duration,   // How long the kernel may cache the attributes and entries
+
+    dirty: bool,
+}
+
+impl FustaFS {
+    pub fn new(settings: FustaSettings, filename: &str) -> Result<FustaFS> {
+        let metadata = fs::metadata(filename).context(format!("while opening `{}`", filename))?;
+        let session_file = settings
+            .session_dir
+            .as_deref()
+            .map(|dir| Session::path_for(filename, dir));
+        let mut session = Session::load_for(filename, session_file.as_deref());
+        // With --catalog, the state of the fragments is read from the
+        // catalog rather than from the session
+        #[cfg(feature = "catalog")]
+        let catalog = match (&session_file, settings.catalog) {
+            (Some(session_file), true) => {
+                let path = Catalog::path_for(session_file);
+                match Catalog::open(&path).and_then(|c| c.load(&mut session).map(|_| c)) {
+                    Ok(catalog) => Some(catalog),
+                    Err(e) => {
+                        warn!(
+                            "Unable to use the catalog {}: {:#}; keeping the state in the session",
+                            path.display(),
+                            e
+                        );
+                        None
+                    }
+                }
+            }
+            (None, true) => {
+                warn!(
+                    "No state directory to keep the catalog in; keeping the state in the session"
+                );
+                None
+            }
+            _ => None,
+        };
+        let notifier = Notifier::new(settings.notifications.clone());
+        let throttle = (settings.reads.rate > 0).then(|| Throttle::new(settings.reads.clone()));
+        let spill = SpillCache::new(settings.memory_budget);
+        let info_csv = InfoCsv::new(&settings.csv_separator);
+        let mut r = FustaFS {
+            fragments: Vec::new(),
+            name2fragment: HashMap::new(),
+            ino2fragment: HashMap::new(),
+
+            filename: String::new(),
+            dir_attrs: btreemap! {
+                // Virtual folders
+                ROOT_DIR         => FustaFS::make_dir_attrs(ROOT_DIR, 0o775),
+                SEQ_DIR          => FustaFS::make_dir_attrs(SEQ_DIR, 0o775),
+                FASTA_DIR        => FustaFS::make_dir_attrs(FASTA_DIR, 0o555),
+                APPEND_DIR       => FustaFS::make_dir_attrs(APPEND_DIR, 0o775),
+                SUBFRAGMENTS_DIR => FustaFS::make_dir_attrs(SUBFRAGMENTS_DIR, 0o755),
+                CTL_DIR          => FustaFS::make_dir_attrs(CTL_DIR, 0o555),
+                SNAPSHOT_DIR     => FustaFS::make_dir_attrs(SNAPSHOT_DIR, 0o555),
+                META_DIR         => FustaFS::make_dir_attrs(META_DIR, 0o555),
+                DOWNLOAD_DIR     => FustaFS::make_dir_attrs(DOWNLOAD_DIR, 0o555),
+                DIGEST_DIR       => FustaFS::make_dir_attrs(DIGEST_DIR, 0o555),
+                PCR_DIR          => FustaFS::make_dir_attrs(PCR_DIR, 0o555),
+                ENDS_DIR         => FustaFS::make_dir_attrs(ENDS_DIR, 0o555),
+                TAGS_DIR         => FustaFS::make_dir_attrs(TAGS_DIR, 0o555),
+                TAXA_DIR         => FustaFS::make_dir_attrs(TAXA_DIR, 0o555),
+                ALIASES_DIR      => FustaFS::make_dir_attrs(ALIASES_DIR, 0o555),
+            },
+            files: vec![
+                Box::new(BufferFile {
+                    name: INFO_FILE_NAME.into(),
+                    ino: INFO_FILE,
+                    attrs: FustaFS::make_file_attrs(INFO_FILE, 0o444),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: INFO_CSV_FILE_NAME.into(),
+                    ino: INFO_CSV_FILE,
+                    attrs: FustaFS::make_file_attrs(INFO_CSV_FILE, 0o444),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: INFO_JSON_FILE_NAME.into(),
+                    ino: INFO_JSON_FILE,
+                    attrs: FustaFS::make_file_attrs(INFO_JSON_FILE, 0o444),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: OFFSETS_FILE_NAME.into(),
+                    ino: OFFSETS_FILE,
+                    attrs: FustaFS::make_file_attrs(OFFSETS_FILE, 0o444),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: APPENDS_FILE_NAME.into(),
+                    ino: APPENDS_FILE,
+                    attrs: FustaFS::make_file_attrs(APPENDS_FILE, 0o444),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: STATS_FILE_NAME.into(),
+                    ino: STATS_FILE,
+                    attrs: FustaFS::make_file_attrs(STATS_FILE, 0o444),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: DUPLICATES_FILE_NAME.into(),
+                    ino: DUPLICATES_FILE,
+                    attrs: FustaFS::make_file_attrs(DUPLICATES_FILE, 0o444),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: REPEATS_FILE_NAME.into(),
+                    ino: REPEATS_FILE,
+                    attrs: FustaFS::make_file_attrs(REPEATS_FILE, 0o444),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: FORMAT_FILE_NAME.into(),
+                    ino: FORMAT_FILE,
+                    attrs: FustaFS::make_file_attrs(FORMAT_FILE, 0o444),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: FAI_FILE_NAME.into(),
+                    ino: FAI_FILE,
+                    attrs: FustaFS::make_file_attrs(FAI_FILE, 0o444),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: CODON_USAGE_FILE_NAME.into(),
+                    ino: CODON_USAGE_FILE,
+                    attrs: FustaFS::make_file_attrs(CODON_USAGE_FILE, 0o444),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: STATUS_FILE_NAME.into(),
+                    ino: STATUS_FILE,
+                    attrs: FustaFS::make_file_attrs(STATUS_FILE, 0o444),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: SUMMARY_FILE_NAME.into(),
+                    ino: SUMMARY_FILE,
+                    attrs: FustaFS::make_file_attrs(SUMMARY_FILE, 0o444),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: MANIFEST_FILE_NAME.into(),
+                    ino: MANIFEST_FILE,
+                    attrs: FustaFS::make_file_attrs(MANIFEST_FILE, 0o444),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: LAST_ERROR_FILE_NAME.into(),
+                    ino: LAST_ERROR_FILE,
+                    attrs: FustaFS::make_file_attrs(LAST_ERROR_FILE, 0o444),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: COMMIT_REPORT_FILE_NAME.into(),
+                    ino: COMMIT_REPORT_FILE,
+                    attrs: FustaFS::make_file_attrs(COMMIT_REPORT_FILE, 0o444),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: COLLISIONS_FILE_NAME.into(),
+                    ino: COLLISIONS_FILE,
+                    attrs: FustaFS::make_file_attrs(COLLISIONS_FILE, 0o444),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: LABELS_FILE_NAME.into(),
+                    ino: LABELS_FILE,
+                    attrs: FustaFS::make_file_attrs(LABELS_FILE, 0o444),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: RENAME_MAP_FILE_NAME.into(),
+                    ino: RENAME_MAP_FILE,
+                    attrs: FustaFS::make_file_attrs(RENAME_MAP_FILE, 0o664),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: REGIONS_FILE_NAME.into(),
+                    ino: REGIONS_FILE,
+                    attrs: FustaFS::make_file_attrs(REGIONS_FILE, 0o664),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: EXPORT_FILE_NAME.into(),
+                    ino: EXPORT_FILE,
+                    attrs: FustaFS::make_file_attrs(EXPORT_FILE, 0o664),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: EXPORT_SPLIT_FILE_NAME.into(),
+                    ino: EXPORT_SPLIT_FILE,
+                    attrs: FustaFS::make_file_attrs(EXPORT_SPLIT_FILE, 0o664),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: COMMIT_FILE_NAME.into(),
+                    ino: COMMIT_FILE,
+                    attrs: FustaFS::make_file_attrs(COMMIT_FILE, 0o664),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: REVERT_FILE_NAME.into(),
+                    ino: REVERT_FILE,
+                    attrs: FustaFS::make_file_attrs(REVERT_FILE, 0o664),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: UNDO_FILE_NAME.into(),
+                    ino: UNDO_FILE,
+                    attrs: FustaFS::make_file_attrs(UNDO_FILE, 0o664),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: DELETE_FILE_NAME.into(),
+                    ino: DELETE_FILE,
+                    attrs: FustaFS::make_file_attrs(DELETE_FILE, 0o664),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+                Box::new(BufferFile {
+                    name: CONFIG_FILE_NAME.into(),
+                    ino: CONFIG_FILE,
+                    attrs: FustaFS::make_file_attrs(CONFIG_FILE, 0o664),
+                    class: FileClass::Text,
+                    _data: Vec::new(),
+                }),
+            ],
+            metadata,
+            settings,
+            inos: InoAllocator::new(),
+            stable_inos: StableInos::new(session.inodes.into_iter().collect()),
+            session_file,
+            session_unsaved: std::cell::Cell::new(false),
+            #[cfg(feature = "catalog")]
+            catalog,
+            reference_digests: session.digests,
+            reference_generations: session.generations,
+            epoch: session.mounts + 1,
+            pending_appends: Default::default(),
+            pending_controls: Default::default(),
+            pending_fastas: Default::default(),
+            history: VecDeque::new(),
+            editing: HashSet::new(),
+            edits: 0,
+            versions: HashMap::new(),
+            subfragments: Default::default(),
+            region_dirs: Default::default(),
+            bookmarks: BTreeMap::new(),
+            directories: session.directories,
+            snapshot_sources: Vec::new(),
+            spill,
+            mappings: mapping_budget(),
+            edit_spill: EditSpill::new(),
+            snapshot: Vec::new(),
+            snapshot_names: HashMap::new(),
+            snapshot_inos: HashMap::new(),
+            downloads: HashMap::new(),
+            download_inos: HashMap::new(),
+            ends: HashMap::new(),
+            end_inos: HashMap::new(),
+            view_dirs: Vec::new(),
+            view_files: HashMap::new(),
+            view_inos: HashMap::new(),
+            taxa: HashMap::new(),
+            taxon_counts: HashMap::new(),
+            taxa_stale: true,
+            taxon_inos: HashMap::new(),
+            tag_files: HashMap::new(),
+            tag_inos: HashMap::new(),
+            pending_tags: Default::default(),
+            listings: HashMap::new(),
+            proteins: Vec::new(),
+            protein_names: HashMap::new(),
+            protein_inos: HashMap::new(),
+            genes: Vec::new(),
+            gene_names: HashMap::new(),
+            flank_dirs: Vec::new(),
+            flank_inos: HashMap::new(),
+            scans: Vec::new(),
+            scan_inos: HashMap::new(),
+            notifier,
+            throttle,
+            pool: Pool::new(
+                std::thread::available_parallelism()
+                    .map(|n| n.get())
+                    .unwrap_or(1)
+                    .min(4),
+            ),
+            stats: None,
+            infos_stale: true,
+            info_csv,
+            duplicates: None,
+            codon_usage: None,
+            repeats: None,
+            format: None,
+            fai: None,
+            user_groups: HashMap::new(),
+            headerless_appends: 0,
+            reference_size: 0,
+            committed: HashMap::new(),
+            held_back: false,
+            ttl: TTL,
+            dirty: false,
+        };
+
+        r.read_fasta(filename)
+            .context(format!("while parsing {}", filename))?;
+        r.reference_size = r.backing_size();
+        for fragment in r.fragments.iter_mut() {
+            if let Some(tags) = session.tags.remove(fragment.id.as_str()) {
+                fragment.tags = tags;
+            }
+        }
+        for (name, region) in session.bookmarks {
+            if let Err(e) = r.bookmark(&name, &region) {
+                warn!("Dropping bookmark `{}`: {}", name, e);
+            }
+        }
+        r.make_info_json_buffer();
+        r.make_config_buffer();
+        // The filtered out fragments are removed on the first write-back,
+        // and the output is written even if nothing changes
+        if r.fragments.iter().any(|f| !r.is_written(f)) || r.writes_elsewhere() {
+            r.dirty = true;
+        }
+        r.verify()?;
+        if let Some(annotation) = r.settings.annotation.clone() {
+            r.load_annotation(&annotation)?;
+        }
+        r.make_views()?;
+        for (ino, ownership) in r.directories.iter() {
+            if let Some(attrs) = r.dir_attrs.get_mut(ino) {
+                attrs.perm = ownership.mode;
+                attrs.uid = ownership.uid;
+                attrs.gid = ownership.gid;
+            }
+        }
+        // Record this mount right away, so that the generations it hands out
+        // are never reused, even if it does not end gracefully
+        r.save_session();
+        Ok(r)
+    }
+
+    /// Expose the translations of the transcripts annotated in the GFF3 file
+    /// `filename` in proteins/
+    fn load_annotation(&mut self, filename: &str) -> Result<()> {
+        info!("Reading {}...", filename);
+        let annotation = Annotation::load(filename)?;
+        let transcripts = annotation
+            .transcripts()
+            .with_context(|| format!("while parsing `{}`", filename))?;
+        for transcript in transcripts {
+            let name = format!(
+                "{}{}",
+                self.settings.codec.encode(&transcript.id),
+                PROTEIN_EXT
+            );
+            if self.protein_names.contains_key(&name) {
+                anyhow::bail!("`{}` contains duplicated transcripts", filename)
+            }
+            let header = format!(
+                ">{} {}:{}-{}:{}\n",
+                transcript.id,
+                transcript.seqid,
+                transcript.cds.first().unwrap().0,
+                transcript.cds.last().unwrap().1,
+                if transcript.strand == Strand::Reverse {
+                    '-'
+                } else {
+                    '+'
+                }
+            );
+            let codons = transcript.codons();
+            let size = header.len() + codons + codons.div_ceil(LINE_WIDTH);
+            let ino = self
+                .stable_inos
+                .ino_for(&format!("proteins/{}", transcript.id));
+            let mut attrs = FustaFS::make_file_attrs_with_size(ino, 0o444, size as u64);
+            attrs.mtime = self.metadata.modified().unwrap();
+            self.protein_names.insert(name.clone(), self.proteins.len());
+            self.protein_inos.insert(ino, self.proteins.len());
+            self.proteins.push(Protein {
+                transcript,
+                name: name.into(),
+                header,
+                attrs,
+            });
+        }
+        self.dir_attrs
+            .insert(PROTEINS_DIR, FustaFS::make_dir_attrs(PROTEINS_DIR, 0o555));
+
+        for gene in annotation.genes() {
+            let id = gene.id.as_ref().unwrap();
+            let name = format!("{}{}", self.settings.codec.encode(id), FEATURE_EXT);
+            if self.gene_names.contains_key(&name) {
+                anyhow::bail!("`{}` contains duplicated genes", filename)
+            }
+            self.gene_names.insert(name, self.genes.len());
+            self.genes.push(gene.clone());
+        }
+        self.dir_attrs
+            .insert(FEATURES_DIR, FustaFS::make_dir_attrs(FEATURES_DIR, 0o555));
+        info!("Done.");
+        Ok(())
+    }
+
+    /// Whether an annotation has been provided, exposing proteins/,
+    /// features/ and codon_usage.csv
+    fn is_annotated(&self) -> bool {
+        self.dir_attrs.contains_key(&PROTEINS_DIR)
+    }
+
+    /// The fragment annotated as `seqid`, either directly or through its
+    /// normalized ID
+    fn annotated_fragment(&self, seqid: &str, feature: &str) -> Result<&Fragment, FustaError> {
+        self.fragment_from_id(seqid)
+            .or_else(|| self.fragment_from_id(&self.settings.id_rules.apply(seqid)))
+            .ok_or_else(|| {
+                FustaError::NotFound(format!(
+                    "`{}`, annotated with `{}`, is not a fragment",
+                    seqid, feature
+                ))
+            })
+    }
+
+    /// The directory of features/ exposing `flank`, created on first access
+    fn flank_dir(&mut self, flank: Flank) -> usize {
+        if let Some(i) = self.flank_dirs.iter().position(|d| d.flank == flank) {
+            return i;
+        }
+        let name = flank.to_string();
+        let ino = self.stable_inos.ino_for(&format!("features/{}", name));
+        let mut files = Vec::with_capacity(self.genes.len());
+        for (i, gene) in self.genes.iter().enumerate() {
+            let ino = self.stable_inos.ino_for(&format!(
+                "features/{}/{}",
+                name,
+                gene.id.as_ref().unwrap()
+            ));
+            self.flank_inos.insert(ino, (self.flank_dirs.len(), i));
+            let mut attrs = FustaFS::make_file_attrs(ino, 0o444);
+            attrs.mtime = self.metadata.modified().unwrap();
+            files.push(attrs);
+        }
+        self.flank_dirs.push(FlankDir {
+            flank,
+            name,
+            attrs: FustaFS::make_dir_attrs(ino, 0o555),
+            files,
+        });
+        self.update_flank_sizes(self.flank_dirs.len() - 1);
+        self.flank_dirs.len() - 1
+    }
+
+    /// The header of the file of features/ holding the `flank` of `gene`,
+    /// and the range it covers in its fragment
+    fn flank_of(
+        &self,
+        flank: Flank,
+        gene: usize,
+    ) -> Result<(String, &Fragment, std::ops::Range<usize>), FustaError> {
+        let gene = &self.genes[gene];
+        let id = gene.id.as_ref().unwrap();
+        let fragment = self.annotated_fragment(&gene.seqid, id)?;
+        let len = fragment
+            .sequence_len()
+            .map_err(|e| fragment.unreadable(e))?;
+        let range = gene.flank(flank, len);
+        let header = if range.is_empty() {
+            format!(">{}_{}\n", id, flank)
+        } else {
+            format!(
+                ">{}_{} {}:{}-{}:{}\n",
+                id,
+                flank,
+                gene.seqid,
+                range.start + 1,
+                range.end,
+                if gene.strand == Strand::Reverse {
+                    '-'
+                } else {
+                    '+'
+                }
+            )
+        };
+        Ok((header, fragment, range))
+    }
+
+    /// The content of the file of features/ holding the flank of `gene` in
+    /// directory `dir`
+    fn read_flank(&self, dir: usize, gene: usize) -> Result<Vec<u8>, FustaError> {
+        let (header, fragment, range) = self.flank_of(self.flank_dirs[dir].flank, gene)?;
+        let mut seq = fragment
+            .pure_chunk(range.start, range.len())
+            .map_err(|e| fragment.unreadable(e))?
+            .into_vec();
+        if self.genes[gene].strand == Strand::Reverse {
+            reverse_complement(&mut seq);
+        }
+        let mut r = header.into_bytes();
+        for line in seq.chunks(LINE_WIDTH) {
+            r.extend_from_slice(line);
+            r.push(b'\n');
+        }
+        Ok(r)
+    }
+
+    /// As the flanks are clipped to their fragment, their size follows the
+    /// length of the fragments
+    fn update_flank_sizes(&mut self, dir: usize) {
+        for gene in 0..self.genes.len() {
+            let size = self
+                .flank_of(self.flank_dirs[dir].flank, gene)
+                .map(|(header, _, range)| {
+                    header.len() + range.len() + range.len().div_ceil(LINE_WIDTH)
+                })
+                .unwrap_or(0);
+            self.flank_dirs[dir].files[gene].size = size as u64;
+        }
+    }
+
+    /// The content of the proteins/ file of protein `i`: its coding sequence,
+    /// spliced and translated
+    fn translate(&self, i: usize) -> Result<Vec<u8>, FustaError> {
+        let protein = &self.proteins[i];
+        let transcript = &protein.transcript;
+        let fragment = self.annotated_fragment(&transcript.seqid, &transcript.id)?;
+        let mut cds = Vec::with_capacity(transcript.len());
+        let len = fragment
+            .sequence_len()
+            .map_err(|e| fragment.unreadable(e))?;
+        for &(start, end) in transcript.cds.iter() {
+            if end > len {
+                return Err(FustaError::OutOfRange(format!(
+                    "a CDS of `{}` ends at {}, past the end of `{}`",
+                    transcript.id, end, fragment.id
+                )));
+            }
+            cds.extend_from_slice(
+                &fragment
+                    .pure_chunk(start - 1, end - start + 1)
+                    .map_err(|e| fragment.unreadable(e))?,
+            );
+        }
+        if transcript.strand == Strand::Reverse {
+            reverse_complement(&mut cds);
+        }
+        let protein_seq = self
+            .settings
+            .genetic_code
+            .translate(cds.get(transcript.phase..).unwrap_or_default());
+        let mut r = protein.header.as_bytes().to_vec();
+        for line in protein_seq.chunks(LINE_WIDTH) {
+            r.extend_from_slice(line);
+            r.push(b'\n');
+        }
+        Ok(r)
+    }
+
+    /// Get the workers reading the file in the background, so that the first
+    /// accesses do not pay for a cold cache or storage; called once mounted
+    fn prefetch(&mut self) {
+        if self.settings.prefetch == Prefetch::All {
+            // Read through a file handle of its own, which fills the same
+            // page cache as the one serving the mmaps and preads
+            let filename = self.filename.clone();
+            self.pool.compute(move || {
+                let start = std::time::Instant::now();
+                let mut buffer = vec![0; MAX_IO_SIZE as usize];
+                let mut total = 0;
+                let r = fs::File::open(&filename).and_then(|mut file| loop {
+                    match file.read(&mut buffer)? {
+                        0 => break Ok(()),
+                        n => total += n,
+                    }
+                });
+                match r {
+                    Ok(_) => debug!(
+                        "Prefetched {} bytes of `{}` in {:.1}s",
+                        total,
+                        filename,
+                        start.elapsed().as_secs_f32()
+                    ),
+                    Err(e) => warn!("Failed to prefetch `{}`: {}", filename, e),
+                }
+            });
+        }
+        if self.settings.prefetch != Prefetch::None {
+            self.start_stats();
+        }
+    }
+
+    /// Compare the digests of all the fragments to the reference ones
+    fn verify(&mut self) -> Result<()> {
+        let reference = match &self.settings.verify {
+            Verify::Off => return Ok(()),
+            #[cfg(feature = "catalog")]
+            Verify::AgainstSession if self.catalog.is_some() => {
+                self.catalog.as_ref().unwrap().reference_digests()?
+            }
+            Verify::AgainstSession => self.reference_digests.clone(),
+            Verify::AgainstFile(filename) => fs::read_to_string(filename)
+                .context(format!("while reading `{}`", filename))?
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(|l| match l.split_whitespace().collect::<Vec<_>>()[..] {
+                    [digest, id] => Ok((id.to_owned(), digest.to_lowercase())),
+                    _ => Err(anyhow::anyhow!("`{}`: expected `DIGEST ID`", l)),
+                })
+                .collect::<Result<BTreeMap<_, _>>>()
+                .context(format!("while parsing `{}`", filename))?,
+        };
+        if reference.is_empty() {
+            warn!("No reference digests available; they will be recorded for the next sessions");
+        }
+
+        info!("Verifying {} fragments...", self.fragments.len());
+        let mut mismatches = Vec::new();
+        for fragment in self.fragments.iter_mut() {
+            let digest = fragment
+                .compute_digest()
+                .with_context(|| format!("while reading `{}`", fragment.id))?;
+            match reference.get(fragment.id.as_str()) {
+                Some(expected) if *expected != digest => mismatches.push(format!(
+                    "`{}`: expected {}, found {}",
+                    fragment.id, expected, digest
+                )),
+                None if !reference.is_empty() => {
+                    warn!("No reference digest for `{}`", fragment.id)
+                }
+                _ => {}
+            }
+            fragment.digest = Some(digest);
+        }
+        for id in reference.keys() {
+            if self.fragment_from_id(id).is_none() {
+                mismatches.push(format!("`{}`: missing", id));
+            }
+        }
+
+        if mismatches.is_empty() {
+            info!("Done.");
+            Ok(())
+        } else {
+            for mismatch in mismatches.iter() {
+                warn!("{}", mismatch);
+            }
+            let msg = format!(
+                "{} fragments differ from their reference digests",
+                mismatches.len()
+            );
+            if self.settings.refuse_mismatches {
+                anyhow::bail!(msg)
+            } else {
+                warn!("{}", msg);
+                notify(msg);
+                Ok(())
+            }
+        }
+    }
+
+    /// Build the ID of a sequence appended without a header in `filename`
+    fn headerless_id(&mut self, filename: &str) -> String {
+        self.headerless_appends += 1;
+        let id = self
+            .settings
+            .append_id_template
+            .replace("{filename}", filename)
+            .replace("{count}", &self.headerless_appends.to_string())
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join("_");
+        info!(
+            "Naming the headerless sequence appended in `{}` `{}`",
+            filename, id
+        );
+        id
+    }
+
+    /// Whether the user behind `req` may modify the mounted file
+    fn may_write(&mut self, req: &Request) -> bool {
+        if self.settings.headers_only {
+            return false;
+        }
+        let writers = &self.settings.writers;
+        if writers.is_empty()
+            || req.uid() == unsafe { geteuid() }
+            || writers.uids.contains(&req.uid())
+            || writers.gids.contains(&req.gid())
+        {
+            return true;
+        }
+        let groups = self
+            .user_groups
+            .entry(req.uid())
+            .or_insert_with(|| user_groups(req.uid(), req.gid()));
+        groups.iter().any(|g| writers.gids.contains(g))
+    }
+
+    fn check_writer(&mut self, req: &Request) -> Result<(), FustaError> {
+        if self.settings.headers_only {
+            Err(FustaError::ReadOnly(
+                "the file is mounted read-only with --headers-only".to_owned(),
+            ))
+        } else if self.may_write(req) {
+            Ok(())
+        } else {
+            Err(FustaError::ReadOnly(format!(
+                "UID {} is not allowed to modify the mounted file",
+                req.uid()
+            )))
+        }
+    }
+
+    /// Exports create files with the permissions of the user running fusta,
+    /// so that only this user may request them
+    fn check_exporter(&self, req: &Request, ino: u64) -> Result<(), FustaError> {
+        if (ino == EXPORT_FILE || ino == EXPORT_SPLIT_FILE) && req.uid() != unsafe { geteuid() } {
+            Err(FustaError::ReadOnly(format!(
+                "UID {} is not allowed to export fragments",
+                req.uid()
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// A human-readable name for `ino`, for error messages
+    fn describe(&self, ino: u64) -> String {
+        let name = match ino {
+            ROOT_DIR => Some("the mountpoint".to_owned()),
+            ino if self.dir_attrs.contains_key(&ino) => None,
+            _ => self
+                .files
+                .iter()
+                .find(|f| f.ino() == ino)
+                .map(|f| f.name().to_owned())
+                .or_else(|| {
+                    self.fragment_from_ino(ino)
+                        .and_then(|f| f.file_from_ino(ino))
+                        .map(|f| f.name().to_owned())
+                })
+                .or_else(|| {
+                    self.pending_appends
+                        .iter()
+                        .find(|(_, p)| p.attrs.ino == ino)
+                        .map(|(name, _)| name.to_owned())
+                }),
+        };
+        name.map(|n| format!("`{}`", n))
+            .unwrap_or_else(|| format!("inode {}", ino))
+    }
+
+    /// Report `e`, and return the errno to reply with; warnings and errors
+    /// are kept in .fusta/last_error, where users can find out why their
+    /// command failed.
+    fn fail(&mut self, e: FustaError) -> c_int {
+        log!(e.level(), "{}", e);
+        if e.level() == Level::Error {
+            notify(e.to_string());
+        }
+        if e.level() <= Level::Warn {
+            let message = format!("{}: {}\n", e, std::io::Error::from_raw_os_error(e.errno()));
+            let file = self.get_file(LAST_ERROR_FILE).unwrap();
+            file.set_data(message.as_bytes());
+            file.mut_attrs().size = message.len() as u64;
+            file.mut_attrs().mtime = SystemTime::now();
+        }
+        e.errno()
+    }
+
+    fn make_dir_attrs(ino: u64, perms: u16) -> FileAttr {
+        FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: std::time::SystemTime::now(),
+            mtime: std::time::SystemTime::now(),
+            ctime: std::time::SystemTime::now(),
+            crtime: std::time::SystemTime::now(),
+            kind: FileType::Directory,
+            perm: perms & !*UMASK,
+            nlink: 1,
+            uid: unsafe { libc::geteuid() },
+            gid: unsafe { libc::getgid() },
+            rdev: 0,
+            flags: 0,
+            blksize: 0,
+        }
+    }
+
+    fn make_file_attrs(ino: u64, perms: u16) -> FileAttr {
+        FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: std::time::SystemTime::now(),
+            mtime: std::time::SystemTime::now(),
+            ctime: std::time::SystemTime::now(),
+            crtime: std::time::SystemTime::now(),
+            kind: FileType::RegularFile,
+            perm: perms & !*UMASK,
+            nlink: 1,
+            uid: unsafe { libc::geteuid() },
+            gid: unsafe { libc::getgid() },
+            rdev: 0,
+            flags: 0,
+            blksize: 512,
+        }
+    }
+
+    fn make_file_attrs_with_size(ino: u64, perms: u16, size: u64) -> FileAttr {
+        let mut attrs = Self::make_file_attrs(ino, perms);
+        attrs.size = size;
+        attrs
+    }
+
+    /// The generation of the inos given to fragment `id`: the one of the
+    /// previous session if it keeps its inos, the current mount otherwise
+    fn fragment_generation(&self, id: &str, inos: (u64, u64)) -> u64 {
+        if self.stable_inos.is_known(id, inos) {
+            self.reference_generations.get(id).copied().unwrap_or(1)
+        } else {
+            self.epoch
+        }
+    }
+
+    /// The generation of `ino`, telling apart the successive files it may
+    /// designate across recyclings and remounts, e.g. in NFS file handles
+    fn generation(&self, ino: u64) -> u64 {
+        if let Some(fragment) = self.fragment_from_ino(ino) {
+            fragment.generation
+        } else if let Some(download) = self.downloads.get(&ino) {
+            self.fragment_from_ino(download.fasta_ino)
+                .map(|f| f.generation)
+                .unwrap_or(self.epoch)
+        } else if let Some(end) = self.ends.get(&ino) {
+            self.fragment_from_ino(end.fasta_ino)
+                .map(|f| f.generation)
+                .unwrap_or(self.epoch)
+        } else if let Some(view) = self.view_files.get(&ino) {
+            self.fragment_from_ino(view.fasta_ino)
+                .map(|f| f.generation)
+                .unwrap_or(self.epoch)
+        } else if let Some(tags) = self.tag_files.get(&ino) {
+            self.fragment_from_ino(tags.fasta_ino)
+                .map(|f| f.generation)
+                .unwrap_or(self.epoch)
+        } else if self.snapshot_inos.contains_key(&ino) {
+            self.epoch
+        } else if self.inos.owns(ino) {
+            (self.epoch << 32) | self.inos.generation(ino)
+        } else {
+            1
+        }
+    }
+
+    fn reply_entry(&self, reply: ReplyEntry, attrs: &FileAttr) {
+        reply.entry(&self.ttl, attrs, self.generation(attrs.ino));
+    }
+
+    fn new_ino(&mut self) -> u64 {
+        self.inos.allocate()
+    }
+
+    /// Recycle `ino` once the kernel has forgotten it, unless it still
+    /// designates a file that may be looked up again
+    fn forget_ino(&mut self, ino: u64) {
+        if !self.inos.owns(ino)
+            || self.region_dirs.values().any(|&i| i == ino)
+            || self.pending_appends.values().any(|p| p.attrs.ino == ino)
+        {
+            return;
+        }
+        match self.subfragment_from_ino(ino) {
+            Some(sf) if sf.listed => {}
+            Some(_) => {
+                trace!("Dropping subfragment {}", ino);
+                self.subfragments.remove_alt(&ino);
+                self.inos.release(ino);
+            }
+            None => {
+                self.versions.remove(&ino);
+                self.inos.release(ino)
+            }
+        }
+    }
+
+    fn save_session(&self) {
+        // With --catalog, the state of the fragments is saved there instead
+        let fragments = if self.catalogued_state() {
+            &[][..]
+        } else {
+            &self.fragments[..]
+        };
+        let session = Session {
+            inodes: fragments
+                .iter()
+                .map(|f| (f.id.to_string(), [f.fasta_file.ino, f.seq_file.ino]))
+                .collect(),
+            generations: fragments
+                .iter()
+                .map(|f| (f.id.to_string(), f.generation))
+                .collect(),
+            mounts: self.epoch,
+            tags: fragments
+                .iter()
+                .filter(|f| !f.tags.is_empty())
+                .map(|f| (f.id.to_string(), f.tags.clone()))
+                .collect(),
+            bookmarks: self.bookmarks.clone(),
+            directories: self.directories.clone(),
+            // Edited fragments are always written out, and thus have a fresh
+            // digest; the others keep the one from the previous session. When
+            // written elsewhere, the mounted file keeps its digests.
+            digests: if self.settings.output.is_some() {
+                self.reference_digests.clone()
+            } else {
+                fragments
+                    .iter()
+                    .filter_map(|f| {
+                        f.digest
+                            .as_ref()
+                            .or_else(|| self.reference_digests.get(f.original_id.as_str()))
+                            .map(|d| (f.id.to_string(), d.to_owned()))
+                    })
+                    .collect()
+            },
+        };
+        let path = match self.session_file.as_ref() {
+            Some(path) => path,
+            None => return,
+        };
+        let saved = session.save(path);
+        #[cfg(feature = "catalog")]
+        let saved = saved.and_then(|_| match &self.catalog {
+            Some(catalog) => catalog.save(
+                || {
+                    self.fragments.iter().map(|f| catalog::State {
+                        id: &f.id,
+                        original_id: &f.original_id,
+                        inos: [f.fasta_file.ino, f.seq_file.ino],
+                        generation: f.generation,
+                        digest: f.digest.as_deref(),
+                        tags: &f.tags,
+                    })
+                },
+                self.settings.output.is_some(),
+            ),
+            None => Ok(()),
+        });
+        match saved {
+            Ok(_) => self.session_unsaved.set(false),
+            Err(e) if !self.session_unsaved.replace(true) => {
+                warn!("Unable to save the session; the inodes, tags and bookmarks will not persist across remounts: {:?}", e)
+            }
+            Err(e) => debug!("Unable to save the session: {:?}", e),
+        }
+    }
+
+    fn get_file(&mut self, ino: u64) -> Option<&mut Box<dyn VirtualFile + Send>> {
+        self.files.iter_mut().find(|f| f.ino() == ino)
+    }
+
+    fn ctl_file_from_name(&self, name: &str) -> Option<&(dyn VirtualFile + Send)> {
+        self.files
+            .iter()
+            .filter(|f| CTL_FILES.contains(&f.ino()))
+            .find(|f| f.name() == name)
+            .map(|f| f.as_ref())
+    }
+
+    /// Read the fragments of `filename`, then those of the --merge files
+    fn read_fasta(&mut self, filename: &str) -> Result<()> {
+        // The sequences read again replace the current ones
+        self.spill = SpillCache::new(self.settings.memory_budget);
+        self.mappings = mapping_budget();
+        let mut collisions = Vec::new();
+        let (file, records) = self.read_records(filename, &mut collisions)?;
+        self.filename = filename.to_owned();
+        self.fragments = self.fragments_from_records(records, &file, None)?;
+        self.load_digests(file.file());
+        let mut sources = vec![file];
+        for (origin, merged) in self.settings.merge.clone().iter().enumerate() {
+            let (file, records) = self
+                .read_records(merged, &mut collisions)
+                .context(format!("while parsing {}", merged))?;
+            let records = self.merge_records(merged, records)?;
+            let fragments = self.fragments_from_records(records, &file, Some(origin))?;
+            self.fragments.extend(fragments);
+            sources.push(file);
+        }
+        let report = if collisions.is_empty() {
+            String::new()
+        } else {
+            std::iter::once("id\tnormalized id\tfile\theader".to_owned())
+                .chain(collisions)
+                .map(|l| l + "\n")
+                .collect()
+        };
+        if let Some(x) = self.get_file(COLLISIONS_FILE) {
+            x.set_data(report.as_bytes());
+            x.mut_attrs().size = report.len() as u64;
+        }
+        self.make_snapshot(sources);
+        self.record_committed();
+        self.refresh_metadata(true);
+        info!("Done.");
+        Ok(())
+    }
+
+    /// Reuse the digests computed by `fusta checksum` for the sequences of
+    /// `file`, the mounted FASTA file, if they are still valid
+    fn load_digests(&mut self, file: &fs::File) {
+        let mtime = match file.metadata() {
+            Ok(metadata) => DigestCache::mtime(&metadata),
+            Err(_) => return,
+        };
+        let cache = DigestCache::load(&DigestCache::path_for(&self.filename));
+        let mut found = 0;
+        for fragment in self.fragments.iter_mut() {
+            if let Some((start, end, _)) = fragment.offsets {
+                fragment.digest = cache.get((start, end), mtime).map(str::to_owned);
+                found += usize::from(fragment.digest.is_some());
+            }
+        }
+        if found > 0 {
+            info!("{} digests found in the checksum cache", found);
+        }
+    }
+
+    /// The records of the FASTA file `filename`, by their normalized ID;
+    /// the records colliding are listed in `collisions`
+    fn read_records(
+        &self,
+        filename: &str,
+        collisions: &mut Vec<String>,
+    ) -> Result<(Backing, Records)> {
+        info!("Reading {}...", filename);
+        let file =
+            Backing::open(filename).context(format!("failed to open file `{}`", filename))?;
+        // With the memory cache, the sequences are only read one by one
+        // afterwards, to be spilled as they come if need be
+        let fragments = match self.catalogued(filename, file.file()) {
+            Some(fragments) => {
+                info!("Using the records of {} kept in the catalog", filename);
+                fragments
+            }
+            None => {
+                let index_path = index::path_for(std::path::Path::new(filename));
+                let fai_path = fai::path_for(std::path::Path::new(filename));
+                let indexed = if index_path.exists() {
+                    Some((&index_path, Index::load(&index_path, file.file())))
+                } else if fai_path.exists() {
+                    // Only the headers are read, right before the sequences
+                    let load = || {
+                        fai::load(&fai_path, file.file(), file.size()?, |buffer, offset| {
+                            file.read_exact_at(buffer, offset)
+                        })
+                    };
+                    Some((&fai_path, load()))
+                } else {
+                    None
+                };
+                match indexed {
+                    Some((path, Ok(fragments))) => {
+                        info!("Using the index {}", path.display());
+                        fragments
+                    }
+                    Some((path, Err(e))) => {
+                        warn!(
+                            "Ignoring {}: {:#}; scanning {} instead",
+                            path.display(),
+                            e,
+                            filename
+                        );
+                        self.save_index(
+                            filename,
+                            file.file(),
+                            FastaReader::new(file.reader(), false).collect(),
+                        )
+                    }
+                    None => self.save_index(
+                        filename,
+                        file.file(),
+                        FastaReader::new(file.reader(), false).collect(),
+                    ),
+                }
+            }
+        };
+        let records = fragments
+            .into_iter()
+            .map(|f| (self.settings.id_rules.apply(&f.id), f))
+            .collect::<Vec<_>>();
+        let records = suffix_collisions(filename, records, collisions);
+        Ok((file, records))
+    }
+
+    /// Whether the state of the fragments is kept in the catalog, with
+    /// --catalog, rather than in the session
+    #[cfg(feature = "catalog")]
+    fn catalogued_state(&self) -> bool {
+        self.catalog.is_some()
+    }
+    #[cfg(not(feature = "catalog"))]
+    fn catalogued_state(&self) -> bool {
+        false
+    }
+
+    /// The records of `filename`, open as `file`, kept in the catalog with
+    /// --catalog, provided that it did not change since
+    #[cfg(feature = "catalog")]
+    fn catalogued(&self, filename: &str, file: &fs::File) -> Option<Vec<fusta::fasta::Fragment>> {
+        let catalog = self.catalog.as_ref()?;
+        index::Source::of(file)
+            .map_err(anyhow::Error::from)
+            .and_then(|source| catalog.records(filename, &source))
+            .unwrap_or_else(|e| {
+                warn!(
+                    "Unable to read the records of {} from the catalog: {:#}",
+                    filename, e
+                );
+                None
+            })
+    }
+    #[cfg(not(feature = "catalog"))]
+    fn catalogued(&self, _: &str, _: &fs::File) -> Option<Vec<fusta::fasta::Fragment>> {
+        None
+    }
+
+    /// Index the freshly scanned `fragments` of `filename`, open as `file`,
+    /// in the catalog with --catalog, or else in FASTA.fusta-idx unless
+    /// --no-index, so that the next mounts do not have to scan it again
+    fn save_index(
+        &self,
+        filename: &str,
+        file: &fs::File,
+        fragments: Vec<fusta::fasta::Fragment>,
+    ) -> Vec<fusta::fasta::Fragment> {
+        if self.settings.no_index && !self.catalogued_state() {
+            return fragments;
+        }
+        let path = index::path_for(Path::new(filename));
+        let source = match index::Source::of(file) {
+            Ok(source) => source,
+            Err(e) => {
+                warn!("Unable to index {}: {}", filename, e);
+                return fragments;
+            }
+        };
+        #[cfg(feature = "catalog")]
+        if let Some(catalog) = &self.catalog {
+            match catalog.set_records(filename, &source, &fragments) {
+                Ok(_) => info!("Kept the records of {} in the catalog", filename),
+                Err(e) => warn!(
+                    "Unable to keep the records of {} in the catalog: {:#}",
+                    filename, e
+                ),
+            }
+            return fragments;
+        }
+        let index = Index { source, fragments };
+        match index.write(&path) {
+            Ok(_) => info!("Indexed the sequences in {}", path.display()),
+            // E.g. in a read-only directory, where the file is merely
+            // scanned again at every mount
+            Err(e) => warn!("Unable to write {}: {}", path.display(), e),
+        }
+        index.fragments
+    }
+
+    /// Rename the `records` of the --merge file `filename` whose ID is
+    /// already taken, as per --on-merge-collision
+    fn merge_records(&self, filename: &str, records: Records) -> Result<Records> {
+        let mut existing = self
+            .fragments
+            .iter()
+            .map(|f| f.id.to_string())
+            .collect::<HashSet<_>>();
+        let stem = std::path::Path::new(filename)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or(filename);
+        let mut r = Vec::with_capacity(records.len());
+        for (id, record) in records {
+            let id = if !existing.contains(&id) {
+                id
+            } else {
+                let renamed = match self.settings.on_merge_collision {
+                    MergeCollision::Refuse => anyhow::bail!(
+                        "`{}` from `{}` is already the ID of another sequence; see --on-merge-collision",
+                        id,
+                        filename
+                    ),
+                    MergeCollision::Suffix => id.clone(),
+                    MergeCollision::Prefix => format!("{}__{}", stem, id),
+                };
+                let renamed = if existing.contains(&renamed) {
+                    (2..)
+                        .map(|n| format!("{}__{}", renamed, n))
+                        .find(|id| !existing.contains(id))
+                        .unwrap()
+                } else {
+                    renamed
+                };
+                warn!("`{}` from `{}` mounted as `{}`", id, filename, renamed);
+                renamed
+            };
+            existing.insert(id.clone());
+            r.push((id, record));
+        }
+        Ok(r)
+    }
+
+    /// The fragments built from the `records` of the FASTA `file`, written
+    /// back to the --merge file `origin` if any
+    fn fragments_from_records(
+        &mut self,
+        records: Records,
+        file: &Backing,
+        origin: Option<usize>,
+    ) -> Result<Vec<Fragment>> {
+        records
+            .into_iter()
+            .map(|(id, fragment)| {
+                if fragment.id.is_empty() {
+                    Err(anyhow::anyhow!(format!(
+                        "The header `>{}`, followed by the sequence at byte {}, has no ID",
+                        fragment.header.trim_end(),
+                        fragment.pos.0
+                    )))
+                } else if id.is_empty() {
+                    Err(anyhow::anyhow!(format!(
+                        "Fragment ID `{}` is empty once normalized",
+                        fragment.id
+                    )))
+                } else if let Err(e) = self.settings.codec.check(&id) {
+                    Err(anyhow::anyhow!(format!(
+                        "Fragment ID `{}`: {}; a --filename-codec other than plain may help",
+                        id, e
+                    )))
+                } else {
+                    self.fragment_from_record(&id, fragment, file, origin)
+                        .with_context(|| format!("while reading `{}`", id))
+                }
+            })
+            .collect()
+    }
+
+    /// Build the fragment `id` from `record`, as read from the FASTA `file`,
+    /// either the --merge file `origin` or the mounted one; with the memory
+    /// cache, its sequence is read from `file` unless the record holds it
+    fn fragment_from_record(
+        &mut self,
+        id: &str,
+        record: fusta::fasta::Fragment,
+        file: &Backing,
+        origin: Option<usize>,
+    ) -> std::io::Result<Fragment> {
+        let seq = match (self.settings.cache, record.seq) {
+            (Cache::RAM, Some(seq)) => Some(seq),
+            (Cache::RAM, None) => {
+                let mut seq = vec![0; record.pos.1 - record.pos.0];
+                file.read_exact_at(&mut seq, record.pos.0 as u64)?;
+                // As read by FastaReader
+                Some(
+                    seq.split(|&c| c == b'\n')
+                        .flat_map(|l| l.trim_ascii_end())
+                        .copied()
+                        .collect(),
+                )
+            }
+            _ => None,
+        };
+        let (fasta_ino, seq_ino) = self.stable_inos.inos_for(id);
+        let offsets = (record.pos.0, record.pos.1, record.line_width);
+        let mapped = match (self.settings.cache, file) {
+            (Cache::Mmap, Backing::Plain(plain)) if record.len > 0 && self.mappings > 0 => {
+                match unsafe {
+                    memmap2::MmapOptions::new()
+                        .offset(record.pos.0 as u64)
+                        .len(record.len)
+                        .map(&**plain)
+                } {
+                    Ok(map) => {
+                        self.mappings -= 1;
+                        if self.mappings == 0 {
+                            warn!(
+                                "{} holds too many sequences to map them all (see vm.max_map_count); \
+                                 reading the others through the file handle, as with --cache file",
+                                self.filename
+                            );
+                        }
+                        Some(map)
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Unable to map `{}` ({}); reading it and the following sequences \
+                             through the file handle, as with --cache file",
+                            id, e
+                        );
+                        self.mappings = 0;
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        let mut fragment = Fragment::new(
+            id,
+            &record.name,
+            match (self.settings.cache, file, mapped) {
+                // Empty sequences can not be mapped
+                (Cache::Mmap, Backing::Plain(_), _) if record.len == 0 => {
+                    Arc::<PureBuffer>::default()
+                }
+                (_, _, Some(map)) => Arc::new(Mmapped(map)),
+                (Cache::RAM, _, _) => Arc::new(self.spill.hold(seq.unwrap())),
+                // Compressed files, or those holding more sequences than can
+                // be mapped, are read through the file handle
+                (Cache::File | Cache::Mmap, _, None) => Arc::new(FileSlice::new(
+                    file.clone(),
+                    origin
+                        .map_or(&self.filename, |o| &self.settings.merge[o])
+                        .as_str()
+                        .into(),
+                    record.pos.0,
+                    record.pos.1,
+                    self.settings.retry,
+                )),
+            },
+            (fasta_ino, seq_ino),
+            self.settings.codec,
+            self.metadata.accessed().unwrap(),
+            self.metadata.modified().unwrap(),
+        );
+        fragment.offsets = Some(offsets);
+        fragment.origin = origin;
+        fragment.generation = self.fragment_generation(id, (fasta_ino, seq_ino));
+        fragment.set_header(&record.header);
+        if self.settings.writable_fasta {
+            fragment.fasta_file.attrs.perm = 0o664 & !*UMASK;
+        }
+        Ok(fragment)
+    }
+
+    fn make_snapshot(&mut self, sources: Vec<Backing>) {
+        let stable_inos = &mut self.stable_inos;
+        self.snapshot = self
+            .fragments
+            .iter()
+            .filter_map(|f| f.offsets.map(|(start, end, _)| (f, start, end)))
+            .map(|(f, start, end)| {
+                let name = format!("{}{}", f.codec.encode(&f.id), SEQ_EXT);
+                let ino = stable_inos.ino_for(&format!("snapshot/{}", f.id));
+                let mut attrs =
+                    FustaFS::make_file_attrs_with_size(ino, 0o444, (end - start) as u64);
+                attrs.mtime = f.seq_file.attrs.mtime;
+                attrs.atime = f.seq_file.attrs.atime;
+                SnapshotEntry {
+                    name: name.into(),
+                    source: f.origin.map_or(0, |i| i + 1),
+                    start: start as u64,
+                    attrs,
+                }
+            })
+            .collect();
+        self.snapshot_names = self
+            .snapshot
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (e.name.to_string(), i))
+            .collect();
+        self.snapshot_inos = self
+            .snapshot
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (e.attrs.ino, i))
+            .collect();
+        self.snapshot_sources = sources;
+    }
+
+    /// The attributes of `seqs/ID.seq@VERSION`, the sequence of a fragment
+    /// as it was when the file was mounted if `VERSION` is `orig`, or before
+    /// its `VERSION`-th last edit, as long as it is kept in the undo history
+    fn version(&mut self, name: &str) -> Result<FileAttr, FustaError> {
+        let (file, version) = name.rsplit_once('@').unwrap();
+        let not_found = |why: &str| FustaError::NotFound(format!("`{}`: {}", name, why));
+        let fragment = self.fragment_from_seq_filename(file);
+        if version == "orig" {
+            // The snapshot is found under the ID the fragment was mounted with
+            let original = match fragment {
+                Some(f) => format!("{}{}", f.codec.encode(&f.original_id), SEQ_EXT),
+                None => file.to_owned(),
+            };
+            return self
+                .snapshot_names
+                .get(&original)
+                .map(|&i| self.snapshot[i].attrs)
+                .ok_or_else(|| not_found("the sequence was not in the mounted file"));
+        }
+
+        let steps = version
+            .parse::<usize>()
+            .ok()
+            .filter(|&n| n > 0)
+            .ok_or_else(|| not_found("the version should be `orig` or a number of edits"))?;
+        let seq_ino = fragment
+            .map(|f| f.seq_file.ino)
+            .ok_or_else(|| not_found("no such sequence"))?;
+        let edit = self
+            .history
+            .iter()
+            .rev()
+            .filter(|e| e.ino == seq_ino)
+            .nth(steps - 1)
+            .ok_or_else(|| not_found("not that many edits are kept in the undo history"))?;
+        let (serial, size) = (edit.serial, edit.data.len());
+        if let Some(version) = self.versions.values().find(|v| v.serial == serial) {
+            return Ok(version.attrs);
+        }
+        let attrs = FustaFS::make_file_attrs_with_size(self.new_ino(), 0o444, size as u64);
+        self.versions.insert(attrs.ino, Version { serial, attrs });
+        Ok(attrs)
+    }
+
+    /// The ino of the compressed rendering of the fasta/ file `fasta_ino`,
+    /// created on first access
+    fn download_ino(&mut self, fasta_ino: u64) -> u64 {
+        if let Some(&ino) = self.download_inos.get(&fasta_ino) {
+            return ino;
+        }
+        let ino = self.stable_inos.ino_for(&format!("download/{}", fasta_ino));
+        // The compressed size is only known once the whole file has been
+        // compressed; reads are thus served in direct I/O, ignoring it.
+        let mut attrs = FustaFS::make_file_attrs(ino, 0o444);
+        if let Some(fasta) = self.attrs_from_ino(fasta_ino) {
+            attrs.mtime = fasta.mtime;
+            attrs.atime = fasta.atime;
+        }
+        self.download_inos.insert(fasta_ino, ino);
+        self.downloads.insert(
+            ino,
+            Download {
+                fasta_ino,
+                attrs,
+                stream: BgzfStream::default(),
+            },
+        );
+        ino
+    }
+
+    /// The ino of the file of ends/ exposing the head, or the `tail`, of the
+    /// fasta/ file `fasta_ino`, created on first access
+    fn end_ino(&mut self, fasta_ino: u64, tail: bool) -> u64 {
+        if let Some(&ino) = self.end_inos.get(&(fasta_ino, tail)) {
+            return ino;
+        }
+        let ino = self.stable_inos.ino_for(&format!(
+            "ends/{}.{}",
+            fasta_ino,
+            if tail { "tail" } else { "head" }
+        ));
+        let mut attrs = FustaFS::make_file_attrs(ino, 0o444);
+        if let Some(fasta) = self.attrs_from_ino(fasta_ino) {
+            attrs.mtime = fasta.mtime;
+            attrs.atime = fasta.atime;
+        }
+        self.end_inos.insert((fasta_ino, tail), ino);
+        self.ends.insert(
+            ino,
+            End {
+                fasta_ino,
+                tail,
+                attrs,
+            },
+        );
+        self.update_end_size(ino);
+        ino
+    }
+
+    /// The entries of the root directory
+    fn root_entries(&self) -> BTreeMap<u64, (FileType, &str)> {
+        let mut entries = btreemap! {
+            ROOT_DIR         => (FileType::Directory, "."),
+            0                => (FileType::Directory, ".."), // TODO
+            FASTA_DIR        => (FileType::Directory, "fasta"),
+            SEQ_DIR          => (FileType::Directory, "seqs"),
+            APPEND_DIR       => (FileType::Directory, "append"),
+            SUBFRAGMENTS_DIR => (FileType::Directory, "get"),
+            CTL_DIR          => (FileType::Directory, "ctl"),
+            SNAPSHOT_DIR     => (FileType::Directory, "snapshot"),
+            DOWNLOAD_DIR     => (FileType::Directory, "download"),
+            DIGEST_DIR       => (FileType::Directory, DIGEST_DIR_NAME),
+            PCR_DIR          => (FileType::Directory, PCR_DIR_NAME),
+            ENDS_DIR         => (FileType::Directory, ENDS_DIR_NAME),
+            TAGS_DIR         => (FileType::Directory, TAGS_DIR_NAME),
+            TAXA_DIR         => (FileType::Directory, TAXA_DIR_NAME),
+            META_DIR         => (FileType::Directory, META_DIR_NAME),
+            INFO_FILE        => (FileType::RegularFile, INFO_FILE_NAME),
+            SUMMARY_FILE     => (FileType::RegularFile, SUMMARY_FILE_NAME),
+            INFO_CSV_FILE    => (FileType::RegularFile, INFO_CSV_FILE_NAME),
+            INFO_JSON_FILE   => (FileType::RegularFile, INFO_JSON_FILE_NAME),
+            LABELS_FILE      => (FileType::RegularFile, LABELS_FILE_NAME),
+            OFFSETS_FILE     => (FileType::RegularFile, OFFSETS_FILE_NAME),
+            APPENDS_FILE     => (FileType::RegularFile, APPENDS_FILE_NAME),
+            STATS_FILE       => (FileType::RegularFile, STATS_FILE_NAME),
+            DUPLICATES_FILE  => (FileType::RegularFile, DUPLICATES_FILE_NAME),
+            REPEATS_FILE     => (FileType::RegularFile, REPEATS_FILE_NAME),
+            FORMAT_FILE      => (FileType::RegularFile, FORMAT_FILE_NAME),
+            FAI_FILE         => (FileType::RegularFile, FAI_FILE_NAME),
+        };
+        if self.settings.aliases.is_some() {
+            entries.insert(ALIASES_DIR, (FileType::Directory, ALIASES_DIR_NAME));
+        }
+        if self.is_annotated() {
+            entries.insert(PROTEINS_DIR, (FileType::Directory, PROTEINS_DIR_NAME));
+            entries.insert(FEATURES_DIR, (FileType::Directory, FEATURES_DIR_NAME));
+            entries.insert(
+                CODON_USAGE_FILE,
+                (FileType::RegularFile, CODON_USAGE_FILE_NAME),
+            );
+        }
+        for view in self.view_dirs.iter() {
+            entries.insert(view.ino, (FileType::Directory, view.name.as_str()));
+        }
+        entries
+    }
+
+    /// Expose the view directories set in the configuration file
+    fn make_views(&mut self) -> Result<()> {
+        for (name, pipeline) in self.settings.views.clone() {
+            if self
+                .root_entries()
+                .values()
+                .any(|(_, entry)| *entry == name)
+            {
+                anyhow::bail!(
+                    "view `{}` clashes with another entry of the mount point",
+                    name
+                )
+            }
+            let ino = self.stable_inos.ino_for(&format!("views/{}", name));
+            self.dir_attrs
+                .insert(ino, FustaFS::make_dir_attrs(ino, 0o555));
+            self.view_dirs.push(ViewDir {
+                name,
+                pipeline,
+                ino,
+            });
+        }
+        Ok(())
+    }
+
+    /// The view directory `ino`, if it is one
+    fn view_dir(&self, ino: u64) -> Option<usize> {
+        self.view_dirs.iter().position(|d| d.ino == ino)
+    }
+
+    /// The ino of the file of view directory `dir` exposing the fasta/ file
+    /// `fasta_ino`, created on first access
+    fn view_ino(&mut self, dir: usize, fasta_ino: u64) -> u64 {
+        if let Some(&ino) = self.view_inos.get(&(dir, fasta_ino)) {
+            return ino;
+        }
+        let ino = self
+            .stable_inos
+            .ino_for(&format!("views/{}/{}", self.view_dirs[dir].name, fasta_ino));
+        let mut attrs = FustaFS::make_file_attrs(ino, 0o444);
+        if let Some(fasta) = self.attrs_from_ino(fasta_ino) {
+            attrs.mtime = fasta.mtime;
+            attrs.atime = fasta.atime;
+        }
+        self.view_inos.insert((dir, fasta_ino), ino);
+        self.view_files.insert(
+            ino,
+            ViewFile {
+                dir,
+                fasta_ino,
+                attrs,
+            },
+        );
+        self.update_view_size(ino);
+        ino
+    }
+
+    /// The header of the file of a view directory `ino`, its fragment, and
+    /// how many bases its lines hold
+    fn view_of(&self, ino: u64) -> Result<(String, &Fragment, usize), FustaError> {
+        let file = &self.view_files[&ino];
+        let fragment = self.fragment_from_ino(file.fasta_ino).ok_or_else(|| {
+            FustaError::NotFound(format!("{} has been removed", self.describe(ino)))
+        })?;
+        let width = if self.view_dirs[file.dir].pipeline.single_line() {
+            fragment
+                .sequence_len()
+                .map_err(|e| fragment.unreadable(e))?
+                .max(1)
+        } else {
+            LINE_WIDTH
+        };
+        Ok((fragment.label(), fragment, width))
+    }
+
+    /// The `size` bytes at `offset` of the file of a view directory `ino`;
+    /// only the bases they cover are read and transformed, so that whole
+    /// chromosomes can be streamed
+    fn read_view(&self, ino: u64, offset: i64, size: u32) -> Result<Vec<u8>, FustaError> {
+        let (header, fragment, width) = self.view_of(ino)?;
+        let pipeline = &self.view_dirs[self.view_files[&ino].dir].pipeline;
+        let len = fragment
+            .sequence_len()
+            .map_err(|e| fragment.unreadable(e))?;
+        let range = window(offset, size, header.len() + len + len.div_ceil(width));
+        let mut r =
+            header.as_bytes()[range.start.min(header.len())..range.end.min(header.len())].to_vec();
+        let body = range.start.saturating_sub(header.len())..range.end.saturating_sub(header.len());
+        if !body.is_empty() {
+            // The whole lines spanned by the range
+            let lines = body.start / (width + 1)..body.end.div_ceil(width + 1);
+            let bases = lines.start * width..(lines.end * width).min(len);
+            let seq = pipeline
+                .extract(len, bases, |o, s| {
+                    fragment.pure_chunk(o, s).map(|c| c.into_vec())
+                })
+                .map_err(|e| fragment.unreadable(e))?;
+            let mut text = Vec::with_capacity(seq.len() + lines.len());
+            for line in seq.chunks(width) {
+                text.extend_from_slice(line);
+                text.push(b'\n');
+            }
+            let skip = body.start - lines.start * (width + 1);
+            r.extend_from_slice(&text[skip..skip + body.len()]);
+        }
+        Ok(r)
+    }
+
+    /// The size of the views follows the length of the fragments
+    fn update_view_size(&mut self, ino: u64) {
+        let size = self
+            .view_of(ino)
+            .and_then(|(header, fragment, width)| {
+                let len = fragment
+                    .sequence_len()
+                    .map_err(|e| fragment.unreadable(e))?;
+                Ok(header.len() + len + len.div_ceil(width))
+            })
+            .unwrap_or(0);
+        self.view_files.get_mut(&ino).unwrap().attrs.size = size as u64;
+    }
+
+    /// The header of the file of ends/ `ino`, and the range it covers in its
+    /// fragment
+    fn end_of(&self, ino: u64) -> Result<(String, &Fragment, std::ops::Range<usize>), FustaError> {
+        let end = &self.ends[&ino];
+        let fragment = self.fragment_from_ino(end.fasta_ino).ok_or_else(|| {
+            FustaError::NotFound(format!("{} has been removed", self.describe(ino)))
+        })?;
+        let len = fragment
+            .sequence_len()
+            .map_err(|e| fragment.unreadable(e))?;
+        let range = if end.tail {
+            len.saturating_sub(self.settings.end_length)..len
+        } else {
+            0..self.settings.end_length.min(len)
+        };
+        let name = if end.tail { "tail" } else { "head" };
+        let header = if range.is_empty() {
+            format!(">{}_{}\n", fragment.id, name)
+        } else {
+            format!(
+                ">{}_{} {}:{}-{}\n",
+                fragment.id,
+                name,
+                fragment.id,
+                range.start + 1,
+                range.end
+            )
+        };
+        Ok((header, fragment, range))
+    }
+
+    /// The content of the file of ends/ `ino`
+    fn read_end(&self, ino: u64) -> Result<Vec<u8>, FustaError> {
+        let (header, fragment, range) = self.end_of(ino)?;
+        let mut r = header.into_bytes();
+        for line in fragment
+            .pure_chunk(range.start, range.len())
+            .map_err(|e| fragment.unreadable(e))?
+            .chunks(LINE_WIDTH)
+        {
+            r.extend_from_slice(line);
+            r.push(b'\n');
+        }
+        Ok(r)
+    }
+
+    /// As the ends may be shorter than `end_length`, their size follows the
+    /// length of the fragments
+    fn update_end_size(&mut self, ino: u64) {
+        let size = self
+            .end_of(ino)
+            .map(|(header, _, range)| header.len() + range.len() + range.len().div_ceil(LINE_WIDTH))
+            .unwrap_or(0);
+        self.ends.get_mut(&ino).unwrap().attrs.size = size as u64;
+    }
+
+    /// The ino of the file of tags/ of the fasta/ file `fasta_ino`, created
+    /// on first access
+    fn tag_ino(&mut self, fasta_ino: u64) -> u64 {
+        if let Some(&ino) = self.tag_inos.get(&fasta_ino) {
+            return ino;
+        }
+        let ino = self.stable_inos.ino_for(&format!("tags/{}", fasta_ino));
+        let mut attrs = FustaFS::make_file_attrs(ino, 0o664);
+        if let Some(fasta) = self.attrs_from_ino(fasta_ino) {
+            attrs.mtime = fasta.mtime;
+            attrs.atime = fasta.atime;
+        }
+        self.tag_inos.insert(fasta_ino, ino);
+        self.tag_files.insert(ino, TagFile { fasta_ino, attrs });
+        self.update_tag_size(ino);
+        ino
+    }
+
+    /// The content of the file of tags/ `ino`, as being written if it is
+    fn read_tags(&self, ino: u64) -> Result<Vec<u8>, FustaError> {
+        if let Some(pending) = self.pending_tags.get(&ino) {
+            return Ok(pending.clone());
+        }
+        self.fragment_from_ino(self.tag_files[&ino].fasta_ino)
+            .map(|f| format_tags(&f.tags).into_bytes())
+            .ok_or_else(|| FustaError::NotFound(format!("{} has been removed", self.describe(ino))))
+    }
+
+    fn update_tag_size(&mut self, ino: u64) {
+        let size = self.read_tags(ino).map(|tags| tags.len()).unwrap_or(0);
+        self.tag_files.get_mut(&ino).unwrap().attrs.size = size as u64;
+    }
+
+    /// The names of the directories of taxa/ leading to fragment `f`, from
+    /// the ranks listed in its description; a rank that can not be a file
+    /// name ends the path
+    fn taxon_path(&self, f: &Fragment) -> Vec<String> {
+        taxonomy(
+            f.name.as_deref().unwrap_or(""),
+            &self.settings.taxa_delimiter,
+        )
+        .into_iter()
+        .take_while(|rank| {
+            *rank != TAXON_COUNT_FILE_NAME && self.settings.codec.check(rank).is_ok()
+        })
+        .map(|rank| self.settings.codec.encode(rank))
+        .collect()
+    }
+
+    /// The ino of the file of taxa/ at `path`, the same one across rebuilds
+    fn taxon_ino(&mut self, path: &str) -> u64 {
+        if let Some(&ino) = self.taxon_inos.get(path) {
+            return ino;
+        }
+        let ino = self.stable_inos.ino_for(path);
+        self.taxon_inos.insert(path.to_owned(), ino);
+        ino
+    }
+
+    /// Add the directory of taxa/ at `path` below `parent`
+    fn make_taxon(&mut self, parent: u64, path: &[String]) -> u64 {
+        let key = std::iter::once(TAXA_DIR_NAME)
+            .chain(path.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join("/");
+        let ino = if path.is_empty() {
+            TAXA_DIR
+        } else {
+            self.taxon_ino(&key)
+        };
+        let count = self.taxon_ino(&format!("{}/{}", key, TAXON_COUNT_FILE_NAME));
+        self.taxa.insert(
+            ino,
+            Taxon {
+                path: path.to_vec(),
+                parent,
+                attrs: self
+                    .dir_attrs
+                    .get(&ino)
+                    .copied()
+                    .unwrap_or_else(|| FustaFS::make_dir_attrs(ino, 0o555)),
+                count: FustaFS::make_file_attrs(count, 0o444),
+                children: BTreeMap::new(),
+                members: Vec::new(),
+            },
+        );
+        self.taxon_counts.insert(count, ino);
+        if let Some(name) = path.last() {
+            self.taxa
+                .get_mut(&parent)
+                .unwrap()
+                .children
+                .insert(name.clone(), ino);
+        }
+        ino
+    }
+
+    /// Group the fragments in taxa/ by the ranks listed in their description,
+    /// unless they have not changed since taxa/ was last built
+    fn make_taxa(&mut self) {
+        if !self.taxa_stale {
+            return;
+        }
+        let paths = self
+            .fragments
+            .iter()
+            .map(|f| (f.fasta_file.ino, self.taxon_path(f)))
+            .collect::<Vec<_>>();
+        self.taxa.clear();
+        self.taxon_counts.clear();
+        self.make_taxon(ROOT_DIR, &[]);
+        for (fasta_ino, path) in paths {
+            let mut dir = TAXA_DIR;
+            self.taxa.get_mut(&dir).unwrap().members.push(fasta_ino);
+            for depth in 0..path.len() {
+                dir = match self.taxa[&dir].children.get(&path[depth]) {
+                    Some(&child) => child,
+                    None => self.make_taxon(dir, &path[..=depth]),
+                };
+                self.taxa.get_mut(&dir).unwrap().members.push(fasta_ino);
+            }
+        }
+        for dir in self.taxa.keys().copied().collect::<Vec<_>>() {
+            let size = self.taxon_count(dir).len() as u64;
+            self.taxa.get_mut(&dir).unwrap().count.size = size;
+        }
+        self.taxa_stale = false;
+    }
+
+    /// The content of the count.txt of the directory of taxa/ `dir`: how many
+    /// fragments lie at or below it, then how many lie below each next rank
+    fn taxon_count(&self, dir: u64) -> String {
+        let taxon = &self.taxa[&dir];
+        std::iter::once(format!("# {} sequences\n", taxon.members.len()))
+            .chain(
+                taxon
+                    .children
+                    .iter()
+                    .map(|(name, child)| format!("{}\t{}\n", name, self.taxa[child].members.len())),
+            )
+            .collect()
+    }
+
+    /// The entry `name` of the directory of taxa/ `dir`: the directory of a
+    /// rank below, its count.txt, or the fasta/ file of one of its members
+    fn taxon_entry(&self, dir: u64, name: &str) -> Option<FileAttr> {
+        let taxon = self.taxa.get(&dir)?;
+        if let Some(child) = taxon.children.get(name) {
+            return Some(self.taxa[child].attrs);
+        }
+        if name == TAXON_COUNT_FILE_NAME {
+            return Some(taxon.count);
+        }
+        self.fragment_from_fasta_filename(name)
+            .filter(|f| f.fasta_file.name == name)
+            .filter(|f| self.taxon_path(f).starts_with(&taxon.path))
+            .map(|f| f.fasta_file.attrs)
+    }
+
+    /// The content being written to the file of tags/ `ino`, initialized
+    /// from the current tags, so that they can be appended to
+    fn pending_tags(&mut self, ino: u64) -> Result<&mut Vec<u8>, FustaError> {
+        if !self.pending_tags.contains_key(&ino) {
+            let tags = self.read_tags(ino)?;
+            self.pending_tags.insert(ino, tags);
+        }
+        Ok(self.pending_tags.get_mut(&ino).unwrap())
+    }
+
+    /// Replace the tags of the fragment of the file of tags/ `ino` by the
+    /// ones written to it
+    fn commit_tags(&mut self, ino: u64) -> Result<(), FustaError> {
+        let content = match self.pending_tags.remove(&ino) {
+            Some(content) => content,
+            None => return Ok(()),
+        };
+        let tags = parse_tags(&String::from_utf8_lossy(&content));
+        let fasta_ino = self.tag_files[&ino].fasta_ino;
+        if let (Ok(tags), Some(i)) = (&tags, self.ino2fragment.get(&fasta_ino).copied()) {
+            let was_written = self.is_written(&self.fragments[i]);
+            self.fragments[i].tags = tags.clone();
+            if self.is_written(&self.fragments[i]) != was_written {
+                self.dirty = true;
+            }
+            // Rather than saving the state of all the fragments again
+            #[cfg(feature = "catalog")]
+            if let Some(catalog) = &self.catalog {
+                if let Err(e) = catalog.set_tags(&self.fragments[i].id, tags) {
+                    warn!(
+                        "Unable to save the tags of `{}`: {:#}",
+                        self.fragments[i].id, e
+                    );
+                }
+            }
+        }
+        self.update_tag_size(ino);
+        self.make_info_json_buffer();
+        if !self.catalogued_state() {
+            self.save_session();
+        }
+        tags.map(|_| ())
+    }
+
+    fn read_snapshot(&self, i: usize, offset: i64, size: u32) -> std::io::Result<Vec<u8>> {
+        let entry = &self.snapshot[i];
+        let range = window(offset, size, entry.attrs.size as usize);
+        let mut buffer = vec![0u8; range.len()];
+        if let Some(source) = self.snapshot_sources.get(entry.source) {
+            source.read_exact_at(&mut buffer, entry.start + range.start as u64)?;
+        }
+        Ok(buffer)
+    }
+
+    /// Apply a mutation to the fragments, then bring the metadata and – if
+    /// need be – the FASTA file up to date.
+    fn apply(&mut self, mutation: Mutation) -> Result<(), FustaError> {
+        // Deletions may come by thousands, e.g. from `rm seqs/*`; with
+        // --defer-deletions, they wait for the next write-back
+        let deferred = self.settings.defer_deletions && matches!(mutation, Mutation::Delete(_));
+        match mutation {
+            Mutation::Append {
+                file,
+                data,
+                atime,
+                mtime,
+            } => self.append(&file, &data, atime, mtime),
+            Mutation::Delete(id) => {
+                self.forget_history(&id);
+                let length_before = self.fragments.len();
+                self.fragments.retain(|f| f.id != id);
+                // Only mark as dirty if we effectively removed something
+                if self.fragments.len() != length_before {
+                    self.dirty = true;
+                }
+            }
+            Mutation::Rename { from, to } => {
+                if let Some(ino) = self.fragment_from_id(&from).map(|f| f.seq_file.ino) {
+                    if from != to {
+                        self.remember(ino);
+                    }
+                }
+                if from != to && self.fragment_from_id(&to).is_some() {
+                    warn!("Replacing {}", to);
+                    self.forget_history(&to);
+                    self.fragments.retain(|f| f.id != to);
+                }
+                if let Some(fragment) = self.fragments.iter_mut().find(|f| f.id == from) {
+                    fragment.rename(&to);
+                    self.dirty = true;
+                }
+            }
+        }
+        self.refresh_metadata(false);
+        if deferred {
+            return Ok(());
+        }
+        self.concretize(false)
+    }
+
+    /// Parse the content written to `file` in the append directory and add
+    /// the fragments it contains
+    fn append(&mut self, file: &str, data: &[u8], atime: SystemTime, mtime: SystemTime) {
+        let (stem, forced) = match file.strip_prefix(FORCE_APPEND_PREFIX) {
+            Some(stem) if !stem.is_empty() => (stem, true),
+            _ => (file, false),
+        };
+        // Sequences appended without a header are named after their file
+        let header = if starts_with_header(data) {
+            String::new()
+        } else {
+            format!(">{}\n", self.headerless_id(stem))
+        };
+
+        let mut existing = self
+            .fragments
+            .iter()
+            .map(|f| f.id.clone())
+            .collect::<HashSet<_>>();
+        let mut report = Vec::new();
+        for new_fragment in FastaReader::new(header.as_bytes().chain(data), true) {
+            let normalized = match self.settings.id_rules.apply(&new_fragment.id) {
+                id if id.is_empty() => new_fragment.id.clone(),
+                id => SString::from(id),
+            };
+            if let Err(e) = self.settings.codec.check(&normalized) {
+                error!("Skipping `{}`: {}", &normalized, e);
+                report.push((file.to_owned(), new_fragment.id, "skipped", None));
+                continue;
+            }
+            // Overwriting a fragment whose edits have not been written back
+            // yet would silently lose them
+            let edited = existing.contains(&normalized)
+                && self.fragment_from_id(&normalized).is_some_and(|f| f.edited);
+            let policy = match self.settings.on_append_collision {
+                _ if forced && edited => self.settings.on_edit_conflict,
+                _ if forced => Collision::Overwrite,
+                Collision::Overwrite if edited => self.settings.on_edit_conflict,
+                policy => policy,
+            };
+            let (id, action) = if !existing.contains(&normalized) {
+                (normalized.clone(), "added")
+            } else {
+                match policy {
+                    Collision::Skip if edited => {
+                        let e = FustaError::Conflict(format!(
+                            "Not replacing `{}` with the one appended from `{}`: it has unsaved edits",
+                            &normalized, file
+                        ));
+                        notify(e.to_string());
+                        self.fail(e);
+                        report.push((file.to_owned(), new_fragment.id, "refused", None));
+                        continue;
+                    }
+                    Collision::Skip => {
+                        error!("Skipping `{}`, already existing", &normalized);
+                        report.push((file.to_owned(), new_fragment.id, "skipped", None));
+                        continue;
+                    }
+                    Collision::Overwrite => {
+                        warn!("Replacing {}", &normalized);
+                        self.forget_history(&normalized);
+                        self.fragments.retain(|f| f.id != normalized);
+                        (normalized.clone(), "replaced")
+                    }
+                    Collision::Suffix => {
+                        let id = (2..)
+                            .map(|n| SString::from(format!("{}__{}", normalized, n)))
+                            .find(|id| !existing.contains(id))
+                            .unwrap();
+                        warn!("`{}` already exists; appending it as `{}`", &normalized, id);
+                        (id, "suffixed")
+                    }
+                }
+            };
+            existing.insert(id.clone());
+            report.push((
+                file.to_owned(),
+                new_fragment.id.clone(),
+                action,
+                Some(id.clone()),
+            ));
+
+            let (fasta_ino, seq_ino) = self.stable_inos.inos_for(&id);
+            let mut seq = new_fragment.seq.unwrap();
+            self.settings.write_transform.apply(&mut seq);
+            let mut fragment = Fragment::new(
+                &id,
+                &new_fragment.name,
+                Arc::new(PureBuffer(seq)),
+                (fasta_ino, seq_ino),
+                self.settings.codec,
+                atime,
+                mtime,
+            );
+            fragment.generation = self.fragment_generation(&id, (fasta_ino, seq_ino));
+            if id == normalized {
+                fragment.set_header(&new_fragment.header);
+            }
+            if self.settings.writable_fasta {
+                fragment.fasta_file.attrs.perm = 0o664 & !*UMASK;
+            }
+            self.fragments.push(fragment);
+            self.dirty = true;
+        }
+        let appended = report.iter().filter(|r| r.3.is_some()).count();
+        if self.notifier.record(Operation::Append(appended)) {
+            let msg = format!("{} sequences appended from `{}`", appended, file);
+            warn!("{}", msg);
+            notify(msg);
+        }
+        self.summarize(false);
+        self.report_appends(&report);
+    }
+
+    /// Notify the summary of the smaller operations, if it is due
+    fn summarize(&mut self, force: bool) {
+        if let Some(msg) = self.notifier.summary(std::time::Instant::now(), force) {
+            info!("{}", msg);
+            notify(msg);
+        }
+    }
+
+    /// Whether the file `ino` may be read at all
+    fn check_readable(&self, ino: u64) -> Result<(), FustaError> {
+        if self.settings.headers_only && !self.is_header_data(ino) {
+            return Err(FustaError::Unavailable(format!(
+                "READ: {} can not be read, as only the headers are available with --headers-only",
+                self.describe(ino)
+            )));
+        }
+        if self.reads_backing(ino) {
+            self.settings.chaos.read(|| self.describe(ino))?;
+        }
+        Ok(())
+    }
+
+    /// The read of `size` bytes at `offset` in the file `ino`, if it is the
+    /// file of a fragment in fasta/ or seqs/ that is not being edited
+    fn sequence_read(&self, ino: u64, offset: i64, size: u32) -> Option<SequenceRead> {
+        if self.pending_fastas.contains_key(&ino) {
+            return None;
+        }
+        let fragment = self.fragment_from_ino(ino)?;
+        let label = match fragment.file_from_ino(ino)?.class() {
+            FileClass::Fasta => Some(fragment.label()),
+            FileClass::Seq => None,
+            FileClass::Text => return None,
+        };
+        let len = label.as_ref().map_or(0, String::len) + fragment.data_size();
+        Some(SequenceRead {
+            id: fragment.id.clone(),
+            label,
+            data: fragment.data.clone(),
+            range: window(offset, size, len),
+        })
+    }
+
+    /// Read up to `size` bytes at `offset` in the file `ino`; the result is
+    /// truncated at the end of the file, and to `MAX_IO_SIZE`
+    fn read_file(&mut self, ino: u64, offset: i64, size: u32) -> Result<Vec<u8>, FustaError> {
+        self.check_readable(ino)?;
+        let not_found = || FustaError::NotFound(format!("READ: {} is not a file", ino));
+        match ino {
+            STATUS_FILE => {
+                self.make_status_buffer();
+                let data = self.get_file(ino).unwrap().data();
+                Ok(data[window(offset, size, data.len())].to_vec())
+            }
+            MANIFEST_FILE => {
+                self.make_manifest_buffer();
+                let data = self.get_file(ino).unwrap().data();
+                Ok(data[window(offset, size, data.len())].to_vec())
+            }
+            INFO_FILE => {
+                self.load_infos();
+                let data = self.get_file(ino).unwrap().data();
+                Ok(data[window(offset, size, data.len())].to_vec())
+            }
+            SUMMARY_FILE => {
+                self.make_summary_buffer();
+                let data = self.get_file(ino).unwrap().data();
+                Ok(data[window(offset, size, data.len())].to_vec())
+            }
+            STATS_FILE => {
+                let data = self.load_stats()?;
+                Ok(data.as_bytes()[window(offset, size, data.len())].to_vec())
+            }
+            DUPLICATES_FILE => {
+                let data = self.load_duplicates()?;
+                Ok(data.as_bytes()[window(offset, size, data.len())].to_vec())
+            }
+            REPEATS_FILE => {
+                let data = self.load_repeats()?;
+                Ok(data.as_bytes()[window(offset, size, data.len())].to_vec())
+            }
+            FORMAT_FILE => {
+                let data = self.load_format()?;
+                Ok(data.as_bytes()[window(offset, size, data.len())].to_vec())
+            }
+            FAI_FILE => {
+                let data = self.load_fai()?;
+                Ok(data.as_bytes()[window(offset, size, data.len())].to_vec())
+            }
+            CODON_USAGE_FILE => {
+                let data = self.load_codon_usage()?;
+                Ok(data.as_bytes()[window(offset, size, data.len())].to_vec())
+            }
+            ino if self.scan_inos.contains_key(&ino) => {
+                let data = self.load_scan(self.scan_inos[&ino])?;
+                Ok(data.as_bytes()[window(offset, size, data.len())].to_vec())
+            }
+            ino if self.protein_inos.contains_key(&ino) => {
+                let data = self.translate(self.protein_inos[&ino])?;
+                Ok(data[window(offset, size, data.len())].to_vec())
+            }
+            ino if self.flank_inos.contains_key(&ino) => {
+                let (dir, gene) = self.flank_inos[&ino];
+                let data = self.read_flank(dir, gene)?;
+                Ok(data[window(offset, size, data.len())].to_vec())
+            }
+            INFO_CSV_FILE => {
+                self.load_info_csv();
+                Ok(self
+                    .info_csv
+                    .read(window(offset, size, self.info_csv.len())))
+            }
+            INFO_JSON_FILE | LABELS_FILE | OFFSETS_FILE | APPENDS_FILE | LAST_ERROR_FILE
+            | COLLISIONS_FILE | COMMIT_REPORT_FILE => {
+                let data = self.get_file(ino).unwrap().data();
+                Ok(data[window(offset, size, data.len())].to_vec())
+            }
+            ino if CTL_FILES.contains(&ino) => {
+                let data = self.get_file(ino).unwrap().data();
+                Ok(data[window(offset, size, data.len())].to_vec())
+            }
+            ino if self.pending_fastas.contains_key(&ino) => {
+                let data = &self.pending_fastas[&ino];
+                Ok(data[window(offset, size, data.len())].to_vec())
+            }
+            ino if self.ino2fragment.contains_key(&ino) => self
+                .sequence_read(ino, offset, size)
+                .ok_or_else(not_found)?
+                .run(),
+            ino if self.downloads.contains_key(&ino) => {
+                let mut download = self.downloads.remove(&ino).unwrap();
+                let fasta_ino = download.fasta_ino;
+                let r = download.stream.read(offset as u64, size as usize, |o, s| {
+                    self.read_file(fasta_ino, o as i64, s as u32)
+                });
+                self.downloads.insert(ino, download);
+                r
+            }
+            ino if self.ends.contains_key(&ino) => {
+                let data = self.read_end(ino)?;
+                Ok(data[window(offset, size, data.len())].to_vec())
+            }
+            ino if self.view_files.contains_key(&ino) => self.read_view(ino, offset, size),
+            ino if self.taxon_counts.contains_key(&ino) => {
+                self.make_taxa();
+                let dir = *self.taxon_counts.get(&ino).ok_or_else(|| {
+                    FustaError::NotFound(format!("{} has been removed", self.describe(ino)))
+                })?;
+                let data = self.taxon_count(dir);
+                Ok(data.as_bytes()[window(offset, size, data.len())].to_vec())
+            }
+            ino if self.tag_files.contains_key(&ino) => {
+                let data = self.read_tags(ino)?;
+                Ok(data[window(offset, size, data.len())].to_vec())
+            }
+            ino if self.subfragment_from_ino(ino).is_some() => {
+                let subfragment = self.subfragment_from_ino(ino).unwrap();
+                let fragment = self
+                    .fragment_from_id(&subfragment.fragment)
+                    .ok_or_else(|| {
+                        FustaError::NotFound(format!("No fragment linked to ino {}", ino))
+                    })?;
+                let start = std::cmp::max(subfragment.start, 0) as usize;
+                let len = subfragment.attrs.size as usize;
+                let range = window(offset, size, len);
+                if subfragment.reverse {
+                    // Read the mirrored window on the forward strand
+                    let mut chunk = fragment
+                        .pure_chunk(start + len - range.end, range.len())
+                        .map_err(|e| fragment.unreadable(e))?;
+                    reverse_complement(&mut chunk);
+                    Ok(chunk.into_vec())
+                } else {
+                    Ok(fragment
+                        .pure_chunk(start + range.start, range.len())
+                        .map_err(|e| fragment.unreadable(e))?
+                        .into_vec())
+                }
+            }
+            ino if self.snapshot_inos.contains_key(&ino) => self
+                .read_snapshot(self.snapshot_inos[&ino], offset, size)
+                .map_err(|e| {
+                    FustaError::BackingIo(
+                        format!("Unable to read the snapshot of {}", &self.filename),
+                        e,
+                    )
+                }),
+            ino if self.versions.contains_key(&ino) => {
+                let serial = self.versions[&ino].serial;
+                match self.history.iter().find(|e| e.serial == serial) {
+                    Some(edit) => Ok(edit.data[window(offset, size, edit.data.len())].to_vec()),
+                    None => Err(FustaError::NotFound(format!(
+                        "READ: the state exposed as inode {} has been dropped from the undo history",
+                        ino
+                    ))),
+                }
+            }
+            _ => Err(not_found()),
+        }
+    }
+
+    /// Record that the file `ino` of a fragment has been read; as per
+    /// relatime, its access time is only updated if older than its last
+    /// change or than a day, so that it does not cost a write on every read
+    fn touch_accessed(&mut self, ino: u64) {
+        if self.settings.noatime {
+            return;
+        }
+        let now = SystemTime::now();
+        if let Some(file) = self
+            .mut_fragment_from_ino(ino)
+            .and_then(|f| f.mut_file_from_ino(ino))
+        {
+            let attrs = file.mut_attrs();
+            if attrs.atime <= attrs.mtime
+                || attrs.atime <= attrs.ctime
+                || now.duration_since(attrs.atime).unwrap_or_default() >= RELATIME_DELAY
+            {
+                attrs.atime = now;
+            }
+        }
+    }
+
+    /// Write `data` at `offset` in the writeable file `ino`, and return the
+    /// number of bytes written
+    fn write_file(&mut self, ino: u64, offset: usize, data: &[u8]) -> Result<usize, FustaError> {
+        let too_big = |name| {
+            FustaError::Quota(format!(
+                "Cannot write {} bytes at {} in {}",
+                data.len(),
+                offset,
+                name
+            ))
+        };
+        let written = if !self.is_writeable(ino) {
+            return Err(FustaError::ReadOnly(format!(
+                "{} is not writeable",
+                self.describe(ino)
+            )));
+        }
+        // We write to a control file
+        else if CTL_FILES.contains(&ino) {
+            let buffer = self.pending_controls.entry(ino).or_default();
+            write_at(buffer, offset, data)
+        }
+        // We write the tags of a fragment
+        else if self.tag_files.contains_key(&ino) {
+            let buffer = self.pending_tags(ino)?;
+            let written = write_at(buffer, offset, data);
+            let size = buffer.len();
+            self.tag_files.get_mut(&ino).unwrap().attrs.size = size as u64;
+            written
+        }
+        // We write to the FASTA file of an existing fragment
+        else if self.is_fasta_file(ino) {
+            self.write_fasta(ino, offset, data)?;
+            Some(data.len())
+        }
+        // We write to an existing fragment
+        else if self.fragment_from_ino(ino).is_some() {
+            self.write_fragment(ino, offset, data)?;
+            Some(data.len())
+        }
+        // We write to a pending fragment
+        else if let Some((name, pending_fragment)) = self
+            .pending_appends
+            .iter_mut()
+            .find(|(_, p)| p.attrs.ino == ino)
+        {
+            trace!("\tWriting to {}", name);
+            write_at(&mut pending_fragment.data, offset, data)
+        } else {
+            return Err(FustaError::NotFound(format!("{} does not exist", ino)));
+        };
+        let written = written.ok_or_else(|| too_big(self.describe(ino)))?;
+        if let Some(fragment) = self.mut_fragment_from_ino(ino) {
+            fragment.touch_modified(SystemTime::now());
+        }
+        Ok(written)
+    }
+
+    /// Write `data` at `offset` in the sequence of the fragment owning `ino`
+    fn write_fragment(&mut self, ino: u64, offset: usize, data: &[u8]) -> Result<(), FustaError> {
+        self.settings.chaos.write(|| self.describe(ino))?;
+        self.remember_once(ino);
+        let fragment = self
+            .mut_fragment_from_ino(ino)
+            .expect("Something went very wrong");
+        // As soon as there's a write, we have to switch this fragment to a buffer-backed storage
+        let buffer = fragment.make_editable()?;
+        write_at(buffer, offset, data).ok_or_else(|| {
+            FustaError::Quota(format!(
+                "Cannot write {} bytes at {} in `{}`",
+                data.len(),
+                offset,
+                fragment.id
+            ))
+        })?;
+        fragment.forget_derived();
+        fragment.edited = true;
+        fragment.refresh_virtual_files();
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Write `data` at `offset` in the FASTA file `ino`. Writes past the
+    /// header go straight to the sequence; otherwise, the whole file is
+    /// buffered until it is closed, then validated and applied.
+    fn write_fasta(&mut self, ino: u64, offset: usize, data: &[u8]) -> Result<(), FustaError> {
+        let label_size = self.fragment_from_ino(ino).unwrap().label_size();
+        if !self.pending_fastas.contains_key(&ino) && offset >= label_size {
+            return self.write_fragment(ino, offset - label_size, data);
+        }
+        let buffer = self.pending_fasta(ino)?;
+        let written = write_at(buffer, offset, data).map(|_| buffer.len());
+        match written {
+            Some(size) => {
+                self.mut_fragment_from_ino(ino)
+                    .unwrap()
+                    .fasta_file
+                    .attrs
+                    .size = size as u64;
+                Ok(())
+            }
+            None => Err(FustaError::Quota(format!(
+                "Cannot write {} bytes at {} in {}",
+                data.len(),
+                offset,
+                self.describe(ino)
+            ))),
+        }
+    }
+
+    /// Truncate or extend the FASTA file `ino` to `size`
+    fn resize_fasta(&mut self, ino: u64, size: usize) -> Result<(), FustaError> {
+        let label_size = self.fragment_from_ino(ino).unwrap().label_size();
+        if !self.pending_fastas.contains_key(&ino) && size >= label_size {
+            return self.resize_fragment(ino, size - label_size);
+        }
+        if resize(self.pending_fasta(ino)?, size).is_none() {
+            return Err(FustaError::Quota(format!(
+                "Cannot resize {} to {} bytes",
+                self.describe(ino),
+                size
+            )));
+        }
+        self.mut_fragment_from_ino(ino)
+            .unwrap()
+            .fasta_file
+            .attrs
+            .size = size as u64;
+        Ok(())
+    }
+
+    /// The edited content of the FASTA file `ino`, initialized from its
+    /// fragment
+    fn pending_fasta(&mut self, ino: u64) -> Result<&mut Vec<u8>, FustaError> {
+        if !self.pending_fastas.contains_key(&ino) {
+            let fragment = self.fragment_from_ino(ino).unwrap();
+            let data = fragment.data().map_err(|e| fragment.unreadable(e))?;
+            let content = [fragment.label().as_bytes(), &data].concat();
+            self.pending_fastas.insert(ino, content);
+        }
+        Ok(self.pending_fastas.get_mut(&ino).unwrap())
+    }
+
+    /// Apply the edits made to the FASTA file `ino`, if they result in a
+    /// valid FASTA file containing a single sequence; they are discarded
+    /// otherwise.
+    fn commit_fasta(&mut self, ino: u64) -> Result<(), FustaError> {
+        let content = match self.pending_fastas.remove(&ino) {
+            Some(content) => content,
+            None => return Ok(()),
+        };
+        // The fragment may have been deleted in the meantime
+        let current_id = match self.fragment_from_ino(ino) {
+            Some(fragment) => fragment.id.clone(),
+            None => return Ok(()),
+        };
+        let invalid = |msg: String| {
+            FustaError::Parse(format!(
+                "Discarding the edits to {}: {}",
+                self.describe(ino),
+                msg
+            ))
+        };
+        if !content.starts_with(b">") {
+            let e = invalid("it should start with a FASTA header".into());
+            self.mut_fragment_from_ino(ino)
+                .unwrap()
+                .refresh_virtual_files();
+            return Err(e);
+        }
+        let edited = FastaReader::new(&content[..], false).collect::<Vec<_>>();
+        let r = match &edited[..] {
+            [edited] => {
+                let id = self.settings.id_rules.apply(&edited.id);
+                check_id(&id, self.settings.codec)
+                    .map_err(invalid)
+                    .and_then(|_| {
+                        if id != current_id && self.fragment_from_id(&id).is_some() {
+                            Err(FustaError::Conflict(format!(
+                                "Discarding the edits to {}: `{}` already exists",
+                                self.describe(ino),
+                                id
+                            )))
+                        } else {
+                            Ok((id, edited))
+                        }
+                    })
+            }
+            _ => Err(invalid(format!(
+                "it should contain a single sequence, found {}",
+                edited.len()
+            ))),
+        };
+        if r.is_ok() {
+            self.remember(ino);
+        }
+        match r {
+            Ok((id, edited)) => {
+                info!("Applying the edits to `{}.fa`", current_id);
+                let mut seq = content[edited.pos.0..edited.pos.1].to_vec();
+                self.settings.write_transform.apply(&mut seq);
+                let fragment = self.mut_fragment_from_ino(ino).unwrap();
+                fragment.name = edited.name.clone();
+                fragment.data = Arc::new(Buffer(seq));
+                fragment.forget_derived();
+                fragment.edited = true;
+                fragment.rename(&id);
+                fragment.set_header(&edited.header);
+                self.dirty = true;
+                self.refresh_metadata(false);
+                self.concretize(false)
+            }
+            Err(e) => {
+                self.mut_fragment_from_ino(ino)
+                    .unwrap()
+                    .refresh_virtual_files();
+                Err(e)
+            }
+        }
+    }
+
+    /// Normalize the sequence of the seqs/ file `ino` once edited, as per
+    /// --write-transform
+    fn normalize_edit(&mut self, ino: u64) -> Result<(), FustaError> {
+        if self.settings.write_transform.is_empty() {
+            return Ok(());
+        }
+        let transform = &self.settings.write_transform;
+        let fragment = self
+            .fragments
+            .iter_mut()
+            .find(|f| f.seq_file.ino == ino)
+            .unwrap();
+        let buffer = fragment.make_editable()?;
+        transform.apply(buffer);
+        fragment.forget_derived();
+        // The sequence may have been rewrapped
+        fragment.refresh_virtual_files();
+        Ok(())
+    }
+
+    /// Truncate or extend the sequence of the fragment owning `ino` to `size`
+    fn resize_fragment(&mut self, ino: u64, size: usize) -> Result<(), FustaError> {
+        self.settings.chaos.write(|| self.describe(ino))?;
+        self.remember_once(ino);
+        let fragment = self
+            .mut_fragment_from_ino(ino)
+            .expect("Something went very wrong");
+        if size == 0 {
+            // Clear the file, called by the truncate syscall
+            fragment.data = Arc::<Buffer>::default();
+        } else if size != fragment.data_size() {
+            let buffer = fragment.make_editable()?;
+            resize(buffer, size).ok_or_else(|| {
+                FustaError::Quota(format!("Cannot resize `{}` to {} bytes", fragment.id, size))
+            })?;
+        } else {
+            return Ok(());
+        }
+        fragment.forget_derived();
+        fragment.edited = true;
+        fragment.refresh_virtual_files();
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn concretize(&mut self, force: bool) -> Result<(), FustaError> {
+        if !self.dirty {
+            debug!("CONCRETIZE: nothing to do");
+            return Ok(());
+        }
+
+        let mut in_memory = self
+            .fragments
+            .iter()
+            .map(|f| f.data.in_memory())
+            .sum::<usize>();
+
+        // Beyond the allowed cache, the edits are first set aside to disk
+        if !force && in_memory > 0 && in_memory >= self.settings.concretize_threshold {
+            in_memory -= self.spill_edits();
+        }
+
+        // We only concretize if the call is not forced and
+        // 1. the allowed cache is not yet used
+        // or
+        // 2. the user wants to cache everything anyway
+        if !force
+            && (in_memory < self.settings.concretize_threshold
+                || !(self.settings.cache == Cache::RAM))
+        {
+            trace!(
+                "Using {:.2}MB out of {}; skipping concretization",
+                in_memory / (1024 * 1024),
+                self.settings.concretize_threshold / (1024 * 1024)
+            );
+            return Ok(());
+        }
+
+        // The pending changes are kept, and will be written back once
+        // confirmed through ctl/commit
+        if let Err(e) = self.check_loss().and_then(|_| self.check_shrinkage()) {
+            self.fail(e);
+            return Ok(());
+        }
+        self.write_back()
+    }
+
+    /// Compress the edited sequences held in memory to the spill file, from
+    /// where they are read until written back; returns how many bytes of
+    /// memory were freed
+    fn spill_edits(&mut self) -> usize {
+        let mut freed = 0;
+        let edit_spill = &self.edit_spill;
+        for fragment in self.fragments.iter_mut() {
+            let size = fragment.data.in_memory();
+            if size == 0 {
+                continue;
+            }
+            match fragment
+                .data
+                .data()
+                .and_then(|data| edit_spill.store(&data))
+            {
+                Ok(compressed) => {
+                    fragment.data = Arc::new(compressed);
+                    freed += size;
+                }
+                Err(e) => {
+                    warn!("Unable to set the edits of `{}` aside: {}", fragment.id, e);
+                    break;
+                }
+            }
+        }
+        if freed > 0 {
+            debug!(
+                "{:.2}MB of edits spilled to disk, taking {:.2}MB",
+                freed as f64 / (1024. * 1024.),
+                self.edit_spill.size() as f64 / (1024. * 1024.)
+            );
+        }
+        freed
+    }
+
+    /// Whether `fragment` is written back, i.e. whether its tags match the
+    /// write filter, if any
+    fn is_written(&self, fragment: &Fragment) -> bool {
+        self.settings
+            .write_filter
+            .as_ref()
+            .is_none_or(|filter| filter.matches(&fragment.tags))
+    }
+
+    /// Whether the fragments are yet to be written to the `--output` file,
+    /// instead of the mounted one; once they have been, they are read from
+    /// there
+    fn writes_elsewhere(&self) -> bool {
+        self.settings
+            .output
+            .as_ref()
+            .is_some_and(|output| *output != self.filename)
+    }
+
+    /// The total size of the files the fragments are written back to
+    fn backing_size(&self) -> u64 {
+        std::iter::once(&self.filename)
+            .chain(self.settings.merge.iter())
+            .filter_map(|f| fs::metadata(f).ok())
+            .map(|m| m.len())
+            .sum()
+    }
+
+    /// Refuse to write back a file that would have lost too much of its
+    /// content since it was mounted, e.g. after an unfortunate `rm seqs/*`
+    fn check_loss(&self) -> Result<(), FustaError> {
+        if self.settings.allow_empty || self.reference_size == 0 {
+            return Ok(());
+        }
+        let size = self
+            .fragments
+            .iter()
+            .filter(|f| self.is_written(f))
+            .map(|f| f.label_size() + f.data_size())
+            .sum::<usize>();
+        let lost = 1. - size as f64 / self.reference_size as f64;
+        if size == 0 || lost > self.settings.max_loss {
+            Err(FustaError::Unconfirmed(format!(
+                "Not writing back `{}`, as it would lose {:.0}% of its content; write `force` to ctl/{} to confirm",
+                self.filename,
+                100. * lost,
+                COMMIT_FILE_NAME
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Remember where the fragments lie on disk, to report what changed at
+    /// the next write-back
+    fn record_committed(&mut self) {
+        self.committed = self
+            .fragments
+            .iter()
+            .filter_map(|f| {
+                let (start, end, _) = f.offsets?;
+                Some((f.seq_file.ino, (f.id.clone(), f.origin, (start, end))))
+            })
+            .collect();
+    }
+
+    /// What becomes of the fragments if written back now: those added,
+    /// deleted, renamed or edited since the last write-back, with their
+    /// lengths before and after if they changed
+    fn changes(&self) -> std::io::Result<Vec<Change>> {
+        let mut files = HashMap::new();
+        let mut committed_length = |origin: Option<usize>, (start, end)| {
+            let file = match files.entry(origin) {
+                std::collections::hash_map::Entry::Occupied(e) => e.into_mut(),
+                std::collections::hash_map::Entry::Vacant(e) => {
+                    let filename = origin.map_or(&self.filename, |i| &self.settings.merge[i]);
+                    e.insert(Backing::open(filename)?)
+                }
+            };
+            let mut length = 0;
+            StatsSource::File(start, end).read(file, |chunk| length += count_bases(chunk))?;
+            Ok::<_, std::io::Error>(length)
+        };
+        let length = |f: &Fragment| -> std::io::Result<usize> {
+            let mut length = 0;
+            let mut offset = 0;
+            while offset < f.data_size() {
+                let chunk = f.chunk(offset, 1 << 20)?;
+                offset += chunk.len();
+                length += count_bases(&chunk);
+            }
+            Ok(length)
+        };
+
+        let mut r = Vec::new();
+        let mut kept = HashSet::new();
+        for f in self.fragments.iter().filter(|f| self.is_written(f)) {
+            let ino = f.seq_file.ino;
+            kept.insert(ino);
+            match self.committed.get(&ino) {
+                None => r.push(Change {
+                    kinds: vec!["added"],
+                    id: f.id.to_string(),
+                    previous_id: None,
+                    previous_length: None,
+                    length: Some(length(f)?),
+                }),
+                Some((id, origin, range)) => {
+                    let mut kinds = Vec::new();
+                    if *id != f.id {
+                        kinds.push("renamed");
+                    }
+                    let edited = f.edited || f.offsets.map(|o| (o.0, o.1)) != Some(*range);
+                    let (previous_length, length) = if edited {
+                        kinds.push("edited");
+                        (Some(committed_length(*origin, *range)?), Some(length(f)?))
+                    } else {
+                        (None, None)
+                    };
+                    if !kinds.is_empty() {
+                        r.push(Change {
+                            kinds,
+                            id: f.id.to_string(),
+                            previous_id: Some(id.to_string()),
+                            previous_length,
+                            length,
+                        });
+                    }
+                }
+            }
+        }
+        let mut deleted = self
+            .committed
+            .iter()
+            .filter(|(ino, _)| !kept.contains(*ino))
+            .map(|(_, committed)| committed)
+            .collect::<Vec<_>>();
+        deleted.sort_by_key(|(_, origin, range)| (*origin, *range));
+        for (id, origin, range) in deleted {
+            r.push(Change {
+                kinds: vec!["deleted"],
+                id: id.to_string(),
+                previous_id: Some(id.to_string()),
+                previous_length: Some(committed_length(*origin, *range)?),
+                length: None,
+            });
+        }
+        Ok(r)
+    }
+
+    fn set_commit_report(&mut self, report: &str) {
+        if let Some(x) = self.get_file(COMMIT_REPORT_FILE) {
+            x.set_data(report.as_bytes());
+            x.mut_attrs().size = report.len() as u64;
+        }
+    }
+
+    /// Refuse to write back edits shrinking a sequence by more than
+    /// --max-shrink, e.g. after an editor accident, reporting them in
+    /// commit-report.txt
+    fn check_shrinkage(&mut self) -> Result<(), FustaError> {
+        let max_shrink = match self.settings.max_shrink {
+            Some(max_shrink) => max_shrink,
+            None => return Ok(()),
+        };
+        let changes = self
+            .changes()
+            .map_err(|e| FustaError::BackingIo("Unable to compare the sequences".into(), e))?;
+        let shrunk = changes
+            .iter()
+            .filter(|c| c.shrinkage() > max_shrink)
+            .map(|c| format!("`{}` ({:.0}%)", c.id, 100. * c.shrinkage()))
+            .collect::<Vec<_>>();
+        self.held_back = !shrunk.is_empty();
+        if shrunk.is_empty() {
+            return Ok(());
+        }
+        let msg = format!(
+            "Not writing back `{}`, as it would shrink {}; see .fusta/{}, and write `force` to ctl/{} to confirm",
+            self.filename,
+            shrunk.join(", "),
+            COMMIT_REPORT_FILE_NAME,
+            COMMIT_FILE_NAME
+        );
+        self.set_commit_report(&render_changes(
+            &format!("held back, as it would shrink {}", shrunk.join(", ")),
+            &changes,
+        ));
+        Err(FustaError::Unconfirmed(msg))
+    }
+
+    /// Log and report in commit-report.txt what the write-back about to
+    /// happen changes
+    fn report_changes(&mut self) {
+        let changes = match self.changes() {
+            Ok(changes) => changes,
+            Err(e) => {
+                warn!("Unable to compare the sequences to the FASTA file: {}", e);
+                return;
+            }
+        };
+        for c in changes.iter() {
+            match (&c.previous_id, c.previous_length, c.length) {
+                (_, Some(before), Some(after)) if before != after => info!(
+                    "{}: `{}` goes from {} to {} bases",
+                    c.kinds.join(", "),
+                    c.id,
+                    before,
+                    after
+                ),
+                (Some(previous), _, _) if *previous != c.id => {
+                    info!("{}: `{}` was `{}`", c.kinds.join(", "), c.id, previous)
+                }
+                _ => info!("{}: `{}`", c.kinds.join(", "), c.id),
+            }
+        }
+        let summary = format!(
+            "{} fragments changed, written back to `{}`",
+            changes.len(),
+            self.settings.output.as_ref().unwrap_or(&self.filename)
+        );
+        self.set_commit_report(&render_changes(&summary, &changes));
+    }
+
+    /// Replace the FASTA file with the current fragments
+    fn write_back(&mut self) -> Result<(), FustaError> {
+        trace!("========== CONCRETIZING ========");
+        self.report_changes();
+        let size = self
+            .fragments
+            .iter()
+            .filter(|f| self.is_written(f))
+            .map(|f| (f.label_size() + f.data_size()) as u64)
+            .sum::<u64>();
+        if self.notifier.is_large(Operation::Rewrite(size)) {
+            let msg = format!("Updating {} ({} MB)", &self.filename, size / (1024 * 1024));
+            warn!("{}", msg);
+            notify(msg);
+        }
+        trace!("Writing fragments");
+        let filename = self
+            .settings
+            .output
+            .clone()
+            .unwrap_or_else(|| self.filename.clone());
+        self.rewrite(&filename, None)?;
+        self.reindex(&filename, None);
+        // The fragments are now read from there
+        self.filename = filename;
+        for (origin, merged) in self.settings.merge.clone().iter().enumerate() {
+            self.rewrite(merged, Some(origin))?;
+            self.reindex(merged, Some(origin));
+        }
+        self.save_session();
+        self.make_offsets_buffer();
+        self.record_committed();
+        self.held_back = false;
+        self.fai = None;
+        trace!("========== DONE ========");
+        if self.notifier.record(Operation::Rewrite(size)) {
+            notify(format!("{} has been updated", &self.filename));
+        } else {
+            debug!("{} has been updated", &self.filename);
+        }
+        self.summarize(false);
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Replace `filename` with the fragments from the --merge file `origin`,
+    /// or from the mounted one
+    fn rewrite(&mut self, filename: &str, origin: Option<usize>) -> Result<(), FustaError> {
+        // Even though such mounts are read-only, the mount option may be
+        // ignored, and the file would be replaced by its uncompressed version
+        if crate::bgzf::is_bgzf_file(Path::new(filename)) {
+            return Err(FustaError::ReadOnly(format!(
+                "`{}` is compressed with bgzip, and can not be written back; see --output",
+                filename
+            )));
+        }
+        let tmp_filename = format!("{}{}", filename, TMP_SUFFIX);
+        if let Err(e) = self.write_fragments(&tmp_filename, origin) {
+            // The fragments written so far keep reading from the unlinked
+            // file, and the next concretization will start afresh
+            let _ = fs::remove_file(&tmp_filename);
+            return Err(e);
+        }
+        trace!("Renaming {} to {}", tmp_filename, filename);
+        fs::rename(&tmp_filename, filename).map_err(|e| {
+            FustaError::BackingIo(
+                format!("Unable to rename `{}` to `{}`", &tmp_filename, filename),
+                e,
+            )
+        })
+    }
+
+    /// Index `filename`, just rewritten with the fragments from `origin`,
+    /// from where they now lie, rather than leaving a stale index to be
+    /// ignored by the next mount
+    fn reindex(&self, filename: &str, origin: Option<usize>) {
+        let fragments = self
+            .fragments
+            .iter()
+            .filter(|f| f.origin == origin)
+            .filter_map(|f| {
+                let (start, end, line_width) = f.offsets?;
+                let label = f.label();
+                let header = label.trim_start_matches('>').trim_end_matches('\n');
+                let (id, name) = split_header(header);
+                Some(fusta::fasta::Fragment {
+                    id: id.into(),
+                    name,
+                    header: header.to_owned(),
+                    pos: (start, end),
+                    len: end - start,
+                    line_width,
+                    seq: None,
+                })
+            })
+            .collect();
+        match fs::File::open(filename) {
+            Ok(file) => {
+                self.save_index(filename, &file, fragments);
+            }
+            Err(e) => warn!("Unable to index {}: {}", filename, e),
+        }
+    }
+
+    /// Write all the fragments from `origin` passing the write filter to
+    /// `tmp_filename`, from where they will then be read; the others keep
+    /// being read from where they are
+    fn write_fragments(
+        &mut self,
+        tmp_filename: &str,
+        origin: Option<usize>,
+    ) -> Result<(), FustaError> {
+        let write_error =
+            |e| FustaError::BackingIo(format!("Unable to write to `{}`", tmp_filename), e);
+        let mut index = 0;
+        // The handle is kept by the fragments to read their sequence from the
+        // new file.
+        let tmp_file = Arc::new(
+            fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(tmp_filename)
+                .map_err(|e| {
+                    FustaError::BackingIo(format!("Unable to create `{}`", tmp_filename), e)
+                })?,
+        );
+        // Where the new file is renamed to once written
+        let path: Arc<str> = tmp_filename
+            .strip_suffix(TMP_SUFFIX)
+            .unwrap_or(tmp_filename)
+            .into();
+        let filter = &self.settings.write_filter;
+        for fragment in self.fragments.iter_mut().filter(|f| f.origin == origin) {
+            if filter.as_ref().is_some_and(|f| !f.matches(&fragment.tags)) {
+                trace!("Filtering {} out", fragment.id);
+                fragment.offsets = None;
+                continue;
+            }
+            trace!("Writing {}", fragment.id);
+            self.settings
+                .chaos
+                .write(|| format!("`{}` to `{}`", fragment.id, tmp_filename))?;
+            (&*tmp_file)
+                .write_all(fragment.label().as_bytes())
+                .map_err(write_error)?;
+            index += fragment.label().len();
+            let last_start = index;
+            let data = fragment.data().map_err(|e| {
+                FustaError::BackingIo(format!("Unable to read `{}`", fragment.id), e)
+            })?;
+            (&*tmp_file).write_all(&data).map_err(write_error)?;
+            index += data.len();
+            let line_width = data.iter().position(|&c| c == b'\n').unwrap_or(data.len());
+            let line_width =
+                line_width - usize::from(line_width > 0 && data[line_width - 1] == b'\r');
+            fragment.digest = Some(sequence_digest(&data));
+            if let Some(c) = data.last() {
+                if *c != b'\n' {
+                    (&*tmp_file).write_all(b"\n").map_err(write_error)?;
+                    index += 1;
+                }
+            }
+
+            fragment.data = Arc::new(FileSlice::new(
+                Backing::Plain(tmp_file.clone()),
+                path.clone(),
+                last_start,
+                index,
+                self.settings.retry,
+            ));
+            fragment.offsets = Some((last_start, index, line_width));
+            fragment.edited = false;
+            fragment.refresh_virtual_files();
+        }
+        Ok(())
+    }
+
+    /// Whether `ino` can be read without reading the sequences, i.e. is
+    /// only made of what the headers tell, or of reports
+    fn is_header_data(&self, ino: u64) -> bool {
+        [
+            INFO_FILE,
+            SUMMARY_FILE,
+            INFO_CSV_FILE,
+            INFO_JSON_FILE,
+            LABELS_FILE,
+            OFFSETS_FILE,
+            APPENDS_FILE,
+            LAST_ERROR_FILE,
+            COLLISIONS_FILE,
+            COMMIT_REPORT_FILE,
+            STATUS_FILE,
+            MANIFEST_FILE,
+        ]
+        .contains(&ino)
+            || CTL_FILES.contains(&ino)
+            || self.tag_files.contains_key(&ino)
+    }
+
+    /// Whether reading `ino` reaches for the sequences storage
+    fn reads_backing(&self, ino: u64) -> bool {
+        self.ino2fragment.contains_key(&ino)
+            || self.subfragment_from_ino(ino).is_some()
+            || self.snapshot_inos.contains_key(&ino)
+    }
+
+    fn fragment_from_id(&self, id: &str) -> Option<&Fragment> {
+        self.name2fragment.get(id).map(|&i| &self.fragments[i])
+    }
+
+    fn fragment_from_ino(&self, ino: u64) -> Option<&Fragment> {
+        self.ino2fragment
+            .get(&ino)
+            .and_then(|&i| self.fragments.get(i))
+    }
+
+    fn mut_fragment_from_ino(&mut self, ino: u64) -> Option<&mut Fragment> {
+        if let Some(i) = self.ino2fragment.get(&ino) {
+            self.fragments.get_mut(*i)
+        } else {
+            None
+        }
+    }
+
+    /// The fragment designated by `name`, either its ID or, with --aliases,
+    /// one of its aliases
+    fn fragment_from_name(&self, name: &str) -> Option<&Fragment> {
+        self.fragment_from_id(name).or_else(|| {
+            self.settings
+                .aliases
+                .as_ref()?
+                .resolve(name, |alias| self.name2fragment.contains_key(alias))
+                .and_then(|id| self.fragment_from_id(id))
+        })
+    }
+
+    /// The file of `dir`, either fasta/ or seqs/, named after an alias of
+    /// its fragment rather than after its ID
+    fn aliased_file(&self, dir: u64, name: &str) -> Option<&FragmentFile> {
+        let ext = if dir == SEQ_DIR { SEQ_EXT } else { FASTA_EXT };
+        let alias = self.settings.codec.decode(name.strip_suffix(ext)?)?;
+        let fragment = self.fragment_from_name(&alias)?;
+        Some(if dir == SEQ_DIR {
+            &fragment.seq_file
+        } else {
+            &fragment.fasta_file
+        })
+    }
+
+    fn fragment_from_fasta_filename(&self, name: &str) -> Option<&Fragment> {
+        name.strip_suffix(FASTA_EXT)
+            .and_then(|stem| self.settings.codec.decode(stem))
+            .and_then(|id| self.name2fragment.get(&id))
+            .and_then(|i| self.fragments.get(*i))
+    }
+
+    fn fragment_from_seq_filename(&self, name: &str) -> Option<&Fragment> {
+        name.strip_suffix(SEQ_EXT)
+            .and_then(|stem| self.settings.codec.decode(stem))
+            .and_then(|id| self.name2fragment.get(&id))
+            .and_then(|i| self.fragments.get(*i))
+    }
+
+    fn subfragment_from_ino(&self, ino: u64) -> Option<&SubFragment> {
+        self.subfragments.get_alt(&ino)
+    }
+
+    fn make_info_buffer(&mut self) {
+        use ascii_table::*;
+        use num_format::*;
+
+        let mut table = AsciiTable::default();
+        table.column(0).set_header("ID");
+        table.column(1).set_header("Infos");
+        table.column(2).set_header("Length (bp)");
+
+        trace!("Making INFO BUFFER");
+        let header = format!("{} - {} sequences", &self.filename, self.fragments.len());
+        let infos = self
+            .fragments
+            .iter()
+            .map(|f| {
+                vec![
+                    f.id.to_owned(),
+                    f.name.as_ref().unwrap_or(&"".to_string()).into(),
+                    f.data_size().to_formatted_string(&Locale::en).into(),
+                ]
+            })
+            .collect::<Vec<_>>();
+        let content = format!(
+            "{}\n{}\n{}",
+            header,
+            "=".repeat(header.len()),
+            &table.format(&infos)
+        );
+        let size = content.as_bytes().len() as u64;
+        if let Some(x) = self.get_file(INFO_FILE) {
+            x.set_data(content.as_bytes());
+            x.mut_attrs().size = size;
+        }
+    }
+
+    /// Rebuild infos.txt if the fragments changed since it was last built;
+    /// as formatting the table is slow for large files, this is only done
+    /// when it is accessed
+    fn load_infos(&mut self) {
+        if self.infos_stale {
+            self.make_info_buffer();
+            self.infos_stale = false;
+        }
+    }
+
+    /// The total length of the readable sequences, newlines excluded
+    fn bases(&self) -> usize {
+        self.fragments
+            .iter()
+            .filter_map(|f| f.cached_sequence_len().ok())
+            .sum()
+    }
+
+    /// summary.txt, cheap enough to always be up to date
+    fn make_summary_buffer(&mut self) {
+        let bases = self.bases();
+        let content = format!("sequences: {}\nbases: {}\n", self.fragments.len(), bases);
+        if let Some(x) = self.get_file(SUMMARY_FILE) {
+            x.set_data(content.as_bytes());
+            x.mut_attrs().size = content.len() as u64;
+        }
+    }
+
+    /// Bring infos.csv up to date, if the fragments changed since it was
+    /// last read
+    fn load_info_csv(&mut self) {
+        if self.info_csv.stale {
+            trace!("Updating INFO_CSV");
+            self.info_csv
+                .update(&self.fragments, &self.settings.csv_separator);
+            let size = self.info_csv.len() as u64;
+            if let Some(x) = self.get_file(INFO_CSV_FILE) {
+                x.mut_attrs().size = size;
+            }
+        }
+    }
+
+    fn make_info_json_buffer(&mut self) {
+        trace!("Making INFO_JSON BUFFER");
+        let infos = self
+            .fragments
+            .iter()
+            .map(|f| {
+                serde_json::json!({
+                    "id": f.id.as_str(),
+                    "name": f.name,
+                    "length": f.data_size(),
+                    "tags": f.tags,
+                })
+            })
+            .collect::<Vec<_>>();
+        let content = serde_json::to_string_pretty(&infos).unwrap();
+        let size = content.len() as u64;
+        if let Some(x) = self.get_file(INFO_JSON_FILE) {
+            x.set_data(content.as_bytes());
+            x.mut_attrs().size = size;
+        }
+    }
+
+    fn make_offsets_buffer(&mut self) {
+        trace!("Making OFFSETS BUFFER");
+        let sep = &self.settings.csv_separator;
+        let mut header = format!("id{}byte_start{}byte_end{}line_width", sep, sep, sep);
+        // With --merge, the offsets are in the file listed in a last column
+        let merge = &self.settings.merge;
+        if !merge.is_empty() {
+            header = format!("{}{}file", header, sep);
+        }
+        let offsets = self
+            .fragments
+            .iter()
+            .map(|f| {
+                let mut line = match f.offsets {
+                    Some((start, end, width)) => {
+                        format!("{}{}{}{}{}{}{}", f.id, sep, start, sep, end, sep, width)
+                    }
+                    None => format!("{}{}{}{}", f.id, sep, sep, sep),
+                };
+                if !merge.is_empty() {
+                    let file = f.origin.map_or(&self.filename, |i| &merge[i]);
+                    line = format!("{}{}{}", line, sep, file);
+                }
+                line
+            })
+            .collect::<Vec<_>>();
+        let content = format!("{}\n{}\n", header, offsets.join("\n"));
+        let size = content.len() as u64;
+        if let Some(x) = self.get_file(OFFSETS_FILE) {
+            x.set_data(content.as_bytes());
+            x.mut_attrs().size = size;
+        }
+    }
+
+    /// Log the outcome of the appended fragments to the appends file, as
+    /// (file, appended ID, action, final ID) tuples
+    fn report_appends(&mut self, report: &[(String, SString, &str, Option<SString>)]) {
+        let sep = self.settings.csv_separator.clone();
+        if let Some(x) = self.get_file(APPENDS_FILE) {
+            let mut content = x.data().to_vec();
+            if content.is_empty() {
+                content.extend(format!("file{}id{}action{}final_id\n", sep, sep, sep).as_bytes());
+            }
+            for (file, id, action, final_id) in report {
+                content.extend(
+                    format!(
+                        "{}{}{}{}{}{}{}\n",
+                        file,
+                        sep,
+                        id,
+                        sep,
+                        action,
+                        sep,
+                        final_id.as_deref().unwrap_or("")
+                    )
+                    .as_bytes(),
+                );
+            }
+            x.mut_attrs().size = content.len() as u64;
+            x.set_data(&content);
+        }
+    }
+
+    fn make_labels_buffer(&mut self) {
+        trace!("Making LABELS BUFFER");
+        let content = self
+            .fragments
+            .iter()
+            .map(Fragment::label)
+            .collect::<Vec<_>>()
+            .join("");
+        let size = content.as_bytes().len() as u64;
+        if let Some(x) = self.get_file(LABELS_FILE) {
+            x.set_data(content.as_bytes());
+            x.mut_attrs().size = size;
+        }
+    }
+
+    /// Where the workers should read the sequence of `fragment` from: the
+    /// sequences of the mounted file left untouched since they were last
+    /// written are read directly from it, the others are copied
+    fn stats_source(fragment: &Fragment) -> std::io::Result<StatsSource> {
+        match fragment.offsets {
+            Some((start, end, _))
+                if fragment.origin.is_none()
+                    && !fragment.edited
+                    && fragment.data.in_memory() == 0 =>
+            {
+                Ok(StatsSource::File(start, end))
+            }
+            _ => fragment.data().map(StatsSource::Memory),
+        }
+    }
+
+    /// Start computing the content of stats.csv in the background, unless
+    /// it is already computed or being computed
+    fn start_stats(&mut self) {
+        if self.stats.is_some() {
+            return;
+        }
+        let sources = self
+            .fragments
+            .iter()
+            .map(|f| {
+                Ok((
+                    f.id.to_string(),
+                    f.digest.clone(),
+                    FustaFS::stats_source(f)?,
+                ))
+            })
+            .collect::<std::io::Result<Vec<_>>>();
+        // Opened right away, as the file may be rewritten in the meantime
+        let file = Backing::open(&self.filename);
+        let separator = self.settings.csv_separator.clone();
+        self.stats = Some(self.pool.compute(move || {
+            file.and_then(|file| compute_stats(&file, sources?, &separator))
+                .map(Arc::new)
+                .map_err(|e| e.to_string())
+        }));
+    }
+
+    /// The content of stats.csv, if it can be computed within `STATS_WAIT`
+    fn load_stats(&mut self) -> Result<Arc<String>, FustaError> {
+        self.start_stats();
+        let r = FustaFS::wait_for(&mut self.stats, STATS_FILE_NAME);
+        if let Ok(content) = &r {
+            self.get_file(STATS_FILE).unwrap().mut_attrs().size = content.len() as u64;
+        }
+        r
+    }
+
+    fn start_duplicates(&mut self) {
+        if self.duplicates.is_some() {
+            return;
+        }
+        let sources = self
+            .fragments
+            .iter()
+            .map(|f| {
+                Ok((
+                    f.id.to_string(),
+                    f.digest.clone(),
+                    FustaFS::stats_source(f)?,
+                ))
+            })
+            .collect::<std::io::Result<Vec<_>>>();
+        let file = Backing::open(&self.filename);
+        self.duplicates = Some(self.pool.compute(move || {
+            file.and_then(|file| compute_duplicates(&file, sources?))
+                .map(Arc::new)
+                .map_err(|e| e.to_string())
+        }));
+    }
+
+    /// The content of duplicates.txt, if it can be computed within
+    /// `STATS_WAIT`
+    fn load_duplicates(&mut self) -> Result<Arc<String>, FustaError> {
+        self.start_duplicates();
+        let r = FustaFS::wait_for(&mut self.duplicates, DUPLICATES_FILE_NAME);
+        if let Ok(content) = &r {
+            self.get_file(DUPLICATES_FILE).unwrap().mut_attrs().size = content.len() as u64;
+        }
+        r
+    }
+
+    fn start_repeats(&mut self) {
+        if self.repeats.is_some() {
+            return;
+        }
+        let sources = self
+            .fragments
+            .iter()
+            .map(|f| Ok((f.id.to_string(), FustaFS::stats_source(f)?)))
+            .collect::<std::io::Result<Vec<_>>>();
+        let file = Backing::open(&self.filename);
+        let motifs = self.settings.telomere_motifs.clone();
+        self.repeats = Some(self.pool.compute(move || {
+            file.and_then(|file| compute_repeats(&file, sources?, &motifs))
+                .map(Arc::new)
+                .map_err(|e| e.to_string())
+        }));
+    }
+
+    /// The content of repeats.txt, if it can be computed within `STATS_WAIT`
+    fn load_repeats(&mut self) -> Result<Arc<String>, FustaError> {
+        self.start_repeats();
+        let r = FustaFS::wait_for(&mut self.repeats, REPEATS_FILE_NAME);
+        if let Ok(content) = &r {
+            self.get_file(REPEATS_FILE).unwrap().mut_attrs().size = content.len() as u64;
+        }
+        r
+    }
+
+    fn start_format(&mut self) {
+        if self.format.is_some() {
+            return;
+        }
+        let sources = self
+            .fragments
+            .iter()
+            .map(|f| Ok((f.id.to_string(), FustaFS::stats_source(f)?)))
+            .collect::<std::io::Result<Vec<_>>>();
+        let file = Backing::open(&self.filename);
+        self.format = Some(self.pool.compute(move || {
+            file.and_then(|file| compute_format(&file, sources?))
+                .map(Arc::new)
+                .map_err(|e| e.to_string())
+        }));
+    }
+
+    /// The content of format.txt, if it can be computed within `STATS_WAIT`
+    fn load_format(&mut self) -> Result<Arc<String>, FustaError> {
+        self.start_format();
+        let r = FustaFS::wait_for(&mut self.format, FORMAT_FILE_NAME);
+        if let Ok(content) = &r {
+            self.get_file(FORMAT_FILE).unwrap().mut_attrs().size = content.len() as u64;
+        }
+        r
+    }
+
+    fn start_fai(&mut self) {
+        if self.fai.is_some() {
+            return;
+        }
+        // The sequences as found in the FASTA file on disk, wherever they
+        // were edited since
+        let mut sources = self
+            .fragments
+            .iter()
+            .filter(|f| f.origin.is_none())
+            .filter_map(|f| f.offsets.map(|(start, end, _)| (start, end)))
+            .collect::<Vec<_>>();
+        sources.sort_unstable();
+        let file = Backing::open(&self.filename);
+        self.fai = Some(self.pool.compute(move || {
+            file.and_then(|file| compute_fai(&file, sources))
+                .map(Arc::new)
+                .map_err(|e| e.to_string())
+        }));
+    }
+
+    /// The content of index.fai, if it can be computed within `STATS_WAIT`
+    fn load_fai(&mut self) -> Result<Arc<String>, FustaError> {
+        self.start_fai();
+        let r = FustaFS::wait_for(&mut self.fai, FAI_FILE_NAME);
+        if let Ok(content) = &r {
+            self.get_file(FAI_FILE).unwrap().mut_attrs().size = content.len() as u64;
+        }
+        r
+    }
+
+    fn start_codon_usage(&mut self) {
+        if self.codon_usage.is_some() {
+            return;
+        }
+        let mut transcripts = BTreeMap::<usize, Vec<Transcript>>::new();
+        for protein in self.proteins.iter() {
+            let seqid = &protein.transcript.seqid;
+            if let Ok(fragment) = self.annotated_fragment(seqid, &protein.transcript.id) {
+                transcripts
+                    .entry(self.name2fragment[fragment.id.as_str()])
+                    .or_default()
+                    .push(protein.transcript.clone());
+            }
+        }
+        let sources = transcripts
+            .into_iter()
+            .map(|(i, transcripts)| Ok((FustaFS::stats_source(&self.fragments[i])?, transcripts)))
+            .collect::<std::io::Result<Vec<_>>>();
+        let file = Backing::open(&self.filename);
+        let code = self.settings.genetic_code;
+        let separator = self.settings.csv_separator.clone();
+        self.codon_usage = Some(self.pool.compute(move || {
+            file.and_then(|file| compute_codon_usage(&file, sources?, code, &separator))
+                .map(Arc::new)
+                .map_err(|e| e.to_string())
+        }));
+    }
+
+    /// The content of codon_usage.csv, if it can be computed within
+    /// `STATS_WAIT`
+    fn load_codon_usage(&mut self) -> Result<Arc<String>, FustaError> {
+        self.start_codon_usage();
+        let r = FustaFS::wait_for(&mut self.codon_usage, CODON_USAGE_FILE_NAME);
+        if let Ok(content) = &r {
+            self.get_file(CODON_USAGE_FILE).unwrap().mut_attrs().size = content.len() as u64;
+        }
+        r
+    }
+
+    /// The file `name` of `dir`, either digest/ or pcr/, created on first
+    /// access
+    fn scan_file(&mut self, dir: u64, name: &str) -> Result<usize, FustaError> {
+        if let Some(i) = self
+            .scans
+            .iter()
+            .position(|s| s.dir == dir && s.name == name)
+        {
+            return Ok(i);
+        }
+        let invalid = |e: anyhow::Error| FustaError::NotFound(e.to_string());
+        let scan = if dir == PCR_DIR {
+            Scan::Amplicons(Primers::parse(name).map_err(invalid)?)
+        } else if let Some(enzyme) = name.strip_suffix(BED_EXT) {
+            Scan::Sites(Enzyme::parse(enzyme).map_err(invalid)?)
+        } else if let Some(enzyme) = name.strip_suffix(SIZES_EXT) {
+            Scan::Pieces(Enzyme::parse(enzyme).map_err(invalid)?)
+        } else {
+            return Err(FustaError::NotFound(format!(
+                "`{}` should be either ENZYME{} or ENZYME{}",
+                name, BED_EXT, SIZES_EXT
+            )));
+        };
+        let dir_name = if dir == PCR_DIR {
+            PCR_DIR_NAME
+        } else {
+            DIGEST_DIR_NAME
+        };
+        let ino = self.stable_inos.ino_for(&format!("{}/{}", dir_name, name));
+        let mut attrs = FustaFS::make_file_attrs(ino, 0o444);
+        attrs.mtime = self.metadata.modified().unwrap();
+        self.scan_inos.insert(ino, self.scans.len());
+        self.scans.push(ScanFile {
+            dir,
+            name: name.to_owned(),
+            scan,
+            attrs,
+            content: None,
+        });
+        Ok(self.scans.len() - 1)
+    }
+
+    fn start_scan(&mut self, i: usize) {
+        if self.scans[i].content.is_some() {
+            return;
+        }
+        let sources = self
+            .fragments
+            .iter()
+            .map(|f| Ok((f.id.to_string(), FustaFS::stats_source(f)?)))
+            .collect::<std::io::Result<Vec<_>>>();
+        let file = Backing::open(&self.filename);
+        let scan = self.scans[i].scan.clone();
+        let max_amplicon = self.settings.max_amplicon;
+        self.scans[i].content = Some(self.pool.compute(move || {
+            file.and_then(|file| compute_scan(&file, sources?, &scan, max_amplicon))
+                .map(Arc::new)
+                .map_err(|e| e.to_string())
+        }));
+    }
+
+    /// The content of the file `i` of digest/ or pcr/, if it can be
+    /// computed within `STATS_WAIT`
+    fn load_scan(&mut self, i: usize) -> Result<Arc<String>, FustaError> {
+        self.start_scan(i);
+        let scan = &mut self.scans[i];
+        let r = FustaFS::wait_for(&mut scan.content, &scan.name);
+        if let Ok(content) = &r {
+            scan.attrs.size = content.len() as u64;
+        }
+        r
+    }
+
+    /// Wait for the file `name` being computed by the workers, for at most
+    /// `STATS_WAIT`
+    fn wait_for(
+        pending: &mut Option<Pending<Result<Arc<String>, String>>>,
+        name: &str,
+    ) -> Result<Arc<String>, FustaError> {
+        match pending.as_ref().unwrap().wait(STATS_WAIT) {
+            Some(Ok(content)) => Ok(content),
+            Some(Err(e)) => {
+                // Try again on the next read
+                *pending = None;
+                Err(FustaError::BackingIo(
+                    format!("while computing {}", name),
+                    std::io::Error::other(e),
+                ))
+            }
+            None => Err(FustaError::Busy(format!(
+                "{} is still being computed",
+                name
+            ))),
+        }
+    }
+
+    fn make_rename_map_buffer(&mut self) {
+        trace!("Making RENAME_MAP BUFFER");
+        let content = self
+            .fragments
+            .iter()
+            .map(|f| format!("{}\t{}\n", f.original_id, f.id))
+            .collect::<String>();
+        let size = content.len() as u64;
+        if let Some(x) = self.get_file(RENAME_MAP_FILE) {
+            x.set_data(content.as_bytes());
+            x.mut_attrs().size = size;
+        }
+    }
+
+    fn refresh_metadata(&mut self, force: bool) {
+        if self.dirty || force {
+            debug!("Refreshing metadata...");
+            self.infos_stale = true;
+            self.info_csv.stale = true;
+            self.make_info_json_buffer();
+            self.make_labels_buffer();
+            self.make_offsets_buffer();
+            self.make_rename_map_buffer();
+            self.update_indices();
+            self.stats = None;
+            self.duplicates = None;
+            self.codon_usage = None;
+            self.repeats = None;
+            self.format = None;
+            self.fai = None;
+            self.taxa_stale = true;
+            for scan in self.scans.iter_mut() {
+                scan.content = None;
+            }
+            for dir in 0..self.flank_dirs.len() {
+                self.update_flank_sizes(dir);
+            }
+            for ino in self.ends.keys().copied().collect::<Vec<_>>() {
+                self.update_end_size(ino);
+            }
+            for ino in self.view_files.keys().copied().collect::<Vec<_>>() {
+                self.update_view_size(ino);
+            }
+            for ino in self.tag_files.keys().copied().collect::<Vec<_>>() {
+                self.update_tag_size(ino);
+            }
+            debug!("Done.")
+        }
+    }
+
+    fn update_indices(&mut self) {
+        // The listings are rendered from the same fragments
+        self.listings.clear();
+
+        self.name2fragment = self
+            .fragments
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.id.clone().into(), i))
+            .collect::<HashMap<_, _>>();
+
+        self.ino2fragment = self
+            .fragments
+            .iter()
+            .enumerate()
+            .flat_map(|(i, f)| {
+                std::iter::IntoIterator::into_iter([(f.fasta_file.ino, i), (f.seq_file.ino, i)])
+            })
+            .collect::<HashMap<_, _>>();
+    }
+
+    /// The entries of `dir`, one of fasta/, seqs/, download/, ends/, tags/ or aliases/; they are
+    /// rendered once, then served as is until the fragments change, as file
+    /// managers and shell completions list large directories over and over
+    fn listing(&mut self, dir: u64) -> Listing {
+        if let Some(listing) = self.listings.get(&dir) {
+            return listing.clone();
+        }
+        let parent = vec![
+            (dir, FileType::Directory, ".".into()),
+            (ROOT_DIR, FileType::Directory, "..".into()),
+        ];
+        let files = match dir {
+            SEQ_DIR => self
+                .fragments
+                .iter()
+                .map(|f| (f.seq_file.ino, f.seq_file.name.clone()))
+                .collect::<Vec<_>>(),
+            FASTA_DIR => self
+                .fragments
+                .iter()
+                .map(|f| (f.fasta_file.ino, f.fasta_file.name.clone()))
+                .collect(),
+            DOWNLOAD_DIR => {
+                let files = self
+                    .fragments
+                    .iter()
+                    .map(|f| {
+                        let stem = f.codec.encode(&f.id);
+                        (f.fasta_file.ino, format!("{}{}", stem, DOWNLOAD_EXT).into())
+                    })
+                    .collect::<Vec<_>>();
+                files
+                    .into_iter()
+                    .map(|(fasta_ino, name)| (self.download_ino(fasta_ino), name))
+                    .collect()
+            }
+            ENDS_DIR => {
+                let files = self
+                    .fragments
+                    .iter()
+                    .map(|f| (f.fasta_file.ino, f.codec.encode(&f.id)))
+                    .collect::<Vec<_>>();
+                files
+                    .into_iter()
+                    .flat_map(|(fasta_ino, stem)| {
+                        [
+                            (fasta_ino, false, format!("{}{}", stem, HEAD_EXT)),
+                            (fasta_ino, true, format!("{}{}", stem, TAIL_EXT)),
+                        ]
+                    })
+                    .map(|(fasta_ino, tail, name)| (self.end_ino(fasta_ino, tail), name.into()))
+                    .collect()
+            }
+            TAGS_DIR => {
+                let files = self
+                    .fragments
+                    .iter()
+                    .map(|f| {
+                        let stem = f.codec.encode(&f.id);
+                        (f.fasta_file.ino, format!("{}{}", stem, TAGS_EXT).into())
+                    })
+                    .collect::<Vec<_>>();
+                files
+                    .into_iter()
+                    .map(|(fasta_ino, name)| (self.tag_ino(fasta_ino), name))
+                    .collect()
+            }
+            // The aliases that are not the ID of another fragment link to
+            // the fasta/ file of the fragment they resolve to
+            ALIASES_DIR => match self.settings.aliases.as_ref() {
+                Some(aliases) => {
+                    let ids = &self.name2fragment;
+                    self.fragments
+                        .iter()
+                        .flat_map(|f| {
+                            aliases
+                                .aliases_of(&f.id)
+                                .filter(move |alias| {
+                                    aliases.resolve(alias, |id| ids.contains_key(id)) == Some(&f.id)
+                                        && !ids.contains_key(*alias)
+                                        && f.codec.check(alias).is_ok()
+                                })
+                                .map(move |alias| {
+                                    let name = format!("{}{}", f.codec.encode(alias), FASTA_EXT);
+                                    (f.fasta_file.ino, name.into())
+                                })
+                        })
+                        .collect()
+                }
+                None => Vec::new(),
+            },
+            dir => {
+                let view = self.view_dir(dir).unwrap();
+                let files = self
+                    .fragments
+                    .iter()
+                    .map(|f| (f.fasta_file.ino, f.fasta_file.name.clone()))
+                    .collect::<Vec<_>>();
+                files
+                    .into_iter()
+                    .map(|(fasta_ino, name)| (self.view_ino(view, fasta_ino), name))
+                    .collect()
+            }
+        };
+        let listing = Arc::new(
+            parent
+                .into_iter()
+                .chain(
+                    files
+                        .into_iter()
+                        .map(|(ino, name)| (ino, FileType::RegularFile, name)),
+                )
+                .collect::<Vec<_>>(),
+        );
+        self.listings.insert(dir, listing.clone());
+        listing
+    }
+
+    fn is_fasta_file(&self, ino: u64) -> bool {
+        self.ino2fragment
+            .get(&ino)
+            .and_then(|i| self.fragments.get(*i))
+            .map(|f| f.fasta_file.ino == ino)
+            .unwrap_or(false)
+    }
+
+    fn is_seq_file(&self, ino: u64) -> bool {
+        self.ino2fragment
+            .get(&ino)
+            .and_then(|i| self.fragments.get(*i))
+            .map(|f| f.seq_file.ino == ino)
+            .unwrap_or(false)
+    }
+
+    fn is_append_file(&self, ino: u64) -> bool {
+        self.pending_appends.iter().any(|p| p.1.attrs.ino == ino)
+    }
+
+    fn attrs_from_ino(&self, ino: u64) -> Option<&FileAttr> {
+        match ino {
+            ino if self.dir_attrs.contains_key(&ino) => self.dir_attrs.get(&ino),
+            INFO_FILE | SUMMARY_FILE | INFO_CSV_FILE | INFO_JSON_FILE | LABELS_FILE
+            | OFFSETS_FILE | APPENDS_FILE | LAST_ERROR_FILE | COLLISIONS_FILE
+            | COMMIT_REPORT_FILE | STATS_FILE | STATUS_FILE | MANIFEST_FILE | DUPLICATES_FILE
+            | REPEATS_FILE | FORMAT_FILE | FAI_FILE | CODON_USAGE_FILE => self
+                .files
+                .iter()
+                .find(|f| f.ino() == ino)
+                .map(|f| f.attrs()),
+            ino if CTL_FILES.contains(&ino) => self
+                .files
+                .iter()
+                .find(|f| f.ino() == ino)
+                .map(|f| f.attrs()),
+            ino if self.subfragment_from_ino(ino).is_some() => {
+                self.subfragment_from_ino(ino).map(|s| &s.attrs)
+            }
+            ino if self.snapshot_inos.contains_key(&ino) => {
+                Some(&self.snapshot[self.snapshot_inos[&ino]].attrs)
+            }
+            ino if self.versions.contains_key(&ino) => Some(&self.versions[&ino].attrs),
+            ino if self.downloads.contains_key(&ino) => Some(&self.downloads[&ino].attrs),
+            ino if self.ends.contains_key(&ino) => Some(&self.ends[&ino].attrs),
+            ino if self.view_files.contains_key(&ino) => Some(&self.view_files[&ino].attrs),
+            ino if self.tag_files.contains_key(&ino) => Some(&self.tag_files[&ino].attrs),
+            ino if self.taxa.contains_key(&ino) => Some(&self.taxa[&ino].attrs),
+            ino if self.taxon_counts.contains_key(&ino) => {
+                Some(&self.taxa[&self.taxon_counts[&ino]].count)
+            }
+            ino if self.protein_inos.contains_key(&ino) => {
+                Some(&self.proteins[self.protein_inos[&ino]].attrs)
+            }
+            ino if self.flank_inos.contains_key(&ino) => {
+                let (dir, gene) = self.flank_inos[&ino];
+                Some(&self.flank_dirs[dir].files[gene])
+            }
+            ino if self.scan_inos.contains_key(&ino) => {
+                Some(&self.scans[self.scan_inos[&ino]].attrs)
+            }
+            ino if self.flank_dirs.iter().any(|d| d.attrs.ino == ino) => self
+                .flank_dirs
+                .iter()
+                .find(|d| d.attrs.ino == ino)
+                .map(|d| &d.attrs),
+            _ => self
+                .fragment_from_ino(ino)
+                .and_then(|f| f.file_from_ino(ino))
+                .map(|f| f.attrs()),
+        }
+    }
+
+    fn is_writeable(&self, ino: u64) -> bool {
+        self.is_append_file(ino)
+            || self.is_seq_file(ino)
+            || CTL_FILES.contains(&ino)
+            || self.tag_files.contains_key(&ino)
+            || (self.settings.writable_fasta && self.is_fasta_file(ino))
+    }
+
+    /// Execute the content written to the control file `ino` once it is closed
+    fn run_control(&mut self, ino: u64, content: &[u8]) {
+        let content = String::from_utf8_lossy(content);
+        let r = match ino {
+            RENAME_MAP_FILE => self.rename_from_map(&content),
+            REGIONS_FILE => self.create_regions(&content),
+            EXPORT_FILE => self.export(&content),
+            EXPORT_SPLIT_FILE => self.export_split(&content),
+            COMMIT_FILE => self.commit(&content),
+            REVERT_FILE => self.revert(&content),
+            UNDO_FILE => self.undo(&content),
+            DELETE_FILE => self.delete(&content),
+            CONFIG_FILE => self.configure(&content),
+            _ => unreachable!(),
+        };
+        match r {
+            Ok(msg) => {
+                info!("{}", msg);
+                notify(msg);
+            }
+            Err(e) => {
+                self.fail(e);
+            }
+        }
+    }
+
+    /// Rename all the fragments listed in a two-column (old ID, new ID) TSV
+    /// map; the map is validated as a whole before any renaming happens.
+    fn rename_from_map(&mut self, map: &str) -> Result<String, FustaError> {
+        let mut renames = HashMap::new();
+        for (i, line) in map.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let columns = line.split_whitespace().collect::<Vec<_>>();
+            if columns.len() != 2 {
+                return Err(FustaError::Parse(format!(
+                    "rename map, line {}: expected two columns, found {}",
+                    i + 1,
+                    columns.len()
+                )));
+            }
+            let (old_id, new_id) = (columns[0], columns[1]);
+            check_id(new_id, self.settings.codec)
+                .map_err(|e| FustaError::Parse(format!("rename map, line {}: {}", i + 1, e)))?;
+            if renames.insert(old_id, new_id).is_some() {
+                return Err(FustaError::Parse(format!(
+                    "rename map, line {}: `{}` is renamed several times",
+                    i + 1,
+                    old_id
+                )));
+            }
+        }
+
+        for old_id in renames.keys() {
+            if self.fragment_from_id(old_id).is_none() {
+                warn!("rename map: `{}` is not a fragment; skipping", old_id);
+            }
+        }
+
+        // Ensure that the IDs are still unique once all the renamings are applied
+        let mut new_ids = std::collections::HashSet::new();
+        for fragment in self.fragments.iter() {
+            let new_id = renames
+                .get(fragment.id.as_str())
+                .copied()
+                .unwrap_or(&fragment.id);
+            if !new_ids.insert(new_id) {
+                return Err(FustaError::Parse(format!(
+                    "rename map: several fragments would be named `{}`; nothing renamed",
+                    new_id
+                )));
+            }
+        }
+
+        let mut count = 0;
+        for fragment in self.fragments.iter_mut() {
+            if let Some(new_id) = renames.get(fragment.id.as_str()) {
+                if fragment.id != *new_id {
+                    debug!("Renaming {} -> {}", fragment.id, new_id);
+                    fragment.rename(new_id);
+                    count += 1;
+                }
+            }
+        }
+        if count > 0 {
+            self.dirty = true;
+            self.refresh_metadata(false);
+            self.concretize(false)?;
+        }
+        Ok(format!("{} fragments renamed", count))
+    }
+
+    /// Remove all the fragments listed, one per line, either by ID or as a
+    /// `/REGEX/` matched against their IDs, then write the file back once;
+    /// nothing is removed if one of the regexes is invalid.
+    fn delete(&mut self, requests: &str) -> Result<String, FustaError> {
+        let mut ids = HashSet::new();
+        let mut unknown = Vec::new();
+        for (i, line) in requests.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line
+                .strip_prefix('/')
+                .and_then(|l| l.strip_suffix('/'))
+                .filter(|l| !l.is_empty())
+            {
+                Some(pattern) => {
+                    let re = Regex::new(pattern).map_err(|e| {
+                        FustaError::Parse(format!("delete, line {}: {}; nothing deleted", i + 1, e))
+                    })?;
+                    ids.extend(
+                        self.fragments
+                            .iter()
+                            .filter(|f| re.is_match(&f.id))
+                            .map(|f| f.id.clone()),
+                    );
+                }
+                None => match self.fragment_from_name(line) {
+                    Some(fragment) => {
+                        ids.insert(fragment.id.clone());
+                    }
+                    None => {
+                        warn!("delete: `{}` is not a fragment; skipping", line);
+                        unknown.push(format!("line {}: `{}` is not a fragment", i + 1, line));
+                    }
+                },
+            }
+        }
+
+        let bases = self
+            .fragments
+            .iter()
+            .filter(|f| ids.contains(&f.id))
+            .map(|f| f.sequence_len().map_err(|e| f.unreadable(e)))
+            .sum::<Result<usize, FustaError>>()?;
+        for id in ids.iter() {
+            self.forget_history(id);
+        }
+        self.fragments.retain(|f| !ids.contains(&f.id));
+        let summary = format!(
+            "{} fragments deleted ({} bases), {} not found",
+            ids.len(),
+            bases,
+            unknown.len()
+        );
+        let report = std::iter::once(summary.clone())
+            .chain(unknown)
+            .collect::<Vec<_>>()
+            .join("\n")
+            + "\n";
+        if let Some(x) = self.get_file(DELETE_FILE) {
+            x.set_data(report.as_bytes());
+            x.mut_attrs().size = report.len() as u64;
+        }
+        if !ids.is_empty() {
+            self.dirty = true;
+            self.refresh_metadata(false);
+            if !self.settings.defer_deletions {
+                self.concretize(false)?;
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Change the settings listed as `key = value` lines, e.g. `log-level =
+    /// debug`; they are all checked before any is applied
+    fn configure(&mut self, content: &str) -> Result<String, FustaError> {
+        let mut log_level = None;
+        let mut ttl = None;
+        let mut max_cache = None;
+        for (i, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |msg: String| FustaError::Parse(format!("config, line {}: {}", i + 1, msg));
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected KEY = VALUE".into()))?;
+            let value = value.trim();
+            match key.trim() {
+                "log-level" => {
+                    log_level = Some(value.parse::<LevelFilter>().map_err(|_| {
+                        error(format!(
+                            "`{}` is not one of off, error, warn, info, debug, trace",
+                            value
+                        ))
+                    })?)
+                }
+                "ttl" => {
+                    ttl = Some(
+                        crate::chaos::parse_duration(value).map_err(|e| error(e.to_string()))?,
+                    )
+                }
+                "max-cache" => {
+                    max_cache = Some(
+                        value
+                            .parse::<usize>()
+                            .map_err(|_| error(format!("`{}` is not a number of MB", value)))?,
+                    )
+                }
+                key => {
+                    return Err(error(format!(
+                        "unknown key `{}`; expected one of log-level, ttl, max-cache",
+                        key
+                    )))
+                }
+            }
+        }
+
+        let mut changes = Vec::new();
+        if let Some(level) = log_level {
+            log::set_max_level(level);
+            changes.push(format!("log-level = {}", level.as_str().to_lowercase()));
+        }
+        if let Some(ttl) = ttl {
+            self.ttl = ttl;
+            changes.push(format!("ttl = {:?}", ttl));
+        }
+        if let Some(max_cache) = max_cache {
+            let budget = max_cache.saturating_mul(1024 * 1024);
+            self.settings.concretize_threshold = budget;
+            self.settings.memory_budget = budget;
+            self.spill.set_budget(budget);
+            changes.push(format!("max-cache = {}", max_cache));
+        }
+        self.make_config_buffer();
+        if max_cache.is_some() {
+            // The edits may now exceed the cache
+            self.concretize(false)?;
+        }
+        Ok(if changes.is_empty() {
+            "No setting changed".to_owned()
+        } else {
+            format!("Settings changed: {}", changes.join(", "))
+        })
+    }
+
+    /// List the settings that can be changed through ctl/config, in the
+    /// format it expects
+    fn make_config_buffer(&mut self) {
+        let content = format!(
+            "log-level = {}\nttl = {}ms\nmax-cache = {}\n",
+            log::max_level().as_str().to_lowercase(),
+            self.ttl.as_millis(),
+            self.settings.memory_budget / (1024 * 1024)
+        );
+        if let Some(x) = self.get_file(CONFIG_FILE) {
+            x.set_data(content.as_bytes());
+            x.mut_attrs().size = content.len() as u64;
+        }
+    }
+
+    /// Parse a region specification, either `ID`, `ID:START-END[:STRAND]`,
+    /// or a window clipped to the fragment, `ID:CENTER~FLANK[:STRAND]` or
+    /// `ID:START+LENGTH[:STRAND]`, into a (fragment ID, 0-based start, end,
+    /// reverse strand) tuple.
+    fn parse_region(&self, spec: &str) -> Result<(SString, isize, isize, bool), FustaError> {
+        if let Some(fragment) = self.fragment_from_name(spec) {
+            let len = fragment
+                .sequence_len()
+                .map_err(|e| fragment.unreadable(e))?;
+            return Ok((fragment.id.clone(), 0, len as isize, false));
+        }
+
+        let error_message = format!("`{}` is not a valid subfragment scheme", spec);
+        if let Some(caps) = WINDOW_RE.captures(spec) {
+            let fragment = self
+                .fragment_from_name(&caps[1])
+                .ok_or_else(|| FustaError::NotFound(format!("`{}` is not a fragment", &caps[1])))?;
+            let number = |s: &str| {
+                s.replace(',', "").parse::<usize>().map_err(|_| {
+                    FustaError::Parse(format!("{}: `{}` is not an integer", &error_message, s))
+                })
+            };
+            let (position, span) = (number(&caps[2])?, number(&caps[4])?);
+            let len = fragment
+                .sequence_len()
+                .map_err(|e| fragment.unreadable(e))?;
+            if position == 0 {
+                return Err(FustaError::Parse(format!(
+                    "{}: positions start at 1",
+                    error_message
+                )));
+            }
+            if position > len {
+                return Err(FustaError::OutOfRange(format!(
+                    "{}: `{}` is only {} bp long",
+                    error_message, fragment.id, len
+                )));
+            }
+            let (start, end) = if &caps[3] == "~" {
+                (
+                    position.saturating_sub(span + 1),
+                    (position + span).min(len),
+                )
+            } else if span > 0 {
+                (position - 1, (position - 1 + span).min(len))
+            } else {
+                return Err(FustaError::Parse(format!(
+                    "{}: the window is empty",
+                    error_message
+                )));
+            };
+            let reverse = caps.get(5).is_some_and(|s| s.as_str() == "-");
+            return Ok((fragment.id.clone(), start as isize, end as isize, reverse));
+        }
+        if spec.contains(':') && spec.contains('-') {
+            let caps = SUBFRAGMENT_RE.captures(spec).ok_or_else(|| {
+                FustaError::Parse(format!(
+                    "{}: it should be of the form ID:START-END[:STRAND]",
+                    error_message
+                ))
+            })?;
+            let fragment = self
+                .fragment_from_name(&caps[1])
+                .ok_or_else(|| FustaError::NotFound(format!("`{}` is not a fragment", &caps[1])))?;
+            // Thousands may be separated by commas, e.g. chr3:1,200,000-1,230,000
+            let start = str::parse::<isize>(&caps[2].replace(',', "")).map_err(|_| {
+                FustaError::Parse(format!(
+                    "{}: `{}` is not an integer",
+                    &error_message, &caps[2]
+                ))
+            })? - 1;
+            let end = str::parse::<isize>(&caps[3].replace(',', "")).map_err(|_| {
+                FustaError::Parse(format!(
+                    "{}: `{}` is not an integer",
+                    &error_message, &caps[3]
+                ))
+            })?;
+            if start < 0 {
+                return Err(FustaError::Parse(format!(
+                    "{}: positions start at 1",
+                    error_message
+                )));
+            }
+            if end <= start {
+                return Err(FustaError::Parse(format!(
+                    "{}: {} is not after {}",
+                    error_message, end, &caps[2]
+                )));
+            }
+            let len = fragment
+                .sequence_len()
+                .map_err(|e| fragment.unreadable(e))? as isize;
+            if end > len {
+                return Err(FustaError::OutOfRange(format!(
+                    "{}: `{}` is only {} bp long",
+                    error_message, fragment.id, len
+                )));
+            }
+            let reverse = caps.get(4).map(|s| s.as_str() == "-").unwrap_or(false);
+            Ok((fragment.id.clone(), start, end, reverse))
+        } else {
+            Err(FustaError::NotFound(format!(
+                "`{}` is not a fragment",
+                spec
+            )))
+        }
+    }
+
+    /// Create – if it does not exist yet – the subfragment `key` mapping to
+    /// the region `spec`.
+    fn create_subfragment(&mut self, key: &str, spec: &str) -> Result<FileAttr, FustaError> {
+        if let Some(sf) = self.subfragments.get(&key.to_owned()) {
+            return Ok(sf.attrs);
+        }
+
+        let (fragment_id, start, end, reverse) = self.parse_region(spec)?;
+        let ino = self.new_ino();
+        let attrs = FustaFS::make_file_attrs_with_size(ino, 0o444, (end - start) as u64);
+        let sf = SubFragment::new(&fragment_id, start, reverse, attrs);
+        self.subfragments.insert(key.to_owned(), ino, sf);
+        Ok(attrs)
+    }
+
+    /// Bookmark `region` as `name` in get/, where it persists across mounts;
+    /// a previous bookmark of the same name is replaced
+    fn bookmark(&mut self, name: &str, region: &str) -> Result<FileAttr, FustaError> {
+        if name.is_empty() || name.contains('/') || self.region_dirs.contains_key(name) {
+            return Err(FustaError::Parse(format!(
+                "`{}` is not a valid bookmark name",
+                name
+            )));
+        }
+        if self.fragment_from_name(name).is_some() {
+            return Err(FustaError::Conflict(format!(
+                "`{}` is already the name of a fragment",
+                name
+            )));
+        }
+        if self.bookmarks.get(name).is_some_and(|r| r != region) {
+            self.subfragments.remove(&name.to_owned());
+        }
+        let attrs = self.create_subfragment(name, region)?;
+        self.subfragments.get_mut(&name.to_owned()).unwrap().listed = true;
+        if self.bookmarks.get(name).map(String::as_str) != Some(region) {
+            info!("Bookmarking {} as `{}`", region, name);
+            self.bookmarks.insert(name.to_owned(), region.to_owned());
+            self.save_session();
+        }
+        Ok(attrs)
+    }
+
+    /// Whether the user `req_uid` may change the mode and/or the owner of
+    /// `ino`, currently described by `attrs`, following the rules of chmod(2)
+    /// and chown(2)
+    #[allow(clippy::too_many_arguments)]
+    fn check_chmod(
+        &mut self,
+        req_uid: u32,
+        req_gid: u32,
+        ino: u64,
+        attrs: &FileAttr,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> Result<(), FustaError> {
+        let is_owner = req_uid == 0 || req_uid == attrs.uid;
+        if mode.is_some() && !is_owner {
+            return Err(FustaError::ReadOnly(format!(
+                "SETATTR: only the owner of {} may change its mode",
+                self.describe(ino)
+            )));
+        }
+        if uid.is_some_and(|uid| uid != attrs.uid) && req_uid != 0 {
+            return Err(FustaError::ReadOnly(format!(
+                "SETATTR: only root may change the owner of {}",
+                self.describe(ino)
+            )));
+        }
+        if let Some(gid) = gid.filter(|&gid| gid != attrs.gid && req_uid != 0) {
+            let member = is_owner
+                && self
+                    .user_groups
+                    .entry(req_uid)
+                    .or_insert_with(|| user_groups(req_uid, req_gid))
+                    .contains(&gid);
+            if !member {
+                return Err(FustaError::ReadOnly(format!(
+                    "SETATTR: UID {} may not give {} to group {}",
+                    req_uid,
+                    self.describe(ino),
+                    gid
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Change the mode and/or the owner of the directory `ino` on behalf of
+    /// the user `req_uid`; the changes are kept in the session, but for the
+    /// directories of get/, whose inos change from one mount to the next.
+    fn chmod_dir(
+        &mut self,
+        req_uid: u32,
+        req_gid: u32,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> Result<FileAttr, FustaError> {
+        let attrs = self.dir_attrs[&ino];
+        self.check_chmod(req_uid, req_gid, ino, &attrs, mode, uid, gid)?;
+
+        let attrs = self.dir_attrs.get_mut(&ino).unwrap();
+        if let Some(mode) = mode {
+            attrs.perm = (mode & 0o7777) as u16;
+        }
+        attrs.uid = uid.unwrap_or(attrs.uid);
+        attrs.gid = gid.unwrap_or(attrs.gid);
+        attrs.ctime = SystemTime::now();
+        let attrs = *attrs;
+        if !self.region_dirs.values().any(|&dir| dir == ino) {
+            self.directories.insert(
+                ino,
+                DirOwnership {
+                    mode: attrs.perm,
+                    uid: attrs.uid,
+                    gid: attrs.gid,
+                },
+            );
+            self.save_session();
+        }
+        Ok(attrs)
+    }
+
+    /// Remove the bookmark `name` from get/
+    fn drop_bookmark(&mut self, name: &str) -> Result<(), FustaError> {
+        if self.bookmarks.remove(name).is_none() {
+            return Err(FustaError::NotFound(format!(
+                "`{}` is not a bookmark",
+                name
+            )));
+        }
+        self.subfragments.remove(&name.to_owned());
+        self.save_session();
+        Ok(())
+    }
+
+    /// Create the subfragments listed in `regions`, one per line, of the form
+    /// `ID:START-END[:STRAND] [[DIRECTORY/]NAME]`.
+    fn create_regions(&mut self, regions: &str) -> Result<String, FustaError> {
+        let mut created = 0;
+        let mut failures = Vec::new();
+        for (i, line) in regions.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let columns = line.split_whitespace().collect::<Vec<_>>();
+            let spec = columns[0];
+            let name = columns.get(1).copied().unwrap_or(spec);
+            let r = if columns.len() > 2 {
+                Err(format!(
+                    "expected at most two columns, found {}",
+                    columns.len()
+                ))
+            } else {
+                match name.split('/').collect::<Vec<_>>()[..] {
+                    [name] if !name.is_empty() => Ok(name.to_owned()),
+                    [dir, name] if !dir.is_empty() && !name.is_empty() => {
+                        if !self.region_dirs.contains_key(dir) {
+                            let ino = self.new_ino();
+                            self.region_dirs.insert(dir.to_owned(), ino);
+                            self.dir_attrs
+                                .insert(ino, FustaFS::make_dir_attrs(ino, 0o555));
+                        }
+                        Ok(format!("{}/{}", dir, name))
+                    }
+                    _ => Err(format!("`{}` is not a valid name", name)),
+                }
+                .and_then(|key| {
+                    self.create_subfragment(&key, spec)
+                        .map(|_| key)
+                        .map_err(|e| e.to_string())
+                })
+            };
+
+            match r {
+                Ok(key) => {
+                    if let Some(sf) = self.subfragments.get_mut(&key) {
+                        sf.listed = true;
+                    }
+                    created += 1;
+                }
+                Err(e) => {
+                    warn!("regions, line {}: {}", i + 1, e);
+                    failures.push(format!("line {}: {}", i + 1, e));
+                }
+            }
+        }
+
+        let summary = format!("{} regions created, {} failed", created, failures.len());
+        let report = std::iter::once(summary.clone())
+            .chain(failures)
+            .collect::<Vec<_>>()
+            .join("\n")
+            + "\n";
+        if let Some(x) = self.get_file(REGIONS_FILE) {
+            x.set_data(report.as_bytes());
+            x.mut_attrs().size = report.len() as u64;
+        }
+        Ok(summary)
+    }
+
+    /// Write the fragments listed in a two-column (ID, destination path) list
+    /// to real files; fragments sharing a destination are written to it in
+    /// the order they are listed.
+    fn export(&mut self, exports: &str) -> Result<String, FustaError> {
+        let mut destinations: Vec<(PathBuf, Vec<usize>)> = Vec::new();
+        let mut failures = Vec::new();
+        for (i, line) in exports.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let r = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [id, path] => match self.name2fragment.get(id) {
+                    None => Err(format!("fragment `{}` not found", id)),
+                    Some(_) if !Path::new(path).is_absolute() => {
+                        Err(format!("`{}` is not an absolute path", path))
+                    }
+                    Some(&f) => Ok((PathBuf::from(path), f)),
+                },
+                ref columns => Err(format!("expected two columns, found {}", columns.len())),
+            };
+
+            match r {
+                Ok((path, f)) => match destinations.iter_mut().find(|d| d.0 == path) {
+                    Some(d) => d.1.push(f),
+                    None => destinations.push((path, vec![f])),
+                },
+                Err(e) => {
+                    warn!("export, line {}: {}", i + 1, e);
+                    failures.push(format!("line {}: {}", i + 1, e));
+                }
+            }
+        }
+
+        let mut exported = 0;
+        let mut files = 0;
+        for (path, fragments) in destinations {
+            match self.export_to(&path, &fragments, None) {
+                Ok(_) => {
+                    exported += fragments.len();
+                    files += 1;
+                }
+                Err(e) => {
+                    let e = FustaError::BackingIo(format!("Unable to export to {:?}", path), e);
+                    warn!("{}", e);
+                    failures.push(e.to_string());
+                }
+            }
+        }
+
+        let summary = format!(
+            "{} fragments exported to {} files, {} failed",
+            exported,
+            files,
+            failures.len()
+        );
+        let report = std::iter::once(summary.clone())
+            .chain(failures)
+            .collect::<Vec<_>>()
+            .join("\n")
+            + "\n";
+        if let Some(x) = self.get_file(EXPORT_FILE) {
+            x.set_data(report.as_bytes());
+            x.mut_attrs().size = report.len() as u64;
+        }
+        Ok(summary)
+    }
+
+    /// Write every fragment to its own FASTA file, named as in fasta/, in
+    /// the absolute directory given on the first line, created if needed; the
+    /// directory may be followed by the width at which the sequences are
+    /// wrapped, 0 writing them on a single line
+    fn export_split(&mut self, request: &str) -> Result<String, FustaError> {
+        let line = request
+            .lines()
+            .map(str::trim)
+            .find(|l| !l.is_empty() && !l.starts_with('#'))
+            .unwrap_or_default();
+        let (dir, width) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [dir] => (dir, None),
+            [dir, width] => (
+                dir,
+                Some(width.parse::<usize>().map_err(|_| {
+                    FustaError::Parse(format!("export-split: `{}` is not a line width", width))
+                })?),
+            ),
+            _ => {
+                return Err(FustaError::Parse(format!(
+                    "export-split: expected a directory and an optional line width, found `{}`",
+                    line
+                )))
+            }
+        };
+        let dir = Path::new(dir);
+        if !dir.is_absolute() {
+            return Err(FustaError::Parse(format!(
+                "export-split: {:?} is not an absolute path",
+                dir
+            )));
+        }
+        fs::create_dir_all(dir)
+            .map_err(|e| FustaError::BackingIo(format!("Unable to create {:?}", dir), e))?;
+
+        let mut failures = Vec::new();
+        for i in 0..self.fragments.len() {
+            let path = dir.join(self.fragments[i].fasta_file.name.as_str());
+            if let Err(e) = self.export_to(&path, &[i], width) {
+                let e = FustaError::BackingIo(format!("Unable to export to {:?}", path), e);
+                warn!("{}", e);
+                failures.push(e.to_string());
+            }
+        }
+
+        let summary = format!(
+            "{} fragments exported to {:?}, {} failed",
+            self.fragments.len() - failures.len(),
+            dir,
+            failures.len()
+        );
+        let report = std::iter::once(summary.clone())
+            .chain(failures)
+            .collect::<Vec<_>>()
+            .join("\n")
+            + "\n";
+        if let Some(x) = self.get_file(EXPORT_SPLIT_FILE) {
+            x.set_data(report.as_bytes());
+            x.mut_attrs().size = report.len() as u64;
+        }
+        Ok(summary)
+    }
+
+    /// Write the `fragments` as a FASTA file to `path`, wrapping the
+    /// sequences at `width`, or at the width they had in the mounted file;
+    /// a width of 0 writes every sequence on a single line
+    fn export_to(
+        &self,
+        path: &Path,
+        fragments: &[usize],
+        width: Option<usize>,
+    ) -> std::io::Result<()> {
+        const DEFAULT_WIDTH: usize = 60;
+
+        let mut out = std::io::BufWriter::new(fs::File::create(path)?);
+        for &i in fragments {
+            let fragment = &self.fragments[i];
+            self.settings
+                .chaos
+                .read(|| fragment.id.to_string())
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            let width = match (width, fragment.offsets) {
+                (Some(0), _) => usize::MAX,
+                (Some(width), _) => width,
+                (None, Some((_, _, width))) if width > 0 => width,
+                _ => DEFAULT_WIDTH,
+            };
+
+            out.write_all(fragment.label().as_bytes())?;
+            let mut column = 0;
+            let mut offset = 0;
+            while offset < fragment.data_size() {
+                let chunk = fragment.chunk(offset, MAX_IO_SIZE as usize)?;
+                offset += chunk.len();
+                for line in chunk.split(|&c| c == b'\n') {
+                    // The CR of CRLF line endings are not part of the sequence
+                    let mut line = line.strip_suffix(b"\r").unwrap_or(line);
+                    while !line.is_empty() {
+                        let take = std::cmp::min(width - column, line.len());
+                        out.write_all(&line[..take])?;
+                        line = &line[take..];
+                        column += take;
+                        if column == width {
+                            out.write_all(b"\n")?;
+                            column = 0;
+                        }
+                    }
+                }
+            }
+            if column > 0 {
+                out.write_all(b"\n")?;
+            }
+        }
+        out.flush()
+    }
+
+    /// Write the pending changes back immediately; `force` bypasses the
+    /// check on how much of the file would be lost, while `discard` drops
+    /// them instead
+    fn commit(&mut self, request: &str) -> Result<String, FustaError> {
+        let force = match request.trim() {
+            "" => false,
+            "force" => true,
+            "discard" => return self.discard(),
+            x => {
+                return Err(FustaError::Parse(format!(
+                    "`{}` is not a valid commit mode; expected nothing, `force` or `discard`",
+                    x
+                )))
+            }
+        };
+        let summary = if !self.dirty {
+            "Nothing to write back".to_owned()
+        } else {
+            if !force {
+                self.check_loss()?;
+                self.check_shrinkage()?;
+            }
+            self.write_back()?;
+            self.reference_size = self.backing_size();
+            format!("Changes written back to `{}`", self.filename)
+        };
+        let report = format!("{}\n", summary);
+        if let Some(x) = self.get_file(COMMIT_FILE) {
+            x.set_data(report.as_bytes());
+            x.mut_attrs().size = report.len() as u64;
+        }
+        Ok(summary)
+    }
+
+    /// Drop all the changes not written back yet, reading the fragments
+    /// again from the FASTA file
+    fn discard(&mut self) -> Result<String, FustaError> {
+        let summary = if !self.dirty && self.pending_fastas.is_empty() {
+            "Nothing to discard".to_owned()
+        } else {
+            self.reload()?;
+            format!("Changes to `{}` discarded", self.filename)
+        };
+        let report = format!("{}\n", summary);
+        if let Some(x) = self.get_file(COMMIT_FILE) {
+            x.set_data(report.as_bytes());
+            x.mut_attrs().size = report.len() as u64;
+        }
+        Ok(summary)
+    }
+
+    /// Read all the fragments again from the FASTA file, dropping the
+    /// changes not written back yet
+    fn reload(&mut self) -> Result<(), FustaError> {
+        self.pending_fastas.clear();
+        self.history.clear();
+        self.editing.clear();
+        let filename = self.filename.clone();
+        self.read_fasta(&filename).map_err(|e| {
+            FustaError::BackingIo(
+                format!("Unable to read `{}` again", filename),
+                std::io::Error::other(format!("{:#}", e)),
+            )
+        })?;
+        self.dirty = self.writes_elsewhere();
+        self.held_back = false;
+        Ok(())
+    }
+
+    /// Restore the fragments listed, one ID per line, as they are in the
+    /// FASTA file, dropping their edits, renaming or deletion; `*` restores
+    /// all of them.
+    fn revert(&mut self, ids: &str) -> Result<String, FustaError> {
+        let ids = ids
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .collect::<Vec<_>>();
+        let mut failures = Vec::new();
+        let summary = if ids.contains(&"*") {
+            self.reload()?;
+            format!("All the fragments reverted to `{}`", self.filename)
+        } else {
+            let io_error =
+                |e| FustaError::BackingIo(format!("Unable to read `{}`", self.filename), e);
+            let file = Backing::open(&self.filename).map_err(io_error)?;
+            let mut records = FastaReader::new(file.reader(), false)
+                .map(|r| (self.settings.id_rules.apply(&r.id), Some(r)))
+                .collect::<Vec<_>>();
+            let mut reverted = 0;
+            for id in ids {
+                match self.revert_fragment(id, &mut records, &file) {
+                    Ok(_) => reverted += 1,
+                    Err(e) => {
+                        warn!("revert: {}", e);
+                        failures.push(e.to_string());
+                    }
+                }
+            }
+            self.refresh_metadata(false);
+            format!("{} fragments reverted, {} failed", reverted, failures.len())
+        };
+
+        let report = std::iter::once(summary.clone())
+            .chain(failures)
+            .collect::<Vec<_>>()
+            .join("\n")
+            + "\n";
+        if let Some(x) = self.get_file(REVERT_FILE) {
+            x.set_data(report.as_bytes());
+            x.mut_attrs().size = report.len() as u64;
+        }
+        Ok(summary)
+    }
+
+    /// Restore the fragment `id` from its record among the `records` of the
+    /// FASTA `file`; a fragment absent from the file is removed.
+    fn revert_fragment(
+        &mut self,
+        id: &str,
+        records: &mut [(String, Option<fusta::fasta::Fragment>)],
+        file: &Backing,
+    ) -> Result<(), String> {
+        let start_of = |r: &Option<fusta::fasta::Fragment>| r.as_ref().map(|r| r.pos.0);
+        // Fragments are tied to their record by where their sequence starts
+        // in the file, as they may have been renamed since
+        let current = self.fragments.iter().position(|f| f.id == id);
+        if let Some(origin) = current.and_then(|i| self.fragments[i].origin) {
+            return Err(format!(
+                "`{}` comes from `{}`, and can only be reverted with all the others, by `*`",
+                id, self.settings.merge[origin]
+            ));
+        }
+        let record = match current.map(|i| self.fragments[i].offsets) {
+            Some(Some((start, _, _))) => records
+                .iter()
+                .position(|r| start_of(&r.1) == Some(start))
+                .ok_or_else(|| format!("`{}` has already been reverted", id))?,
+            // It has been created since the file was last written
+            Some(None) => {
+                let fragment = self.fragments.remove(current.unwrap());
+                self.pending_fastas.remove(&fragment.fasta_file.ino);
+                self.dirty = true;
+                return Ok(());
+            }
+            None => records
+                .iter()
+                .position(|r| r.0 == id && r.1.is_some())
+                .ok_or_else(|| format!("`{}` is neither mounted nor in the FASTA file", id))?,
+        };
+        let start = start_of(&records[record].1);
+        let current = current.or_else(|| {
+            self.fragments
+                .iter()
+                .position(|f| f.origin.is_none() && f.offsets.map(|o| o.0) == start)
+        });
+        let original_id = records[record].0.clone();
+        if let Some(other) = self
+            .fragments
+            .iter()
+            .position(|f| f.id == original_id)
+            .filter(|&i| Some(i) != current)
+        {
+            return Err(format!(
+                "`{}` can not be restored as `{}`, which is the ID of another fragment",
+                id, self.fragments[other].id
+            ));
+        }
+
+        let record = records[record].1.take().unwrap();
+        let mut fragment = self
+            .fragment_from_record(&original_id, record, file, None)
+            .map_err(|e| format!("unable to read `{}`: {}", original_id, e))?;
+        if let Some(i) = current {
+            self.remember(self.fragments[i].seq_file.ino);
+        }
+        match current {
+            Some(i) => {
+                fragment.original_id = self.fragments[i].original_id.clone();
+                fragment.generation = self.fragments[i].generation;
+                fragment.tags = std::mem::take(&mut self.fragments[i].tags);
+                self.pending_fastas
+                    .remove(&self.fragments[i].fasta_file.ino);
+                self.fragments[i] = fragment;
+            }
+            // It has been deleted; put it back where it was in the file
+            None => {
+                let i = self
+                    .fragments
+                    .iter()
+                    .position(|f| f.origin.is_some() || f.offsets.is_none_or(|o| Some(o.0) > start))
+                    .unwrap_or(self.fragments.len());
+                self.fragments.insert(i, fragment);
+            }
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Record the state of the fragment owning `ino` before it is edited,
+    /// dropping the oldest states once past the undo budget
+    fn remember(&mut self, ino: u64) {
+        let edit = match self.fragment_from_ino(ino) {
+            Some(fragment) => Edit {
+                ino: fragment.seq_file.ino,
+                serial: self.edits,
+                id: fragment.id.clone(),
+                name: fragment.name.clone(),
+                header: fragment.header.clone(),
+                data: match fragment.data() {
+                    Ok(data) => data.into_vec(),
+                    Err(e) => {
+                        warn!(
+                            "The edit of `{}` can not be undone: {}",
+                            fragment.id,
+                            fragment.unreadable(e)
+                        );
+                        return;
+                    }
+                },
+            },
+            None => return,
+        };
+        self.edits += 1;
+        let budget = self.settings.undo_budget;
+        if edit.data.len() > budget {
+            debug!("`{}` is too large to be undone", edit.id);
+            return;
+        }
+        self.history.push_back(edit);
+        let mut size = self.history.iter().map(|e| e.data.len()).sum::<usize>();
+        while size > budget {
+            let oldest = self.history.pop_front().unwrap();
+            debug!("Dropping the oldest undo state of `{}`", oldest.id);
+            size -= oldest.data.len();
+        }
+    }
+
+    /// Record the state of the fragment owning `ino`, unless it is already
+    /// being edited: all the writes between its opening and its closing make
+    /// up a single edit
+    fn remember_once(&mut self, ino: u64) {
+        if let Some(seq_ino) = self.fragment_from_ino(ino).map(|f| f.seq_file.ino) {
+            if self.editing.insert(seq_ino) {
+                self.remember(ino);
+            }
+        }
+    }
+
+    /// Drop the previous states of fragment `id`, e.g. before it is removed,
+    /// as its inos may then be given to another fragment
+    fn forget_history(&mut self, id: &str) {
+        if let Some(ino) = self.fragment_from_id(id).map(|f| f.seq_file.ino) {
+            self.history.retain(|e| e.ino != ino);
+            self.editing.remove(&ino);
+        }
+    }
+
+    /// Step back the last edits of the fragments listed, one `ID [STEPS]`
+    /// per line, `STEPS` defaulting to 1
+    fn undo(&mut self, requests: &str) -> Result<String, FustaError> {
+        let mut undone = 0;
+        let mut lines = Vec::new();
+        let mut failures = 0;
+        for (i, line) in requests.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let r = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [id] => Ok((id, 1)),
+                [id, steps] => steps
+                    .parse::<usize>()
+                    .map(|steps| (id, steps))
+                    .map_err(|_| format!("`{}` is not a number of steps", steps)),
+                ref columns => Err(format!(
+                    "expected at most two columns, found {}",
+                    columns.len()
+                )),
+            }
+            .and_then(|(id, steps)| {
+                (0..steps)
+                    .try_fold(id.to_owned(), |id, _| self.undo_fragment(&id))
+                    .map(|id| (id, steps))
+            });
+
+            match r {
+                Ok((id, steps)) => {
+                    let ino = self.fragment_from_id(&id).unwrap().seq_file.ino;
+                    let left = self.history.iter().filter(|e| e.ino == ino).count();
+                    undone += steps;
+                    lines.push(format!("`{}`: {} edits undone, {} left", id, steps, left));
+                }
+                Err(e) => {
+                    warn!("undo, line {}: {}", i + 1, e);
+                    failures += 1;
+                    lines.push(format!("line {}: {}", i + 1, e));
+                }
+            }
+        }
+        self.refresh_metadata(false);
+
+        let summary = format!("{} edits undone, {} failed", undone, failures);
+        let report = std::iter::once(summary.clone())
+            .chain(lines)
+            .collect::<Vec<_>>()
+            .join("\n")
+            + "\n";
+        if let Some(x) = self.get_file(UNDO_FILE) {
+            x.set_data(report.as_bytes());
+            x.mut_attrs().size = report.len() as u64;
+        }
+        Ok(summary)
+    }
+
+    /// Restore fragment `id` to its state before its last edit; returns its
+    /// ID once restored
+    fn undo_fragment(&mut self, id: &str) -> Result<String, String> {
+        let i = *self
+            .name2fragment
+            .get(id)
+            .ok_or_else(|| format!("fragment `{}` not found", id))?;
+        let ino = self.fragments[i].seq_file.ino;
+        let last = self
+            .history
+            .iter()
+            .rposition(|e| e.ino == ino)
+            .ok_or_else(|| format!("no more edits of `{}` to undo", id))?;
+        if self.history[last].id != id
+            && self
+                .name2fragment
+                .contains_key(self.history[last].id.as_str())
+        {
+            return Err(format!(
+                "`{}` can not be renamed back to `{}`, which is the ID of another fragment",
+                id, self.history[last].id
+            ));
+        }
+
+        let edit = self.history.remove(last).unwrap();
+        self.editing.remove(&ino);
+        let fragment = &mut self.fragments[i];
+        self.pending_fastas.remove(&fragment.fasta_file.ino);
+        fragment.id = edit.id;
+        fragment.name = edit.name;
+        fragment.header = edit.header;
+        fragment.data = Arc::new(Buffer(edit.data));
+        fragment.forget_derived();
+        fragment.edited = true;
+        fragment.refresh_virtual_files();
+        let id = fragment.id.to_string();
+        self.dirty = true;
+        self.update_indices();
+        Ok(id)
+    }
+
+    /// What becomes of the fragments appended or renamed over existing ones
+    fn policies(&self) -> [(&'static str, &'static str); 3] {
+        [
+            (
+                "on-collision",
+                match self.settings.on_append_collision {
+                    Collision::Skip => "skip",
+                    Collision::Overwrite => "overwrite",
+                    Collision::Suffix => "suffix",
+                },
+            ),
+            (
+                "on-edit-conflict",
+                match self.settings.on_edit_conflict {
+                    Collision::Skip => "refuse",
+                    Collision::Overwrite => "overwrite",
+                    Collision::Suffix => "suffix",
+                },
+            ),
+            (
+                "rename-overwrite",
+                if self.settings.no_overwrite {
+                    "refuse"
+                } else {
+                    "overwrite"
+                },
+            ),
+        ]
+    }
+
+    /// Describe the mount for provenance purposes: what is mounted, how,
+    /// and by which version of FUSTA
+    fn make_manifest_buffer(&mut self) {
+        let file_description = |path: &str| {
+            let metadata = fs::metadata(path).ok();
+            serde_json::json!({
+                "path": fs::canonicalize(path).map_or_else(|_| path.to_owned(), |p| p.display().to_string()),
+                "size": metadata.as_ref().map(|m| m.len()),
+                "mtime": metadata
+                    .and_then(|m| m.modified().ok())
+                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs()),
+            })
+        };
+        // The companion files are stale if older than the FASTA file
+        let fasta_mtime = fs::metadata(&self.filename).and_then(|m| m.modified()).ok();
+        let derived = Companion::ALL
+            .iter()
+            .map(|c| c.path(Path::new(&self.filename)))
+            .filter_map(|path| {
+                let mtime = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+                Some(serde_json::json!({
+                    "path": path.display().to_string(),
+                    "stale": fasta_mtime.is_some_and(|fasta| mtime < fasta),
+                }))
+            })
+            .collect::<Vec<_>>();
+        let settings = &self.settings;
+        let manifest = serde_json::json!({
+            "fusta_version": env!("CARGO_PKG_VERSION"),
+            "source": file_description(&self.filename),
+            "merged": settings.merge.iter().map(|f| file_description(f)).collect::<Vec<_>>(),
+            "output": settings.output,
+            "session": self.session_file.as_ref().map(|f| f.display().to_string()),
+            "mounts": self.epoch,
+            "fragments": self.fragments.len(),
+            "dirty": self.dirty,
+            "options": {
+                "cache": match settings.cache {
+                    Cache::Mmap => "mmap",
+                    Cache::File => "file",
+                    Cache::RAM => "memory",
+                },
+                "max_cache": settings.memory_budget,
+                "headers_only": settings.headers_only,
+                "writable_fasta": settings.writable_fasta,
+                "defer_deletions": settings.defer_deletions,
+                "seed": settings.seed,
+                "policies": self.policies().iter().copied().collect::<BTreeMap<_, _>>(),
+                "gff": settings.annotation,
+                "write_transform": settings.write_transform.to_string(),
+                "views": settings
+                    .views
+                    .iter()
+                    .map(|(name, pipeline)| (name.clone(), pipeline.to_string()))
+                    .collect::<BTreeMap<_, _>>(),
+            },
+            "derived_files": derived,
+        });
+        let content = serde_json::to_string_pretty(&manifest).unwrap() + "\n";
+        let size = content.len() as u64;
+        if let Some(x) = self.get_file(MANIFEST_FILE) {
+            x.set_data(content.as_bytes());
+            x.mut_attrs().size = size;
+        }
+    }
+
+    /// Describe whether some changes have not been written back to the FASTA
+    /// file yet: `clean` or `dirty`, followed by the reasons why
+    fn make_status_buffer(&mut self) {
+        let mut reasons = Vec::new();
+        if self.dirty {
+            reasons.push("the sequences have changed since the FASTA file was last written".into());
+            if self.check_loss().is_err() || self.held_back {
+                reasons.push(format!(
+                    "the write-back is held back until confirmed through ctl/{}",
+                    COMMIT_FILE_NAME
+                ));
+            }
+            if self.writes_elsewhere() {
+                reasons.push(format!(
+                    "`{}` has not been written yet",
+                    self.settings.output.as_ref().unwrap()
+                ));
+            }
+            let filtered = self
+                .fragments
+                .iter()
+                .filter(|f| !self.is_written(f))
+                .count();
+            if filtered > 0 {
+                reasons.push(format!(
+                    "{} sequences will be left out of the FASTA file by --write-filter",
+                    filtered
+                ));
+            }
+        }
+        if !self.pending_fastas.is_empty() {
+            reasons.push(format!(
+                "{} files of fasta/ are being edited",
+                self.pending_fastas.len()
+            ));
+        }
+        if !self.pending_appends.is_empty() {
+            reasons.push(format!(
+                "{} files are being appended",
+                self.pending_appends.len()
+            ));
+        }
+        // Then the policies in effect, that tell what the next operations
+        // will do, and the fragments that could not be read lately
+        let degraded = self
+            .fragments
+            .iter()
+            .filter(|f| f.data.is_degraded())
+            .map(|f| f.id.as_str())
+            .collect::<Vec<_>>();
+        let degraded_line =
+            (!degraded.is_empty()).then(|| format!("degraded: {}", degraded.join(", ")));
+        let content =
+            std::iter::once(if reasons.is_empty() { "clean" } else { "dirty" }.to_owned())
+                .chain(reasons.into_iter().map(|r| format!("- {}", r)))
+                .chain(self.policies().iter().map(|(k, v)| format!("{}: {}", k, v)))
+                .chain(degraded_line)
+                .map(|l| l + "\n")
+                .collect::<String>();
+        let size = content.len() as u64;
+        if let Some(x) = self.get_file(STATUS_FILE) {
+            x.set_data(content.as_bytes());
+            x.mut_attrs().size = size;
+        }
+    }
+
+    /// Lists the subfragments to show in the directory `dir` of get/
+    fn listed_subfragments(&self, dir: Option<&str>) -> Vec<(u64, String)> {
+        self.subfragments
+            .iter()
+            .filter(|(_, (_, sf))| sf.listed)
+            .filter_map(|(key, (ino, _))| match (key.split_once('/'), dir) {
+                (None, None) => Some((*ino, key.to_owned())),
+                (Some((d, name)), Some(dir)) if d == dir => Some((*ino, name.to_owned())),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// The operations of `fusta browse`, which manipulates a FASTA file as the
+/// mount would, without mounting it
+#[cfg_attr(not(feature = "browse"), allow(dead_code))]
+impl FustaFS {
+    /// The IDs of the fragments and the lengths of their sequences, in the
+    /// order of the file
+    pub(crate) fn sequences(&self) -> Result<Vec<(String, usize)>, FustaError> {
+        self.fragments
+            .iter()
+            .map(|f| {
+                let len = f.sequence_len().map_err(|e| f.unreadable(e))?;
+                Ok((f.id.to_string(), len))
+            })
+            .collect()
+    }
+
+    /// Up to `size` bases of the sequence of `id`, starting at `offset`
+    pub(crate) fn sequence_window(
+        &self,
+        id: &str,
+        offset: usize,
+        size: usize,
+    ) -> Result<Box<[u8]>, FustaError> {
+        let fragment = self
+            .fragment_from_id(id)
+            .ok_or_else(|| FustaError::NotFound(format!("`{}` is not a fragment", id)))?;
+        fragment
+            .pure_chunk(offset, size)
+            .map_err(|e| fragment.unreadable(e))
+    }
+
+    /// The content of stats.csv, if it has already been computed in the
+    /// background
+    pub(crate) fn stats(&mut self) -> Result<Option<Arc<String>>, FustaError> {
+        self.start_stats();
+        if self.stats.as_ref().unwrap().wait(Duration::ZERO).is_none() {
+            return Ok(None);
+        }
+        self.load_stats().map(Some)
+    }
+
+    /// Rename the fragment `id` to `new_id`, as a renaming in seqs/ does
+    pub(crate) fn rename_fragment(&mut self, id: &str, new_id: &str) -> Result<(), FustaError> {
+        check_id(new_id, self.settings.codec)
+            .map_err(|e| FustaError::Parse(format!("Cannot rename `{}`: {}", id, e)))?;
+        // Shortcut if we cannot overwrite existing fragments
+        if self.fragment_from_id(new_id).is_some() && self.settings.no_overwrite {
+            return Err(FustaError::Conflict(format!(
+                "Cannot rename `{}` to `{}`: already existing.",
+                id, new_id
+            )));
+        }
+        self.apply(Mutation::Rename {
+            from: id.into(),
+            to: new_id.into(),
+        })
+    }
+
+    /// Remove the fragment `id`, as a deletion in seqs/ does
+    pub(crate) fn delete_fragment(&mut self, id: &str) -> Result<(), FustaError> {
+        match self.fragment_from_id(id) {
+            Some(f) => {
+                let id = f.id.clone();
+                self.apply(Mutation::Delete(id))
+            }
+            None => Err(FustaError::NotFound(format!("`{}` does not exist", id))),
+        }
+    }
+
+    /// Write the fragment `id` to the FASTA file `path`, as ctl/export does
+    pub(crate) fn extract(&self, id: &str, path: &Path) -> Result<(), FustaError> {
+        let i = *self
+            .name2fragment
+            .get(id)
+            .ok_or_else(|| FustaError::NotFound(format!("`{}` does not exist", id)))?;
+        self.export_to(path, &[i], None)
+            .map_err(|e| FustaError::BackingIo(format!("Unable to export to {:?}", path), e))
+    }
+
+    /// Whether some changes have not been written back yet
+    pub(crate) fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// The separator of the fields of the CSV files, e.g. stats.csv
+    pub(crate) fn csv_separator(&self) -> &str {
+        &self.settings.csv_separator
+    }
+
+    /// Write the changes back right away, whatever the size of the file
+    /// afterwards, or drop them, as ctl/commit does
+    pub(crate) fn write_changes(&mut self, keep: bool) -> Result<String, FustaError> {
+        self.commit(if keep { "force" } else { "discard" })
+    }
+}
+
+impl Drop for FustaFS {
     fn drop(&mut self) {
-        self.concretize(true);
+        if let Err(e) = self.concretize(true) {
+            self.fail(e);
+        }
+        self.save_session();
+    }
+}
+impl Filesystem for FustaFS {
+    fn init(&mut self, _req: &Request, config: &mut KernelConfig) -> Result<(), c_int> {
+        // The kernel may offer less; fall back to what it supports. fuser
+        // requests max_pages from the largest of both, so that the reads and
+        // writes are not split in 128kB requests. The readahead can not be
+        // raised past what the kernel offers, i.e. the read_ahead_kb of the
+        // connection in /sys/class/bdi, that only root may change.
+        let max_write = config
+            .set_max_write(MAX_IO_SIZE)
+            .or_else(|nearest| config.set_max_write(nearest));
+        let max_readahead = config
+            .set_max_readahead(MAX_IO_SIZE)
+            .or_else(|nearest| config.set_max_readahead(nearest));
+        if max_write.is_err() || max_readahead.is_err() {
+            warn!("Unable to negotiate large I/O sizes with the kernel");
+        }
+        self.prefetch();
+        Ok(())
+    }
+
+    fn lookup(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let _op = op!(req, "lookup", parent);
+        let name = name.to_str().unwrap();
+        let not_found = || FustaError::NotFound(format!("LOOKUP: `{}` does not exist", name));
+        match parent {
+            ROOT_DIR => match name {
+                "fasta" => {
+                    self.reply_entry(reply, &self.dir_attrs[&FASTA_DIR]);
+                }
+                "seqs" => {
+                    self.reply_entry(reply, &self.dir_attrs[&SEQ_DIR]);
+                }
+                "append" => {
+                    self.reply_entry(reply, &self.dir_attrs[&APPEND_DIR]);
+                }
+                "get" => {
+                    self.reply_entry(reply, &self.dir_attrs[&SUBFRAGMENTS_DIR]);
+                }
+                "ctl" => {
+                    self.reply_entry(reply, &self.dir_attrs[&CTL_DIR]);
+                }
+                "snapshot" => {
+                    self.reply_entry(reply, &self.dir_attrs[&SNAPSHOT_DIR]);
+                }
+                "download" => {
+                    self.reply_entry(reply, &self.dir_attrs[&DOWNLOAD_DIR]);
+                }
+                DIGEST_DIR_NAME => {
+                    self.reply_entry(reply, &self.dir_attrs[&DIGEST_DIR]);
+                }
+                PCR_DIR_NAME => {
+                    self.reply_entry(reply, &self.dir_attrs[&PCR_DIR]);
+                }
+                ENDS_DIR_NAME => {
+                    self.reply_entry(reply, &self.dir_attrs[&ENDS_DIR]);
+                }
+                TAGS_DIR_NAME => {
+                    self.reply_entry(reply, &self.dir_attrs[&TAGS_DIR]);
+                }
+                TAXA_DIR_NAME => {
+                    self.reply_entry(reply, &self.dir_attrs[&TAXA_DIR]);
+                }
+                ALIASES_DIR_NAME if self.settings.aliases.is_some() => {
+                    self.reply_entry(reply, &self.dir_attrs[&ALIASES_DIR]);
+                }
+                PROTEINS_DIR_NAME if self.is_annotated() => {
+                    self.reply_entry(reply, &self.dir_attrs[&PROTEINS_DIR]);
+                }
+                FEATURES_DIR_NAME if self.is_annotated() => {
+                    self.reply_entry(reply, &self.dir_attrs[&FEATURES_DIR]);
+                }
+                META_DIR_NAME => {
+                    self.reply_entry(reply, &self.dir_attrs[&META_DIR]);
+                }
+                INFO_FILE_NAME => {
+                    self.load_infos();
+                    let attrs = *self.get_file(INFO_FILE).unwrap().attrs();
+                    self.reply_entry(reply, &attrs);
+                }
+                SUMMARY_FILE_NAME => {
+                    self.make_summary_buffer();
+                    let attrs = *self.get_file(SUMMARY_FILE).unwrap().attrs();
+                    self.reply_entry(reply, &attrs);
+                }
+                INFO_CSV_FILE_NAME => {
+                    self.load_info_csv();
+                    let attrs = *self.get_file(INFO_CSV_FILE).unwrap().attrs();
+                    self.reply_entry(reply, &attrs);
+                }
+                INFO_JSON_FILE_NAME => {
+                    let attrs = *self.get_file(INFO_JSON_FILE).unwrap().attrs();
+                    self.reply_entry(reply, &attrs);
+                }
+                STATS_FILE_NAME => {
+                    let attrs = *self.get_file(STATS_FILE).unwrap().attrs();
+                    self.reply_entry(reply, &attrs);
+                }
+                DUPLICATES_FILE_NAME => {
+                    let attrs = *self.get_file(DUPLICATES_FILE).unwrap().attrs();
+                    self.reply_entry(reply, &attrs);
+                }
+                FORMAT_FILE_NAME => {
+                    let attrs = *self.get_file(FORMAT_FILE).unwrap().attrs();
+                    self.reply_entry(reply, &attrs);
+                }
+                FAI_FILE_NAME => {
+                    let attrs = *self.get_file(FAI_FILE).unwrap().attrs();
+                    self.reply_entry(reply, &attrs);
+                }
+                REPEATS_FILE_NAME => {
+                    let attrs = *self.get_file(REPEATS_FILE).unwrap().attrs();
+                    self.reply_entry(reply, &attrs);
+                }
+                CODON_USAGE_FILE_NAME if self.is_annotated() => {
+                    let attrs = *self.get_file(CODON_USAGE_FILE).unwrap().attrs();
+                    self.reply_entry(reply, &attrs);
+                }
+                LABELS_FILE_NAME => {
+                    let attrs = *self.get_file(LABELS_FILE).unwrap().attrs();
+                    self.reply_entry(reply, &attrs);
+                }
+                OFFSETS_FILE_NAME => {
+                    let attrs = *self.get_file(OFFSETS_FILE).unwrap().attrs();
+                    self.reply_entry(reply, &attrs);
+                }
+                APPENDS_FILE_NAME => {
+                    let attrs = *self.get_file(APPENDS_FILE).unwrap().attrs();
+                    self.reply_entry(reply, &attrs);
+                }
+                name => match self.view_dirs.iter().find(|d| d.name == name) {
+                    Some(view) => self.reply_entry(reply, &self.dir_attrs[&view.ino]),
+                    None => reply.error(self.fail(not_found())),
+                },
+            },
+            SEQ_DIR | FASTA_DIR => {
+                let file = (if parent == SEQ_DIR {
+                    self.fragment_from_seq_filename(name)
+                } else {
+                    self.fragment_from_fasta_filename(name)
+                })
+                .and_then(|f| f.file_from_filename(name))
+                .map(|f| *f.attrs())
+                .or_else(|| self.aliased_file(parent, name).map(|f| f.attrs));
+
+                match file {
+                    Some(attrs) => self.reply_entry(reply, &attrs),
+                    None if parent == SEQ_DIR && name.contains('@') => match self.version(name) {
+                        Ok(attrs) => self.reply_entry(reply, &attrs),
+                        Err(e) => reply.error(self.fail(e)),
+                    },
+                    None => reply.error(self.fail(not_found())),
+                }
+            }
+            META_DIR => match name {
+                LAST_ERROR_FILE_NAME => {
+                    let attrs = *self.get_file(LAST_ERROR_FILE).unwrap().attrs();
+                    self.reply_entry(reply, &attrs);
+                }
+                COLLISIONS_FILE_NAME => {
+                    let attrs = *self.get_file(COLLISIONS_FILE).unwrap().attrs();
+                    self.reply_entry(reply, &attrs);
+                }
+                COMMIT_REPORT_FILE_NAME => {
+                    let attrs = *self.get_file(COMMIT_REPORT_FILE).unwrap().attrs();
+                    self.reply_entry(reply, &attrs);
+                }
+                MANIFEST_FILE_NAME => {
+                    self.make_manifest_buffer();
+                    let attrs = *self.get_file(MANIFEST_FILE).unwrap().attrs();
+                    self.reply_entry(reply, &attrs);
+                }
+                STATUS_FILE_NAME => {
+                    self.make_status_buffer();
+                    let attrs = *self.get_file(STATUS_FILE).unwrap().attrs();
+                    self.reply_entry(reply, &attrs);
+                }
+                _ => {
+                    reply.error(self.fail(not_found()));
+                }
+            },
+            DOWNLOAD_DIR => {
+                let fasta_ino = name
+                    .strip_suffix(DOWNLOAD_EXT)
+                    .and_then(|stem| self.settings.codec.decode(stem))
+                    .and_then(|id| self.fragment_from_id(&id))
+                    .map(|f| f.fasta_file.ino);
+                if let Some(fasta_ino) = fasta_ino {
+                    let ino = self.download_ino(fasta_ino);
+                    self.reply_entry(reply, &self.downloads[&ino].attrs);
+                } else {
+                    reply.error(self.fail(not_found()));
+                }
+            }
+            ENDS_DIR => {
+                let end = name
+                    .strip_suffix(HEAD_EXT)
+                    .map(|stem| (stem, false))
+                    .or_else(|| name.strip_suffix(TAIL_EXT).map(|stem| (stem, true)))
+                    .and_then(|(stem, tail)| Some((self.settings.codec.decode(stem)?, tail)))
+                    .and_then(|(id, tail)| {
+                        Some((self.fragment_from_id(&id)?.fasta_file.ino, tail))
+                    });
+                if let Some((fasta_ino, tail)) = end {
+                    let ino = self.end_ino(fasta_ino, tail);
+                    self.reply_entry(reply, &self.ends[&ino].attrs);
+                } else {
+                    reply.error(self.fail(not_found()));
+                }
+            }
+            ALIASES_DIR => {
+                let attrs = name
+                    .strip_suffix(FASTA_EXT)
+                    .and_then(|stem| self.settings.codec.decode(stem))
+                    .filter(|alias| self.fragment_from_id(alias).is_none())
+                    .and_then(|alias| self.fragment_from_name(&alias))
+                    .map(|f| f.fasta_file.attrs);
+                if let Some(attrs) = attrs {
+                    self.reply_entry(reply, &attrs);
+                } else {
+                    reply.error(self.fail(not_found()));
+                }
+            }
+            TAGS_DIR => {
+                let fasta_ino = name
+                    .strip_suffix(TAGS_EXT)
+                    .and_then(|stem| self.settings.codec.decode(stem))
+                    .and_then(|id| self.fragment_from_id(&id))
+                    .map(|f| f.fasta_file.ino);
+                if let Some(fasta_ino) = fasta_ino {
+                    let ino = self.tag_ino(fasta_ino);
+                    self.reply_entry(reply, &self.tag_files[&ino].attrs);
+                } else {
+                    reply.error(self.fail(not_found()));
+                }
+            }
+            PROTEINS_DIR => {
+                if let Some(&i) = self.protein_names.get(name) {
+                    self.reply_entry(reply, &self.proteins[i].attrs);
+                } else {
+                    reply.error(self.fail(not_found()));
+                }
+            }
+            DIGEST_DIR | PCR_DIR => match self.scan_file(parent, name) {
+                Ok(i) => self.reply_entry(reply, &self.scans[i].attrs),
+                Err(e) => reply.error(self.fail(e)),
+            },
+            FEATURES_DIR => {
+                if let Some(flank) = Flank::parse(name) {
+                    let dir = self.flank_dir(flank);
+                    self.reply_entry(reply, &self.flank_dirs[dir].attrs);
+                } else {
+                    let e = FustaError::NotFound(format!(
+                        "`{}` should be of the form upstreamN or downstreamN",
+                        name
+                    ));
+                    reply.error(self.fail(e));
+                }
+            }
+            parent if self.flank_dirs.iter().any(|d| d.attrs.ino == parent) => {
+                let dir = self
+                    .flank_dirs
+                    .iter()
+                    .position(|d| d.attrs.ino == parent)
+                    .unwrap();
+                if let Some(&gene) = self.gene_names.get(name) {
+                    self.reply_entry(reply, &self.flank_dirs[dir].files[gene]);
+                } else {
+                    reply.error(self.fail(not_found()));
+                }
+            }
+            parent if parent == TAXA_DIR || self.taxa.contains_key(&parent) => {
+                self.make_taxa();
+                match self.taxon_entry(parent, name) {
+                    Some(attrs) => self.reply_entry(reply, &attrs),
+                    None => reply.error(self.fail(not_found())),
+                }
+            }
+            parent if self.view_dir(parent).is_some() => {
+                let fasta_ino = self
+                    .fragment_from_fasta_filename(name)
+                    .map(|f| f.fasta_file.ino);
+                if let Some(fasta_ino) = fasta_ino {
+                    let ino = self.view_ino(self.view_dir(parent).unwrap(), fasta_ino);
+                    self.reply_entry(reply, &self.view_files[&ino].attrs);
+                } else {
+                    reply.error(self.fail(not_found()));
+                }
+            }
+            SNAPSHOT_DIR => {
+                if let Some(&i) = self.snapshot_names.get(name) {
+                    self.reply_entry(reply, &self.snapshot[i].attrs);
+                } else {
+                    reply.error(self.fail(not_found()));
+                }
+            }
+            CTL_DIR => {
+                if let Some(file) = self.ctl_file_from_name(name) {
+                    self.reply_entry(reply, file.attrs());
+                } else {
+                    reply.error(self.fail(not_found()));
+                }
+            }
+            SUBFRAGMENTS_DIR => {
+                if let Some(&dir_ino) = self.region_dirs.get(name) {
+                    self.reply_entry(reply, &self.dir_attrs[&dir_ino]);
+                    return;
+                }
+                let sf = match name.split_once('=') {
+                    Some((bookmark, region)) => self.bookmark(bookmark, region),
+                    None => self.create_subfragment(name, name),
+                };
+                match sf {
+                    Ok(attrs) => {
+                        self.reply_entry(reply, &attrs);
+                    }
+                    Err(e) => {
+                        reply.error(self.fail(e));
+                    }
+                }
+            }
+            parent if self.region_dirs.values().any(|&ino| ino == parent) => {
+                let dir = self
+                    .region_dirs
+                    .iter()
+                    .find(|(_, &ino)| ino == parent)
+                    .unwrap()
+                    .0;
+                if let Some(sf) = self.subfragments.get(&format!("{}/{}", dir, name)) {
+                    self.reply_entry(reply, &sf.attrs);
+                } else {
+                    reply.error(self.fail(not_found()));
+                }
+            }
+            _ => {
+                let e = FustaError::NotFound(format!("LOOKUP: parent {} does not exist", parent));
+                reply.error(self.fail(e));
+            }
+        }
+    }
+
+    fn forget(&mut self, req: &Request, ino: u64, _nlookup: u64) {
+        let _op = op!(req, "forget", ino);
+        self.forget_ino(ino);
+    }
+
+    fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
+        let _op = op!(req, "getattr", ino);
+        if let Some(attrs) = self.attrs_from_ino(ino) {
+            reply.attr(&self.ttl, attrs)
+        } else {
+            let e = FustaError::NotFound(format!("GETATTR: ino `{}` does not exist", ino));
+            reply.error(self.fail(e))
+        }
+    }
+
+    /// Describe the fragments as `df` would, so that their count and total
+    /// length can be checked without reading anything: the fragments are
+    /// the inodes, and their bases the 1-byte fragments (f_frsize)
+    fn statfs(&mut self, req: &Request, _ino: u64, reply: ReplyStatfs) {
+        let _op = op!(req, "statfs", ROOT_DIR);
+        let bases = self.bases();
+        reply.statfs(
+            bases as u64,
+            0,
+            0,
+            self.fragments.len() as u64,
+            0,
+            MAX_IO_SIZE,
+            255,
+            1,
+        );
+    }
+
+    fn access(&mut self, req: &Request, ino: u64, mask: i32, reply: ReplyEmpty) {
+        let _op = op!(req, "access", ino);
+        let (perm, gid) = match self.attrs_from_ino(ino) {
+            Some(attrs) => (attrs.perm as i32, attrs.gid),
+            None => {
+                let e = FustaError::NotFound(format!("ACCESS: ino `{}` does not exist", ino));
+                reply.error(self.fail(e));
+                return;
+            }
+        };
+        if mask == F_OK {
+            reply.ok();
+            return;
+        }
+
+        // When writers are restricted, the modes are not enforced by the
+        // kernel and all users are held to the owner permissions.
+        let granted = if req.uid() == 0 {
+            R_OK | W_OK | if perm & 0o111 != 0 { X_OK } else { 0 }
+        } else if !self.settings.writers.is_empty() || req.uid() == unsafe { geteuid() } {
+            (perm >> 6) & 0o7
+        } else if req.gid() == gid {
+            (perm >> 3) & 0o7
+        } else {
+            perm & 0o7
+        };
+        if mask & !granted != 0 || (mask & W_OK != 0 && !self.may_write(req)) {
+            reply.error(EACCES)
+        } else {
+            reply.ok()
+        }
+    }
+
+    fn read(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let _op = op!(req, "read", ino);
+        debug!("READING {}", ino);
+        // Clients are admitted on what they ask for before anything is read,
+        // so that a throttled client does not hold up the others while its
+        // sequences are read
+        let size_left = self.attrs_from_ino(ino).map_or(size as u64, |attrs| {
+            let offset = std::cmp::max(offset, 0) as u64;
+            std::cmp::min(attrs.size.saturating_sub(offset), size as u64)
+        });
+        let now = std::time::Instant::now();
+        let delayed = self.throttle.as_mut().and_then(|throttle| {
+            let client = throttle.client(req.pid(), req.uid());
+            let when = throttle.admit(client, size_left as usize, now);
+            (when > now).then_some((client, when))
+        });
+        let (client, when) = match delayed {
+            Some(delayed) => delayed,
+            None => {
+                match self.read_file(ino, offset, size) {
+                    Ok(data) => {
+                        self.touch_accessed(ino);
+                        reply.data(&data)
+                    }
+                    Err(e) => reply.error(self.fail(e)),
+                }
+                return;
+            }
+        };
+        debug!("Delaying the read of {} by {:?}", client, when - now);
+        // The sequences are read once the client may read again; the other
+        // files, computed or held in memory, right away
+        let read = match self.check_readable(ino) {
+            Ok(()) => self.sequence_read(ino, offset, size),
+            Err(e) => return reply.error(self.fail(e)),
+        };
+        let deferred: Box<dyn FnOnce() + Send> = match read {
+            Some(read) => Box::new(move || match read.run() {
+                Ok(data) => reply.data(&data),
+                Err(e) => {
+                    log!(e.level(), "{}", e);
+                    reply.error(e.errno())
+                }
+            }),
+            None => match self.read_file(ino, offset, size) {
+                Ok(data) => Box::new(move || reply.data(&data)),
+                Err(e) => return reply.error(self.fail(e)),
+            },
+        };
+        self.touch_accessed(ino);
+        self.throttle.as_mut().unwrap().defer(when, deferred);
+    }
+
+    fn readdir(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let _op = op!(req, "readdir", ino);
+        match ino {
+            ROOT_DIR => {
+                let entries = self.root_entries();
+                for (o, (ino, entry)) in entries.iter().enumerate().skip(offset as usize) {
+                    let _ = reply.add(*ino, o as i64 + 1, entry.0, entry.1);
+                }
+                reply.ok();
+            }
+            ino if matches!(
+                ino,
+                FASTA_DIR | SEQ_DIR | DOWNLOAD_DIR | ENDS_DIR | TAGS_DIR | ALIASES_DIR
+            ) || self.view_dir(ino).is_some() =>
+            {
+                let listing = self.listing(ino);
+                for (i, entry) in listing.iter().enumerate().skip(offset as usize) {
+                    if reply.add(entry.0, i as i64 + 1, entry.1, entry.2.as_str()) {
+                        break;
+                    }
+                }
+                reply.ok();
+            }
+            DIGEST_DIR | PCR_DIR => {
+                let entries = vec![
+                    (ino, FileType::Directory, "."),
+                    (ROOT_DIR, FileType::Directory, ".."),
+                ]
+                .into_iter()
+                .chain(
+                    self.scans
+                        .iter()
+                        .filter(|s| s.dir == ino)
+                        .map(|s| (s.attrs.ino, FileType::RegularFile, s.name.as_str())),
+                );
+                for (o, entry) in entries.enumerate().skip(offset as usize) {
+                    if reply.add(entry.0, o as i64 + 1, entry.1, entry.2) {
+                        break;
+                    }
+                }
+                reply.ok();
+            }
+            FEATURES_DIR => {
+                let entries = vec![
+                    (FEATURES_DIR, FileType::Directory, "."),
+                    (ROOT_DIR, FileType::Directory, ".."),
+                ]
+                .into_iter()
+                .chain(
+                    self.flank_dirs
+                        .iter()
+                        .map(|d| (d.attrs.ino, FileType::Directory, d.name.as_str())),
+                );
+                for (o, entry) in entries.enumerate().skip(offset as usize) {
+                    if reply.add(entry.0, o as i64 + 1, entry.1, entry.2) {
+                        break;
+                    }
+                }
+                reply.ok();
+            }
+            ino if ino == TAXA_DIR || self.taxa.contains_key(&ino) => {
+                self.make_taxa();
+                let taxon = match self.taxa.get(&ino) {
+                    Some(taxon) => taxon,
+                    None => {
+                        let e = FustaError::NotFound(format!("READDIR: {} has been removed", ino));
+                        reply.error(self.fail(e));
+                        return;
+                    }
+                };
+                let entries = vec![
+                    (ino, FileType::Directory, "."),
+                    (taxon.parent, FileType::Directory, ".."),
+                ]
+                .into_iter()
+                .chain(
+                    taxon
+                        .children
+                        .iter()
+                        .map(|(name, &child)| (child, FileType::Directory, name.as_str())),
+                )
+                .chain(std::iter::once((
+                    taxon.count.ino,
+                    FileType::RegularFile,
+                    TAXON_COUNT_FILE_NAME,
+                )))
+                .chain(taxon.members.iter().filter_map(|&fasta_ino| {
+                    self.fragment_from_ino(fasta_ino)
+                        .map(|f| (fasta_ino, FileType::RegularFile, f.fasta_file.name.as_str()))
+                }));
+                for (o, entry) in entries.enumerate().skip(offset as usize) {
+                    if reply.add(entry.0, o as i64 + 1, entry.1, entry.2) {
+                        break;
+                    }
+                }
+                reply.ok();
+            }
+            ino if self.flank_dirs.iter().any(|d| d.attrs.ino == ino) => {
+                let dir = self.flank_dirs.iter().find(|d| d.attrs.ino == ino).unwrap();
+                let entries = vec![
+                    (ino, FileType::Directory, "."),
+                    (FEATURES_DIR, FileType::Directory, ".."),
+                ]
+                .into_iter()
+                .chain(self.gene_names.iter().map(|(name, &gene)| {
+                    (dir.files[gene].ino, FileType::RegularFile, name.as_str())
+                }));
+                for (o, entry) in entries.enumerate().skip(offset as usize) {
+                    if reply.add(entry.0, o as i64 + 1, entry.1, entry.2) {
+                        break;
+                    }
+                }
+                reply.ok();
+            }
+            PROTEINS_DIR => {
+                let entries = vec![
+                    (PROTEINS_DIR, FileType::Directory, "."),
+                    (ROOT_DIR, FileType::Directory, ".."),
+                ]
+                .into_iter()
+                .chain(
+                    self.proteins
+                        .iter()
+                        .map(|p| (p.attrs.ino, FileType::RegularFile, p.name.as_str())),
+                );
+                for (o, entry) in entries.enumerate().skip(offset as usize) {
+                    if reply.add(entry.0, o as i64 + 1, entry.1, entry.2) {
+                        break;
+                    }
+                }
+                reply.ok();
+            }
+            APPEND_DIR => {
+                let entries = btreemap! {
+                    APPEND_DIR => (FileType::Directory, ".".to_owned()),
+                    ROOT_DIR   => (FileType::Directory, "..".to_owned()),
+                };
+                for (o, (ino, entry)) in entries.iter().enumerate().skip(offset as usize) {
+                    let _ = reply.add(*ino, o as i64 + 1, entry.0, &entry.1);
+                }
+                reply.ok();
+            }
+            SUBFRAGMENTS_DIR => {
+                let entries = vec![
+                    (SUBFRAGMENTS_DIR, FileType::Directory, ".".to_owned()),
+                    (ROOT_DIR, FileType::Directory, "..".to_owned()),
+                ]
+                .into_iter()
+                .chain(
+                    self.region_dirs
+                        .iter()
+                        .map(|(name, ino)| (*ino, FileType::Directory, name.to_owned())),
+                )
+                .chain(
+                    self.listed_subfragments(None)
+                        .into_iter()
+                        .map(|(ino, name)| (ino, FileType::RegularFile, name)),
+                );
+                for (o, entry) in entries.enumerate().skip(offset as usize) {
+                    if reply.add(entry.0, o as i64 + 1, entry.1, &entry.2) {
+                        break;
+                    }
+                }
+                reply.ok();
+            }
+            META_DIR => {
+                let entries = [
+                    (META_DIR, FileType::Directory, "."),
+                    (ROOT_DIR, FileType::Directory, ".."),
+                    (LAST_ERROR_FILE, FileType::RegularFile, LAST_ERROR_FILE_NAME),
+                    (COLLISIONS_FILE, FileType::RegularFile, COLLISIONS_FILE_NAME),
+                    (
+                        COMMIT_REPORT_FILE,
+                        FileType::RegularFile,
+                        COMMIT_REPORT_FILE_NAME,
+                    ),
+                    (STATUS_FILE, FileType::RegularFile, STATUS_FILE_NAME),
+                    (MANIFEST_FILE, FileType::RegularFile, MANIFEST_FILE_NAME),
+                ];
+                for (o, entry) in entries.iter().enumerate().skip(offset as usize) {
+                    if reply.add(entry.0, o as i64 + 1, entry.1, entry.2) {
+                        break;
+                    }
+                }
+                reply.ok();
+            }
+            SNAPSHOT_DIR => {
+                let entries = vec![
+                    (SNAPSHOT_DIR, FileType::Directory, "."),
+                    (ROOT_DIR, FileType::Directory, ".."),
+                ]
+                .into_iter()
+                .chain(
+                    self.snapshot
+                        .iter()
+                        .map(|e| (e.attrs.ino, FileType::RegularFile, e.name.as_str())),
+                );
+                for (o, entry) in entries.enumerate().skip(offset as usize) {
+                    if reply.add(entry.0, o as i64 + 1, entry.1, entry.2) {
+                        break;
+                    }
+                }
+                reply.ok();
+            }
+            ino if self.region_dirs.values().any(|&i| i == ino) => {
+                let dir = self.region_dirs.iter().find(|(_, &i)| i == ino).unwrap().0;
+                let entries = vec![
+                    (ino, FileType::Directory, ".".to_owned()),
+                    (SUBFRAGMENTS_DIR, FileType::Directory, "..".to_owned()),
+                ]
+                .into_iter()
+                .chain(
+                    self.listed_subfragments(Some(dir))
+                        .into_iter()
+                        .map(|(ino, name)| (ino, FileType::RegularFile, name)),
+                );
+                for (o, entry) in entries.enumerate().skip(offset as usize) {
+                    if reply.add(entry.0, o as i64 + 1, entry.1, &entry.2) {
+                        break;
+                    }
+                }
+                reply.ok();
+            }
+            CTL_DIR => {
+                let entries = vec![
+                    (CTL_DIR, FileType::Directory, ".".to_owned()),
+                    (ROOT_DIR, FileType::Directory, "..".to_owned()),
+                ]
+                .into_iter()
+                .chain(
+                    self.files
+                        .iter()
+                        .filter(|f| CTL_FILES.contains(&f.ino()))
+                        .map(|f| (f.ino(), FileType::RegularFile, f.name().to_owned())),
+                );
+                for (o, entry) in entries.enumerate().skip(offset as usize) {
+                    if reply.add(entry.0, o as i64 + 1, entry.1, &entry.2) {
+                        break;
+                    }
+                }
+                reply.ok();
+            }
+            _ => {
+                let e = FustaError::NotFound(format!("READDIR: {} is not a directory", ino));
+                reply.error(self.fail(e));
+            }
+        }
+    }
+
+    fn unlink(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let _op = op!(req, "unlink", parent);
+        if let Err(e) = self.check_writer(req) {
+            reply.error(self.fail(e));
+            return;
+        }
+        match parent {
+            ROOT_DIR => {
+                let e = FustaError::ReadOnly(format!("UNLINK: cannot remove `{:?}`", name));
+                reply.error(self.fail(e));
+            }
+            SEQ_DIR | FASTA_DIR => {
+                let name = name.to_str().unwrap();
+                if let Some(id) = (if parent == SEQ_DIR {
+                    self.fragment_from_seq_filename(name)
+                } else {
+                    self.fragment_from_fasta_filename(name)
+                })
+                .filter(|f| f.file_from_filename(name).is_some())
+                .map(|f| f.id.clone())
+                {
+                    match self.apply(Mutation::Delete(id)) {
+                        Ok(()) => reply.ok(),
+                        Err(e) => reply.error(self.fail(e)),
+                    }
+                } else {
+                    let e = FustaError::NotFound(format!("UNLINK: unknown file: `{:?}`", name));
+                    reply.error(self.fail(e));
+                }
+            }
+            APPEND_DIR | CTL_DIR | SNAPSHOT_DIR | META_DIR | DOWNLOAD_DIR | PROTEINS_DIR
+            | FEATURES_DIR | DIGEST_DIR | PCR_DIR | ENDS_DIR | TAGS_DIR | ALIASES_DIR
+            | TAXA_DIR => {
+                let e =
+                    FustaError::ReadOnly(format!("UNLINK: unauthorized in {} virtual dir", parent));
+                reply.error(self.fail(e));
+            }
+            parent
+                if self.flank_dirs.iter().any(|d| d.attrs.ino == parent)
+                    || self.view_dir(parent).is_some()
+                    || self.taxa.contains_key(&parent) =>
+            {
+                let e =
+                    FustaError::ReadOnly(format!("UNLINK: unauthorized in {} virtual dir", parent));
+                reply.error(self.fail(e));
+            }
+            SUBFRAGMENTS_DIR => match self.drop_bookmark(&name.to_string_lossy()) {
+                Ok(()) => reply.ok(),
+                Err(e) => reply.error(self.fail(e)),
+            },
+            _ => {
+                let e = FustaError::NotFound(format!("UNLINK: parent {} does not exist", parent));
+                reply.error(self.fail(e));
+            }
+        }
+    }
+
+    fn mknod(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _rdev: u32,
+        reply: ReplyEntry,
+    ) {
+        let _op = op!(req, "mknod", parent);
+        if let Err(e) = self.check_writer(req) {
+            reply.error(self.fail(e));
+            return;
+        }
+        match parent {
+            ROOT_DIR | SEQ_DIR | FASTA_DIR | SUBFRAGMENTS_DIR | CTL_DIR | SNAPSHOT_DIR
+            | META_DIR | DOWNLOAD_DIR | PROTEINS_DIR | FEATURES_DIR | DIGEST_DIR | PCR_DIR
+            | ENDS_DIR | TAGS_DIR | ALIASES_DIR | TAXA_DIR => {
+                let e = FustaError::ReadOnly(format!("MKNOD: writing in {} is forbidden", parent));
+                reply.error(self.fail(e));
+            }
+            parent
+                if self.flank_dirs.iter().any(|d| d.attrs.ino == parent)
+                    || self.view_dir(parent).is_some()
+                    || self.taxa.contains_key(&parent) =>
+            {
+                let e = FustaError::ReadOnly(format!("MKNOD: writing in {} is forbidden", parent));
+                reply.error(self.fail(e));
+            }
+            APPEND_DIR => {
+                let name = name.to_str().unwrap();
+                let basename = std::path::Path::new(name)
+                    .file_stem()
+                    .unwrap()
+                    .to_str()
+                    .unwrap();
+                // From man: if pathname already exists [...], this call fails with an EEXIST error.
+                if self.fragments.iter().any(|f| f.id == basename)
+                    && self.settings.on_append_collision == Collision::Skip
+                {
+                    let e =
+                        FustaError::Conflict(format!("Cannot create `{:?}`, already exists", name));
+                    notify(e.to_string());
+                    reply.error(self.fail(e));
+                    return;
+                }
+
+                trace!("Creating pending append {}", name);
+                let attrs = FileAttr {
+                    ino: self.new_ino(),
+                    size: 0,
+                    blocks: 0,
+                    atime: std::time::SystemTime::now(),
+                    mtime: std::time::SystemTime::now(),
+                    ctime: std::time::SystemTime::now(),
+                    crtime: std::time::SystemTime::now(),
+                    kind: FileType::RegularFile,
+                    perm: 0o775 & !*UMASK,
+                    nlink: 0,
+                    uid: unsafe { libc::geteuid() },
+                    gid: unsafe { libc::getgid() },
+                    rdev: 0,
+                    flags: 0,
+                    blksize: 512,
+                };
+                let pending = PendingAppend {
+                    data: Vec::new(),
+                    attrs,
+                };
+                self.reply_entry(reply, &pending.attrs);
+                self.pending_appends.insert(basename.to_string(), pending);
+            }
+            _ => {
+                let e = FustaError::NotFound(format!("MKNOD: parent {} does not exist", parent));
+                reply.error(self.fail(e));
+            }
+        }
+    }
+
+    fn write(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        let _op = op!(req, "write", ino);
+        match self
+            .check_writer(req)
+            .and_then(|_| self.check_exporter(req, ino))
+            .and_then(|_| self.write_file(ino, offset as usize, data))
+        {
+            Ok(written) => reply.written(written as u32),
+            Err(e) => reply.error(self.fail(e)),
+        }
+    }
+
+    /// Copy a range of a file to another one without a round-trip through
+    /// the calling process, e.g. when `cp`-ing a fragment to append/
+    fn copy_file_range(
+        &mut self,
+        req: &Request<'_>,
+        ino_in: u64,
+        _fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        _fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        _flags: u32,
+        reply: ReplyWrite,
+    ) {
+        let _op = op!(req, "copy_file_range", ino_out);
+        // Partial copies are resumed by the caller
+        let size = std::cmp::min(len, MAX_IO_SIZE as u64) as u32;
+        match self
+            .check_writer(req)
+            .and_then(|_| self.check_exporter(req, ino_out))
+            .and_then(|_| self.read_file(ino_in, offset_in, size))
+            .and_then(|data| {
+                self.touch_accessed(ino_in);
+                self.write_file(ino_out, offset_out as usize, &data)
+            }) {
+            Ok(written) => reply.written(written as u32),
+            Err(e) => reply.error(self.fail(e)),
+        }
+    }
+
+    fn ioctl(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        _flags: u32,
+        cmd: u32,
+        _in_data: &[u8],
+        _out_size: u32,
+        reply: ReplyIoctl,
+    ) {
+        let _op = op!(req, "ioctl", ino);
+        // Sequences can not share extents with other files; `cp --reflink`
+        // then falls back to copy_file_range.
+        #[cfg(target_os = "linux")]
+        if [FICLONE, FICLONERANGE].contains(&(cmd as c_ulong)) {
+            debug!("IOCTL: cloning {} is not supported", ino);
+            reply.error(EOPNOTSUPP);
+            return;
+        }
+        debug!("IOCTL: unknown command {:#x} on {}", cmd, ino);
+        reply.error(ENOTTY);
+    }
+
+    fn setattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        crtime: Option<SystemTime>,
+        chgtime: Option<SystemTime>,
+        bkuptime: Option<SystemTime>,
+        flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let _op = op!(req, "setattr", ino);
+        trace!("SETATTR");
+        trace!("mode       {:?}", mode);
+        trace!("gid        {:?}", gid);
+        trace!("uid        {:?}", uid);
+        trace!("size       {:?}", size);
+        trace!("atime      {:?}", atime);
+        trace!("mtime      {:?}", mtime);
+        trace!("bkuptime   {:?}", bkuptime);
+        trace!("chgtime    {:?}", chgtime);
+        trace!("crtime     {:?}", crtime);
+        trace!("flags      {:?}", flags);
+
+        if let Err(e) = self.check_writer(req) {
+            reply.error(self.fail(e));
+            return;
+        }
+        let read_only = FustaError::ReadOnly(format!(
+            "SETATTR: {} can not be modified",
+            self.describe(ino)
+        ));
+        let too_big = FustaError::Quota(format!(
+            "SETATTR: cannot resize {} to {:?}",
+            self.describe(ino),
+            size
+        ));
+        match ino {
+            ino if self.dir_attrs.contains_key(&ino) => {
+                match self.chmod_dir(req.uid(), req.gid(), ino, mode, uid, gid) {
+                    Ok(attrs) => reply.attr(&self.ttl, &attrs),
+                    Err(e) => reply.error(self.fail(e)),
+                }
+            }
+            INFO_FILE | SUMMARY_FILE | INFO_CSV_FILE | INFO_JSON_FILE | LABELS_FILE
+            | OFFSETS_FILE | APPENDS_FILE | LAST_ERROR_FILE | COLLISIONS_FILE
+            | COMMIT_REPORT_FILE | STATS_FILE | STATUS_FILE | MANIFEST_FILE | DUPLICATES_FILE
+            | REPEATS_FILE | FORMAT_FILE | FAI_FILE | CODON_USAGE_FILE => {
+                reply.error(self.fail(read_only))
+            }
+            ino if self.snapshot_inos.contains_key(&ino)
+                || self.versions.contains_key(&ino)
+                || self.downloads.contains_key(&ino)
+                || self.ends.contains_key(&ino)
+                || self.view_files.contains_key(&ino)
+                || self.protein_inos.contains_key(&ino)
+                || self.flank_inos.contains_key(&ino)
+                || self.scan_inos.contains_key(&ino)
+                || self.taxa.contains_key(&ino)
+                || self.taxon_counts.contains_key(&ino) =>
+            {
+                reply.error(self.fail(read_only))
+            }
+            ino if self.tag_files.contains_key(&ino) => {
+                if let Some(size) = size {
+                    // Called on truncation, typically when the tags are overwritten
+                    let resized = self
+                        .pending_tags(ino)
+                        .map(|buffer| resize(buffer, size as usize).map(|_| buffer.len()));
+                    match resized {
+                        Ok(Some(size)) => {
+                            self.tag_files.get_mut(&ino).unwrap().attrs.size = size as u64
+                        }
+                        Ok(None) => {
+                            reply.error(self.fail(too_big));
+                            return;
+                        }
+                        Err(e) => {
+                            reply.error(self.fail(e));
+                            return;
+                        }
+                    }
+                }
+                reply.attr(&self.ttl, &self.tag_files[&ino].attrs);
+            }
+            ino if CTL_FILES.contains(&ino) => {
+                if let Some(size) = size {
+                    // Called on truncation, typically when a control file is overwritten
+                    let buffer = self.pending_controls.entry(ino).or_default();
+                    if resize(buffer, size as usize).is_none() {
+                        reply.error(self.fail(too_big));
+                        return;
+                    }
+                }
+                let ttl = self.ttl;
+                reply.attr(&ttl, self.get_file(ino).unwrap().attrs());
+            }
+            _ => {
+                if self.fragment_from_ino(ino).is_some() {
+                    // Without DefaultPermissions, i.e. when the writers are
+                    // restricted, the kernel does not check the ownership
+                    let attrs = self
+                        .fragment_from_ino(ino)
+                        .and_then(|f| f.file_from_ino(ino))
+                        .map(|f| *f.attrs());
+                    if !self.is_writeable(ino) {
+                        reply.error(self.fail(read_only));
+                    } else if let Some(Err(e)) = attrs.map(|attrs| {
+                        self.check_chmod(req.uid(), req.gid(), ino, &attrs, mode, uid, gid)
+                    }) {
+                        reply.error(self.fail(e));
+                    } else {
+                        if let Some(file) = self
+                            .mut_fragment_from_ino(ino)
+                            .and_then(|f| f.mut_file_from_ino(ino))
+                        {
+                            if let Some(uid) = uid {
+                                file.mut_attrs().uid = uid
+                            }
+                            if let Some(gid) = gid {
+                                file.mut_attrs().gid = gid
+                            }
+                            if let Some(chgtime) = chgtime {
+                                file.mut_attrs().mtime = chgtime
+                            }
+                            if let Some(crtime) = crtime {
+                                file.mut_attrs().crtime = crtime
+                            }
+                            // macOS only
+                            if let Some(flags) = flags {
+                                file.mut_attrs().flags = flags
+                            }
+                            if let Some(mode) = mode {
+                                file.mut_attrs().perm = mode as u16
+                            }
+                        }
+                        if let Some(Err(e)) = size.map(|s| {
+                            if self.is_fasta_file(ino) {
+                                self.resize_fasta(ino, s as usize)
+                            } else {
+                                self.resize_fragment(ino, s as usize)
+                            }
+                        }) {
+                            reply.error(self.fail(e));
+                            return;
+                        }
+                        // Set last, e.g. by `touch -d` or `cp -p`, so that
+                        // they are not overwritten by the truncation
+                        let now = SystemTime::now();
+                        let fragment = self.mut_fragment_from_ino(ino).unwrap();
+                        if size.is_some() {
+                            fragment.touch_modified(now);
+                        }
+                        let attrs = fragment.mut_file_from_ino(ino).unwrap().mut_attrs();
+                        let time = |t| match t {
+                            TimeOrNow::SpecificTime(t) => t,
+                            TimeOrNow::Now => now,
+                        };
+                        if let Some(atime) = atime {
+                            attrs.atime = time(atime);
+                        }
+                        if let Some(mtime) = mtime {
+                            attrs.mtime = time(mtime);
+                        }
+                        attrs.ctime = now;
+                        reply.attr(
+                            &self.ttl,
+                            self.fragment_from_ino(ino)
+                                .and_then(|f| f.file_from_ino(ino))
+                                .unwrap()
+                                .attrs(),
+                        );
+                    }
+                } else if let Some((name, pending_fragment)) = self
+                    .pending_appends
+                    .iter_mut()
+                    .find(|(_, p)| p.attrs.ino == ino)
+                {
+                    if let Some(size) = size {
+                        trace!("\tResizing {} @{}", name, size);
+                        if resize(&mut pending_fragment.data, size as usize).is_none() {
+                            reply.error(self.fail(too_big));
+                            return;
+                        }
+                    }
+                    reply.attr(&self.ttl, &pending_fragment.attrs);
+                } else {
+                    let e = FustaError::NotFound(format!("SETATTR: {} does not exist", ino));
+                    reply.error(self.fail(e));
+                }
+            }
+        }
     }
-}
-impl Filesystem for FustaFS {
-    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        let name = name.to_str().unwrap();
+
+    fn destroy(&mut self) {
+        info!("Closing FUSTA");
+        if let Err(e) = self.concretize(false) {
+            self.fail(e);
+        }
+        self.summarize(true);
+    }
+
+    fn rename(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        _flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        let _op = op!(req, "rename", parent);
+        if let Err(e) = self.check_writer(req) {
+            reply.error(self.fail(e));
+            return;
+        }
         match parent {
-            ROOT_DIR => match name {
-                "fasta" => {
-                    reply.entry(&TTL, &self.dir_attrs[&FASTA_DIR], 0);
+            ROOT_DIR | APPEND_DIR | SUBFRAGMENTS_DIR | CTL_DIR | SNAPSHOT_DIR | META_DIR
+            | DOWNLOAD_DIR | PROTEINS_DIR | FEATURES_DIR | DIGEST_DIR | PCR_DIR | ENDS_DIR
+            | TAGS_DIR | ALIASES_DIR | TAXA_DIR => {
+                let e = FustaError::ReadOnly(format!("RENAME: forbidden in {}", parent));
+                reply.error(self.fail(e));
+            }
+            parent
+                if self.flank_dirs.iter().any(|d| d.attrs.ino == parent)
+                    || self.view_dir(parent).is_some()
+                    || self.taxa.contains_key(&parent) =>
+            {
+                let e = FustaError::ReadOnly(format!("RENAME: forbidden in {}", parent));
+                reply.error(self.fail(e));
+            }
+            SEQ_DIR | FASTA_DIR => {
+                if newparent != parent {
+                    let e = FustaError::ReadOnly(
+                        "Cannot move files out of folder, please copy them instead".into(),
+                    );
+                    reply.error(self.fail(e));
+                } else {
+                    // Remove the artificial extension if it exists
+                    let ext = if parent == SEQ_DIR {
+                        SEQ_EXT
+                    } else {
+                        FASTA_EXT
+                    };
+                    let new_id = match newname.to_str() {
+                        Some(newname) => {
+                            let stem = newname.strip_suffix(ext).unwrap_or(newname);
+                            match self.settings.codec.decode(stem) {
+                                Some(id) => id,
+                                None => {
+                                    let e = FustaError::Parse(format!(
+                                        "Cannot rename {:?} to {:?}: not a valid {:?}-encoded file name",
+                                        name, newname, self.settings.codec
+                                    ));
+                                    reply.error(self.fail(e));
+                                    return;
+                                }
+                            }
+                        }
+                        None => {
+                            let e = FustaError::Parse(format!(
+                                "Cannot rename {:?} to {:?}: not valid UTF-8",
+                                name, newname
+                            ));
+                            reply.error(self.fail(e));
+                            return;
+                        }
+                    };
+                    if let Some(id) = if parent == SEQ_DIR {
+                        self.fragment_from_seq_filename(name.to_str().unwrap())
+                    } else {
+                        self.fragment_from_fasta_filename(name.to_str().unwrap())
+                    }
+                    .map(|f| f.id.clone())
+                    {
+                        info!("Renaming {:?} -> {:?}", name, newname);
+                        match self.rename_fragment(&id, &new_id) {
+                            Ok(()) => reply.ok(),
+                            Err(e) => reply.error(self.fail(e)),
+                        }
+                    } else {
+                        let e = FustaError::NotFound(format!("{:?} does not exist", name));
+                        reply.error(self.fail(e));
+                    }
                 }
-                "seqs" => {
-                    reply.entry(&TTL, &self.dir_attrs[&SEQ_DIR], 0);
+            }
+            _ => {
+                let e = FustaError::NotFound(format!("RENAME: unknown parent {}", parent));
+                reply.error(self.fail(e));
+            }
+        }
+    }
+
+    fn fsync(&mut self, req: &Request, ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        let _op = op!(req, "fsync", ino);
+        trace!("FSYNC");
+        self.refresh_metadata(false);
+        match self.concretize(false) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(self.fail(e)),
+        }
+    }
+
+    fn fsyncdir(&mut self, req: &Request, ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        let _op = op!(req, "fsyncdir", ino);
+        trace!("FSYNCDIR");
+        self.refresh_metadata(false);
+        match self.concretize(false) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(self.fail(e)),
+        }
+    }
+
+    fn flush(&mut self, req: &Request, ino: u64, _fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
+        let _op = op!(req, "flush", ino);
+        trace!("FLUSH");
+        // self.concretize();
+        reply.ok();
+    }
+
+    fn open(&mut self, req: &Request, ino: u64, _flags: i32, reply: ReplyOpen) {
+        let _op = op!(req, "open", ino);
+        // Get the statistics or the duplicates computing while the file is
+        // being opened
+        if ino == STATS_FILE {
+            self.start_stats();
+        } else if ino == DUPLICATES_FILE {
+            self.start_duplicates();
+        } else if ino == REPEATS_FILE {
+            self.start_repeats();
+        } else if ino == FORMAT_FILE {
+            self.start_format();
+        } else if ino == FAI_FILE {
+            self.start_fai();
+        } else if ino == CODON_USAGE_FILE {
+            self.start_codon_usage();
+        } else if let Some(&i) = self.scan_inos.get(&ino) {
+            self.start_scan(i);
+        }
+        // The last error, the status and the summaries may change at any
+        // time, and the size of the downloads and statistics is unknown; in
+        // both cases, cached sizes would truncate reads
+        let flags = if [
+            INFO_FILE,
+            INFO_CSV_FILE,
+            SUMMARY_FILE,
+            LAST_ERROR_FILE,
+            STATUS_FILE,
+            MANIFEST_FILE,
+            STATS_FILE,
+            DUPLICATES_FILE,
+            REPEATS_FILE,
+            FORMAT_FILE,
+            FAI_FILE,
+            CODON_USAGE_FILE,
+        ]
+        .contains(&ino)
+            || self.downloads.contains_key(&ino)
+            || self.scan_inos.contains_key(&ino)
+            || self.tag_files.contains_key(&ino)
+            || self.taxon_counts.contains_key(&ino)
+        {
+            consts::FOPEN_DIRECT_IO
+        } else {
+            0
+        };
+        reply.opened(0, flags);
+    }
+
+    fn release(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        _fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        let _op = op!(req, "release", ino);
+        debug!("RELEASE {}", ino);
+        if CTL_FILES.contains(&ino) {
+            if let Some(content) = self.pending_controls.remove(&ino) {
+                self.run_control(ino, &content);
+            }
+        } else if self.tag_files.contains_key(&ino) {
+            if let Err(e) = self.commit_tags(ino) {
+                reply.error(self.fail(e));
+                return;
+            }
+        } else if let Some(name) = self
+            .pending_appends
+            .iter()
+            .find(|(_, p)| p.attrs.ino == ino)
+            .map(|(name, _)| name.clone())
+        {
+            trace!("RELEASE: {}", name);
+            let pending = self.pending_appends.remove(&name).unwrap();
+            if let Err(e) = self.apply(Mutation::Append {
+                file: name,
+                data: pending.data,
+                atime: pending.attrs.atime,
+                mtime: pending.attrs.mtime,
+            }) {
+                reply.error(self.fail(e));
+                return;
+            }
+        } else if self.is_writeable(ino) {
+            // The next writes will be another edit
+            if let Some(seq_ino) = self.fragment_from_ino(ino).map(|f| f.seq_file.ino) {
+                // Whole edits through fasta/ are normalized when committed
+                if self.editing.remove(&seq_ino) && !self.pending_fastas.contains_key(&ino) {
+                    if let Err(e) = self.normalize_edit(seq_ino) {
+                        reply.error(self.fail(e));
+                        return;
+                    }
                 }
-                "append" => {
-                    reply.entry(&TTL, &self.dir_attrs[&APPEND_DIR], 0);
+            }
+            self.refresh_metadata(false);
+            if let Err(e) = self.commit_fasta(ino).and_then(|_| self.concretize(false)) {
+                reply.error(self.fail(e));
+                return;
+            }
+        } else {
+            debug!("Not a writeable file; ignoring")
+        }
+        reply.ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    lazy_static! {
+        // Where the sessions of the test mounts are saved, rather than in
+        // the state directory of the user
+        static ref SESSIONS: tempfile::TempDir = tempfile::tempdir().unwrap();
+    }
+
+    fn settings(cache: Cache) -> FustaSettings {
+        FustaSettings {
+            // Rewrite the file after every mutation when caching in memory
+            concretize_threshold: if cache == Cache::RAM { 0 } else { 1 << 30 },
+            memory_budget: usize::MAX,
+            cache,
+            csv_separator: ",".into(),
+            no_overwrite: false,
+            defer_deletions: false,
+            headers_only: false,
+            noatime: false,
+            no_index: true,
+            catalog: false,
+            seed: 0,
+            on_append_collision: Collision::Overwrite,
+            on_edit_conflict: Collision::Skip,
+            verify: Verify::Off,
+            refuse_mismatches: false,
+            writers: Writers::default(),
+            append_id_template: "{filename}".into(),
+            writable_fasta: false,
+            chaos: Chaos::default(),
+            retry: Retry::default(),
+            id_rules: IdRules::default(),
+            codec: Codec::Plain,
+            allow_empty: true,
+            max_loss: 1.,
+            max_shrink: None,
+            notifications: Thresholds::default(),
+            prefetch: Prefetch::None,
+            undo_budget: 1 << 20,
+            annotation: None,
+            genetic_code: GeneticCode::default(),
+            max_amplicon: 4000,
+            telomere_motifs: TelomereMotifs::new(&["TTAGGG"]).unwrap(),
+            end_length: 1000,
+            taxa_delimiter: ";".into(),
+            write_filter: None,
+            output: None,
+            merge: Vec::new(),
+            on_merge_collision: MergeCollision::Refuse,
+            aliases: None,
+            views: Vec::new(),
+            session_dir: Some(SESSIONS.path().to_owned()),
+            reads: Limits::default(),
+            write_transform: Pipeline::default(),
+        }
+    }
+
+    /// Create a FASTA file of `n` fragments named `orig_0`..`orig_{n-1}`
+    fn make_fasta(dir: &std::path::Path, n: usize) -> String {
+        let filename = dir.join("test.fa").to_str().unwrap().to_owned();
+        let content = (0..n)
+            .map(|i| format!(">orig_{}\nACGT{}\n", i, "T".repeat(i)))
+            .collect::<String>();
+        fs::write(&filename, content).unwrap();
+        filename
+    }
+
+    /// Mount a FASTA file of `n` fragments, as written by `make_fasta`,
+    /// with the test settings; the file lives as long as its directory
+    fn mount(n: usize, cache: Cache) -> (tempfile::TempDir, String, FustaFS) {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), n);
+        let fusta = FustaFS::new(settings(cache), &filename).unwrap();
+        (dir, filename, fusta)
+    }
+
+    /// The (ID, sequence) pairs stored in `filename`
+    fn read_back(filename: &str) -> Vec<(String, Vec<u8>)> {
+        FastaReader::new(fs::File::open(filename).unwrap(), true)
+            .map(|f| (f.id.to_string(), f.seq.unwrap()))
+            .collect()
+    }
+
+    fn append(fs: &mut FustaFS, id: &str, seq: &str) {
+        fs.apply(Mutation::Append {
+            file: id.to_owned(),
+            data: format!(">{}\n{}\n", id, seq).into_bytes(),
+            atime: SystemTime::now(),
+            mtime: SystemTime::now(),
+        })
+        .unwrap();
+    }
+
+    /// A tiny deterministic PRNG, so that failures can be reproduced
+    struct Lcg(u64);
+    impl Lcg {
+        fn next(&mut self, n: usize) -> usize {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((self.0 >> 33) as usize) % n
+        }
+    }
+
+    #[test]
+    fn interleaved_mutations() {
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            for seed in 0..16 {
+                let (_dir, filename, mut fs) = mount(10, cache);
+                let mut expected = (0..10)
+                    .map(|i| (format!("orig_{}", i), format!("ACGT{}", "T".repeat(i))))
+                    .collect::<Vec<_>>();
+
+                let mut rng = Lcg(seed);
+                for step in 0..60 {
+                    match rng.next(3) {
+                        0 => {
+                            let id = format!("new_{}", step);
+                            let seq = "G".repeat(1 + rng.next(20));
+                            append(&mut fs, &id, &seq);
+                            expected.push((id, seq));
+                        }
+                        1 if !expected.is_empty() => {
+                            let (id, _) = expected.remove(rng.next(expected.len()));
+                            fs.apply(Mutation::Delete(id.into())).unwrap();
+                        }
+                        2 if !expected.is_empty() => {
+                            let i = rng.next(expected.len());
+                            let to = format!("renamed_{}", step);
+                            fs.apply(Mutation::Rename {
+                                from: expected[i].0.clone().into(),
+                                to: to.clone().into(),
+                            })
+                            .unwrap();
+                            expected[i].0 = to;
+                        }
+                        _ => {}
+                    }
+                    assert_eq!(
+                        fs.fragments
+                            .iter()
+                            .map(|f| f.id.as_str())
+                            .collect::<Vec<_>>(),
+                        expected.iter().map(|e| e.0.as_str()).collect::<Vec<_>>(),
+                        "{:?}, seed {}, step {}",
+                        cache,
+                        seed,
+                        step
+                    );
                 }
-                "get" => {
-                    reply.entry(&TTL, &self.dir_attrs[&SUBFRAGMENTS_DIR], 0);
+                drop(fs);
+
+                let expected = expected
+                    .into_iter()
+                    .map(|(id, seq)| (id, seq.into_bytes()))
+                    .collect::<Vec<_>>();
+                assert_eq!(read_back(&filename), expected, "{:?}, seed {}", cache, seed);
+            }
+        }
+    }
+
+    /// A fragment as the model of the round-trip tests sees it
+    #[derive(Debug, Clone, PartialEq)]
+    struct Record {
+        id: String,
+        name: Option<String>,
+        raw: Vec<u8>,
+    }
+    impl Record {
+        fn label(&self) -> String {
+            make_label(&self.id, &self.name)
+        }
+
+        fn pure(&self) -> Vec<u8> {
+            self.raw.iter().cloned().filter(|&c| c != b'\n').collect()
+        }
+    }
+
+    fn random_seq(rng: &mut Lcg, alphabet: &[u8]) -> Vec<u8> {
+        let len = match rng.next(4) {
+            0 => rng.next(2),
+            1 => rng.next(200),
+            2 => rng.next(5000),
+            _ => 60 * (1 + rng.next(4)),
+        };
+        (0..len)
+            .map(|_| alphabet[rng.next(alphabet.len())])
+            .collect()
+    }
+
+    fn random_description(rng: &mut Lcg) -> Option<String> {
+        const WORDS: &[&str] = &["chromosome", "len=12", "x|y:3-5", "[Homo sapiens]", "a"];
+        match rng.next(3) {
+            0 => None,
+            n => Some(
+                (0..n)
+                    .map(|_| WORDS[rng.next(WORDS.len())])
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+        }
+    }
+
+    /// Wrap `seq` in lines of random width, as found in a FASTA file
+    fn random_wrap(rng: &mut Lcg, seq: &[u8]) -> Vec<u8> {
+        let width = match rng.next(4) {
+            0 => 1 + rng.next(3),
+            1 => 60,
+            2 => 80,
+            _ => seq.len().max(1),
+        };
+        let mut r = seq.chunks(width).collect::<Vec<_>>().join(&b'\n');
+        if !seq.is_empty() {
+            r.push(b'\n');
+        }
+        r
+    }
+
+    /// Create a random FASTA file, and return its content and its fragments
+    fn random_fasta(rng: &mut Lcg, filename: &str) -> (Vec<u8>, Vec<Record>) {
+        let records = (0..rng.next(8))
+            .map(|i| {
+                let seq = random_seq(rng, b"ACGTNacgtn");
+                Record {
+                    id: format!("seq{}", i),
+                    name: random_description(rng),
+                    raw: random_wrap(rng, &seq),
                 }
-                INFO_FILE_NAME => {
-                    reply.entry(&TTL, self.get_file(INFO_FILE).unwrap().attrs(), 0);
+            })
+            .collect::<Vec<_>>();
+        let mut content = records
+            .iter()
+            .flat_map(|r| [r.label().as_bytes(), &r.raw].concat())
+            .collect::<Vec<_>>();
+        // The last line may lack its newline
+        if content.last() == Some(&b'\n')
+            && records.last().unwrap().raw.len() > 1
+            && rng.next(2) == 0
+        {
+            content.pop();
+        }
+        fs::write(filename, &content).unwrap();
+        (content, records)
+    }
+
+    /// What the FASTA file should contain once `records` are written
+    fn concretized(records: &mut [Record]) -> Vec<u8> {
+        let mut r = Vec::new();
+        for record in records.iter_mut() {
+            if record.raw.last().is_some_and(|&c| c != b'\n') {
+                record.raw.push(b'\n');
+            }
+            r.extend(record.label().as_bytes());
+            r.extend(&record.raw);
+        }
+        r
+    }
+
+    #[test]
+    fn round_trip() {
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            for seed in 0..64 {
+                let dir = tempfile::tempdir().unwrap();
+                let filename = dir.path().join("test.fa").to_str().unwrap().to_owned();
+                let mut rng = Lcg(seed);
+                let (mut expected_file, mut expected) = random_fasta(&mut rng, &filename);
+                let mut fs = FustaFS::new(
+                    FustaSettings {
+                        // Only write the file when explicitly asked to
+                        concretize_threshold: 1 << 30,
+                        ..settings(cache)
+                    },
+                    &filename,
+                )
+                .unwrap();
+                if expected_file.last().is_some_and(|&c| c != b'\n') {
+                    expected.last_mut().unwrap().raw.pop();
                 }
-                INFO_CSV_FILE_NAME => {
-                    reply.entry(&TTL, self.get_file(INFO_CSV_FILE).unwrap().attrs(), 0);
+                if cache == Cache::RAM {
+                    // Sequences are stored without their newlines in memory
+                    for record in expected.iter_mut() {
+                        record.raw = record.pure();
+                    }
                 }
-                LABELS_FILE_NAME => {
-                    reply.entry(&TTL, self.get_file(LABELS_FILE).unwrap().attrs(), 0);
+                let mut dirty = false;
+
+                for step in 0..40 {
+                    let i = rng.next(expected.len().max(1));
+                    let ctx = format!("{:?}, seed {}, step {}", cache, seed, step);
+                    match rng.next(6) {
+                        0 if !expected.is_empty() => {
+                            let raw = &mut expected[i].raw;
+                            let offset = rng.next(raw.len() + 3);
+                            let data = random_seq(&mut rng, b"ACGTN\n");
+                            let ino = fs.fragments[i].seq_file.ino;
+                            fs.write_fragment(ino, offset, &data).unwrap();
+                            if offset + data.len() > raw.len() {
+                                raw.resize(offset + data.len(), 0);
+                            }
+                            raw.splice(offset..offset + data.len(), data);
+                            dirty = true;
+                        }
+                        1 if !expected.is_empty() => {
+                            let raw = &mut expected[i].raw;
+                            let size = rng.next(raw.len() + 10);
+                            let ino = fs.fragments[i].seq_file.ino;
+                            fs.resize_fragment(ino, size).unwrap();
+                            dirty |= size != raw.len();
+                            raw.resize(size, 0);
+                        }
+                        2 => {
+                            let record = Record {
+                                id: format!("new{}", step),
+                                name: random_description(&mut rng),
+                                raw: random_seq(&mut rng, b"ACGTNacgtn"),
+                            };
+                            fs.apply(Mutation::Append {
+                                file: record.id.clone(),
+                                data: [
+                                    record.label().as_bytes(),
+                                    &random_wrap(&mut rng, &record.raw),
+                                ]
+                                .concat(),
+                                atime: SystemTime::now(),
+                                mtime: SystemTime::now(),
+                            })
+                            .unwrap();
+                            expected.push(record);
+                            dirty = true;
+                        }
+                        3 if !expected.is_empty() => {
+                            fs.apply(Mutation::Delete(expected.remove(i).id.into()))
+                                .unwrap();
+                            dirty = true;
+                        }
+                        4 if !expected.is_empty() => {
+                            let to = format!("renamed{}", step);
+                            fs.apply(Mutation::Rename {
+                                from: expected[i].id.clone().into(),
+                                to: to.clone().into(),
+                            })
+                            .unwrap();
+                            expected[i].id = to;
+                            dirty = true;
+                        }
+                        5 => {
+                            fs.concretize(true).unwrap();
+                            if dirty {
+                                expected_file = concretized(&mut expected);
+                                dirty = false;
+                            }
+                            assert_eq!(fs::read(&filename).unwrap(), expected_file, "{}", ctx);
+                        }
+                        _ => {}
+                    }
+
+                    let actual = fs
+                        .fragments
+                        .iter()
+                        .map(|f| Record {
+                            id: f.id.to_string(),
+                            name: f.name.clone(),
+                            raw: f.data().unwrap().into_vec(),
+                        })
+                        .collect::<Vec<_>>();
+                    assert_eq!(actual, expected, "{}", ctx);
+                    for f in fs.fragments.iter() {
+                        assert_eq!(f.seq_file.attrs.size as usize, f.data_size(), "{}", ctx);
+                    }
                 }
-                _ => {
-                    reply.error(ENOENT);
+                drop(fs);
+                if dirty {
+                    expected_file = concretized(&mut expected);
                 }
-            },
-            SEQ_DIR | FASTA_DIR => {
-                let file = (if parent == SEQ_DIR {
-                    self.fragment_from_seq_filename(name)
-                } else {
-                    self.fragment_from_fasta_filename(name)
-                })
-                .and_then(|f| f.file_from_filename(name));
+                let ctx = format!("{:?}, seed {}", cache, seed);
+                assert_eq!(fs::read(&filename).unwrap(), expected_file, "{}", ctx);
+
+                // Mounting the result again should give back the same fragments
+                let remounted = FastaReader::new(fs::File::open(&filename).unwrap(), true)
+                    .map(|f| (f.id.to_string(), f.name, f.seq.unwrap()))
+                    .collect::<Vec<_>>();
+                let expected = expected
+                    .iter()
+                    .map(|r| (r.id.clone(), r.name.clone(), r.pure()))
+                    .collect::<Vec<_>>();
+                assert_eq!(remounted, expected, "{}", ctx);
+            }
+        }
+    }
+
+    #[test]
+    fn id_validation() {
+        for id in [
+            "chr1",
+            "NC_000001.11",
+            "gi|123|ref",
+            ">x",
+            &"A".repeat(MAX_ID_SIZE),
+        ] {
+            assert!(check_id(id, Codec::Plain).is_ok(), "{}", id);
+        }
+        for id in [
+            "",
+            ".",
+            "..",
+            "b/c",
+            "a\\b",
+            "a\0b",
+            "a b",
+            "a\tb",
+            "a\nb",
+            "a\rb",
+            "a\u{1b}b",
+            &"A".repeat(MAX_ID_SIZE + 1),
+        ] {
+            assert!(check_id(id, Codec::Plain).is_err(), "{:?}", id);
+        }
+        // File names that would be forbidden can be encoded, but the
+        // encoding may make them too long
+        for id in ["b/c", "a\\b", ".."] {
+            assert!(check_id(id, Codec::Percent).is_ok(), "{:?}", id);
+        }
+        assert!(check_id(&"/".repeat(MAX_ID_SIZE / 2), Codec::Percent).is_err());
+
+        let (_dir, _, mut fusta) = mount(2, Cache::Mmap);
+        let e = fusta.rename_from_map("orig_0 new/0\n").unwrap_err();
+        assert_eq!(e.errno(), EINVAL);
+        assert_eq!(fusta.fragments[0].id, "orig_0");
+    }
+
+    #[test]
+    fn rename_map() {
+        let (_dir, filename, mut fusta) = mount(3, Cache::Mmap);
+        let ids = |fusta: &FustaFS| {
+            fusta
+                .fragments
+                .iter()
+                .map(|f| f.id.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // The maps that are invalid as a whole rename nothing
+        for invalid in [
+            "orig_0\n",
+            "orig_0 a b\n",
+            "orig_0 a\norig_0 b\n",
+            "orig_0 orig_1\n",
+            "orig_0 a\norig_1 a\n",
+        ] {
+            assert!(fusta.rename_from_map(invalid).is_err(), "{:?}", invalid);
+            assert_eq!(ids(&fusta), ["orig_0", "orig_1", "orig_2"]);
+        }
+
+        // The IDs may be swapped, the unknown ones being skipped
+        assert_eq!(
+            fusta
+                .rename_from_map("# swap\norig_0\torig_1\norig_1 orig_0\n\nunknown x\n")
+                .unwrap(),
+            "2 fragments renamed"
+        );
+        assert_eq!(ids(&fusta), ["orig_1", "orig_0", "orig_2"]);
+
+        // The map read back pairs the original IDs with the current ones
+        fusta.make_rename_map_buffer();
+        assert_eq!(
+            fusta.get_file(RENAME_MAP_FILE).unwrap().data(),
+            b"orig_0\torig_1\norig_1\torig_0\norig_2\torig_2\n"
+        );
+        drop(fusta);
+        assert_eq!(
+            read_back(&filename)
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>(),
+            ["orig_1", "orig_0", "orig_2"]
+        );
+    }
 
-                if let Some(file) = file {
-                    reply.entry(&TTL, file.attrs(), 0);
-                } else {
-                    reply.error(ENOENT);
-                }
+    #[test]
+    fn fasta_edits() {
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            let dir = tempfile::tempdir().unwrap();
+            let filename = make_fasta(dir.path(), 3);
+            let mut fusta = FustaFS::new(
+                FustaSettings {
+                    writable_fasta: true,
+                    concretize_threshold: 1 << 30,
+                    ..settings(cache)
+                },
+                &filename,
+            )
+            .unwrap();
+            let ino = fusta.fragments[0].fasta_file.ino;
+            let overwrite = |fusta: &mut FustaFS, content: &[u8]| {
+                fusta.resize_fasta(ino, 0).unwrap();
+                fusta.write_file(ino, 0, content).unwrap();
+                assert_eq!(
+                    fusta.attrs_from_ino(ino).unwrap().size,
+                    content.len() as u64
+                );
+                assert_eq!(fusta.read_file(ino, 0, u32::MAX).unwrap(), content);
+                fusta.commit_fasta(ino)
+            };
+
+            // Writes to the sequence are applied directly
+            fusta.write_file(ino, b">orig_0\n".len(), b"GG").unwrap();
+            assert!(fusta.pending_fastas.is_empty());
+            assert!(fusta.fragments[0].data().unwrap().starts_with(b"GGGT"));
+
+            // Invalid edits are discarded
+            for (content, errno) in [
+                (&b"ACGT\n"[..], EINVAL),
+                (b">a\nAC\n>b\nGG\n", EINVAL),
+                (b">\nAC\n", EINVAL),
+                (b">orig_1 taken\nAC\n", EEXIST),
+            ] {
+                let e = overwrite(&mut fusta, content).unwrap_err();
+                assert_eq!(e.errno(), errno, "{:?}", content);
+                assert_eq!(fusta.fragments[0].id, "orig_0");
+                assert_eq!(
+                    fusta.attrs_from_ino(ino).unwrap().size as usize,
+                    b">orig_0\n".len() + fusta.fragments[0].data_size()
+                );
             }
-            SUBFRAGMENTS_DIR => {
-                let sf = self.create_subfragment(name);
-                match sf {
-                    Ok(attrs) => {
-                        reply.entry(&TTL, &attrs, 0);
-                    }
-                    Err(e) => {
-                        warn!("{}", &e);
-                        reply.error(ENOENT);
+
+            // Header edits rename the fragment and change its description
+            overwrite(&mut fusta, b">renamed some description\nACGT\nTT\n").unwrap();
+            let fragment = &fusta.fragments[0];
+            assert_eq!(fragment.id, "renamed");
+            assert_eq!(fragment.name.as_deref(), Some("some description"));
+            assert_eq!(&*fragment.data().unwrap(), b"ACGT\nTT\n");
+            assert_eq!(fusta.fragment_from_ino(ino).unwrap().id, "renamed");
+
+            fusta.concretize(true).unwrap();
+            drop(fusta);
+            let content = fs::read_to_string(&filename).unwrap();
+            assert!(content.starts_with(">renamed some description\nACGT\nTT\n>orig_1\n"));
+        }
+    }
+
+    #[test]
+    fn bounded_reads() {
+        let big = "A".repeat(MAX_IO_SIZE as usize + 10);
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            let dir = tempfile::tempdir().unwrap();
+            let filename = dir.path().join("test.fa").to_str().unwrap().to_owned();
+            fs::write(&filename, format!(">short desc\nACGTTT\n>big\n{}\n", big)).unwrap();
+            let mut fusta = FustaFS::new(settings(cache), &filename).unwrap();
+            let forward = fusta.create_subfragment("fw", "short:2-5").unwrap().ino;
+            let reverse = fusta.create_subfragment("rv", "short:2-5:-").unwrap().ino;
+            fusta.load_infos();
+
+            let short = &fusta.fragments[0];
+            let mut files = vec![
+                (
+                    short.fasta_file.ino,
+                    [&b">short desc\n"[..], &short.data().unwrap()].concat(),
+                ),
+                (short.seq_file.ino, short.data().unwrap().to_vec()),
+                (forward, b"CGTT".to_vec()),
+                (reverse, b"AACG".to_vec()),
+                (fusta.snapshot[0].attrs.ino, b"ACGTTT\n".to_vec()),
+            ];
+            fusta.load_info_csv();
+            files.push((INFO_CSV_FILE, fusta.info_csv.read(0..fusta.info_csv.len())));
+            for ino in [INFO_FILE, LABELS_FILE, OFFSETS_FILE, REGIONS_FILE] {
+                files.push((ino, fusta.get_file(ino).unwrap().data().to_vec()));
+            }
+
+            for (ino, expected) in files {
+                let len = expected.len();
+                for offset in [
+                    0,
+                    1,
+                    len.saturating_sub(1),
+                    len,
+                    len + 1,
+                    len + 1000,
+                    i64::MAX as usize,
+                ] {
+                    for size in [0, 1, len.saturating_sub(1), len, len + 1, u32::MAX as usize] {
+                        let start = std::cmp::min(offset, len);
+                        let end = std::cmp::min(start.saturating_add(size), len);
+                        assert_eq!(
+                            fusta.read_file(ino, offset as i64, size as u32).unwrap(),
+                            &expected[start..end],
+                            "{:?}: {} bytes at {} in {}",
+                            cache,
+                            size,
+                            offset,
+                            fusta.describe(ino)
+                        );
                     }
                 }
             }
-            _ => {
-                warn!("LOOKUP: parent {} does not exist", parent);
-                reply.error(ENOENT);
+
+            // Replies never exceed the negotiated size
+            let big_fasta = fusta.fragments[1].fasta_file.ino;
+            let big_seq = fusta.fragments[1].seq_file.ino;
+            for ino in [big_fasta, big_seq] {
+                let data = fusta.read_file(ino, 0, u32::MAX).unwrap();
+                assert_eq!(data.len(), MAX_IO_SIZE as usize);
+                let tail = fusta.read_file(ino, MAX_IO_SIZE as i64, u32::MAX).unwrap();
+                assert_eq!(
+                    (data.len() + tail.len()) as u64,
+                    fusta.attrs_from_ino(ino).unwrap().size
+                );
             }
         }
     }
 
-    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
-        match ino {
-            ROOT_DIR | SEQ_DIR | APPEND_DIR | FASTA_DIR | SUBFRAGMENTS_DIR => {
-                reply.attr(&TTL, self.dir_attrs.get(&ino).unwrap())
+    #[test]
+    fn deferred_reads() {
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            let (_dir, _, mut fusta) = mount(2, cache);
+            let fasta = fusta.fragments[0].fasta_file.ino;
+            let seq = fusta.fragments[0].seq_file.ino;
+            let data = fusta.fragments[0].data().unwrap().to_vec();
+            let before = fusta.sequence_read(fasta, 4, 100).unwrap();
+            let within = fusta.sequence_read(seq, 2, 2).unwrap();
+            assert!(fusta.sequence_read(INFO_FILE, 0, 100).is_none());
+
+            // The reads run later see the sequence as it was when issued...
+            fusta.write_file(seq, 0, b"TTTT").unwrap();
+            assert!(fusta
+                .read_file(fasta, 4, 100)
+                .unwrap()
+                .starts_with(b"g_0\nTTTT"));
+            assert_eq!(before.run().unwrap(), [&b"g_0\n"[..], &data].concat());
+            assert_eq!(within.run().unwrap(), b"GT");
+            // ...and are not run on the FASTA files being edited
+            fusta.settings.writable_fasta = true;
+            fusta.write_file(fasta, 0, b">").unwrap();
+            assert!(fusta.sequence_read(fasta, 0, 100).is_none());
+        }
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            let dir = tempfile::tempdir().unwrap();
+            let filename = dir.path().join("test.fa").to_str().unwrap().to_owned();
+            fs::write(&filename, ">a first \r\nACG\r\nTA\r\n>b\nAC\n").unwrap();
+            let mut fusta = FustaFS::new(settings(cache), &filename).unwrap();
+            let a = fusta.fragment_from_id("a").unwrap();
+            assert_eq!(a.name.as_deref(), Some("first"));
+            // seqs/ exposes the lines as they are, but the CRs are not bases
+            assert_eq!(a.sequence_len().unwrap(), 5);
+            assert_eq!(&*a.pure_chunk(2, 10).unwrap(), b"GTA");
+            let ino = a.seq_file.ino;
+            let seq = fusta.read_file(ino, 0, 100).unwrap();
+            if cache != Cache::RAM {
+                assert_eq!(seq, b"ACG\r\nTA\r\n");
             }
-            INFO_FILE => reply.attr(&TTL, self.get_file(INFO_FILE).unwrap().attrs()),
-            INFO_CSV_FILE => reply.attr(&TTL, self.get_file(INFO_CSV_FILE).unwrap().attrs()),
-            LABELS_FILE => reply.attr(&TTL, self.get_file(LABELS_FILE).unwrap().attrs()),
-            ino if self.subfragment_from_ino(ino).is_some() => {
-                reply.attr(&TTL, &self.subfragment_from_ino(ino).unwrap().attrs);
+            let region = fusta.create_subfragment("x", "a:1-5:-").unwrap();
+            assert_eq!(region.size, 5);
+            assert_eq!(fusta.read_file(region.ino, 0, 100).unwrap(), b"TACGT");
+            assert!(fusta.create_subfragment("y", "a:1-6").is_err());
+            let stats = fusta.load_stats().unwrap();
+            assert!(stats.contains("\na,5,"), "{}", stats);
+        }
+    }
+
+    #[test]
+    fn degraded_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("test.fa").to_str().unwrap().to_owned();
+        fs::write(&filename, ">a\nACGT\n>b\nACGTACGT\nAC\n").unwrap();
+        let mut fusta = FustaFS::new(settings(Cache::File), &filename).unwrap();
+        let forward = fusta.create_subfragment("fw", "b:2-5").unwrap().ino;
+        let reverse = fusta.create_subfragment("rv", "b:2-5:-").unwrap().ino;
+        assert_eq!(fusta.read_file(reverse, 0, 100).unwrap(), b"TACG");
+        let b = fusta.fragment_from_id("b").unwrap();
+        let inos = [forward, reverse, b.seq_file.ino, b.fasta_file.ino];
+
+        // The sequence of `b` vanishes from under the mount
+        fs::File::options()
+            .write(true)
+            .open(&filename)
+            .unwrap()
+            .set_len(12)
+            .unwrap();
+        for ino in inos {
+            let e = fusta.read_file(ino, 0, 100).unwrap_err();
+            assert_eq!(e.errno(), EIO, "{}: {}", fusta.describe(ino), e);
+        }
+        let e = fusta.create_subfragment("x", "b:1-2").unwrap_err();
+        assert_eq!(e.errno(), EIO);
+        let e = fusta.extract("b", &dir.path().join("b.fa")).unwrap_err();
+        assert_eq!(e.errno(), EIO);
+        let status = fusta.read_file(STATUS_FILE, 0, 1000).unwrap();
+        assert!(String::from_utf8(status).unwrap().contains("degraded: b"));
+        // ...while the other sequences are still served
+        let a = fusta.fragment_from_id("a").unwrap().seq_file.ino;
+        assert_eq!(fusta.read_file(a, 0, 100).unwrap(), b"ACGT\n");
+    }
+
+    #[test]
+    fn empty_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("test.fa").to_str().unwrap().to_owned();
+        for content in [">a\nAC\n>\nACGT\n", ">a\nAC\n> \r\nACGT\n"] {
+            fs::write(&filename, content).unwrap();
+            let records = FastaReader::new(content.as_bytes(), false).collect::<Vec<_>>();
+            assert_eq!(records[1].id, "");
+            let e = format!(
+                "{:#}",
+                FustaFS::new(settings(Cache::Mmap), &filename)
+                    .err()
+                    .unwrap()
+            );
+            assert!(e.contains("has no ID"), "{}", e);
+        }
+    }
+
+    #[test]
+    fn injected_faults() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 3);
+        let original = fs::read(&filename).unwrap();
+        let mut fusta = FustaFS::new(
+            FustaSettings {
+                chaos: Chaos::parse("write-error=1", 0).unwrap(),
+                ..settings(Cache::RAM)
+            },
+            &filename,
+        )
+        .unwrap();
+        let ino = fusta.fragments[0].seq_file.ino;
+
+        let e = fusta.write_fragment(ino, 0, b"GG").unwrap_err();
+        assert_eq!(e.errno(), EIO);
+        assert_eq!(&*fusta.fragments[0].data().unwrap(), b"ACGT");
+        assert!(!fusta.dirty);
+
+        // A failed rewrite leaves the file untouched, and the changes pending
+        fusta.settings.chaos.write_error = 0.;
+        fusta.write_fragment(ino, 0, b"GG").unwrap();
+        fusta.settings.chaos.write_error = 1.;
+        assert_eq!(fusta.concretize(true).unwrap_err().errno(), EIO);
+        assert!(fusta.dirty);
+        assert_eq!(fs::read(&filename).unwrap(), original);
+        assert!(!std::path::Path::new(&format!("{}#fusta#", filename)).exists());
+
+        fusta.settings.chaos.write_error = 0.;
+        fusta.concretize(true).unwrap();
+        assert!(fs::read(&filename).unwrap().starts_with(b">orig_0\nGGGT\n"));
+    }
+
+    #[test]
+    fn normalized_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("test.fa");
+        let filename = filename.to_str().unwrap();
+        fs::write(filename, ">gi|42|gb|X1| first\nACGT\n>gi|43|gb|X2|\nTTTT\n").unwrap();
+        let settings = || FustaSettings {
+            id_rules: IdRules::parse("strip-prefix=gi|,truncate-at=|").unwrap(),
+            ..settings(Cache::RAM)
+        };
+        let mut fusta = FustaFS::new(settings(), filename).unwrap();
+        assert!(fusta.fragment_from_fasta_filename("42.fa").is_some());
+        assert_eq!(fusta.fragments[0].label(), ">gi|42|gb|X1| first\n");
+
+        append(&mut fusta, "gi|44|gb|X3|", "GG");
+        fusta
+            .apply(Mutation::Rename {
+                from: "43".into(),
+                to: "renamed".into(),
+            })
+            .unwrap();
+        drop(fusta);
+        assert_eq!(
+            fs::read_to_string(filename).unwrap(),
+            ">gi|42|gb|X1| first\nACGT\n>renamed\nTTTT\n>gi|44|gb|X3|\nGG\n"
+        );
+
+        // IDs colliding once normalized are suffixed, and reported
+        fs::write(
+            filename,
+            ">gi|42|a\nACGT\n>gi|42|b\nTTTT\n>42__2\nCC\n>gi|42|c\nG\n",
+        )
+        .unwrap();
+        let mut fusta = FustaFS::new(settings(), filename).unwrap();
+        let ids = fusta
+            .fragments
+            .iter()
+            .map(|f| f.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["42", "42__3", "42__2", "42__4"]);
+        assert_eq!(fusta.fragments[1].label(), ">gi|42|b\n");
+        let report = String::from_utf8(fusta.read_file(COLLISIONS_FILE, 0, 1000).unwrap()).unwrap();
+        assert_eq!(
+            report,
+            format!(
+                "id\tnormalized id\tfile\theader\n\
+                 42\t42\t{0}\tgi|42|a\n42__3\t42\t{0}\tgi|42|b\n42__4\t42\t{0}\tgi|42|c\n",
+                filename
+            )
+        );
+        // ...and written back as they were
+        fusta.dirty = true;
+        drop(fusta);
+        assert_eq!(
+            fs::read_to_string(filename).unwrap(),
+            ">gi|42|a\nACGT\n>gi|42|b\nTTTT\n>42__2\nCC\n>gi|42|c\nG\n"
+        );
+    }
+
+    #[test]
+    fn raw_headers() {
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            let dir = tempfile::tempdir().unwrap();
+            let filename = dir.path().join("test.fa");
+            let filename = filename.to_str().unwrap();
+            fs::write(
+                filename,
+                ">a\tfirst  one\nACGT\n>b  second\nTTTT\n>c third \nGG\n",
+            )
+            .unwrap();
+            let mut fusta = FustaFS::new(settings(cache), filename).unwrap();
+            assert_eq!(fusta.fragments[1].id, "b");
+            assert_eq!(fusta.fragments[1].label(), ">b  second\n");
+
+            // Only the renamed fragment gets a regenerated header
+            append(&mut fusta, "d", "CC");
+            fusta
+                .apply(Mutation::Rename {
+                    from: "b".into(),
+                    to: "renamed".into(),
+                })
+                .unwrap();
+            drop(fusta);
+            assert_eq!(
+                fs::read_to_string(filename).unwrap(),
+                ">a\tfirst  one\nACGT\n>renamed second\nTTTT\n>c third \nGG\n>d\nCC\n",
+                "{:?}",
+                cache
+            );
+        }
+    }
+
+    #[test]
+    fn loss_guard() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 4);
+        let mut fusta = FustaFS::new(
+            FustaSettings {
+                allow_empty: false,
+                max_loss: 0.5,
+                ..settings(Cache::RAM)
+            },
+            &filename,
+        )
+        .unwrap();
+        let ids = |filename: &str| {
+            read_back(filename)
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>()
+        };
+        let delete = |fusta: &mut FustaFS, id: &str| fusta.apply(Mutation::Delete(id.into()));
+
+        delete(&mut fusta, "orig_0").unwrap();
+        delete(&mut fusta, "orig_1").unwrap();
+        assert_eq!(ids(&filename), ["orig_2", "orig_3"]);
+        // Past half of the original content, the changes are held back
+        delete(&mut fusta, "orig_2").unwrap();
+        assert!(fusta.dirty);
+        assert_eq!(ids(&filename), ["orig_2", "orig_3"]);
+        assert_eq!(fusta.commit("").unwrap_err().errno(), ECANCELED);
+        assert!(fusta.commit("yes").is_err());
+        fusta.commit("force").unwrap();
+        assert_eq!(ids(&filename), ["orig_3"]);
+
+        // Confirmed write-backs become the new reference, but emptying the
+        // file always needs a confirmation
+        delete(&mut fusta, "orig_3").unwrap();
+        drop(fusta);
+        assert_eq!(ids(&filename), ["orig_3"]);
+    }
+
+    #[test]
+    fn ino_recycling() {
+        let (_dir, _, mut fusta) = mount(2, Cache::Mmap);
+
+        let first = fusta
+            .create_subfragment("orig_0:1-2", "orig_0:1-2")
+            .unwrap()
+            .ino;
+        assert_eq!(fusta.inos.generation(first), 0);
+        fusta.forget_ino(first);
+        assert!(fusta.subfragment_from_ino(first).is_none());
+        let second = fusta
+            .create_subfragment("orig_1:1-2", "orig_1:1-2")
+            .unwrap()
+            .ino;
+        assert_eq!(second, first);
+        assert_eq!(fusta.inos.generation(second), 1);
+
+        // Files that may still be looked up keep their ino
+        fusta.create_regions("orig_0:1-3 listed").unwrap();
+        let listed = fusta
+            .subfragments
+            .get(&"listed".to_owned())
+            .unwrap()
+            .attrs
+            .ino;
+        fusta.forget_ino(listed);
+        assert!(fusta.subfragment_from_ino(listed).is_some());
+        let fasta_ino = fusta.fragments[0].fasta_file.ino;
+        fusta.forget_ino(fasta_ino);
+        assert!(fusta.fragment_from_ino(fasta_ino).is_some());
+        assert_ne!(fusta.new_ino(), fasta_ino);
+    }
+
+    #[test]
+    fn generations() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 2);
+        let remount = || FustaFS::new(settings(Cache::Mmap), &filename).unwrap();
+        let files = |fusta: &FustaFS| {
+            fusta
+                .fragments
+                .iter()
+                .map(|f| {
+                    (
+                        f.id.to_string(),
+                        f.fasta_file.ino,
+                        fusta.generation(f.fasta_file.ino),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut fusta = remount();
+        let first = files(&fusta);
+        assert!(first.iter().all(|f| f.2 == 1));
+        fusta.apply(Mutation::Delete("orig_0".into())).unwrap();
+        fusta.concretize(true).unwrap();
+        drop(fusta);
+
+        // Renamed fragments keep their handles, but a new fragment reusing
+        // the inos of a deleted one gets a new generation
+        let mut fusta = remount();
+        assert_eq!(files(&fusta), first[1..]);
+        fusta
+            .apply(Mutation::Rename {
+                from: "orig_1".into(),
+                to: "renamed".into(),
+            })
+            .unwrap();
+        append(&mut fusta, "orig_0", "GG");
+        let second = files(&fusta);
+        assert_eq!((second[0].1, second[0].2), (first[1].1, first[1].2));
+        assert_eq!(second[1].1, first[0].1);
+        assert_eq!(second[1].2, 2);
+
+        let sf = fusta.create_subfragment("x", "renamed:1-2").unwrap().ino;
+        assert_ne!(fusta.generation(sf), 0);
+        assert_eq!(fusta.generation(ROOT_DIR), 1);
+    }
+
+    #[test]
+    fn session_location() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 2);
+        let sessions = tempfile::tempdir().unwrap();
+        let in_dir = |sessions: &Path| FustaSettings {
+            session_dir: Some(sessions.join("fusta")),
+            ..settings(Cache::Mmap)
+        };
+
+        // The session is kept out of the directory of the FASTA file...
+        let fusta = FustaFS::new(in_dir(sessions.path()), &filename).unwrap();
+        let inos = fusta.fragments[1].fasta_file.ino;
+        drop(fusta);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        let session = Session::path_for(&filename, &sessions.path().join("fusta"));
+        assert!(session.exists());
+
+        // ...and is found back when remounting
+        let mut fusta = FustaFS::new(in_dir(sessions.path()), &filename).unwrap();
+        fusta.apply(Mutation::Delete("orig_0".into())).unwrap();
+        fusta.concretize(true).unwrap();
+        drop(fusta);
+        let fusta = FustaFS::new(in_dir(sessions.path()), &filename).unwrap();
+        assert_eq!(fusta.fragments[0].fasta_file.ino, inos);
+        drop(fusta);
+
+        // The session of the previous versions is still read
+        fs::rename(&session, Session::legacy_path_for(&filename)).unwrap();
+        let fusta = FustaFS::new(in_dir(sessions.path()), &filename).unwrap();
+        assert_eq!(fusta.fragments[0].fasta_file.ino, inos);
+        drop(fusta);
+
+        // An unwritable state directory does not prevent mounting
+        let file = sessions.path().join("file");
+        fs::write(&file, "").unwrap();
+        let fusta = FustaFS::new(in_dir(&file), &filename).unwrap();
+        assert!(fusta.session_unsaved.get());
+    }
+
+    #[test]
+    #[cfg(feature = "catalog")]
+    fn catalog() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 3);
+        let sessions = tempfile::tempdir().unwrap();
+        let session = Session::path_for(&filename, sessions.path());
+        let in_session = || FustaSettings {
+            session_dir: Some(sessions.path().to_owned()),
+            ..settings(Cache::Mmap)
+        };
+        let catalogued = || FustaSettings {
+            catalog: true,
+            no_index: false,
+            verify: Verify::AgainstSession,
+            refuse_mismatches: true,
+            ..in_session()
+        };
+        let fusta = FustaFS::new(in_session(), &filename).unwrap();
+        let inos = fusta.fragments[1].fasta_file.ino;
+        drop(fusta);
+
+        // The previous session is carried over into the catalog...
+        let mut fusta = FustaFS::new(catalogued(), &filename).unwrap();
+        assert_eq!(fusta.fragments[1].fasta_file.ino, inos);
+        let tags = fusta.listing(TAGS_DIR)[2].0;
+        fusta.write_file(tags, 0, b"keep=true\n").unwrap();
+        fusta.commit_tags(tags).unwrap();
+        fusta
+            .apply(Mutation::Rename {
+                from: "orig_2".into(),
+                to: "renamed".into(),
+            })
+            .unwrap();
+        drop(fusta);
+        // ...which then holds the state of the fragments in its stead
+        let saved = Session::load(&session);
+        assert!(saved.inodes.is_empty() && saved.tags.is_empty() && saved.digests.is_empty());
+        assert_eq!(saved.mounts, 2);
+
+        // The records are kept there rather than in FASTA.fusta-idx, and
+        // follow the write-backs
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        let file = fs::File::open(&filename).unwrap();
+        let records = Catalog::open(&Catalog::path_for(&session))
+            .unwrap()
+            .records(&filename, &index::Source::of(&file).unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(records[2].id, "renamed");
+
+        let fusta = FustaFS::new(catalogued(), &filename).unwrap();
+        assert_eq!(fusta.fragments[1].fasta_file.ino, inos);
+        assert_eq!(
+            fusta.fragment_from_id("orig_0").unwrap().tags["keep"],
+            "true"
+        );
+        assert!(fusta.fragment_from_id("renamed").unwrap().tags.is_empty());
+        drop(fusta);
+
+        // The digests recorded there are checked by --verify
+        let content = fs::read_to_string(&filename).unwrap();
+        fs::write(&filename, content.replace("ACGTT\n", "ACGTA\n")).unwrap();
+        let e = FustaFS::new(catalogued(), &filename).err().unwrap();
+        assert!(format!("{:#}", e).contains("differ"), "{:#}", e);
+    }
+
+    #[test]
+    fn export() {
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            let dir = tempfile::tempdir().unwrap();
+            let filename = dir.path().join("test.fa");
+            fs::write(&filename, ">a first\nACGTA\nCGTAC\nGT\n>b\nTTTT\n>c\nGG\n").unwrap();
+            let mut fusta = FustaFS::new(settings(cache), filename.to_str().unwrap()).unwrap();
+            let out = dir.path().join("out");
+            fs::create_dir(&out).unwrap();
+
+            let summary = fusta
+                .export(&format!(
+                    "# comment\nc {out}/ac.fa\na {out}/ac.fa\nb {out}/b.fa\nz {out}/z.fa\nb relative.fa\n",
+                    out = out.display()
+                ))
+                .unwrap();
+            assert_eq!(
+                summary, "3 fragments exported to 2 files, 2 failed",
+                "{:?}",
+                cache
+            );
+            assert_eq!(
+                fs::read_to_string(out.join("ac.fa")).unwrap(),
+                ">c\nGG\n>a first\nACGTA\nCGTAC\nGT\n"
+            );
+            assert_eq!(fs::read_to_string(out.join("b.fa")).unwrap(), ">b\nTTTT\n");
+            assert!(!out.join("z.fa").exists());
+            let report =
+                String::from_utf8(fusta.get_file(EXPORT_FILE).unwrap().data().to_vec()).unwrap();
+            assert!(report.contains("line 5: fragment `z` not found"));
+            assert!(report.contains("line 6: `relative.fa` is not an absolute path"));
+        }
+    }
+
+    #[test]
+    fn export_split() {
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            let dir = tempfile::tempdir().unwrap();
+            let filename = dir.path().join("test.fa");
+            fs::write(&filename, ">a first\nACGTA\nCGTAC\nGT\n>b\nTTTT\n").unwrap();
+            let mut fusta = FustaFS::new(settings(cache), filename.to_str().unwrap()).unwrap();
+            let out = dir.path().join("out").join("split");
+
+            let summary = fusta
+                .export_split(&format!("# comment\n{}\n", out.display()))
+                .unwrap();
+            assert!(summary.starts_with("2 fragments exported"), "{}", summary);
+            assert_eq!(
+                fs::read_to_string(out.join("a.fa")).unwrap(),
+                ">a first\nACGTA\nCGTAC\nGT\n"
+            );
+            assert_eq!(fs::read_to_string(out.join("b.fa")).unwrap(), ">b\nTTTT\n");
+
+            fusta.export_split(&format!("{} 3", out.display())).unwrap();
+            assert_eq!(
+                fs::read_to_string(out.join("a.fa")).unwrap(),
+                ">a first\nACG\nTAC\nGTA\nCGT\n"
+            );
+            fusta.export_split(&format!("{} 0", out.display())).unwrap();
+            assert_eq!(
+                fs::read_to_string(out.join("a.fa")).unwrap(),
+                ">a first\nACGTACGTACGT\n"
+            );
+            for invalid in ["", "relative/dir", "/tmp/out x", "/tmp/out 1 2"] {
+                assert!(fusta.export_split(invalid).is_err(), "{}", invalid);
             }
-            _ => {
-                if let Some(file) = self
-                    .fragment_from_ino(ino)
-                    .and_then(|f| f.file_from_ino(ino))
-                {
-                    reply.attr(&TTL, file.attrs())
-                } else {
-                    warn!("GETATTR: ino `{}` does not exist", ino);
-                    reply.error(ENOENT)
+        }
+    }
+
+    #[test]
+    fn fai_index() {
+        let content = ">a first\nACGT\nAC\n>b\nNANA\n>c\n";
+        for compressed in [false, true] {
+            let dir = tempfile::tempdir().unwrap();
+            let filename = dir
+                .path()
+                .join(if compressed { "test.fa.gz" } else { "test.fa" });
+            if compressed {
+                fs::write(&filename, crate::bgzf::block(content.as_bytes())).unwrap();
+            } else {
+                fs::write(&filename, content).unwrap();
+            }
+            let filename = filename.to_str().unwrap();
+            let fai = fai::path_for(Path::new(filename));
+            fs::write(&fai, "a\t6\t9\t4\t5\nb\t4\t20\t4\t5\nc\t0\t28\t0\t0\n").unwrap();
+            let fusta = FustaFS::new(settings(Cache::Mmap), filename).unwrap();
+            let fragments = fusta
+                .fragments
+                .iter()
+                .map(|f| (f.label(), f.data.pure_chunk(0, 100).unwrap()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                fragments,
+                [
+                    (">a first\n".to_owned(), b"ACGTAC"[..].into()),
+                    (">b\n".to_owned(), b"NANA"[..].into()),
+                    (">c\n".to_owned(), b""[..].into()),
+                ]
+            );
+
+            // A stale index is ignored
+            fs::write(&fai, "a\t6\t9\t4\t5\nc\t0\t28\t0\t0\n").unwrap();
+            let fusta = FustaFS::new(settings(Cache::Mmap), filename).unwrap();
+            assert_eq!(fusta.fragments.len(), 3);
+        }
+    }
+
+    #[test]
+    fn sidecar_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("test.fa");
+        fs::write(&filename, ">a first\nACGT\nAC\n>b\r\nNANA\r\n>c\n").unwrap();
+        let path = index::path_for(&filename);
+        let filename = filename.to_str().unwrap();
+        let matches_scan = || {
+            let file = fs::File::open(filename).unwrap();
+            let indexed = Index::load(&path, &file).unwrap();
+            let scanned = FastaReader::new(&file, false).collect::<Vec<_>>();
+            assert_eq!(indexed.len(), scanned.len());
+            for (i, s) in indexed.iter().zip(scanned.iter()) {
+                assert_eq!(
+                    (&i.id, &i.name, &i.header, i.pos, i.len, i.line_width),
+                    (&s.id, &s.name, &s.header, s.pos, s.len, s.line_width)
+                );
+            }
+        };
+
+        // Not written with --no-index
+        FustaFS::new(settings(Cache::Mmap), filename).unwrap();
+        assert!(!path.exists());
+
+        // Written after the first scan, and used by the next mounts
+        let indexed = || FustaSettings {
+            no_index: false,
+            ..settings(Cache::Mmap)
+        };
+        FustaFS::new(indexed(), filename).unwrap();
+        matches_scan();
+        let mut fusta = FustaFS::new(indexed(), filename).unwrap();
+        assert_eq!(fusta.fragments.len(), 3);
+
+        // Kept up to date by the write-backs
+        let ino = fusta.fragment_from_name("a").unwrap().seq_file.ino;
+        fusta.write_fragment(ino, 0, b"TTTTTTTT\n").unwrap();
+        fusta.rename_fragment("b", "bb").unwrap();
+        fusta.concretize(true).unwrap();
+        matches_scan();
+        let fusta = FustaFS::new(indexed(), filename).unwrap();
+        let ids = fusta
+            .fragments
+            .iter()
+            .map(|f| f.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["a", "bb", "c"]);
+        assert_eq!(
+            &*fusta.fragments[0].data.pure_chunk(0, 100).unwrap(),
+            b"TTTTTTTT"
+        );
+    }
+
+    #[test]
+    fn bgzf_backing() {
+        let content = format!(">a first\n{}\n>b\nNANA\n>c\n", "ACGT".repeat(30_000));
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            let dir = tempfile::tempdir().unwrap();
+            let filename = dir.path().join("test.fa.gz");
+            let compressed = content
+                .as_bytes()
+                .chunks(crate::bgzf::BLOCK_INPUT_SIZE)
+                .flat_map(crate::bgzf::block)
+                .collect::<Vec<_>>();
+            fs::write(&filename, compressed).unwrap();
+            let mut fusta = FustaFS::new(settings(cache), filename.to_str().unwrap()).unwrap();
+            let ids = fusta
+                .fragments
+                .iter()
+                .map(|f| f.id.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(ids, ["a", "b", "c"]);
+            let a = &fusta.fragments[0];
+            assert_eq!(a.data.pure_chunk(0, 1 << 20).unwrap().len(), 120_000);
+            assert_eq!(a.data.chunk(70_000, 6).unwrap(), b"ACGTAC"[..].into());
+            assert_eq!(a.label(), ">a first\n");
+            assert_eq!(
+                &*fusta.fragments[1].data.pure_chunk(0, 10).unwrap(),
+                b"NANA"
+            );
+
+            // The background computations and the snapshot read it too
+            let stats = loop {
+                match fusta.read_file(STATS_FILE, 0, MAX_IO_SIZE) {
+                    Ok(data) => break String::from_utf8(data).unwrap(),
+                    Err(e) => assert_eq!(e.errno(), EAGAIN),
                 }
+            };
+            assert!(stats.contains("\nb,4,0.0000,0.5000,2,"), "{}", stats);
+            assert_eq!(fusta.read_snapshot(1, 0, 100).unwrap(), b"NANA\n");
+
+            // It is never replaced by its uncompressed version
+            let e = fusta
+                .apply(Mutation::Append {
+                    file: "d".into(),
+                    data: b">d\nGG\n".to_vec(),
+                    atime: SystemTime::now(),
+                    mtime: SystemTime::now(),
+                })
+                .and_then(|_| fusta.concretize(true))
+                .unwrap_err();
+            assert_eq!(e.errno(), EACCES);
+            drop(fusta);
+            assert!(crate::bgzf::is_bgzf_file(&filename));
+        }
+    }
+
+    #[test]
+    fn background_stats() {
+        let read_stats = |fusta: &mut FustaFS| loop {
+            match fusta.read_file(STATS_FILE, 0, MAX_IO_SIZE) {
+                Ok(data) => break String::from_utf8(data).unwrap(),
+                Err(e) => assert_eq!(e.errno(), EAGAIN),
+            }
+        };
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            let dir = tempfile::tempdir().unwrap();
+            let filename = dir.path().join("test.fa");
+            fs::write(&filename, ">a\nACGGN\nNNTA\n>b\nNANA\n>c\n").unwrap();
+            let mut fusta = FustaFS::new(settings(cache), filename.to_str().unwrap()).unwrap();
+
+            let stats = read_stats(&mut fusta);
+            assert_eq!(
+                stats,
+                format!(
+                    "id,length,gc_content,n_content,gaps,md5\n\
+                     a,9,0.5000,0.3333,1,{}\n\
+                     b,4,0.0000,0.5000,2,{}\n\
+                     c,0,0.0000,0.0000,0,{}\n",
+                    sequence_digest(b"ACGGNNNTA"),
+                    sequence_digest(b"NANA"),
+                    sequence_digest(b""),
+                ),
+                "{:?}",
+                cache
+            );
+            assert_eq!(
+                fusta.attrs_from_ino(STATS_FILE).unwrap().size,
+                stats.len() as u64
+            );
+
+            // Editing a sequence makes them computed again
+            let ino = fusta.fragment_from_id("b").unwrap().seq_file.ino;
+            fusta.write_file(ino, 0, b"GG").unwrap();
+            fusta.refresh_metadata(false);
+            assert!(read_stats(&mut fusta).contains(&format!(
+                "b,4,0.6667,0.2500,1,{}\n",
+                sequence_digest(b"GGNA")
+            )));
+        }
+    }
+
+    #[test]
+    fn prefetch() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 3);
+        for prefetch in [Prefetch::None, Prefetch::Index, Prefetch::All] {
+            let mut fusta = FustaFS::new(
+                FustaSettings {
+                    prefetch,
+                    ..settings(Cache::Mmap)
+                },
+                &filename,
+            )
+            .unwrap();
+            assert!(fusta.stats.is_none());
+            fusta.prefetch();
+            assert_eq!(fusta.stats.is_some(), prefetch != Prefetch::None);
+        }
+    }
+
+    #[test]
+    fn filename_codecs() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("test.fa");
+        fs::write(&filename, ">a/b x\nACGT\n>Chr1\nGG\n>chr1\nTT\n").unwrap();
+        let filename = filename.to_str().unwrap();
+        assert!(FustaFS::new(settings(Cache::RAM), filename).is_err());
+
+        let mut fusta = FustaFS::new(
+            FustaSettings {
+                codec: Codec::Folded,
+                ..settings(Cache::RAM)
+            },
+            filename,
+        )
+        .unwrap();
+        let names = fusta
+            .fragments
+            .iter()
+            .map(|f| f.fasta_file.name.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a%2Fb.fa", "^chr1.fa", "chr1.fa"]);
+        assert_eq!(
+            fusta.fragment_from_seq_filename("a%2Fb.seq").unwrap().id,
+            "a/b"
+        );
+        assert_eq!(
+            fusta.fragment_from_fasta_filename("^chr1.fa").unwrap().id,
+            "Chr1"
+        );
+        assert!(fusta.fragment_from_fasta_filename("a%2fb.fa").is_none());
+        assert!(fusta.fragment_from_fasta_filename("Chr1.fa").is_none());
+
+        // The FASTA file only ever contains the IDs
+        fusta
+            .apply(Mutation::Rename {
+                from: "chr1".into(),
+                to: "X/1".into(),
+            })
+            .unwrap();
+        assert_eq!(
+            fusta.fragment_from_id("X/1").unwrap().seq_file.name,
+            "^x%2F1.seq"
+        );
+        assert_eq!(
+            fs::read_to_string(filename).unwrap(),
+            ">a/b x\nACGT\n>Chr1\nGG\n>X/1\nTT\n"
+        );
+    }
+
+    #[test]
+    fn cached_listings() {
+        let (_dir, _, mut fusta) = mount(3, Cache::Mmap);
+        let names = |listing: &Listing| listing.iter().map(|e| e.2.to_string()).collect::<Vec<_>>();
+
+        let seqs = fusta.listing(SEQ_DIR);
+        assert_eq!(
+            names(&seqs),
+            [".", "..", "orig_0.seq", "orig_1.seq", "orig_2.seq"]
+        );
+        assert!(Arc::ptr_eq(&seqs, &fusta.listing(SEQ_DIR)));
+        assert_eq!(names(&fusta.listing(DOWNLOAD_DIR))[2], "orig_0.fa.gz");
+
+        // Any change to the fragments renders them again
+        fusta.apply(Mutation::Delete("orig_1".into())).unwrap();
+        assert_eq!(
+            names(&fusta.listing(FASTA_DIR)),
+            [".", "..", "orig_0.fa", "orig_2.fa"]
+        );
+        assert_eq!(fusta.listing(SEQ_DIR).len(), 4);
+    }
+
+    #[test]
+    fn discarded_changes() {
+        let (_dir, filename, mut fusta) = mount(3, Cache::Mmap);
+        let status = |fusta: &mut FustaFS| {
+            String::from_utf8(fusta.read_file(STATUS_FILE, 0, 1000).unwrap()).unwrap()
+        };
+        assert!(status(&mut fusta).starts_with("clean\non-collision: overwrite\n"));
+
+        fusta.apply(Mutation::Delete("orig_1".into())).unwrap();
+        assert!(status(&mut fusta).starts_with("dirty\n- the sequences have changed"));
+        assert!(fusta.commit("maybe").is_err());
+
+        fusta.commit("discard").unwrap();
+        assert!(status(&mut fusta).starts_with("clean\n"));
+        assert_eq!(fusta.fragments.len(), 3);
+        assert_eq!(read_back(&filename).len(), 3);
+        assert_eq!(fusta.commit("discard").unwrap(), "Nothing to discard");
+    }
+
+    #[test]
+    fn reverted_fragments() {
+        for cache in [Cache::Mmap, Cache::RAM] {
+            let dir = tempfile::tempdir().unwrap();
+            let filename = make_fasta(dir.path(), 4);
+            let mut fusta = FustaFS::new(
+                FustaSettings {
+                    concretize_threshold: 1 << 30,
+                    ..settings(cache)
+                },
+                &filename,
+            )
+            .unwrap();
+            let ids = |fusta: &FustaFS| {
+                fusta
+                    .fragments
+                    .iter()
+                    .map(|f| f.id.to_string())
+                    .collect::<Vec<_>>()
+            };
+
+            let ino = fusta.fragment_from_id("orig_0").unwrap().seq_file.ino;
+            fusta.write_file(ino, 0, b"GG").unwrap();
+            fusta
+                .apply(Mutation::Rename {
+                    from: "orig_1".into(),
+                    to: "renamed".into(),
+                })
+                .unwrap();
+            fusta.apply(Mutation::Delete("orig_2".into())).unwrap();
+            fusta.apply(Mutation::Delete("orig_3".into())).unwrap();
+            append(&mut fusta, "new", "TTTT");
+            assert_eq!(ids(&fusta), ["orig_0", "renamed", "new"]);
+
+            let summary = fusta
+                .revert("orig_0\nrenamed\norig_2\nnew\nmissing\n")
+                .unwrap();
+            assert_eq!(summary, "4 fragments reverted, 1 failed", "{:?}", cache);
+            assert_eq!(ids(&fusta), ["orig_0", "orig_1", "orig_2"]);
+            assert_eq!(
+                &*fusta
+                    .fragment_from_id("orig_0")
+                    .unwrap()
+                    .pure_chunk(0, 100)
+                    .unwrap(),
+                b"ACGT"
+            );
+            assert_eq!(
+                &*fusta
+                    .fragment_from_id("orig_2")
+                    .unwrap()
+                    .pure_chunk(0, 100)
+                    .unwrap(),
+                b"ACGTTT"
+            );
+            // Nothing has been written back meanwhile
+            assert_eq!(read_back(&filename).len(), 4);
+
+            fusta.revert("*").unwrap();
+            assert_eq!(ids(&fusta), ["orig_0", "orig_1", "orig_2", "orig_3"]);
+            assert!(!fusta.dirty);
+        }
+    }
+
+    #[test]
+    fn undo() {
+        let (_dir, _, mut fusta) = mount(4, Cache::Mmap);
+        let seq = |fusta: &FustaFS, id: &str| {
+            fusta
+                .fragment_from_id(id)
+                .unwrap()
+                .pure_chunk(0, 100)
+                .unwrap()
+                .to_vec()
+        };
+
+        // All the writes until the file is closed are a single edit
+        let ino = fusta.fragment_from_id("orig_0").unwrap().seq_file.ino;
+        fusta.write_file(ino, 0, b"GG").unwrap();
+        fusta.write_file(ino, 2, b"GG").unwrap();
+        fusta.editing.clear();
+        fusta
+            .apply(Mutation::Rename {
+                from: "orig_0".into(),
+                to: "first".into(),
+            })
+            .unwrap();
+
+        assert_eq!(fusta.undo("first").unwrap(), "1 edits undone, 0 failed");
+        assert_eq!(seq(&fusta, "orig_0"), b"GGGG");
+        assert_eq!(
+            String::from_utf8(fusta.get_file(UNDO_FILE).unwrap().data().to_vec()).unwrap(),
+            "1 edits undone, 0 failed\n`orig_0`: 1 edits undone, 1 left\n"
+        );
+        fusta.undo("orig_0").unwrap();
+        assert_eq!(seq(&fusta, "orig_0"), b"ACGT");
+        assert_eq!(
+            fusta.undo("orig_0\nmissing\norig_1 x").unwrap(),
+            "0 edits undone, 3 failed"
+        );
+
+        // Past the budget, the oldest states are dropped
+        fusta.settings.undo_budget = 20;
+        for i in [1, 2, 3] {
+            let ino = fusta
+                .fragment_from_id(&format!("orig_{}", i))
+                .unwrap()
+                .seq_file
+                .ino;
+            fusta.write_file(ino, 0, b"N").unwrap();
+            fusta.write_file(ino, 0, b"N").unwrap();
+        }
+        assert_eq!(fusta.undo("orig_1").unwrap(), "0 edits undone, 1 failed");
+        assert_eq!(
+            fusta.undo("orig_2\norig_3").unwrap(),
+            "2 edits undone, 0 failed"
+        );
+        assert_eq!(seq(&fusta, "orig_3"), b"ACGTTTT");
+
+        // Deleted fragments take their history with them
+        fusta.write_file(ino, 0, b"GG").unwrap();
+        fusta.apply(Mutation::Delete("orig_0".into())).unwrap();
+        append(&mut fusta, "orig_0", "TTTT");
+        assert!(fusta.undo("orig_0").is_ok_and(|s| s.ends_with("1 failed")));
+    }
+
+    #[test]
+    fn duplicates() {
+        let read_duplicates = |fusta: &mut FustaFS| loop {
+            match fusta.read_file(DUPLICATES_FILE, 0, MAX_IO_SIZE) {
+                Ok(data) => break String::from_utf8(data).unwrap(),
+                Err(e) => assert_eq!(e.errno(), EAGAIN),
+            }
+        };
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            let dir = tempfile::tempdir().unwrap();
+            let filename = dir.path().join("test.fa");
+            fs::write(
+                &filename,
+                ">a\nACGT\n>b\nAC\nGT\n>c\nacgt\n>d\nGGGG\n>e\nggGG\n",
+            )
+            .unwrap();
+            let mut fusta = FustaFS::new(settings(cache), filename.to_str().unwrap()).unwrap();
+            assert_eq!(
+                read_duplicates(&mut fusta),
+                "identical\ta\tb\n\
+                 case-insensitive\ta\tb\tc\n\
+                 case-insensitive\td\te\n",
+                "{:?}",
+                cache
+            );
+
+            fusta.apply(Mutation::Delete("b".into())).unwrap();
+            let ino = fusta.fragment_from_id("e").unwrap().seq_file.ino;
+            fusta.write_file(ino, 0, b"GG").unwrap();
+            fusta.refresh_metadata(false);
+            assert_eq!(
+                read_duplicates(&mut fusta),
+                "case-insensitive\ta\tc\nidentical\td\te\n",
+                "{:?}",
+                cache
+            );
+        }
+    }
+
+    #[test]
+    fn proteins() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("test.fa");
+        fs::write(
+            &filename,
+            ">chr1\nCCATGAAATT\nTTGAGG\n>chr2 minus\nTTCCACATCTT\n",
+        )
+        .unwrap();
+        let gff = dir.path().join("test.gff");
+        fs::write(
+            &gff,
+            "##gff-version 3\n\
+             chr1\t.\tmRNA\t3\t14\t.\t+\t.\tID=t1\n\
+             chr1\t.\tCDS\t3\t8\t.\t+\t0\tParent=t1\n\
+             chr1\t.\tCDS\t12\t14\t.\t+\t0\tParent=t1\n\
+             chr2\t.\tCDS\t3\t5\t.\t-\t0\tID=c2;Parent=t2\n\
+             chr2\t.\tCDS\t6\t9\t.\t-\t1\tID=c2;Parent=t2\n",
+        )
+        .unwrap();
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            let mut settings = settings(cache);
+            settings.annotation = Some(gff.to_str().unwrap().to_owned());
+            let mut fusta = FustaFS::new(settings, filename.to_str().unwrap()).unwrap();
+            for (name, expected) in [
+                ("t1.faa", ">t1 chr1:3-14:+\nMK*\n"),
+                ("t2.faa", ">t2 chr2:3-9:-\nMW\n"),
+            ] {
+                let i = fusta.protein_names[name];
+                let ino = fusta.proteins[i].attrs.ino;
+                assert_eq!(
+                    String::from_utf8(fusta.read_file(ino, 0, MAX_IO_SIZE).unwrap()).unwrap(),
+                    expected,
+                    "{:?}",
+                    cache
+                );
+                assert_eq!(
+                    fusta.attrs_from_ino(ino).unwrap().size,
+                    expected.len() as u64
+                );
+            }
+        }
+
+        let mut settings = settings(Cache::Mmap);
+        settings.annotation = Some(gff.to_str().unwrap().to_owned());
+        settings.genetic_code = GeneticCode::new(2).unwrap();
+        let mut fusta = FustaFS::new(settings, filename.to_str().unwrap()).unwrap();
+        let ino = fusta.proteins[fusta.protein_names["t1.faa"]].attrs.ino;
+        assert_eq!(
+            fusta.read_file(ino, 0, MAX_IO_SIZE).unwrap(),
+            b">t1 chr1:3-14:+\nMKW\n"
+        );
+    }
+
+    #[test]
+    fn flanks() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("test.fa");
+        let gff = dir.path().join("test.gff");
+        fs::write(
+            &gff,
+            "chr1\t.\tgene\t5\t8\t.\t+\t.\tID=g1\n\
+             chr1\t.\tgene\t5\t8\t.\t-\t.\tID=g2\n\
+             chr1\t.\tmRNA\t5\t8\t.\t-\t.\tID=t2;Parent=g2\n",
+        )
+        .unwrap();
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            fs::write(&filename, ">chr1\nAACCGGTTAC\nGTAC\n").unwrap();
+            let mut settings = settings(cache);
+            settings.annotation = Some(gff.to_str().unwrap().to_owned());
+            let mut fusta = FustaFS::new(settings, filename.to_str().unwrap()).unwrap();
+            assert_eq!(fusta.gene_names.len(), 2);
+            let upstream = fusta.flank_dir(Flank::parse("upstream3").unwrap());
+            let downstream = fusta.flank_dir(Flank::parse("downstream100").unwrap());
+            assert_eq!(fusta.flank_dir(Flank::Upstream(3)), upstream);
+            let read = |fusta: &mut FustaFS, dir: usize, gene: &str| {
+                let attrs = fusta.flank_dirs[dir].files[fusta.gene_names[gene]];
+                let data = fusta.read_file(attrs.ino, 0, MAX_IO_SIZE).unwrap();
+                assert_eq!(attrs.size, data.len() as u64, "{:?}", cache);
+                String::from_utf8(data).unwrap()
+            };
+            assert_eq!(
+                read(&mut fusta, upstream, "g1.fa"),
+                ">g1_upstream3 chr1:2-4:+\nACC\n"
+            );
+            assert_eq!(
+                read(&mut fusta, upstream, "g2.fa"),
+                ">g2_upstream3 chr1:9-11:-\nCGT\n"
+            );
+            assert_eq!(
+                read(&mut fusta, downstream, "g1.fa"),
+                ">g1_downstream100 chr1:9-14:+\nACGTAC\n"
+            );
+            assert_eq!(
+                read(&mut fusta, downstream, "g2.fa"),
+                ">g2_downstream100 chr1:1-4:-\nGGTT\n"
+            );
+
+            // The flanks are clipped to the current sequence
+            let ino = fusta.fragment_from_id("chr1").unwrap().seq_file.ino;
+            fusta.resize_fragment(ino, 8).unwrap();
+            fusta.refresh_metadata(false);
+            assert_eq!(read(&mut fusta, downstream, "g1.fa"), ">g1_downstream100\n");
+        }
+    }
+
+    #[test]
+    fn codon_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("test.fa");
+        fs::write(
+            &filename,
+            ">chr1\nCCATGAAATT\nTTGAGG\n>chr2\nTTCCACATCTTATGAAG\n",
+        )
+        .unwrap();
+        let gff = dir.path().join("test.gff");
+        fs::write(
+            &gff,
+            "chr1\t.\tCDS\t3\t8\t.\t+\t0\tParent=t1\n\
+             chr1\t.\tCDS\t12\t14\t.\t+\t0\tParent=t1\n\
+             chr2\t.\tCDS\t3\t5\t.\t-\t0\tParent=t2\n\
+             chr2\t.\tCDS\t6\t9\t.\t-\t1\tParent=t2\n\
+             chr2\t.\tCDS\t12\t17\t.\t+\t0\tParent=t3\n\
+             chr3\t.\tCDS\t1\t3\t.\t+\t0\tParent=t4\n",
+        )
+        .unwrap();
+        let mut settings = settings(Cache::Mmap);
+        settings.annotation = Some(gff.to_str().unwrap().to_owned());
+        let mut fusta = FustaFS::new(settings, filename.to_str().unwrap()).unwrap();
+        let content = loop {
+            match fusta.read_file(CODON_USAGE_FILE, 0, MAX_IO_SIZE) {
+                Ok(data) => break String::from_utf8(data).unwrap(),
+                Err(e) => assert_eq!(e.errno(), EAGAIN),
             }
+        };
+        let lines = content.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 65);
+        assert_eq!(lines[0], "codon,amino_acid,count,per_thousand,fraction");
+        for expected in [
+            "ATG,M,3,428.57,1.0000",
+            "AAA,K,1,142.86,0.5000",
+            "AAG,K,1,142.86,0.5000",
+            "TGA,*,1,142.86,1.0000",
+            "TGG,W,1,142.86,1.0000",
+            "TTT,F,0,0.00,0.0000",
+        ] {
+            assert!(lines.contains(&expected), "{}", expected);
         }
+        assert_eq!(
+            fusta.attrs_from_ino(CODON_USAGE_FILE).unwrap().size,
+            content.len() as u64
+        );
     }
 
-    fn read(
-        &mut self,
-        _req: &Request,
-        ino: u64,
-        _fh: u64,
-        offset: i64,
-        size: u32,
-        _flags: i32,
-        _lock_owner: Option<u64>,
-        reply: ReplyData,
-    ) {
-        debug!("READING {}", ino);
-        match ino {
-            INFO_FILE => {
-                let data = self.get_file(INFO_FILE).unwrap().data();
-                let start = offset as usize;
-                let end = std::cmp::min(start + size as usize, data.len());
-                reply.data(&data[start..end]);
-            }
-            INFO_CSV_FILE => {
-                let data = self.get_file(INFO_CSV_FILE).unwrap().data();
-                let start = offset as usize;
-                let end = std::cmp::min(start + size as usize, data.len());
-                reply.data(&data[start..end]);
-            }
-            LABELS_FILE => {
-                let data = self.get_file(LABELS_FILE).unwrap().data();
-                let start = offset as usize;
-                let end = std::cmp::min(start + size as usize, data.len());
-                reply.data(&data[start..end]);
-            }
-            ino if self.ino2fragment.contains_key(&ino) => {
-                let fragment = match self.mut_fragment_from_ino(ino) {
-                    Some(f) => f,
-                    _ => {
-                        error!("No fragment linked to ino {}", ino);
-                        return;
-                    }
-                };
-                match fragment
-                    .file_from_ino(ino)
-                    .expect("No file linked to this fragment")
-                    .class()
-                {
-                    FileClass::Fasta(_) => {
-                        let label_size = fragment.label_size() as i64;
-                        if offset > label_size as i64 {
-                            reply.data(&fragment.chunk(offset - label_size, size))
-                        } else {
-                            let end = offset as usize + size as usize;
-                            let label = fragment.label();
-                            let data_chunk =
-                                fragment.chunk(0, std::cmp::min(fragment.data_size() as u32, size));
-                            match fragment
-                                .mut_file_from_ino(ino)
-                                .expect("No file linked to this fragment")
-                                .class()
-                            {
-                                FileClass::Fasta(header_buffer) => {
-                                    if end > header_buffer.borrow().len() {
-                                        header_buffer.replace(
-                                            label
-                                                .as_bytes()
-                                                .iter()
-                                                .chain(data_chunk.iter())
-                                                .cloned()
-                                                .take(end)
-                                                .collect::<Vec<_>>(),
-                                        );
-                                    }
-                                    reply.data(
-                                        &header_buffer.borrow()[offset as usize
-                                            ..std::cmp::min(end, header_buffer.borrow().len())],
-                                    );
-                                }
-                                _ => panic!("WTF"),
-                            }
-                        }
+    #[test]
+    fn digests() {
+        let read = |fusta: &mut FustaFS, name: &str| {
+            let i = fusta.scan_file(DIGEST_DIR, name).unwrap();
+            let ino = fusta.scans[i].attrs.ino;
+            loop {
+                match fusta.read_file(ino, 0, MAX_IO_SIZE) {
+                    Ok(data) => {
+                        assert_eq!(fusta.attrs_from_ino(ino).unwrap().size, data.len() as u64);
+                        break String::from_utf8(data).unwrap();
                     }
-                    FileClass::Seq => reply.data(&fragment.chunk(offset, size)),
-                    FileClass::Text => unimplemented!(), // A fragment can never refer to a text file
+                    Err(e) => assert_eq!(e.errno(), EAGAIN),
                 }
             }
-            ino if self.subfragment_from_ino(ino).is_some() => {
-                let subfragment = self.subfragment_from_ino(ino).unwrap();
-                match self.fragment_from_id(&subfragment.fragment) {
-                    Some(fragment) => {
-                        reply.data(&fragment.pure_chunk(offset + subfragment.start as i64, size))
-                    }
-                    _ => {
-                        error!("No fragment linked to ino {}", ino);
-                        reply.error(ENOENT);
-                    }
-                };
-            }
-            _ => {
-                warn!("READ: {} is not a file", ino);
-                reply.error(ENOENT);
-            }
+        };
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            let dir = tempfile::tempdir().unwrap();
+            let filename = dir.path().join("test.fa");
+            fs::write(&filename, ">a\nAAGAAT\nTCAAGAATTC\n>b\nGATGCCATCAA\n").unwrap();
+            let mut fusta = FustaFS::new(settings(cache), filename.to_str().unwrap()).unwrap();
+            assert_eq!(
+                read(&mut fusta, "EcoRI.bed"),
+                "a\t2\t8\tEcoRI\t0\t+\na\t10\t16\tEcoRI\t0\t+\n",
+                "{:?}",
+                cache
+            );
+            assert_eq!(read(&mut fusta, "EcoRI.sizes"), "a\t3\na\t8\na\t5\nb\t11\n");
+            assert_eq!(
+                read(&mut fusta, "GA^TG.bed"),
+                "b\t0\t4\tGA^TG\t0\t+\nb\t5\t9\tGA^TG\t0\t-\n"
+            );
+            assert_eq!(read(&mut fusta, "GA^TG.sizes"), "a\t16\nb\t2\nb\t5\nb\t4\n");
+            assert!(fusta.scan_file(DIGEST_DIR, "EcoRI.txt").is_err());
+            assert!(fusta.scan_file(DIGEST_DIR, "EcoRJ.bed").is_err());
+
+            let ino = fusta.fragment_from_id("a").unwrap().seq_file.ino;
+            fusta.resize_fragment(ino, 3).unwrap();
+            fusta.refresh_metadata(false);
+            assert_eq!(read(&mut fusta, "EcoRI.sizes"), "a\t3\nb\t11\n");
         }
     }
 
-    fn readdir(
-        &mut self,
-        _req: &Request,
-        ino: u64,
-        _fh: u64,
-        offset: i64,
-        mut reply: ReplyDirectory,
-    ) {
-        match ino {
-            ROOT_DIR => {
-                let entries = btreemap! {
-                    ROOT_DIR         => (FileType::Directory, "."),
-                    0                => (FileType::Directory, ".."), // TODO
-                    FASTA_DIR        => (FileType::Directory, "fasta"),
-                    SEQ_DIR          => (FileType::Directory, "seqs"),
-                    APPEND_DIR       => (FileType::Directory, "append"),
-                    SUBFRAGMENTS_DIR => (FileType::Directory, "get"),
-                    INFO_FILE        => (FileType::RegularFile, INFO_FILE_NAME),
-                    INFO_CSV_FILE    => (FileType::RegularFile, INFO_CSV_FILE_NAME),
-                    LABELS_FILE      => (FileType::RegularFile, LABELS_FILE_NAME),
-                };
-                for (o, (ino, entry)) in entries.iter().enumerate().skip(offset as usize) {
-                    let _ = reply.add(*ino, o as i64 + 1, entry.0, entry.1);
-                }
-                reply.ok();
-            }
-            FASTA_DIR | SEQ_DIR => {
-                for (i, file) in vec![
-                    (ino, FileType::Directory, &".".into()),
-                    (ROOT_DIR, FileType::Directory, &"..".into()),
-                ]
-                .into_iter()
-                .chain(self.fragments.iter().map(|f| {
-                    let (ino, name) = if ino == SEQ_DIR {
-                        (f.seq_file.ino, &f.seq_file.name)
-                    } else {
-                        (f.fasta_file.ino, &f.fasta_file.name)
-                    };
-                    (ino, FileType::RegularFile, name)
-                }))
-                .enumerate()
-                .skip(offset.try_into().unwrap())
-                {
-                    if reply.add(file.0, (i + 1).try_into().unwrap(), file.1, file.2.as_str()) {
-                        break;
-                    }
-                }
-                reply.ok();
-            }
-            APPEND_DIR => {
-                let entries = btreemap! {
-                    APPEND_DIR => (FileType::Directory, ".".to_owned()),
-                    ROOT_DIR   => (FileType::Directory, "..".to_owned()),
-                };
-                for (o, (ino, entry)) in entries.iter().enumerate().skip(offset as usize) {
-                    let _ = reply.add(*ino, o as i64 + 1, entry.0, &entry.1);
+    #[test]
+    fn pcr() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("test.fa");
+        fs::write(&filename, ">a\nTTACGATTTTTT\nGGCCTTACGGTTGGNN\n>b\nACGA\n").unwrap();
+        let mut settings = settings(Cache::Mmap);
+        settings.max_amplicon = 12;
+        let mut fusta = FustaFS::new(settings, filename.to_str().unwrap()).unwrap();
+        let mut read = |name: &str| {
+            let i = fusta.scan_file(PCR_DIR, name).unwrap();
+            let ino = fusta.scans[i].attrs.ino;
+            loop {
+                match fusta.read_file(ino, 0, MAX_IO_SIZE) {
+                    Ok(data) => break String::from_utf8(data).unwrap(),
+                    Err(e) => assert_eq!(e.errno(), EAGAIN),
                 }
-                reply.ok();
             }
-            SUBFRAGMENTS_DIR => {
-                reply.ok();
-            }
-            _ => {
-                warn!("{} is not a directory", ino);
-                reply.error(ENOENT);
+        };
+        assert_eq!(
+            read("ACGR__CCAA"),
+            ">a:3-14:+ 12bp\nACGATTTTTTGG\n>a:19-26:+ 8bp\nACGGTTGG\n"
+        );
+        assert_eq!(
+            read("CCAA__ACGR"),
+            ">a:3-14:- 12bp\nCCAAAAAATCGT\n>a:19-26:- 8bp\nCCAACCGT\n"
+        );
+        assert_eq!(read("ACGT__TTTT"), "");
+        assert!(fusta.scan_file(PCR_DIR, "ACGT").is_err());
+        assert!(fusta.scan_file(DIGEST_DIR, "ACGR__CCAA").is_err());
+    }
+
+    #[test]
+    fn repeats() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("test.fa");
+        fs::write(
+            &filename,
+            ">tel\nCCCTAACCCTAACCCTAA\nCCCTAAACGT\n>ssr\nACGTAAAAAAAAAAAATGCA\n>empty\n",
+        )
+        .unwrap();
+        let mut fusta = FustaFS::new(settings(Cache::Mmap), filename.to_str().unwrap()).unwrap();
+        let content = loop {
+            match fusta.read_file(REPEATS_FILE, 0, MAX_IO_SIZE) {
+                Ok(data) => break String::from_utf8(data).unwrap(),
+                Err(e) => assert_eq!(e.errno(), EAGAIN),
             }
+        };
+        assert_eq!(
+            content,
+            "id\tlength\thead\ttail\tsimple_repeats\n\
+             tel\t28\tCCCTAA:0.86\tCCCTAA:0.86\t0.8571\n\
+             ssr\t20\t-\t-\t0.6000\n\
+             empty\t0\t-\t-\t0.0000\n"
+        );
+        assert_eq!(
+            fusta.attrs_from_ino(REPEATS_FILE).unwrap().size,
+            content.len() as u64
+        );
+    }
+
+    #[test]
+    fn ends() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("test.fa");
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            fs::write(&filename, ">a\nAACCGGTTAC\nGTAC\n>b\nTTT\n>c\n").unwrap();
+            let mut settings = settings(cache);
+            settings.end_length = 6;
+            let mut fusta = FustaFS::new(settings, filename.to_str().unwrap()).unwrap();
+            let listing = fusta.listing(ENDS_DIR);
+            assert_eq!(
+                listing.iter().map(|e| e.2.as_str()).collect::<Vec<_>>(),
+                [
+                    ".",
+                    "..",
+                    "a.head.fa",
+                    "a.tail.fa",
+                    "b.head.fa",
+                    "b.tail.fa",
+                    "c.head.fa",
+                    "c.tail.fa"
+                ]
+            );
+            let inos = listing.iter().skip(2).map(|e| e.0).collect::<Vec<_>>();
+            let read = |fusta: &mut FustaFS, ino: u64| {
+                let data = fusta.read_file(ino, 0, MAX_IO_SIZE).unwrap();
+                assert_eq!(
+                    fusta.attrs_from_ino(ino).unwrap().size,
+                    data.len() as u64,
+                    "{:?}",
+                    cache
+                );
+                String::from_utf8(data).unwrap()
+            };
+            assert_eq!(read(&mut fusta, inos[0]), ">a_head a:1-6\nAACCGG\n");
+            assert_eq!(read(&mut fusta, inos[1]), ">a_tail a:9-14\nACGTAC\n");
+            assert_eq!(read(&mut fusta, inos[2]), ">b_head b:1-3\nTTT\n");
+            assert_eq!(read(&mut fusta, inos[3]), ">b_tail b:1-3\nTTT\n");
+            assert_eq!(read(&mut fusta, inos[4]), ">c_head\n");
+
+            // The ends follow the current sequence
+            let ino = fusta.fragment_from_id("a").unwrap().seq_file.ino;
+            fusta.resize_fragment(ino, 3).unwrap();
+            fusta.refresh_metadata(false);
+            assert_eq!(read(&mut fusta, inos[1]), ">a_tail a:1-3\nAAC\n");
         }
     }
 
-    fn unlink(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
-        match parent {
-            ROOT_DIR => {
-                warn!("UNLINK: cannot remove `{:?}`", name);
+    #[test]
+    fn tags() {
+        let (_dir, filename, mut fusta) = mount(2, Cache::Mmap);
+        let listing = fusta.listing(TAGS_DIR);
+        assert_eq!(listing[2].2.as_str(), "orig_0.tags");
+        let ino = listing[2].0;
+        let read = |fusta: &mut FustaFS| {
+            let data = fusta.read_file(ino, 0, MAX_IO_SIZE).unwrap();
+            assert_eq!(fusta.attrs_from_ino(ino).unwrap().size, data.len() as u64);
+            String::from_utf8(data).unwrap()
+        };
+        assert_eq!(read(&mut fusta), "");
+
+        fusta.write_file(ino, 0, b"status=suspect\n").unwrap();
+        fusta.commit_tags(ino).unwrap();
+        // Writes past the end append to the current tags
+        fusta
+            .write_file(ino, 15, b"# reviewed\n reviewer = me \n")
+            .unwrap();
+        fusta.commit_tags(ino).unwrap();
+        assert_eq!(read(&mut fusta), "reviewer=me\nstatus=suspect\n");
+        fusta.write_file(ino, 0, b"status keep\n").unwrap();
+        assert!(fusta.commit_tags(ino).is_err());
+        assert_eq!(read(&mut fusta), "reviewer=me\nstatus=suspect\n");
+        let infos: serde_json::Value =
+            serde_json::from_slice(fusta.get_file(INFO_JSON_FILE).unwrap().data()).unwrap();
+        assert_eq!(infos[0]["tags"]["status"], "suspect");
+        assert_eq!(infos[1]["tags"], serde_json::json!({}));
+
+        // The tags follow their fragment, and are kept across mounts
+        fusta
+            .apply(Mutation::Rename {
+                from: "orig_0".into(),
+                to: "renamed".into(),
+            })
+            .unwrap();
+        drop(fusta);
+        let fusta = FustaFS::new(settings(Cache::Mmap), &filename).unwrap();
+        let fragment = fusta.fragment_from_id("renamed").unwrap();
+        assert_eq!(fragment.tags["reviewer"], "me");
+        assert!(fusta.fragment_from_id("orig_1").unwrap().tags.is_empty());
+    }
+
+    #[test]
+    fn write_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 3);
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            let mut fusta = FustaFS::new(settings(cache), &filename).unwrap();
+            let listing = fusta.listing(TAGS_DIR);
+            for (ino, tags) in listing.iter().skip(2).zip(["keep=true", "", "keep=true"]) {
+                // As truncated by `>`
+                fusta.pending_tags(ino.0).unwrap().clear();
+                fusta.write_file(ino.0, 0, tags.as_bytes()).unwrap();
+                fusta.commit_tags(ino.0).unwrap();
             }
-            SEQ_DIR | FASTA_DIR => {
-                let name = name.to_str().unwrap();
-                let file = (if parent == SEQ_DIR {
-                    self.fragment_from_seq_filename(name)
-                } else {
-                    self.fragment_from_fasta_filename(name)
+            drop(fusta);
+
+            let mut settings = settings(cache);
+            settings.write_filter = Some(TagFilter::parse("keep=true").unwrap());
+            let mut fusta = FustaFS::new(settings, &filename).unwrap();
+            assert!(fusta.dirty);
+            fusta.concretize(true).unwrap();
+            assert_eq!(
+                read_back(&filename),
+                [
+                    ("orig_0".to_string(), b"ACGT".to_vec()),
+                    ("orig_2".to_string(), b"ACGTTT".to_vec())
+                ]
+            );
+            // The filtered out fragments are still there, and are written
+            // back as soon as they pass the filter
+            let ino = fusta.fragment_from_id("orig_1").unwrap().seq_file.ino;
+            assert!(fusta.read_file(ino, 0, 10).unwrap().starts_with(b"ACGTT"));
+            let tags = fusta.listing(TAGS_DIR)[3].0;
+            fusta.write_file(tags, 0, b"keep=true").unwrap();
+            fusta.commit_tags(tags).unwrap();
+            assert!(fusta.dirty);
+            fusta.concretize(true).unwrap();
+            assert_eq!(read_back(&filename).len(), 3);
+        }
+    }
+
+    #[test]
+    fn output() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 2);
+        let output = dir.path().join("out.fa").to_str().unwrap().to_owned();
+        let original = fs::read(&filename).unwrap();
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            let _ = fs::remove_file(&output);
+            let mut settings = settings(cache);
+            settings.output = Some(output.clone());
+            let mut fusta = FustaFS::new(settings, &filename).unwrap();
+            // The output is written even without any change
+            assert!(fusta.dirty);
+            fusta.concretize(true).unwrap();
+            assert_eq!(fs::read(&output).unwrap(), original);
+
+            fusta
+                .apply(Mutation::Rename {
+                    from: "orig_0".into(),
+                    to: "renamed".into(),
                 })
-                .and_then(|f| f.file_from_filename(name));
+                .unwrap();
+            let ino = fusta.fragment_from_id("orig_1").unwrap().seq_file.ino;
+            fusta.resize_fragment(ino, 2).unwrap();
+            fusta.refresh_metadata(false);
+            fusta.concretize(true).unwrap();
+            assert_eq!(
+                read_back(&output),
+                [
+                    ("renamed".to_string(), b"ACGT".to_vec()),
+                    ("orig_1".to_string(), b"AC".to_vec())
+                ]
+            );
+            drop(fusta);
+            assert_eq!(fs::read(&filename).unwrap(), original);
+        }
+    }
 
-                if file.is_some() {
-                    let length_before = self.fragments.len();
-                    self.fragments
-                        .retain(|f| f.fasta_file.name != name && f.seq_file.name != name);
-                    let length_after = self.fragments.len();
-                    // Only mark as dirty if we effectively removed something
-                    if length_after != length_before {
-                        self.dirty = true;
-                    }
-                    self.refresh_metadata(false);
-                    self.concretize(false);
-                    reply.ok();
-                } else {
-                    warn!("UNLINK: unknown file: `{:?}`", name);
-                    reply.error(ENOENT);
-                }
-            }
-            APPEND_DIR => {
-                warn!("UNLINK: unauthorized in append virtual dir");
-                reply.error(EACCES);
-            }
-            SUBFRAGMENTS_DIR => {
-                reply.error(ENOENT);
-            }
-            _ => {
-                warn!("UNLINK: parent {} does not exist", parent);
-                reply.error(ENOENT);
-            }
+    #[test]
+    fn merge() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 2);
+        let original = fs::read(&filename).unwrap();
+        let merged = dir.path().join("b.fa").to_str().unwrap().to_owned();
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            fs::write(&merged, ">orig_0 dup\nGG\n>extra\nTT\n").unwrap();
+            let merging = |policy| {
+                let mut settings = settings(cache);
+                settings.merge = vec![merged.clone()];
+                settings.on_merge_collision = policy;
+                FustaFS::new(settings, &filename)
+            };
+            assert!(merging(MergeCollision::Refuse).is_err());
+            let mut fusta = merging(MergeCollision::Prefix).unwrap();
+            assert_eq!(
+                fusta.fragments.iter().map(|f| &f.id).collect::<Vec<_>>(),
+                ["orig_0", "orig_1", "b__orig_0", "extra"]
+            );
+            assert_eq!(fusta.reference_size, (original.len() + 25) as u64);
+
+            fusta
+                .apply(Mutation::Rename {
+                    from: "extra".into(),
+                    to: "renamed".into(),
+                })
+                .unwrap();
+            append(&mut fusta, "new", "CC");
+            fusta.refresh_metadata(false);
+            fusta.concretize(true).unwrap();
+            // The prefixed fragment keeps its original header
+            assert_eq!(
+                fs::read_to_string(&merged).unwrap(),
+                ">orig_0 dup\nGG\n>renamed\nTT\n"
+            );
+            assert_eq!(
+                read_back(&filename),
+                [
+                    ("orig_0".to_string(), b"ACGT".to_vec()),
+                    ("orig_1".to_string(), b"ACGTT".to_vec()),
+                    ("new".to_string(), b"CC".to_vec())
+                ]
+            );
+            // The fragments are read from where they have been written
+            let fragment = fusta.fragment_from_id("renamed").unwrap();
+            assert!(
+                fusta.snapshot_sources.len() == 2 && fragment.data().unwrap().starts_with(b"TT")
+            );
+            drop(fusta);
+            fs::write(&filename, &original).unwrap();
         }
     }
 
-    fn mknod(
-        &mut self,
-        _req: &Request,
-        parent: u64,
-        name: &OsStr,
-        _mode: u32,
-        _umask: u32,
-        _rdev: u32,
-        reply: ReplyEntry,
-    ) {
-        match parent {
-            ROOT_DIR | SEQ_DIR | FASTA_DIR | SUBFRAGMENTS_DIR => {
-                warn!("MKNOD: writing in {} is forbidden", parent);
-                reply.error(EACCES);
-            }
-            APPEND_DIR => {
-                let name = name.to_str().unwrap();
-                let basename = std::path::Path::new(name)
-                    .file_stem()
+    #[test]
+    fn aliases() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 3);
+        let mut settings = settings(Cache::RAM);
+        settings.aliases = Some(Aliases::parse("orig_0\t1\tNC_1\norig_1\torig_2\n").unwrap());
+        let mut fusta = FustaFS::new(settings, &filename).unwrap();
+        let names = |fusta: &mut FustaFS| {
+            fusta
+                .listing(ALIASES_DIR)
+                .iter()
+                .skip(2)
+                .map(|e| e.2.to_string())
+                .collect::<Vec<_>>()
+        };
+        // orig_2 is mounted under its own name
+        assert_eq!(names(&mut fusta), ["1.fa", "NC_1.fa"]);
+        assert_eq!(fusta.fragment_from_name("NC_1").unwrap().id, "orig_0");
+        assert_eq!(fusta.fragment_from_name("orig_2").unwrap().id, "orig_2");
+        assert!(fusta.fragment_from_name("2").is_none());
+        let seq_ino = fusta.fragment_from_id("orig_0").unwrap().seq_file.ino;
+        assert_eq!(fusta.aliased_file(SEQ_DIR, "1.seq").unwrap().ino, seq_ino);
+        assert_eq!(
+            fusta.parse_region("NC_1:2-3").unwrap(),
+            ("orig_0".into(), 1, 3, false)
+        );
+
+        fusta
+            .apply(Mutation::Rename {
+                from: "orig_0".into(),
+                to: "1".into(),
+            })
+            .unwrap();
+        fusta.refresh_metadata(false);
+        assert_eq!(names(&mut fusta), ["orig_0.fa", "NC_1.fa"]);
+    }
+
+    #[test]
+    fn spill() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 20);
+        let mut settings = settings(Cache::RAM);
+        settings.memory_budget = 16;
+        let fusta = FustaFS::new(settings, &filename).unwrap();
+        assert!(fusta.spill.resident() <= 16);
+        for (i, fragment) in fusta.fragments.iter().enumerate().rev() {
+            assert_eq!(
+                &*fragment.data().unwrap(),
+                format!("ACGT{}", "T".repeat(i)).as_bytes()
+            );
+        }
+        assert!(fusta.spill.resident() <= 16);
+    }
+
+    #[test]
+    fn timestamps() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 2);
+        for noatime in [false, true] {
+            let mut fusta = FustaFS::new(
+                FustaSettings {
+                    noatime,
+                    ..settings(Cache::Mmap)
+                },
+                &filename,
+            )
+            .unwrap();
+            let fragment = fusta.fragment_from_id("orig_1").unwrap();
+            let (fasta_ino, seq_ino) = (fragment.fasta_file.ino, fragment.seq_file.ino);
+            let attrs = |fusta: &FustaFS, ino| *fusta.attrs_from_ino(ino).unwrap();
+            let past = SystemTime::now() - Duration::from_secs(3 * 24 * 3600);
+            for ino in [fasta_ino, seq_ino] {
+                let file = fusta
+                    .mut_fragment_from_ino(ino)
                     .unwrap()
-                    .to_str()
+                    .mut_file_from_ino(ino)
                     .unwrap();
-                // From man: if pathname already exists [...], this call fails with an EEXIST error.
-                if self.fragments.iter().any(|f| f.id == basename) && self.settings.no_overwrite {
-                    notify(format!("Cannot create `{:?}`, already exists", name));
-                    error!("Cannot create `{:?}`, already exists", name);
-                    reply.error(EEXIST);
-                    return;
-                }
+                file.mut_attrs().atime = past + Duration::from_secs(1);
+                file.mut_attrs().mtime = past;
+                file.mut_attrs().ctime = past;
+            }
 
-                trace!("Creating pending append {}", name);
-                let attrs = FileAttr {
-                    ino: self.new_ino(),
-                    size: 0,
-                    blocks: 0,
-                    atime: std::time::SystemTime::now(),
-                    mtime: std::time::SystemTime::now(),
-                    ctime: std::time::SystemTime::now(),
-                    crtime: std::time::SystemTime::now(),
-                    kind: FileType::RegularFile,
-                    perm: 0o775,
-                    nlink: 0,
-                    uid: unsafe { libc::geteuid() },
-                    gid: unsafe { libc::getgid() },
-                    rdev: 0,
-                    flags: 0,
-                    blksize: 512,
-                };
-                let pending = PendingAppend {
-                    data: Vec::new(),
-                    attrs,
-                    seq_ino: self.new_ino(),
-                    fasta_ino: self.new_ino(),
-                };
-                reply.entry(&TTL, &pending.attrs, 0);
-                self.pending_appends.insert(basename.to_string(), pending);
+            // Reads only update the access times older than a day...
+            fusta.touch_accessed(seq_ino);
+            let read = attrs(&fusta, seq_ino).atime;
+            assert_eq!(read > past + Duration::from_secs(1), !noatime);
+            fusta.touch_accessed(seq_ino);
+            assert_eq!(attrs(&fusta, seq_ino).atime, read);
+
+            // ...or than the last modification, which writes update
+            let before = SystemTime::now();
+            fusta.write_file(seq_ino, 0, b"GG").unwrap();
+            for ino in [fasta_ino, seq_ino] {
+                assert!(attrs(&fusta, ino).mtime >= before);
+                assert!(attrs(&fusta, ino).ctime >= before);
             }
-            _ => {
-                warn!("MKNOD: parent {} does not exist", parent);
-                reply.error(ENOENT);
+            assert_eq!(
+                attrs(&fusta, fasta_ino).atime,
+                past + Duration::from_secs(1)
+            );
+            fusta.touch_accessed(seq_ino);
+            assert_eq!(attrs(&fusta, seq_ino).atime > read, !noatime);
+        }
+    }
+
+    #[test]
+    fn spill_edits() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 3);
+        let mut settings = settings(Cache::Mmap);
+        settings.concretize_threshold = 16;
+        let mut fusta = FustaFS::new(settings, &filename).unwrap();
+        let in_memory = |fusta: &FustaFS| {
+            fusta
+                .fragments
+                .iter()
+                .map(|f| f.data.in_memory())
+                .sum::<usize>()
+        };
+
+        // Edits within the budget are kept in memory...
+        let ino = fusta.fragment_from_name("orig_0").unwrap().seq_file.ino;
+        fusta.write_fragment(ino, 0, b"GG").unwrap();
+        fusta.concretize(false).unwrap();
+        assert_eq!(in_memory(&fusta), 5);
+
+        // ...and set aside beyond, without being written back
+        let ino = fusta.fragment_from_name("orig_2").unwrap().seq_file.ino;
+        let seq = "CA".repeat(1000) + "\n";
+        fusta.resize_fragment(ino, 0).unwrap();
+        fusta.write_fragment(ino, 0, seq.as_bytes()).unwrap();
+        fusta.concretize(false).unwrap();
+        assert_eq!(in_memory(&fusta), 0);
+        assert!(fusta.edit_spill.size() > 0);
+        assert!(fusta.is_dirty());
+        assert_eq!(read_back(&filename)[2].1, b"ACGTTT");
+        let fragment = fusta.fragment_from_name("orig_2").unwrap();
+        assert_eq!(&*fragment.data().unwrap(), seq.as_bytes());
+        assert_eq!(fragment.sequence_len().unwrap(), 2000);
+        let stats = fusta.load_stats().unwrap();
+        assert!(stats.contains("\norig_2,2000,0.5000,"), "{}", stats);
+
+        // Spilled sequences can still be edited
+        fusta.write_fragment(ino, 1, b"G").unwrap();
+        fusta.concretize(true).unwrap();
+        assert_eq!(
+            read_back(&filename),
+            vec![
+                ("orig_0".to_owned(), b"GGGT".to_vec()),
+                ("orig_1".to_owned(), b"ACGTT".to_vec()),
+                (
+                    "orig_2".to_owned(),
+                    format!("CG{}", "CA".repeat(999)).into_bytes()
+                ),
+            ]
+        );
+        assert_eq!(fusta.edit_spill.size(), 0);
+    }
+
+    #[test]
+    fn views() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("test.fa");
+        let seq = "ACGTTGCAac".repeat(13);
+        fs::write(
+            &filename,
+            format!(">a desc\n{}\n{}\n>b\n", &seq[..70], &seq[70..]),
+        )
+        .unwrap();
+        let filename = filename.to_str().unwrap();
+
+        let mut clashing = settings(Cache::File);
+        clashing.views = vec![("seqs".to_owned(), Pipeline::parse("revcomp").unwrap())];
+        assert!(FustaFS::new(clashing, filename).is_err());
+
+        let mut settings = settings(Cache::File);
+        settings.views = vec![
+            (
+                "rc".to_owned(),
+                Pipeline::parse("revcomp,uppercase").unwrap(),
+            ),
+            (
+                "flat".to_owned(),
+                Pipeline::parse("mask,strip-newlines").unwrap(),
+            ),
+        ];
+        let mut fusta = FustaFS::new(settings, filename).unwrap();
+        let mut rc = seq.to_uppercase().into_bytes();
+        reverse_complement(&mut rc);
+        let mut expected = b">a desc\n".to_vec();
+        for line in rc.chunks(LINE_WIDTH) {
+            expected.extend_from_slice(line);
+            expected.push(b'\n');
+        }
+        let masked = seq.replace("ac", "NN");
+        let fasta_ino = fusta.fragment_from_id("a").unwrap().fasta_file.ino;
+        for (view, expected) in [
+            (0, expected),
+            (1, format!(">a desc\n{}\n", masked).into_bytes()),
+        ] {
+            let ino = fusta.view_ino(view, fasta_ino);
+            assert_eq!(fusta.view_files[&ino].attrs.size, expected.len() as u64);
+            for offset in 0..expected.len() {
+                for size in [1, 7, 61, 200] {
+                    let range = window(offset as i64, size, expected.len());
+                    assert_eq!(
+                        fusta.read_view(ino, offset as i64, size).unwrap(),
+                        &expected[range]
+                    );
+                }
             }
         }
+        let empty = fusta.fragment_from_id("b").unwrap().fasta_file.ino;
+        let ino = fusta.view_ino(1, empty);
+        assert_eq!(fusta.read_view(ino, 0, 100).unwrap(), b">b\n");
+
+        let names = fusta
+            .listing(fusta.view_dirs[0].ino)
+            .iter()
+            .map(|e| e.2.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, [".", "..", "a.fa", "b.fa"]);
+    }
+
+    #[test]
+    fn write_transform() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 1);
+        let mut fusta = FustaFS::new(
+            FustaSettings {
+                writable_fasta: true,
+                write_transform: Pipeline::parse("strip-whitespace,uppercase,dna").unwrap(),
+                ..settings(Cache::Mmap)
+            },
+            &filename,
+        )
+        .unwrap();
+        let seq = |fusta: &FustaFS, id: &str| {
+            fusta.fragment_from_id(id).unwrap().data().unwrap().to_vec()
+        };
+        append(&mut fusta, "pasted", "ac gu\n ua\t");
+        assert_eq!(seq(&fusta, "pasted"), b"ACGTTA");
+
+        let ino = fusta.fragment_from_id("orig_0").unwrap().seq_file.ino;
+        fusta.write_file(ino, 4, b" u u\n").unwrap();
+        assert_eq!(seq(&fusta, "orig_0"), b"ACGT u u\n");
+        fusta.normalize_edit(ino).unwrap();
+        assert_eq!(seq(&fusta, "orig_0"), b"ACGTTT");
+
+        let ino = fusta.fragment_from_id("orig_0").unwrap().fasta_file.ino;
+        fusta.resize_fasta(ino, 0).unwrap();
+        fusta.write_file(ino, 0, b">orig_0\nac\ngu\n").unwrap();
+        fusta.commit_fasta(ino).unwrap();
+        assert_eq!(seq(&fusta, "orig_0"), b"ACGT");
+    }
+
+    #[test]
+    fn bookmarks() {
+        let (_dir, filename, mut fusta) = mount(1, Cache::RAM);
+        append(&mut fusta, "long", &"ACGT".repeat(500));
+        let attrs = fusta.bookmark("mygene", "long:1,001-1,200").unwrap();
+        assert_eq!(attrs.size, 200);
+        assert_eq!(fusta.parse_region("long:1,001-1,200").unwrap().1, 1000);
+        assert!(fusta.bookmark("orig_0", "long:1-2").is_err());
+        assert!(fusta.bookmark("a/b", "long:1-2").is_err());
+        assert!(fusta.bookmark("other", "long:1-3000").is_err());
+        assert!(fusta.bookmark("other", "long:1-2").is_ok());
+        drop(fusta);
+
+        // Bookmarks persist across mounts
+        let mut fusta = FustaFS::new(settings(Cache::RAM), &filename).unwrap();
+        let mygene = fusta.subfragments.get(&"mygene".to_owned()).unwrap();
+        assert!(mygene.listed);
+        assert_eq!((mygene.start, mygene.attrs.size), (1000, 200));
+        // ...until replaced or removed
+        assert_eq!(fusta.bookmark("mygene", "long:1-10").unwrap().size, 10);
+        fusta.drop_bookmark("other").unwrap();
+        assert!(fusta.drop_bookmark("other").is_err());
+        drop(fusta);
+        let fusta = FustaFS::new(settings(Cache::RAM), &filename).unwrap();
+        assert_eq!(
+            fusta.bookmarks.iter().collect::<Vec<_>>(),
+            [(&"mygene".to_owned(), &"long:1-10".to_owned())]
+        );
+    }
+
+    #[test]
+    fn windows() {
+        let (_dir, _, mut fusta) = mount(1, Cache::Mmap);
+        append(&mut fusta, "chr5", &"ACGT".repeat(2500));
+        let fusta = &fusta;
+        let window = |spec: &str| {
+            fusta
+                .parse_region(spec)
+                .map(|(_, start, end, _)| start..end)
+        };
+        assert_eq!(
+            fusta.parse_region("chr5:5000+100:-").unwrap(),
+            ("chr5".into(), 4999, 5099, true)
+        );
+        assert_eq!(window("chr5:5,000~100").unwrap(), 4899..5100);
+        // Windows are clipped to the fragment
+        assert_eq!(window("chr5:50~100").unwrap(), 0..150);
+        assert_eq!(window("chr5:9990~100").unwrap(), 9889..10000);
+        assert_eq!(window("chr5:9990+100").unwrap(), 9989..10000);
+        assert_eq!(window("chr5:1~0").unwrap(), 0..1);
+        for invalid in ["chr5:10001~10", "chr5:0+10", "chr5:10+0", "chr3:10+10"] {
+            assert!(window(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn edit_conflicts() {
+        let (_dir, _, mut fusta) = mount(2, Cache::Mmap);
+        let seq = |fusta: &FustaFS, id: &str| {
+            fusta.fragment_from_id(id).unwrap().data().unwrap().to_vec()
+        };
+        let ino = fusta.fragment_from_id("orig_0").unwrap().seq_file.ino;
+        fusta.write_file(ino, 0, b"GG").unwrap();
+
+        // The edits are kept, and the conflict reported
+        append(&mut fusta, "orig_0", "TTTT");
+        assert_eq!(seq(&fusta, "orig_0"), b"GGGT\n");
+        let last_error = fusta.get_file(LAST_ERROR_FILE).unwrap().data().to_vec();
+        assert!(String::from_utf8(last_error)
+            .unwrap()
+            .contains("unsaved edits"));
+        let appends = fusta.get_file(APPENDS_FILE).unwrap().data().to_vec();
+        assert!(String::from_utf8(appends)
+            .unwrap()
+            .contains("orig_0,refused,\n"));
+
+        // ...until they are written back
+        fusta.concretize(true).unwrap();
+        append(&mut fusta, "orig_0", "TTTT");
+        assert_eq!(seq(&fusta, "orig_0"), b"TTTT");
+
+        fusta.settings.on_edit_conflict = Collision::Suffix;
+        let ino = fusta.fragment_from_id("orig_1").unwrap().seq_file.ino;
+        fusta.write_file(ino, 0, b"GG").unwrap();
+        append(&mut fusta, "orig_1", "TTTT");
+        assert_eq!(seq(&fusta, "orig_1"), b"GGGTT\n");
+        assert_eq!(seq(&fusta, "orig_1__2"), b"TTTT");
+    }
+
+    #[test]
+    fn forced_appends() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 2);
+        let mut fusta = FustaFS::new(
+            FustaSettings {
+                no_overwrite: true,
+                on_append_collision: Collision::Skip,
+                ..settings(Cache::Mmap)
+            },
+            &filename,
+        )
+        .unwrap();
+        let seq = |fusta: &FustaFS, id: &str| {
+            fusta.fragment_from_id(id).unwrap().data().unwrap().to_vec()
+        };
+        let append_to = |fusta: &mut FustaFS, file: &str, data: &str| {
+            fusta
+                .apply(Mutation::Append {
+                    file: file.to_owned(),
+                    data: data.as_bytes().to_vec(),
+                    atime: SystemTime::now(),
+                    mtime: SystemTime::now(),
+                })
+                .unwrap()
+        };
+        append_to(&mut fusta, "new", ">orig_0\nTTTT\n");
+        assert_eq!(seq(&fusta, "orig_0"), b"ACGT\n");
+        append_to(&mut fusta, "force_new", ">orig_0\nTTTT\n");
+        assert_eq!(seq(&fusta, "orig_0"), b"TTTT");
+
+        // Headerless sequences are named after the file, without the prefix
+        append_to(&mut fusta, "force_orig_1", "GG\n");
+        assert_eq!(seq(&fusta, "orig_1"), b"GG");
+
+        // Unsaved edits are still protected
+        let ino = fusta.fragment_from_id("orig_1").unwrap().seq_file.ino;
+        fusta.write_file(ino, 0, b"C").unwrap();
+        append_to(&mut fusta, "force_new", ">orig_1\nTTTT\n");
+        assert_eq!(seq(&fusta, "orig_1"), b"CG");
+
+        let status = fusta.read_file(STATUS_FILE, 0, 1000).unwrap();
+        let status = String::from_utf8(status).unwrap();
+        assert!(status
+            .ends_with("on-collision: skip\non-edit-conflict: refuse\nrename-overwrite: refuse\n"));
+    }
+
+    #[test]
+    fn versions() {
+        let (_dir, _, mut fusta) = mount(2, Cache::Mmap);
+        let read = |fusta: &mut FustaFS, name: &str| {
+            let ino = fusta.version(name)?.ino;
+            fusta.read_file(ino, 0, 100)
+        };
+        fusta
+            .apply(Mutation::Rename {
+                from: "orig_1".into(),
+                to: "renamed".into(),
+            })
+            .unwrap();
+        let ino = fusta.fragment_from_id("renamed").unwrap().seq_file.ino;
+        for edit in [b"GG", b"CC"] {
+            fusta.write_file(ino, 0, edit).unwrap();
+            fusta.editing.clear();
+        }
+
+        assert_eq!(read(&mut fusta, "renamed.seq@orig").unwrap(), b"ACGTT\n");
+        assert_eq!(read(&mut fusta, "renamed.seq@1").unwrap(), b"GGGTT\n");
+        assert_eq!(read(&mut fusta, "renamed.seq@2").unwrap(), b"ACGTT\n");
+        // Renamings are edits too
+        assert_eq!(read(&mut fusta, "renamed.seq@3").unwrap(), b"ACGTT\n");
+        assert!(read(&mut fusta, "renamed.seq@4").is_err());
+        assert!(read(&mut fusta, "renamed.seq@0").is_err());
+        assert!(read(&mut fusta, "renamed.seq@latest").is_err());
+        assert!(read(&mut fusta, "orig_0.seq@1").is_err());
+        let version = fusta.version("renamed.seq@1").unwrap().ino;
+        assert_eq!(fusta.version("renamed.seq@1").unwrap().ino, version);
+
+        // The states are only available as long as they can be undone
+        fusta.undo("renamed").unwrap();
+        assert!(fusta.read_file(version, 0, 100).is_err());
+        assert_eq!(read(&mut fusta, "renamed.seq@1").unwrap(), b"ACGTT\n");
     }
 
-    fn write(
-        &mut self,
-        _req: &Request,
-        ino: u64,
-        _fh: u64,
-        offset: i64,
-        data: &[u8],
-        _write_flags: u32,
-        _flags: i32,
-        _lock_owner: Option<u64>,
-        reply: ReplyWrite,
-    ) {
-        if !self.is_writeable(ino) {
-            error!("{} is not writeable", ino);
-            reply.error(EACCES);
-        } else {
-            // We write to an existing fragment
-            if self.fragment_from_ino(ino).is_some() {
-                let fragment = self
-                    .mut_fragment_from_ino(ino)
-                    .expect("Something went very wrong");
-                // As soon as there's a write, we have to switch this fragment to a buffer-backed storage
-                if !matches!(fragment.data, Backing::Buffer(_)) {
-                    fragment.data = Backing::Buffer(fragment.data().to_vec());
-                }
+    #[test]
+    fn deferred_deletions() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 4);
+        let mut fusta = FustaFS::new(
+            FustaSettings {
+                defer_deletions: true,
+                ..settings(Cache::RAM)
+            },
+            &filename,
+        )
+        .unwrap();
+        for id in ["orig_1", "orig_2"] {
+            fusta.apply(Mutation::Delete(id.into())).unwrap();
+        }
+        assert_eq!(fusta.fragments.len(), 2);
+        assert_eq!(read_back(&filename).len(), 4);
 
-                // Ensure that the backing buffer is big enough
-                let max_size = offset as usize + data.len();
-                if max_size > fragment.data_size() {
-                    fragment.extend(max_size)
-                }
+        fusta.commit("").unwrap();
+        assert_eq!(read_back(&filename).len(), 2);
 
-                // Then finally write the data
-                if let Backing::Buffer(b) = &mut fragment.data {
-                    let start = offset as usize;
-                    let end = start + data.len();
-                    b.splice(start..end, data.iter().cloned());
-                    fragment.refresh_virtual_files();
-                    reply.written(data.len() as u32);
-                    self.dirty = true;
-                } else {
-                    panic!("Something went very wrong...")
-                }
-            }
-            // We write to a pending fragment
-            else if let Some((name, pending_fragment)) = self
-                .pending_appends
-                .iter_mut()
-                .find(|(_, p)| p.attrs.ino == ino)
-            {
-                let start = offset as usize;
-                let end = start + data.len();
-                if end > pending_fragment.data.len() {
-                    pending_fragment.data.resize_with(end, Default::default);
-                }
-                pending_fragment
-                    .data
-                    .splice(start..end, data.iter().cloned());
-                reply.written(data.len() as u32);
-                trace!("\tWriting to {}", name);
-            } else {
-                reply.error(ENOENT);
-            }
+        // The other mutations write the deletions back as usual
+        fusta.apply(Mutation::Delete("orig_3".into())).unwrap();
+        append(&mut fusta, "new", "ACGT");
+        assert_eq!(read_back(&filename).len(), 2);
+    }
+
+    #[test]
+    fn bulk_deletions() {
+        let (_dir, filename, mut fusta) = mount(12, Cache::RAM);
+        let ids = |fusta: &FustaFS| {
+            fusta
+                .fragments
+                .iter()
+                .map(|f| f.id.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert!(fusta.delete("/orig_(/\norig_0").is_err());
+        assert_eq!(fusta.fragments.len(), 12);
+
+        assert_eq!(
+            fusta
+                .delete("# short ones\n/^orig_1.$/\norig_2\nmissing\n")
+                .unwrap(),
+            "3 fragments deleted (35 bases), 1 not found"
+        );
+        assert_eq!(
+            ids(&fusta),
+            [
+                "orig_0", "orig_1", "orig_3", "orig_4", "orig_5", "orig_6", "orig_7", "orig_8",
+                "orig_9"
+            ]
+        );
+        assert_eq!(read_back(&filename).len(), 9);
+        let report = fusta.get_file(DELETE_FILE).unwrap().data().to_vec();
+        assert!(String::from_utf8(report)
+            .unwrap()
+            .ends_with("line 4: `missing` is not a fragment\n"));
+    }
+
+    #[test]
+    fn headers_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 2);
+        let mut fusta = FustaFS::new(
+            FustaSettings {
+                headers_only: true,
+                ..settings(Cache::File)
+            },
+            &filename,
+        )
+        .unwrap();
+        let fragment = fusta.fragment_from_id("orig_1").unwrap();
+        let (fasta_ino, seq_ino) = (fragment.fasta_file.ino, fragment.seq_file.ino);
+        assert_eq!(fusta.attrs_from_ino(seq_ino).unwrap().size, 6);
+        for ino in [fasta_ino, seq_ino, STATS_FILE] {
+            assert_eq!(fusta.read_file(ino, 0, 100).unwrap_err().errno(), EIO);
         }
+        let labels = fusta.read_file(LABELS_FILE, 0, 100).unwrap();
+        assert_eq!(labels, b">orig_0\n>orig_1\n");
+        assert!(fusta.read_file(INFO_CSV_FILE, 0, 1000).is_ok());
     }
 
-    fn setattr(
-        &mut self,
-        _req: &Request<'_>,
-        ino: u64,
-        mode: Option<u32>,
-        uid: Option<u32>,
-        gid: Option<u32>,
-        size: Option<u64>,
-        atime: Option<TimeOrNow>,
-        mtime: Option<TimeOrNow>,
-        _ctime: Option<SystemTime>,
-        _fh: Option<u64>,
-        crtime: Option<SystemTime>,
-        chgtime: Option<SystemTime>,
-        bkuptime: Option<SystemTime>,
-        flags: Option<u32>,
-        reply: ReplyAttr,
-    ) {
-        trace!("SETATTR");
-        trace!("mode       {:?}", mode);
-        trace!("gid        {:?}", gid);
-        trace!("uid        {:?}", uid);
-        trace!("size       {:?}", size);
-        trace!("atime      {:?}", atime);
-        trace!("mtime      {:?}", mtime);
-        trace!("bkuptime   {:?}", bkuptime);
-        trace!("chgtime    {:?}", chgtime);
-        trace!("crtime     {:?}", crtime);
-        trace!("flags      {:?}", flags);
+    #[test]
+    fn manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 3);
+        let fai = format!("{}.fai", filename);
+        fs::write(&fai, "").unwrap();
+        let mut fusta = FustaFS::new(settings(Cache::RAM), &filename).unwrap();
+        let manifest = |fusta: &mut FustaFS| {
+            let content = fusta.read_file(MANIFEST_FILE, 0, 1 << 16).unwrap();
+            serde_json::from_slice::<serde_json::Value>(&content).unwrap()
+        };
+        let m = manifest(&mut fusta);
+        assert_eq!(m["fusta_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(m["fragments"], 3);
+        assert_eq!(m["source"]["size"], fs::metadata(&filename).unwrap().len());
+        assert_eq!(m["options"]["cache"], "memory");
+        assert_eq!(m["options"]["seed"], 0);
+        assert_eq!(m["options"]["policies"]["on-collision"], "overwrite");
+        assert_eq!(m["derived_files"].as_array().unwrap().len(), 1);
 
-        match ino {
-            ROOT_DIR | SEQ_DIR | FASTA_DIR => reply.error(EACCES),
-            INFO_FILE | INFO_CSV_FILE | LABELS_FILE => reply.error(EACCES),
-            _ => {
-                if self.fragment_from_ino(ino).is_some() {
-                    if !self.is_writeable(ino) {
-                        reply.error(EACCES);
-                    } else {
-                        if let Some(file) = self
-                            .mut_fragment_from_ino(ino)
-                            .and_then(|f| f.mut_file_from_ino(ino))
-                        {
-                            if let Some(uid) = uid {
-                                file.mut_attrs().uid = uid
-                            }
-                            if let Some(gid) = gid {
-                                file.mut_attrs().gid = gid
-                            }
-                            // if let Some(atime) = atime {
-                            //     file.mut_attrs().atime = atime
-                            // }
-                            // if let Some(mtime) = mtime {
-                            //     file.mut_attrs().mtime = mtime
-                            // }
-                            if let Some(chgtime) = chgtime {
-                                file.mut_attrs().mtime = chgtime
-                            }
-                            if let Some(crtime) = crtime {
-                                file.mut_attrs().crtime = crtime
-                            }
-                            // macOS only
-                            if let Some(flags) = flags {
-                                file.mut_attrs().flags = flags
-                            }
-                            if let Some(mode) = mode {
-                                file.mut_attrs().perm = mode as u16
-                            }
-                        }
-                        if let Some(size) = size.map(|s| s as usize) {
-                            if size == 0 {
-                                // Clear the file, called by the truncate syscall
-                                if let Some(fragment) = self.mut_fragment_from_ino(ino) {
-                                    fragment.data = Backing::Buffer(Vec::new());
-                                    fragment.refresh_virtual_files();
-                                }
-                            } else if size
-                                != self
-                                    .fragment_from_ino(ino)
-                                    .map(Fragment::data_size)
-                                    .unwrap()
-                            {
-                                // Redim the file
-                                let fragment = self.mut_fragment_from_ino(ino).unwrap();
-                                if !matches!(fragment.data, Backing::Buffer(_)) {
-                                    fragment.data = Backing::Buffer(fragment.data().to_vec());
-                                }
-                                fragment.extend(size);
-                                fragment.refresh_virtual_files();
-                                self.dirty = true;
-                            }
-                            self.mut_fragment_from_ino(ino)
-                                .and_then(|f| f.mut_file_from_ino(ino))
-                                .unwrap()
-                                .mut_attrs()
-                                .size = size as u64;
-                        }
-                        reply.attr(
-                            &TTL,
-                            self.fragment_from_ino(ino)
-                                .and_then(|f| f.file_from_ino(ino))
-                                .unwrap()
-                                .attrs(),
-                        );
-                    }
-                } else if let Some((name, pending_fragment)) = self
-                    .pending_appends
-                    .iter_mut()
-                    .find(|(_, p)| p.attrs.ino == ino)
-                {
-                    if let Some(size) = size {
-                        trace!("\tResizing {} @{}", name, size);
-                        pending_fragment
-                            .data
-                            .resize_with(size as usize, Default::default);
-                        reply.attr(&TTL, &pending_fragment.attrs);
-                    }
-                } else {
-                    warn!("\t{:?} does not exist", ino);
-                    reply.error(ENOENT);
-                }
-            }
+        append(&mut fusta, "new", "ACGT");
+        let fai = fs::File::options().write(true).open(&fai).unwrap();
+        fai.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+        let m = manifest(&mut fusta);
+        assert_eq!(m["fragments"], 4);
+        assert_eq!(m["derived_files"][0]["stale"], true);
+    }
+
+    #[test]
+    fn directory_modes() {
+        let (owner, group) = unsafe { (geteuid(), getgid()) };
+        let (_dir, filename, mut fusta) = mount(1, Cache::RAM);
+        assert_eq!(fusta.dir_attrs[&SEQ_DIR].perm, 0o775 & !*UMASK);
+        let attrs = fusta
+            .chmod_dir(owner, group, SEQ_DIR, Some(0o40770), None, None)
+            .unwrap();
+        assert_eq!(attrs.perm, 0o770);
+        assert!(fusta
+            .chmod_dir(
+                owner,
+                group,
+                SUBFRAGMENTS_DIR,
+                Some(0o700),
+                None,
+                Some(group)
+            )
+            .is_ok());
+
+        // Only the owner may chmod, and only root may give a directory away
+        let stranger = owner + 1;
+        assert!(fusta
+            .chmod_dir(stranger, group, SEQ_DIR, Some(0o777), None, None)
+            .is_err());
+        if owner != 0 {
+            assert!(fusta
+                .chmod_dir(owner, group, SEQ_DIR, None, Some(stranger), None)
+                .is_err());
         }
+        assert!(fusta
+            .chmod_dir(0, 0, FASTA_DIR, None, Some(stranger), Some(stranger))
+            .is_ok());
+        // So do the fragment files, which are not protected by the kernel
+        // when the writers are restricted
+        let ino = fusta.fragments[0].seq_file.ino;
+        let file = *fusta.fragments[0].seq_file.attrs();
+        assert!(fusta
+            .check_chmod(owner, group, ino, &file, Some(0o600), None, None)
+            .is_ok());
+        assert!(fusta
+            .check_chmod(stranger, group, ino, &file, Some(0o666), None, None)
+            .is_err());
+        assert!(fusta
+            .check_chmod(stranger, group, ino, &file, None, Some(stranger), None)
+            .is_err());
+        drop(fusta);
+
+        // The changes persist across mounts
+        let fusta = FustaFS::new(settings(Cache::RAM), &filename).unwrap();
+        assert_eq!(fusta.dir_attrs[&SEQ_DIR].perm, 0o770);
+        assert_eq!(fusta.dir_attrs[&SUBFRAGMENTS_DIR].perm, 0o700);
+        let fasta = fusta.dir_attrs[&FASTA_DIR];
+        assert_eq!((fasta.uid, fasta.gid), (stranger, stranger));
+        assert_eq!(fusta.dir_attrs[&META_DIR].perm, 0o555 & !*UMASK);
     }
 
-    fn destroy(&mut self) {
-        info!("Closing FUSTA");
-        self.concretize(false)
+    #[test]
+    fn summaries() {
+        let (_dir, _, mut fusta) = mount(3, Cache::Mmap);
+        let read = |fusta: &mut FustaFS, ino| {
+            String::from_utf8(fusta.read_file(ino, 0, 1 << 16).unwrap()).unwrap()
+        };
+        assert_eq!(read(&mut fusta, SUMMARY_FILE), "sequences: 3\nbases: 15\n");
+        assert!(read(&mut fusta, INFO_FILE).contains("3 sequences"));
+
+        // infos.txt is only rebuilt once read, summary.txt is always fresh
+        append(&mut fusta, "new", "ACGT");
+        assert!(fusta.infos_stale);
+        assert_eq!(read(&mut fusta, SUMMARY_FILE), "sequences: 4\nbases: 19\n");
+        assert!(fusta.infos_stale);
+        let infos = read(&mut fusta, INFO_FILE);
+        assert!(infos.contains("4 sequences") && infos.contains("new"));
+        assert!(!fusta.infos_stale);
+        assert_eq!(
+            fusta.attrs_from_ino(INFO_FILE).unwrap().size,
+            infos.len() as u64
+        );
+
+        // The lengths kept are refreshed once the sequences change
+        let new = fusta.fragment_from_id("new").unwrap();
+        let (ino, end) = (new.seq_file.ino, new.data_size());
+        fusta.write_file(ino, end, b"GG\nT").unwrap();
+        assert_eq!(read(&mut fusta, SUMMARY_FILE), "sequences: 4\nbases: 22\n");
     }
 
-    fn rename(
-        &mut self,
-        _req: &Request,
-        parent: u64,
-        name: &OsStr,
-        newparent: u64,
-        newname: &OsStr,
-        _flags: u32,
-        reply: ReplyEmpty,
-    ) {
-        match parent {
-            ROOT_DIR | APPEND_DIR | SUBFRAGMENTS_DIR => {
-                warn!("RENAME: forbidden here");
-                reply.error(EACCES);
+    #[test]
+    fn from_scratch() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("new.fa");
+        fs::write(&filename, "").unwrap();
+        let filename = filename.to_str().unwrap();
+        let mut fusta = FustaFS::new(settings(Cache::Mmap), filename).unwrap();
+        assert!(fusta.fragments.is_empty());
+        assert!(!fusta.is_dirty());
+        append(&mut fusta, "a", "ACGT");
+        append(&mut fusta, "b", "");
+        let ino = fusta.fragment_from_name("b").unwrap().seq_file.ino;
+        fusta.write_fragment(ino, 0, b"GGCC\n").unwrap();
+        fusta.concretize(true).unwrap();
+        assert_eq!(
+            read_back(filename),
+            vec![
+                ("a".to_owned(), b"ACGT".to_vec()),
+                ("b".to_owned(), b"GGCC".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn commit_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = make_fasta(dir.path(), 4);
+        let report = |fusta: &mut FustaFS| {
+            String::from_utf8(fusta.read_file(COMMIT_REPORT_FILE, 0, 1000).unwrap()).unwrap()
+        };
+        let mut fusta = FustaFS::new(settings(Cache::Mmap), &filename).unwrap();
+        assert_eq!(report(&mut fusta), "");
+        fusta.rename_fragment("orig_0", "first").unwrap();
+        let ino = fusta.fragment_from_name("orig_1").unwrap().seq_file.ino;
+        fusta.resize_fragment(ino, 2).unwrap();
+        fusta.delete_fragment("orig_2").unwrap();
+        append(&mut fusta, "new", "GGG");
+        fusta.concretize(true).unwrap();
+        assert_eq!(
+            report(&mut fusta),
+            format!(
+                "# 4 fragments changed, written back to `{}`\n\
+                 change\tid\tprevious_id\tprevious_length\tlength\tdelta\n\
+                 renamed\tfirst\torig_0\t\t\t\n\
+                 edited\torig_1\torig_1\t5\t2\t-3\n\
+                 added\tnew\t\t\t3\t\n\
+                 deleted\torig_2\torig_2\t6\t\t\n",
+                filename
+            )
+        );
+
+        // Beyond --max-shrink, the write-back waits for a confirmation
+        let mut fusta = FustaFS::new(
+            FustaSettings {
+                max_shrink: Some(0.5),
+                ..settings(Cache::Mmap)
+            },
+            &filename,
+        )
+        .unwrap();
+        let ino = fusta.fragment_from_name("orig_3").unwrap().seq_file.ino;
+        fusta.resize_fragment(ino, 5).unwrap();
+        fusta.concretize(true).unwrap();
+        assert!(report(&mut fusta).starts_with("# 1 fragments changed"));
+        let ino = fusta.fragment_from_name("orig_1").unwrap().seq_file.ino;
+        fusta.resize_fragment(ino, 0).unwrap();
+        fusta.concretize(true).unwrap();
+        assert_eq!(
+            read_back(&filename)[1],
+            ("orig_1".to_owned(), b"AC".to_vec())
+        );
+        let content = report(&mut fusta);
+        assert!(
+            content.starts_with("# held back, as it would shrink `orig_1` (100%)\n"),
+            "{}",
+            content
+        );
+        assert!(content.contains("\nedited\torig_1\torig_1\t2\t0\t-2\n"));
+        let status = String::from_utf8(fusta.read_file(STATUS_FILE, 0, 1000).unwrap()).unwrap();
+        assert!(status.contains("held back until confirmed"), "{}", status);
+        assert!(fusta.commit("").is_err());
+        fusta.commit("force").unwrap();
+        assert_eq!(read_back(&filename)[1], ("orig_1".to_owned(), b"".to_vec()));
+        assert!(report(&mut fusta).starts_with("# 1 fragments changed"));
+        let status = String::from_utf8(fusta.read_file(STATUS_FILE, 0, 1000).unwrap()).unwrap();
+        assert!(status.starts_with("clean"), "{}", status);
+    }
+
+    #[test]
+    fn samtools_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("test.fa");
+        fs::write(
+            &filename,
+            ">a first\nACGT\nACGT\nAC\n>crlf\r\nACG\r\nA\r\n>empty\n>line\nACGTACGT",
+        )
+        .unwrap();
+        let filename = filename.to_str().unwrap();
+        let mut fusta = FustaFS::new(settings(Cache::Mmap), filename).unwrap();
+        let read = |fusta: &mut FustaFS| loop {
+            match fusta.read_file(FAI_FILE, 0, MAX_IO_SIZE) {
+                Ok(data) => break Ok(String::from_utf8(data).unwrap()),
+                Err(e) if e.errno() == EAGAIN => continue,
+                Err(e) => break Err(e),
             }
-            SEQ_DIR | FASTA_DIR => {
-                if newparent != parent {
-                    error!("Cannot move files out of folder, please copy them instead");
-                    reply.error(EACCES);
-                } else {
-                    let mut new_id = newname.to_str().unwrap().to_string();
-                    let newname_path = std::path::Path::new(&new_id);
-                    // Remove the artificial extension if it exists
-                    if newname_path
-                        .extension()
-                        .map(|ext| ext == DOTLESS_SEQ_EXT)
-                        .unwrap_or(false)
-                    {
-                        debug!("Using {:?} for {}", newname_path.file_stem(), new_id);
-                        new_id = newname_path
-                            .file_stem()
-                            .unwrap()
-                            .to_str()
-                            .unwrap()
-                            .to_string();
-                    }
-                    // Shortcut if we cannot overwrite existing fragments
-                    let replaced_fragment = self.fragment_from_id(&new_id);
-                    if replaced_fragment.is_some() && self.settings.no_overwrite {
-                        error!("Cannot rename {:?} to {}: already existing.", name, new_id);
-                        reply.error(EACCES);
-                    } else {
-                        if replaced_fragment.is_some() {
-                            warn!("Replacing {}", new_id);
-                            self.fragments.retain(|f| f.id != new_id);
-                        }
-                        if let Some(ref mut fragment) = if parent == SEQ_DIR {
-                            self.mut_fragment_from_seq_filename(name.to_str().unwrap())
-                        } else {
-                            self.mut_fragment_from_fasta_filename(name.to_str().unwrap())
-                        } {
-                            fragment.rename(&new_id);
-                            info!("Renaming {:?} -> {:?}", name, newname);
-                            self.dirty = true;
-                            self.concretize(false);
-                            self.refresh_metadata(false);
-                            reply.ok();
-                        } else {
-                            warn!("\t{:?} does not exist", name);
-                            reply.error(ENOENT);
-                        }
+        };
+        let content = read(&mut fusta).unwrap();
+        assert_eq!(
+            content,
+            "a\t10\t9\t4\t5\ncrlf\t4\t29\t3\t5\nempty\t0\t44\t0\t0\nline\t8\t50\t8\t9\n"
+        );
+        assert_eq!(
+            fusta.attrs_from_ino(FAI_FILE).unwrap().size,
+            content.len() as u64
+        );
+        // ...as expected from a .fai
+        let fai_path = fai::path_for(Path::new(filename));
+        fs::write(&fai_path, &content).unwrap();
+        let file = fs::File::open(filename).unwrap();
+        let loaded = fai::load(&fai_path, &file, file.metadata().unwrap().len(), |b, o| {
+            std::os::unix::fs::FileExt::read_exact_at(&file, b, o)
+        })
+        .unwrap();
+        assert_eq!(loaded.len(), 4);
+
+        // It describes the file on disk, until the edits are written back
+        let ino = fusta.fragment_from_name("line").unwrap().seq_file.ino;
+        fusta.resize_fragment(ino, 0).unwrap();
+        fusta.write_fragment(ino, 0, b"GG\n").unwrap();
+        fusta.rename_fragment("a", "b").unwrap();
+        assert_eq!(read(&mut fusta).unwrap(), content);
+        fusta.concretize(true).unwrap();
+        assert_eq!(
+            read(&mut fusta).unwrap(),
+            "b\t10\t9\t4\t5\ncrlf\t4\t29\t3\t5\nempty\t0\t44\t0\t0\nline\t2\t50\t2\t3\n"
+        );
+
+        // Sequences that samtools could not index make it fail
+        fusta.resize_fragment(ino, 0).unwrap();
+        fusta.write_fragment(ino, 0, b"ACGT\nAC\nACGT\n").unwrap();
+        fusta.concretize(true).unwrap();
+        let e = read(&mut fusta).unwrap_err();
+        assert_eq!(e.errno(), EIO);
+        assert!(
+            e.to_string().contains("`line` is not uniformly wrapped"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn concatenation() {
+        let head = b">chr1 test\n";
+        let body = b"ACGTACGTAC\nGTACG\n";
+        let whole = [&head[..], &body[..]].concat();
+        let read_body = |offset: usize, size: usize| -> Result<Box<[u8]>, ()> {
+            assert!(size > 0 && offset + size <= body.len());
+            Ok(body[offset..offset + size].into())
+        };
+        for offset in 0..whole.len() + 2 {
+            for size in 0..whole.len() + 2 {
+                let range = window(offset as i64, size as u32, whole.len());
+                assert_eq!(
+                    read_concatenated(head, body.len(), range.clone(), read_body).unwrap(),
+                    &whole[range],
+                    "{}+{}",
+                    offset,
+                    size
+                );
+            }
+        }
+        // The body is left alone by the reads of the head only...
+        let fail = |_, _| -> Result<Box<[u8]>, ()> { Err(()) };
+        assert_eq!(
+            read_concatenated(head, body.len(), 0..4, fail).unwrap(),
+            b">chr"
+        );
+        // ...but not by those reaching it
+        assert!(read_concatenated(head, body.len(), 4..12, fail).is_err());
+    }
+
+    #[test]
+    fn rewrapped_reads() {
+        for cache in [Cache::Mmap, Cache::File, Cache::RAM] {
+            let dir = tempfile::tempdir().unwrap();
+            let filename = dir.path().join("test.fa").to_str().unwrap().to_owned();
+            fs::write(
+                &filename,
+                ">ragged desc\nACGTA\nCG\nTACGTAC\n>crlf\r\nACG\r\nTA\r\n>long\nACGTACGTAC\n",
+            )
+            .unwrap();
+            let mut fusta = FustaFS::new(
+                FustaSettings {
+                    write_transform: Pipeline::parse("strip-newlines").unwrap(),
+                    ..settings(cache)
+                },
+                &filename,
+            )
+            .unwrap();
+            // Rewrap the first sequence in lines of 3 bases, later unwrapped
+            // by the write transformation
+            let seq = fusta.fragment_from_id("ragged").unwrap().seq_file.ino;
+            fusta.resize_fragment(seq, 0).unwrap();
+            fusta
+                .write_file(seq, 0, b"ACG\nTAC\nGTA\nCGT\nAC\n")
+                .unwrap();
+            let rewrapped = fusta.fragment_from_id("ragged").unwrap().fasta_file.ino;
+            let size = fusta.attrs_from_ino(rewrapped).unwrap().size;
+            assert_eq!(size as usize, b">ragged desc\n".len() + 19);
+            fusta.normalize_edit(seq).unwrap();
+            assert!(fusta.attrs_from_ino(rewrapped).unwrap().size < size);
+
+            for fragment in 0..fusta.fragments.len() {
+                let fragment = &fusta.fragments[fragment];
+                let ino = fragment.fasta_file.ino;
+                let expected = [fragment.label().as_bytes(), &fragment.data().unwrap()].concat();
+                assert_eq!(
+                    fusta.attrs_from_ino(ino).unwrap().size as usize,
+                    expected.len()
+                );
+                // Reads of any size, at any offset, as well as the sequential
+                // reads of text tools, see the label followed by the sequence
+                for offset in 0..expected.len() + 2 {
+                    for size in 0..expected.len() + 2 {
+                        let start = std::cmp::min(offset, expected.len());
+                        let end = std::cmp::min(offset + size, expected.len());
+                        assert_eq!(
+                            fusta.read_file(ino, offset as i64, size as u32).unwrap(),
+                            &expected[start..end],
+                            "{:?}: {} bytes at {} in {}",
+                            cache,
+                            size,
+                            offset,
+                            fusta.describe(ino)
+                        );
                     }
                 }
+                for size in 1..8 {
+                    let read = (0..expected.len())
+                        .step_by(size)
+                        .flat_map(|o| fusta.read_file(ino, o as i64, size as u32).unwrap())
+                        .collect::<Vec<_>>();
+                    assert_eq!(read, expected);
+                }
             }
-            _ => {
-                warn!("RENAME: unknown parent {}", parent);
-                reply.error(ENOENT);
-            }
+            let ragged = fusta.fragment_from_id("ragged").unwrap();
+            assert_eq!(&*ragged.data().unwrap(), b"ACGTACGTACGTAC");
         }
     }
 
-    fn fsync(&mut self, _req: &Request, _ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
-        trace!("FSYNC");
-        self.concretize(false);
-        self.refresh_metadata(false);
-        reply.ok();
+    #[test]
+    fn too_many_mappings() {
+        let (_dir, filename, mut fusta) = mount(3, Cache::Mmap);
+
+        // Past the budget, the sequences are read through the file handle
+        fusta.mappings = 1;
+        let (file, records) = fusta.read_records(&filename, &mut Vec::new()).unwrap();
+        let fragments = fusta.fragments_from_records(records, &file, None).unwrap();
+        assert_eq!(fusta.mappings, 0);
+        assert_eq!(
+            fragments
+                .iter()
+                .map(|f| f.data().unwrap().to_vec())
+                .collect::<Vec<_>>(),
+            read_back(&filename)
+                .into_iter()
+                .map(|(_, seq)| [seq, b"\n".to_vec()].concat())
+                .collect::<Vec<_>>()
+        );
     }
 
-    fn fsyncdir(
-        &mut self,
-        _req: &Request,
-        _ino: u64,
-        _fh: u64,
-        _datasync: bool,
-        reply: ReplyEmpty,
-    ) {
-        trace!("FSYNCDIR");
-        self.concretize(false);
-        self.refresh_metadata(false);
-        reply.ok();
+    #[test]
+    fn taxa() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("test.fa");
+        fs::write(
+            &filename,
+            ">a Bacteria;Firmicutes;Bacilli\nACGT\n>b Bacteria;Proteobacteria\nAC\n\
+             >c not a taxonomy\nA\n>d Bacteria; Firmicutes ;Clostridia;\nG\n",
+        )
+        .unwrap();
+        let mut fusta = FustaFS::new(settings(Cache::Mmap), filename.to_str().unwrap()).unwrap();
+        let read = |fusta: &mut FustaFS, dir: u64| {
+            let ino = fusta.taxa[&dir].count.ino;
+            String::from_utf8(fusta.read_file(ino, 0, MAX_IO_SIZE).unwrap()).unwrap()
+        };
+        fusta.make_taxa();
+        assert_eq!(read(&mut fusta, TAXA_DIR), "# 4 sequences\nBacteria\t3\n");
+        let bacteria = fusta.taxa[&TAXA_DIR].children["Bacteria"];
+        assert_eq!(
+            read(&mut fusta, bacteria),
+            "# 3 sequences\nFirmicutes\t2\nProteobacteria\t1\n"
+        );
+        let firmicutes = fusta.taxa[&bacteria].children["Firmicutes"];
+        assert_eq!(
+            fusta.taxa[&firmicutes].children.keys().collect::<Vec<_>>(),
+            ["Bacilli", "Clostridia"]
+        );
+        let a = fusta.fragment_from_id("a").unwrap().fasta_file.ino;
+        assert_eq!(fusta.taxon_entry(firmicutes, "a.fa").unwrap().ino, a);
+        assert!(fusta.taxon_entry(firmicutes, "b.fa").is_none());
+        assert!(fusta.taxon_entry(TAXA_DIR, "c.fa").is_some());
+
+        // The directories follow the fragments, and keep their inos
+        fusta.apply(Mutation::Delete("b".into())).unwrap();
+        fusta.refresh_metadata(false);
+        assert_eq!(read(&mut fusta, bacteria), "# 2 sequences\nFirmicutes\t2\n");
+        assert_eq!(fusta.taxa[&bacteria].children["Firmicutes"], firmicutes);
     }
 
-    fn flush(&mut self, _req: &Request, _ino: u64, _fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
-        trace!("FLUSH");
-        // self.concretize();
-        reply.ok();
+    #[test]
+    fn formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("test.fa");
+        fs::write(
+            &filename,
+            ">wrapped\nACGT\nACGT\nAC\n>crlf\r\nACG\r\nA\r\n>ragged\nACGT\nAC\nACGT\n\
+             >blanks\nACGT \nAC\n>empty\n>line\nACGTACGT",
+        )
+        .unwrap();
+        let mut fusta = FustaFS::new(settings(Cache::Mmap), filename.to_str().unwrap()).unwrap();
+        let read = |fusta: &mut FustaFS| loop {
+            match fusta.read_file(FORMAT_FILE, 0, MAX_IO_SIZE) {
+                Ok(data) => break String::from_utf8(data).unwrap(),
+                Err(e) => assert_eq!(e.errno(), EAGAIN),
+            }
+        };
+        let content = read(&mut fusta);
+        assert_eq!(
+            content,
+            "# strict: no, 2 fragments are not uniformly wrapped\n\
+             id\tline_widths\tcrlf\ttrailing_blanks\tstrict\n\
+             wrapped\t4:2,2:1\tno\tno\tyes\n\
+             crlf\t1:1,3:1\tyes\tno\tyes\n\
+             ragged\t4:2,2:1\tno\tno\tno\n\
+             blanks\t2:1,5:1\tno\tyes\tno\n\
+             empty\t\tno\tno\tyes\n\
+             line\t8:1\tno\tno\tyes\n"
+        );
+        assert_eq!(
+            fusta.attrs_from_ino(FORMAT_FILE).unwrap().size,
+            content.len() as u64
+        );
+
+        // Rewrapped sequences are taken into account
+        for id in ["ragged", "blanks"] {
+            let ino = fusta.fragment_from_name(id).unwrap().seq_file.ino;
+            fusta.resize_fragment(ino, 0).unwrap();
+            fusta.write_fragment(ino, 0, b"ACGT\nAC\n").unwrap();
+        }
+        fusta.refresh_metadata(true);
+        assert!(read(&mut fusta).starts_with("# strict: yes\n"));
     }
 
-    fn release(
-        &mut self,
-        _req: &Request,
-        ino: u64,
-        _fh: u64,
-        _flags: i32,
-        _lock_owner: Option<u64>,
-        _flush: bool,
-        reply: ReplyEmpty,
-    ) {
-        debug!("RELEASE {}", ino);
-        if self.is_writeable(ino) {
-            for pending in self.pending_appends.iter() {
-                // FS is dirty at the first pending fragment
-                self.dirty = true;
+    #[test]
+    fn hot_config() {
+        let (_dir, _, mut fusta) = mount(3, Cache::RAM);
+        let config = |fusta: &mut FustaFS| {
+            String::from_utf8(fusta.read_file(CONFIG_FILE, 0, 1 << 16).unwrap()).unwrap()
+        };
+        assert!(config(&mut fusta).contains("ttl = 1000ms\n"));
+        assert!(fusta.spill.resident() > 0);
 
-                if pending.1.attrs.ino == ino {
-                    trace!("RELEASE: {}", pending.0);
-                    trace!("Dumping...");
-                    // Dump the pending in its own file and parse it
-                    let mut tmpfile =
-                        tempfile::tempfile().expect("Unable to create a temporary file");
-                    tmpfile
-                        .write_all(&pending.1.data)
-                        .expect("Unable to write to temporary file");
-                    trace!("Parsing...");
-                    tmpfile
-                        .seek(SeekFrom::Start(0))
-                        .expect("Unable to seek in temporary file");
-                    let new_fragments = FastaReader::new(&tmpfile, true).collect::<Vec<_>>();
-                    let new_keys = new_fragments.iter().map(|f| &f.id).collect::<Vec<_>>();
-                    let old_keys = self
-                        .fragments
-                        .iter()
-                        .map(|f| &f.id)
-                        .cloned()
-                        .collect::<Vec<_>>();
+        fusta.run_control(CONFIG_FILE, b"# Cache less\nttl = 50ms\nmax-cache=0\n");
+        assert_eq!(fusta.ttl, Duration::from_millis(50));
+        assert_eq!(fusta.settings.memory_budget, 0);
+        assert_eq!(fusta.spill.resident(), 0);
+        let content = config(&mut fusta);
+        assert!(content.contains("ttl = 50ms\nmax-cache = 0\n"));
+        assert_eq!(
+            fusta.attrs_from_ino(CONFIG_FILE).unwrap().size,
+            content.len() as u64
+        );
 
-                    // Remove the fragments sharing an existing key if overwrite is allowed
-                    let no_overwrite = self.settings.no_overwrite;
-                    if !no_overwrite {
-                        self.fragments.retain(|f| !new_keys.contains(&&f.id))
-                    }
-                    self.fragments
-                        .extend(new_fragments.into_iter().filter_map(|new_fragment| {
-                            if old_keys.contains(&new_fragment.id) && no_overwrite {
-                                error!("Skipping `{}`, already existing", &new_fragment.id);
-                                None
-                            } else {
-                                Some(Fragment::new(
-                                    &new_fragment.id,
-                                    &new_fragment.name,
-                                    Backing::PureBuffer(new_fragment.seq.unwrap()),
-                                    pending.1.seq_ino,
-                                    pending.1.fasta_ino,
-                                    pending.1.attrs.atime,
-                                    pending.1.attrs.mtime,
-                                ))
-                            }
-                        }));
-                }
+        // Nothing is changed if any setting is invalid
+        fusta.run_control(CONFIG_FILE, b"ttl = 2s\nmax-cache = lots\n");
+        assert_eq!(fusta.ttl, Duration::from_millis(50));
+        fusta.run_control(CONFIG_FILE, b"autocommit = 1\n");
+        let error = fusta.read_file(LAST_ERROR_FILE, 0, 1 << 16).unwrap();
+        assert!(String::from_utf8(error)
+            .unwrap()
+            .contains("unknown key `autocommit`"));
+    }
+
+    #[test]
+    fn incremental_info_csv() {
+        let (_dir, _, mut fusta) = mount(3, Cache::Mmap);
+        let read = |fusta: &mut FustaFS, offset, size| {
+            String::from_utf8(fusta.read_file(INFO_CSV_FILE, offset, size).unwrap()).unwrap()
+        };
+        let content = read(&mut fusta, 0, 1 << 16);
+        assert_eq!(
+            content,
+            "id,name,length\norig_0,\"\",5\norig_1,\"\",6\norig_2,\"\",7"
+        );
+        for offset in 0..content.len() {
+            for size in [1, 7, 100] {
+                let end = (offset + size).min(content.len());
+                assert_eq!(
+                    read(&mut fusta, offset as i64, size as u32),
+                    content[offset..end]
+                );
             }
-            self.concretize(false);
-            self.refresh_metadata(false);
-        } else {
-            debug!("Not a writeable file; ignoring")
         }
-        reply.ok();
+
+        // Only the rows of the new fragments are rendered
+        let row = |fusta: &FustaFS, id: &str| fusta.info_csv.rows[id].line.as_ptr();
+        let before = row(&fusta, "orig_1");
+        append(&mut fusta, "new", "ACGT");
+        assert!(fusta.info_csv.stale);
+        assert!(read(&mut fusta, 0, 1 << 16).ends_with("orig_2,\"\",7\nnew,\"\",4"));
+        assert_eq!(row(&fusta, "orig_1"), before);
+        assert_eq!(
+            fusta.attrs_from_ino(INFO_CSV_FILE).unwrap().size,
+            content.len() as u64 + 9
+        );
     }
 }
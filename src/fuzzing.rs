@@ -0,0 +1,151 @@
+//! Entry points for the fuzz targets in `fuzz/`, checking that the FASTA
+//! parser and the write path hold their invariants on arbitrary inputs.
+use crate::fasta::*;
+
+/// Parse `data`, and check that the fragments found are consistent with it
+pub fn parse(data: &[u8]) -> Vec<Fragment> {
+    let fragments = FastaReader::new(data, true).collect::<Vec<_>>();
+    let mut last_end = 0;
+    for f in fragments.iter() {
+        assert!(last_end <= f.pos.0 && f.pos.0 <= f.pos.1 && f.pos.1 <= data.len());
+        assert_eq!(f.len, f.pos.1 - f.pos.0);
+        last_end = f.pos.1;
+
+        let raw = &data[f.pos.0..f.pos.1];
+        assert!(!raw.starts_with(b">") && !contains_header(raw));
+        assert!(f.line_width <= raw.len());
+        let seq = raw
+            .split(|&c| c == b'\n')
+            .flat_map(|l| l.trim_ascii_end())
+            .copied()
+            .collect::<Vec<_>>();
+        assert_eq!(f.seq.as_deref(), Some(&seq[..]));
+    }
+
+    let positions = FastaReader::new(data, false)
+        .map(|f| (f.pos, f.line_width))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        positions,
+        fragments
+            .iter()
+            .map(|f| (f.pos, f.line_width))
+            .collect::<Vec<_>>()
+    );
+
+    fragments
+}
+
+fn contains_header(raw: &[u8]) -> bool {
+    raw.windows(2).any(|w| w == b"\n>")
+}
+
+/// Apply `writes`, as (fragment, offset, data) triplets, to the sequences of
+/// the fragments parsed from `data` as a FASTA file mounted by FUSTA would,
+/// and return the resulting FASTA file
+pub fn write(data: &[u8], writes: &[(usize, usize, &[u8])]) -> Vec<u8> {
+    let fragments = parse(data);
+    let mut raws = fragments
+        .iter()
+        .map(|f| data[f.pos.0..f.pos.1].to_vec())
+        .collect::<Vec<_>>();
+    if !raws.is_empty() {
+        for &(i, offset, chunk) in writes {
+            let raw = &mut raws[i % fragments.len()];
+            let len = raw.len();
+            if write_at(raw, offset, chunk).is_none() {
+                // Failed writes must leave the sequence untouched
+                assert_eq!(raw.len(), len);
+            }
+        }
+    }
+
+    let mut r = Vec::new();
+    for (f, raw) in fragments.iter().zip(raws.iter_mut()) {
+        if raw.last().is_some_and(|&c| c != b'\n') {
+            raw.push(b'\n');
+        }
+        // Untouched headers are written back verbatim
+        r.extend(format!(">{}\n", f.header).as_bytes());
+        r.extend_from_slice(raw);
+    }
+
+    // Unless the writes introduced new headers, the result should be parsed
+    // back to the same fragments
+    if !raws
+        .iter()
+        .any(|raw| raw.starts_with(b">") || contains_header(raw))
+    {
+        let reparsed = parse(&r);
+        assert_eq!(reparsed.len(), fragments.len());
+        for ((before, after), raw) in fragments.iter().zip(reparsed.iter()).zip(raws.iter()) {
+            assert_eq!(before.id, after.id);
+            assert_eq!(before.name, after.name);
+            assert_eq!(before.header, after.header);
+            assert_eq!(&r[after.pos.0..after.pos.1], &raw[..]);
+        }
+    }
+
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pathological() -> Vec<Vec<u8>> {
+        let mut r: Vec<Vec<u8>> = [
+            &b""[..],
+            b">",
+            b">\n",
+            b"\n\n\n",
+            b"> \n>  \n",
+            b"ACGT\n>after garbage\nAC",
+            b">a\r\nAC\r\nGT\r\n>b  c   d \r\n\r\n",
+            b">a\nAC>GT\n>b",
+            b">\0\0\n\0AC\0\n",
+            b">\xff\xfe description \xc3\n\xff\xff\n",
+            b">a\n  \t\nAC \t\nG\x0b\n",
+        ]
+        .iter()
+        .map(|s| s.to_vec())
+        .collect();
+        // A giant header and a line spanning many reader buffers
+        r.push([b">".to_vec(), b"x".repeat(1 << 20), b"\n".to_vec()].concat());
+        r.push(
+            [
+                b">long\n".to_vec(),
+                b"ACGT".repeat(3 << 20),
+                b"\n>next\nA".to_vec(),
+            ]
+            .concat(),
+        );
+        r
+    }
+
+    #[test]
+    fn pathological_inputs() {
+        for input in pathological() {
+            parse(&input);
+            write(
+                &input,
+                &[
+                    (0, 0, b"AC\nGT"),
+                    (1, 3, b">"),
+                    (2, 10, b"\0\0"),
+                    (0, usize::MAX, b"A"),
+                    (1, 1 << 62, b"A"),
+                    (3, 2, b""),
+                ],
+            );
+        }
+    }
+
+    #[test]
+    fn long_lines() {
+        let input = [b">long\n".to_vec(), b"A".repeat(3 << 20), b"\r\n".to_vec()].concat();
+        let fragment = FastaReader::new(&input[..], false).next().unwrap();
+        assert_eq!(fragment.pos, (6, input.len()));
+        assert_eq!(fragment.line_width, 3 << 20);
+    }
+}
@@ -0,0 +1,310 @@
+//! Rules deriving the file name of a fragment from the ID found in its
+//! header, so that unwieldy IDs (`gi|…|gb|…|`, very long ones, …) still give
+//! usable file names.
+use anyhow::{anyhow, bail, Result};
+
+/// The characters that can not appear in a file name, besides `/`
+#[cfg(unix)]
+pub const FORBIDDEN_CHARS: [char; 2] = ['\\', '\0'];
+#[cfg(windows)]
+pub const FORBIDDEN_CHARS: [char; 8] = ['\\', '/', ':', '*', '?', '|', '<', '>'];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Rule {
+    StripPrefix(String),
+    StripSuffix(String),
+    /// Cut the ID at the first whitespace, e.g. a tab
+    TruncateAtSpace,
+    /// Cut the ID at the first occurrence of any of these characters
+    TruncateAt(Vec<char>),
+    /// Replace the characters that can not appear in a file name
+    Sanitize,
+    MaxLen(usize),
+}
+impl Rule {
+    fn apply(&self, id: &mut String) {
+        match self {
+            Rule::StripPrefix(prefix) => {
+                if id.starts_with(prefix.as_str()) {
+                    id.drain(..prefix.len());
+                }
+            }
+            Rule::StripSuffix(suffix) => {
+                if id.ends_with(suffix.as_str()) {
+                    id.truncate(id.len() - suffix.len());
+                }
+            }
+            Rule::TruncateAtSpace => {
+                if let Some(i) = id.find(char::is_whitespace) {
+                    id.truncate(i);
+                }
+            }
+            Rule::TruncateAt(chars) => {
+                if let Some(i) = id.find(chars.as_slice()) {
+                    id.truncate(i);
+                }
+            }
+            Rule::Sanitize => {
+                *id = id
+                    .chars()
+                    .map(|c| {
+                        if c == '/' || c == '\\' || c.is_whitespace() || c.is_control() {
+                            '_'
+                        } else {
+                            c
+                        }
+                    })
+                    .collect()
+            }
+            Rule::MaxLen(len) => {
+                if let Some((i, _)) = id.char_indices().nth(*len) {
+                    id.truncate(i);
+                }
+            }
+        }
+    }
+}
+
+/// An ordered list of rules, applied one after the other
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdRules(Vec<Rule>);
+impl IdRules {
+    /// Parse a comma-separated list of rules, e.g.
+    /// `strip-prefix=gi|,truncate-at-space,max-len=64`
+    pub fn parse(spec: &str) -> Result<IdRules> {
+        spec.split(',')
+            .filter(|s| !s.trim().is_empty())
+            .map(|rule| {
+                let (key, value) = match rule.split_once('=') {
+                    Some((key, value)) => (key.trim(), Some(value)),
+                    None => (rule.trim(), None),
+                };
+                let value = || value.ok_or_else(|| anyhow!("`{}` expects a value", key));
+                Ok(match key {
+                    "strip-prefix" => Rule::StripPrefix(value()?.to_owned()),
+                    "strip-suffix" => Rule::StripSuffix(value()?.to_owned()),
+                    "truncate-at-space" => Rule::TruncateAtSpace,
+                    "truncate-at" if value()?.is_empty() => bail!("`truncate-at` expects characters"),
+                    "truncate-at" => Rule::TruncateAt(value()?.chars().collect()),
+                    "sanitize" => Rule::Sanitize,
+                    "max-len" => match value()?.trim().parse::<usize>() {
+                        Ok(len) if len > 0 => Rule::MaxLen(len),
+                        _ => bail!("`{}` is not a valid length", value()?),
+                    },
+                    _ => bail!(
+                        "unknown rule `{}`; expected one of strip-prefix, strip-suffix, truncate-at-space, truncate-at, sanitize, max-len",
+                        key
+                    ),
+                })
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(IdRules)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The ID to use for the file names of a fragment whose header bears `id`
+    pub fn apply(&self, id: &str) -> String {
+        let mut r = id.to_owned();
+        for rule in self.0.iter() {
+            rule.apply(&mut r);
+        }
+        r
+    }
+}
+
+/// How the ID of a fragment is turned into the name of its files, and back.
+/// For the IDs it accepts, a codec is lossless: decoding the name of a file
+/// always gives back the ID it was encoded from, and only the names produced
+/// by the codec can be decoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Codec {
+    /// File names are the IDs themselves; IDs that can not be used as file
+    /// names are refused
+    #[default]
+    Plain,
+    /// The characters that can not appear in a file name, `%`, control
+    /// characters and a leading `.` are percent-encoded
+    Percent,
+    /// As `Percent`, upper-case letters being further escaped (`A` as `^a`),
+    /// so that IDs differing only by their case do not collide on
+    /// case-insensitive filesystems
+    Folded,
+}
+impl Codec {
+    pub fn parse(name: &str) -> Result<Codec> {
+        match name {
+            "plain" => Ok(Codec::Plain),
+            "percent" => Ok(Codec::Percent),
+            "folded" => Ok(Codec::Folded),
+            _ => bail!(
+                "unknown codec `{}`; expected one of plain, percent, folded",
+                name
+            ),
+        }
+    }
+
+    /// Whether `id` can be encoded into a file name
+    pub fn check(&self, id: &str) -> Result<(), String> {
+        match self {
+            Codec::Plain if id == "." || id == ".." => {
+                Err(format!("`{}` can not be used as a file name", id))
+            }
+            Codec::Plain if id.chars().any(|c| c == '/' || FORBIDDEN_CHARS.contains(&c)) => Err(
+                format!("`{}` contains a character forbidden in file names", id),
+            ),
+            _ if id.is_empty() => Err("empty IDs can not be used as file names".into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether `c`, at position `i` of an ID, has to be percent-encoded
+    fn escapes(&self, i: usize, c: char) -> bool {
+        match self {
+            Codec::Plain => false,
+            Codec::Percent | Codec::Folded => {
+                c == '/'
+                    || c == '%'
+                    || (c == '.' && i == 0)
+                    || c.is_control()
+                    || FORBIDDEN_CHARS.contains(&c)
+                    || (*self == Codec::Folded && (c == '^' || (c.is_uppercase() && !c.is_ascii())))
+            }
+        }
+    }
+
+    /// The file name, extension excluded, of the files of the fragment `id`
+    pub fn encode(&self, id: &str) -> String {
+        let mut r = String::with_capacity(id.len());
+        for (i, c) in id.chars().enumerate() {
+            if self.escapes(i, c) {
+                let mut buffer = [0; 4];
+                for b in c.encode_utf8(&mut buffer).bytes() {
+                    r.push_str(&format!("%{:02X}", b));
+                }
+            } else if *self == Codec::Folded && c.is_ascii_uppercase() {
+                r.push('^');
+                r.push(c.to_ascii_lowercase());
+            } else {
+                r.push(c);
+            }
+        }
+        r
+    }
+
+    /// The ID of the fragment whose files are named `name`, extension
+    /// excluded, if it is a name produced by this codec
+    pub fn decode(&self, name: &str) -> Option<String> {
+        let mut bytes = Vec::with_capacity(name.len());
+        let mut chars = name.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '%' if *self != Codec::Plain => {
+                    let hex = chars.by_ref().take(2).collect::<String>();
+                    if hex.len() != 2 {
+                        return None;
+                    }
+                    bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+                }
+                '^' if *self == Codec::Folded => {
+                    let c = chars.next().filter(char::is_ascii_lowercase)?;
+                    bytes.push(c.to_ascii_uppercase() as u8);
+                }
+                c => {
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                }
+            }
+        }
+        let id = String::from_utf8(bytes).ok()?;
+        // Only accept the canonical encoding of the ID, so that each ID has a
+        // single name
+        (self.check(&id).is_ok() && self.encode(&id) == name).then_some(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalization() {
+        let rules = IdRules::parse("strip-prefix=gi|,truncate-at=|,max-len=5").unwrap();
+        assert_eq!(rules.apply("gi|123456789|gb|AB001234.1|"), "12345");
+        assert_eq!(rules.apply("ref|NC_000001|"), "ref");
+
+        let rules = IdRules::parse("truncate-at-space, sanitize").unwrap();
+        assert_eq!(rules.apply("chr1\tassembled"), "chr1");
+        assert_eq!(rules.apply("a/b\\c"), "a_b_c");
+
+        // Lengths are counted in characters, not bytes
+        assert_eq!(IdRules::parse("max-len=2").unwrap().apply("éàù"), "éà");
+        assert!(IdRules::parse("").unwrap().is_empty());
+
+        for invalid in [
+            "max-len=0",
+            "max-len",
+            "strip-prefix",
+            "truncate-at=",
+            "upcase",
+        ] {
+            assert!(IdRules::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    /// All the strings of up to `len` characters taken from `alphabet`
+    fn strings(alphabet: &[char], len: usize) -> Vec<String> {
+        let mut r = vec![String::new()];
+        let mut last = vec![String::new()];
+        for _ in 0..len {
+            last = last
+                .iter()
+                .flat_map(|s| alphabet.iter().map(move |c| format!("{}{}", s, c)))
+                .collect();
+            r.extend(last.iter().cloned());
+        }
+        r
+    }
+
+    #[test]
+    fn lossless_codecs() {
+        let alphabet = [
+            'a', 'A', 'f', 'F', '2', '%', '^', '.', '/', '\\', '\0', '\n', 'é', 'É', '|',
+        ];
+        let candidates = strings(&alphabet, 4);
+        for codec in [Codec::Plain, Codec::Percent, Codec::Folded] {
+            let mut names = std::collections::HashMap::new();
+            for id in candidates.iter() {
+                // Every accepted ID survives the round-trip...
+                if codec.check(id).is_ok() {
+                    let name = codec.encode(id);
+                    assert_eq!(codec.decode(&name).as_ref(), Some(id), "{:?}", codec);
+                    assert!(!name.is_empty() && name != "." && name != "..");
+                    assert!(!name.contains('/') && !name.contains(FORBIDDEN_CHARS));
+                    let folded = if codec == Codec::Folded {
+                        name.to_lowercase()
+                    } else {
+                        name.clone()
+                    };
+                    // ...to a name no other ID is given
+                    assert_eq!(names.insert(folded, id), None, "{:?}", codec);
+                }
+                // ...and the only names that can be decoded are the encoded ones
+                if let Some(decoded) = codec.decode(id) {
+                    assert_eq!(&codec.encode(&decoded), id, "{:?}", codec);
+                }
+            }
+        }
+
+        assert_eq!(Codec::Percent.encode("gi|12/3%"), "gi|12%2F3%25");
+        assert_eq!(Codec::Percent.encode(".hidden.1"), "%2Ehidden.1");
+        assert_eq!(Codec::Folded.encode("Chr^1"), "^chr%5E1");
+        assert_eq!(Codec::Percent.decode("a%2f"), None);
+        assert_eq!(Codec::Percent.decode("a%2"), None);
+        assert!(Codec::Plain.check("a/b").is_err());
+        assert!(Codec::Percent.check("..").is_ok());
+        assert!(Codec::parse("rot13").is_err());
+    }
+}
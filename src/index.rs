@@ -0,0 +1,266 @@
+//! The on-disk index of a FASTA file, written by `fusta index` next to it so
+//! that mounting it does not require to scan it again.
+//!
+//! All the integers are stored little-endian; the strings are prefixed by
+//! their length, as a `u32`:
+//!
+//! ```text
+//! magic      b"FUSTAIDX"
+//! version    u32
+//! source     u64 size, u64 mtime (ns since the epoch), [u8; 16] sample digest
+//! count      u64
+//! fragments  count × (id, has_name: u8, name, header, u64 start, u64 end, u64 line width)
+//! checksum   [u8; 16], the MD5 digest of all the above
+//! ```
+use crate::fasta::{FastaReader, Fragment};
+use anyhow::{bail, ensure, Context, Result};
+use std::convert::{TryFrom, TryInto};
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const MAGIC: &[u8; 8] = b"FUSTAIDX";
+pub const VERSION: u32 = 1;
+const EXT: &str = "fusta-idx";
+// How much of the start and of the end of the source is digested to check
+// that it did not change, hashing it whole being as slow as scanning it
+const SAMPLE_SIZE: u64 = 64 * 1024;
+const CHECKSUM_SIZE: usize = 16;
+
+/// Where the index of `fasta` is looked for
+pub fn path_for(fasta: &Path) -> PathBuf {
+    let mut name = fasta.as_os_str().to_owned();
+    name.push(".");
+    name.push(EXT);
+    PathBuf::from(name)
+}
+
+/// What identifies the state of the indexed file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Source {
+    pub size: u64,
+    pub mtime: u64, // In ns since the epoch
+    pub sample: [u8; 16],
+}
+impl Source {
+    pub fn of(file: &fs::File) -> std::io::Result<Source> {
+        let metadata = file.metadata()?;
+        let size = metadata.len();
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        let mut sample = md5::Context::new();
+        for start in [0, size.saturating_sub(SAMPLE_SIZE)] {
+            let mut buffer = vec![0; std::cmp::min(SAMPLE_SIZE, size) as usize];
+            file.read_exact_at(&mut buffer, start)?;
+            sample.consume(&buffer);
+        }
+        Ok(Source {
+            size,
+            mtime,
+            sample: sample.compute().0,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Index {
+    pub source: Source,
+    pub fragments: Vec<Fragment>,
+}
+
+/// Reads the fields of an index one after the other
+struct Cursor<'a>(&'a [u8]);
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        ensure!(self.0.len() >= n, "truncated index");
+        let (r, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(r)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> Result<usize> {
+        usize::try_from(self.u64()?).context("offset out of range")
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).context("invalid UTF-8 string")
+    }
+}
+
+fn push_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+impl Index {
+    /// Index `file` by scanning `content`, the content of `file` once
+    /// decompressed if need be
+    pub fn build(file: &fs::File, content: impl Read) -> std::io::Result<Index> {
+        let source = Source::of(file)?;
+        Ok(Index {
+            source,
+            fragments: FastaReader::new(content, false).collect(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut r = Vec::new();
+        r.extend_from_slice(MAGIC);
+        r.extend_from_slice(&VERSION.to_le_bytes());
+        r.extend_from_slice(&self.source.size.to_le_bytes());
+        r.extend_from_slice(&self.source.mtime.to_le_bytes());
+        r.extend_from_slice(&self.source.sample);
+        r.extend_from_slice(&(self.fragments.len() as u64).to_le_bytes());
+        for f in self.fragments.iter() {
+            push_string(&mut r, &f.id);
+            r.push(u8::from(f.name.is_some()));
+            push_string(&mut r, f.name.as_deref().unwrap_or_default());
+            push_string(&mut r, &f.header);
+            for x in [f.pos.0, f.pos.1, f.line_width] {
+                r.extend_from_slice(&(x as u64).to_le_bytes());
+            }
+        }
+        let checksum = md5::compute(&r);
+        r.extend_from_slice(&checksum.0);
+        r
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Index> {
+        ensure!(bytes.starts_with(MAGIC), "not a FUSTA index");
+        ensure!(
+            bytes.len() >= MAGIC.len() + CHECKSUM_SIZE,
+            "truncated index"
+        );
+        let (content, checksum) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+        let mut cursor = Cursor(&content[MAGIC.len()..]);
+        let version = cursor.u32()?;
+        if version != VERSION {
+            bail!(
+                "index version {} is not supported, expected {}",
+                version,
+                VERSION
+            );
+        }
+        ensure!(md5::compute(content).0 == checksum, "corrupted index");
+
+        let source = Source {
+            size: cursor.u64()?,
+            mtime: cursor.u64()?,
+            sample: cursor.take(16)?.try_into().unwrap(),
+        };
+        let count = cursor.usize()?;
+        let mut fragments = Vec::with_capacity(std::cmp::min(count, content.len()));
+        for _ in 0..count {
+            let id = cursor.string()?;
+            let has_name = cursor.u8()? != 0;
+            let name = cursor.string()?;
+            let header = cursor.string()?;
+            let (start, end, line_width) = (cursor.usize()?, cursor.usize()?, cursor.usize()?);
+            ensure!(
+                start <= end && end as u64 <= source.size,
+                "invalid range for `{}`",
+                id
+            );
+            fragments.push(Fragment {
+                id: id.into(),
+                name: has_name.then_some(name),
+                header,
+                pos: (start, end),
+                len: end - start,
+                line_width,
+                seq: None,
+            });
+        }
+        ensure!(cursor.0.is_empty(), "trailing data in index");
+        Ok(Index { source, fragments })
+    }
+
+    /// Write the index to `path`, atomically
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push("#fusta#");
+        let mut tmp = fs::File::create(&tmp_path)?;
+        tmp.write_all(&self.to_bytes())?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, path)
+    }
+
+    /// The fragments of `file` as indexed in `path`, provided that the
+    /// index is well-formed and that `file` did not change since
+    pub fn load(path: &Path, file: &fs::File) -> Result<Vec<Fragment>> {
+        let bytes = fs::read(path)?;
+        let index = Index::from_bytes(&bytes)?;
+        let source = Source::of(file)?;
+        if index.source.size != source.size {
+            bail!("the file size changed since it was indexed")
+        }
+        if index.source.mtime != source.mtime || index.source.sample != source.sample {
+            bail!("the file was modified since it was indexed")
+        }
+        Ok(index.fragments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let fasta = dir.path().join("ref.fa");
+        fs::write(&fasta, ">a desc  here\nACGT\nAC\n>b\r\nGG\r\n>c\n>d\nacgt").unwrap();
+        let file = fs::File::open(&fasta).unwrap();
+        let path = path_for(&fasta);
+        assert_eq!(path, dir.path().join("ref.fa.fusta-idx"));
+        let index = Index::build(&file, &file).unwrap();
+        index.write(&path).unwrap();
+
+        let scanned = FastaReader::new(&file, false).collect::<Vec<_>>();
+        let loaded = Index::load(&path, &file).unwrap();
+        assert_eq!(loaded.len(), 4);
+        for (l, s) in loaded.iter().zip(scanned.iter()) {
+            assert_eq!(
+                (&l.id, &l.name, &l.header, l.pos, l.len, l.line_width),
+                (&s.id, &s.name, &s.header, s.pos, s.len, s.line_width)
+            );
+        }
+
+        // Any change is detected
+        let bytes = fs::read(&path).unwrap();
+        let mut corrupted = bytes.clone();
+        corrupted[30] ^= 1;
+        assert!(Index::from_bytes(&corrupted).is_err());
+        assert!(Index::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut newer = bytes.clone();
+        newer[8] = 2;
+        let e = Index::from_bytes(&newer).unwrap_err().to_string();
+        assert!(e.contains("version 2"), "{}", e);
+
+        // ...as well as the changes of the indexed file
+        fs::write(&fasta, ">a desc  here\nACGT\nAC\n>b\r\nGG\r\n>c\n>d\nacgT").unwrap();
+        let file = fs::File::open(&fasta).unwrap();
+        assert!(Index::load(&path, &file).is_err());
+        fs::write(&fasta, ">a\n").unwrap();
+        assert!(Index::load(&path, &file).is_err());
+    }
+}
@@ -1 +1,16 @@
+pub mod aliases;
+pub mod annotation;
+pub mod assembly;
+pub mod digest;
+pub mod fai;
 pub mod fasta;
+// Only built for the fuzz targets in fuzz/, which enable this feature
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzzing;
+pub mod ids;
+pub mod index;
+pub mod pcr;
+pub mod repeats;
+pub mod sanity;
+pub mod tags;
+pub mod transform;
@@ -0,0 +1,91 @@
+//! A logger writing one JSON object per line, for log collectors such as the
+//! ones of container runtimes.
+use log::{LevelFilter, Log, Metadata, Record};
+use serde_json::json;
+use simplelog::{Config, SharedLogger};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct JsonLogger<W: Write + Send> {
+    level: LevelFilter,
+    writer: Mutex<W>,
+}
+impl<W: Write + Send + 'static> JsonLogger<W> {
+    pub fn new(level: LevelFilter, writer: W) -> Box<JsonLogger<W>> {
+        Box::new(JsonLogger {
+            level,
+            writer: Mutex::new(writer),
+        })
+    }
+}
+
+/// The JSON line describing `record`
+fn format(record: &Record) -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    json!({
+        "time": time,
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    })
+    .to_string()
+}
+
+impl<W: Write + Send> Log for JsonLogger<W> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            if let Ok(mut writer) = self.writer.lock() {
+                let _ = writeln!(writer, "{}", format(record));
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writer.flush();
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> SharedLogger for JsonLogger<W> {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_lines() {
+        let line = format(
+            &Record::builder()
+                .level(log::Level::Warn)
+                .target("fusta::fs")
+                .args(format_args!("`{}` is \"odd\"", "a\tb"))
+                .build(),
+        );
+        let parsed: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed["level"], "WARN");
+        assert_eq!(parsed["target"], "fusta::fs");
+        assert_eq!(parsed["message"], "`a\tb` is \"odd\"");
+        assert!(!line.contains('\n'));
+    }
+}
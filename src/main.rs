@@ -1,4 +1,4 @@
-#![allow(clippy::redundant_field_names)]
+#![allow(clippy::redundant_field_names, clippy::needless_as_bytes)]
 #[macro_use]
 extern crate lazy_static;
 
@@ -6,68 +6,440 @@ use anyhow::{bail, Context, Result};
 use clap::*;
 use daemonize::*;
 use log::*;
+use output::Output;
+use serde::Serialize;
 use simplelog::*;
 
+pub mod backing;
+pub mod bgzf;
+#[cfg(feature = "browse")]
+pub mod browse;
+#[cfg(feature = "catalog")]
+pub mod catalog;
+pub mod chaos;
+pub mod checksum;
+pub mod collection;
+pub mod config;
+pub mod derive;
+pub mod doctor;
+pub mod error;
 pub mod fs;
+pub mod logging;
+pub mod notifications;
+pub mod output;
+pub mod placement;
+pub mod pool;
+pub mod session;
+pub mod stats;
+pub mod throttle;
+pub mod trace;
+pub mod umount;
+use collection::Collection;
 use fs::*;
+use fusta::aliases::Aliases;
+use fusta::annotation::GeneticCode;
+use fusta::ids::{Codec, IdRules};
+use fusta::repeats::TelomereMotifs;
+use fusta::sanity::Alphabet;
+use fusta::tags::TagFilter;
+use fusta::transform::Pipeline;
+
+/// Whether desktop notifications are sent, i.e. unless --no-notify is given
+static NOTIFICATIONS: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(true);
 
 #[cfg(not(feature = "notifications"))]
 fn notify<S: AsRef<str>>(_: S) {}
 #[cfg(feature = "notifications")]
 fn notify<S: AsRef<str>>(msg: S) {
     use notify_rust::Notification;
-    Notification::new()
+    if !NOTIFICATIONS.load(std::sync::atomic::Ordering::Relaxed) {
+        return;
+    }
+    // There may be no notification daemon to talk to, e.g. in a container
+    if let Err(e) = Notification::new()
         .summary("FUSTA")
         .body(msg.as_ref())
         .show()
-        .unwrap();
+    {
+        debug!("Unable to send a notification: {}", e);
+    }
+}
+
+/// Whether a fusermount binary, required by --auto-unmount, is in $PATH
+fn has_fusermount() -> bool {
+    std::env::var_os("PATH")
+        .map(|paths| {
+            std::env::split_paths(&paths)
+                .any(|p| p.join("fusermount").is_file() || p.join("fusermount3").is_file())
+        })
+        .unwrap_or(false)
+}
+
+/// Returns the absolute, symlink-free version of `path`, even if its last
+/// component does not exist yet.
+fn absolute_path(path: &std::path::Path) -> Result<std::path::PathBuf> {
+    if path.exists() {
+        return path
+            .canonicalize()
+            .context(format!("Unable to resolve {:?}", path));
+    }
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => std::env::current_dir().context("Unable to read current directory")?,
+    };
+    Ok(absolute_path(&parent)?.join(path.file_name().unwrap_or_default()))
+}
+
+/// Lists the directories where a FUSTA instance is currently mounted
+#[cfg(target_os = "linux")]
+fn fusta_mountpoints() -> Vec<std::path::PathBuf> {
+    std::fs::read_to_string("/proc/self/mounts")
+        .map(|mounts| {
+            mounts
+                .lines()
+                .map(|l| l.split(' ').collect::<Vec<_>>())
+                .filter(|fields| fields.len() > 2 && fields[0] == "FUSTA")
+                .map(|fields| std::path::PathBuf::from(placement::unescape(fields[1])))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+#[cfg(not(target_os = "linux"))]
+fn fusta_mountpoints() -> Vec<std::path::PathBuf> {
+    Vec::new()
+}
+
+/// Ensure that FUSTA will never have to go through a FUSTA mount, including
+/// its own, to access its data.
+fn check_nesting(fasta_file: &std::path::Path, mountpoint: &std::path::Path) -> Result<()> {
+    let fasta_file = absolute_path(fasta_file)?;
+    let mountpoint = absolute_path(mountpoint)?;
+
+    if fasta_file.starts_with(&mountpoint) {
+        bail!(
+            "{:?} lies within the mount point {:?}; please choose another mount point",
+            fasta_file,
+            mountpoint
+        );
+    }
+    for existing in fusta_mountpoints() {
+        if fasta_file.starts_with(&existing) {
+            bail!(
+                "{:?} is exposed by another FUSTA instance mounted on {:?}; please use the original file instead",
+                fasta_file,
+                existing
+            );
+        }
+        if mountpoint.starts_with(&existing) {
+            bail!(
+                "the mount point {:?} lies within another FUSTA instance mounted on {:?}",
+                mountpoint,
+                existing
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Create the empty FASTA file `filename` for --create, unless it already
+/// exists and is empty
+fn create_fasta(filename: &str) -> Result<()> {
+    match std::fs::metadata(filename) {
+        Ok(metadata) if metadata.len() > 0 => bail!(
+            "`{}` already exists and is not empty; mount it without --create",
+            filename
+        ),
+        Ok(_) => Ok(()),
+        Err(_) => {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(filename)
+                .with_context(|| format!("Unable to create `{}`", filename))?;
+            info!("Created `{}`", filename);
+            Ok(())
+        }
+    }
+}
+
+/// Refuse to mount `filename` if its beginning shows that it is not a FASTA
+/// file of `expected`, and warn about what looks suspicious; reading errors
+/// are left for the actual parsing to report.
+fn check_sanity(filename: &str, expected: Option<Alphabet>) -> Result<()> {
+    use std::io::Read;
+    let mut sample = Vec::new();
+    // bgzip-compressed files are checked from their content
+    if let Ok(file) = backing::Backing::open(filename) {
+        let _ = file
+            .reader()
+            .take(fusta::sanity::SAMPLE_SIZE as u64)
+            .read_to_end(&mut sample);
+    }
+    for warning in fusta::sanity::check(&sample, expected)
+        .with_context(|| format!("`{}` can not be mounted", filename))?
+    {
+        warn!("`{}`: {}", filename, warning);
+    }
+    Ok(())
+}
+
+/// Returns the GID of a group given either by name or by GID
+fn resolve_group(group: &str) -> Result<u32> {
+    if let Ok(gid) = group.parse::<u32>() {
+        return Ok(gid);
+    }
+    let name = std::ffi::CString::new(group).context(format!("invalid group `{}`", group))?;
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if entry.is_null() {
+        bail!("group `{}` does not exist", group)
+    }
+    Ok(unsafe { (*entry).gr_gid })
+}
+
+/// Where to find a running instance, reported with `--json`
+#[derive(Serialize)]
+struct MountReport {
+    fasta: std::path::PathBuf,
+    mountpoint: std::path::PathBuf,
+    log: Option<std::path::PathBuf>,
 }
 
 #[derive(Debug, Clone)]
 struct RunEnvironment {
     mountpoint: std::path::PathBuf,
     created_mountpoint: bool,
+    scratch: bool, // Whether the mount point should be removed once unmounted
+}
+
+/// The ID of the batch job FUSTA runs in and of its process, to tell apart
+/// the scratch mount points of the jobs sharing a node
+fn job_id() -> String {
+    let job = ["SLURM_JOB_ID", "PBS_JOBID", "LSB_JOBID"]
+        .iter()
+        .find_map(|v| std::env::var(v).ok());
+    match job {
+        Some(job) => format!("{}-{}", job, std::process::id()),
+        None => std::process::id().to_string(),
+    }
 }
 fn main() -> Result<()> {
     human_panic::setup_panic!();
-    let args =
+    let app =
         App::new("fusta")
         .setting(AppSettings::ColoredHelp)
         .setting(AppSettings::ColorAuto)
         .setting(AppSettings::UnifiedHelpMessage)
         .version(crate_version!())
         .author(crate_authors!())
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(App::new("doctor")
+                    .about("Check that a running FUSTA instance works as expected, and suggests fixes otherwise")
+                    .arg(Arg::with_name("MOUNTPOINT")
+                         .help("The mount point of the FUSTA instance to check")
+                         .required(true)
+                         .index(1)))
+        .subcommand(App::new("umount")
+                    .about("Unmount a FUSTA instance, first asking what to do with the changes not written back to the FASTA file yet, if any")
+                    .arg(Arg::with_name("MOUNTPOINT")
+                         .help("The mount point of the FUSTA instance to unmount")
+                         .required(true)
+                         .index(1))
+                    .arg(Arg::with_name("if-clean")
+                         .long("if-clean")
+                         .help("Refuse to unmount if some changes have not been written back"))
+                    .arg(Arg::with_name("force-discard")
+                         .long("force-discard")
+                         .help("Drop the changes not written back, leaving the FASTA file untouched")
+                         .conflicts_with("if-clean")))
+        .subcommand(App::new("stats")
+                    .about("Print the statistics of a FASTA file (length, N50, GC content, ...) and of each of its sequences, without mounting it")
+                    .arg(Arg::with_name("FASTA")
+                         .help("The (multi)FASTA file to describe")
+                         .required(true)
+                         .index(1)))
+        .subcommand(App::new("derive")
+                    .about("Write the companion files of a FASTA file next to it, in a single pass and without mounting it; all of them are written unless some are selected")
+                    .arg(Arg::with_name("FASTA")
+                         .help("The (multi)FASTA file to derive the companion files of")
+                         .required(true)
+                         .index(1))
+                    .arg(Arg::with_name("fai")
+                         .long("fai")
+                         .help("Write the samtools index, FASTA.fai"))
+                    .arg(Arg::with_name("dict")
+                         .long("dict")
+                         .help("Write the sequence dictionary, as a SAM header, to FASTA without extension.dict"))
+                    .arg(Arg::with_name("chromsizes")
+                         .long("chromsizes")
+                         .help("Write the tab-separated ID and length of every sequence to FASTA without extension.chrom.sizes"))
+                    .arg(Arg::with_name("checksums")
+                         .long("checksums")
+                         .help("Write the MD5 digest of every sequence to FASTA without extension.checksums.md5, as expected by --verify")))
+        .subcommand(App::new("index")
+                    .about("Index a FASTA file, so that it is mounted without being scanned again for as long as it is not modified")
+                    .arg(Arg::with_name("FASTA")
+                         .help("The (multi)FASTA file to index, in FASTA.fusta-idx")
+                         .required(true)
+                         .index(1)))
+        .subcommand(App::new("checksum")
+                    .about("Compute the MD5 digest of every sequence of a FASTA file in parallel, without mounting it; they are cached so that an interrupted run can be resumed, and reused by the mounts of the file")
+                    .arg(Arg::with_name("FASTA")
+                         .help("The (multi)FASTA file to checksum")
+                         .required(true)
+                         .index(1))
+                    .arg(Arg::with_name("threads")
+                         .long("threads")
+                         .short('j')
+                         .help("How many sequences to checksum at once; defaults to the number of CPUs")
+                         .value_name("N")
+                         .takes_value(true))
+                    .arg(Arg::with_name("cache")
+                         .long("cache")
+                         .help("Where to cache the digests; defaults to FASTA.fusta-digests, which is where the mounts look for them")
+                         .value_name("FILE")
+                         .takes_value(true)))
         .arg(Arg::with_name("FASTA")
-             .help("A (multi)FASTA file containing the sequences to mount")
+             .help("A (multi)FASTA file containing the sequences to mount, or a directory of *.fa, *.fasta, *.fa.gz and *.fasta.gz files to mount side by side, one subtree per file")
              .required(true)
              .index(1))
+        .arg(Arg::with_name("watch")
+             .long("watch")
+             .help("When mounting a directory, mount the FASTA files written or moved to it while mounted, and unmount the ones deleted or moved away"))
+        .arg(Arg::with_name("create")
+             .long("create")
+             .help("Create FASTA if it does not exist, to compose it from scratch through append/ and seqs/; an existing FASTA must be empty")
+             .conflicts_with("headers-only"))
         .arg(Arg::with_name("verbose")
              .short('v')
              .action(ArgAction::Count)
              .help("Sets the level of verbosity"))
+        .arg(Arg::with_name("json")
+             .long("json")
+             .global(true)
+             .help("Report the results as JSON on the standard output"))
+        .arg(Arg::with_name("quiet")
+             .short('q')
+             .long("quiet")
+             .global(true)
+             .conflicts_with("json")
+             .help("Only print errors; the outcome is reported through the exit status"))
 
         .arg(Arg::with_name("mountpoint")
              .short('o')
              .long("mountpoint")
              .help("Specifies the directory to use as mountpoint; it will be created if it does not exist")
              .takes_value(true))
+        .arg(Arg::with_name("session-scratch")
+             .long("session-scratch")
+             .conflicts_with_all(&["mountpoint", "in-tmpdir"])
+             .help("Mount in a directory of $TMPDIR unique to the batch job (SLURM, PBS or LSF) and process, reported with --json; on SIGTERM or SIGINT, e.g. at the end of the job, FUSTA unmounts itself, writing the changes back, and removes the mount point"))
+        .arg(Arg::with_name("in-tmpdir")
+             .long("in-tmpdir")
+             .conflicts_with("mountpoint")
+             .help("Create the default mount point in $TMPDIR rather than in the current directory, e.g. on the local disk of an HPC node; this is done by default if the current directory lies on a parallel filesystem such as Lustre or GPFS"))
         .arg(Arg::with_name("nodaemon")
              .short('D')
              .long("no-daemon")
+             .alias("foreground")
              .help("Do not daemonize"))
+        .arg(Arg::with_name("auto-unmount")
+             .long("auto-unmount")
+             .help("Have the mount point unmounted if FUSTA is killed; requires fusermount and, for non-root users, user_allow_other in /etc/fuse.conf"))
+        .arg(Arg::with_name("log-format")
+             .long("log-format")
+             .help("Write the logs as colored text, or as one JSON object per line, e.g. for log collectors")
+             .possible_values(["text", "json"])
+             .default_value("text"))
+        .arg(Arg::with_name("no-notify")
+             .long("no-notify")
+             .help("Do not send desktop notifications"))
+        .arg(Arg::with_name("container")
+             .long("container")
+             .help("Run in a container: the same as --no-daemon --log-format json --no-notify, plus --auto-unmount if fusermount is available; on SIGTERM or SIGINT, FUSTA unmounts itself, writing the changes back"))
+        .arg(Arg::with_name("type")
+             .long("type")
+             .help("The type of the sequences; FUSTA refuses to mount files that look otherwise, and warns about proteins if it is not set")
+             .possible_values(["dna", "protein"])
+             .takes_value(true))
 
     // Technical options
         .arg(Arg::with_name("max-cache")
              .short('C')
              .long("max-cache")
-             .help("Set the maximum amount of memory to use to cache writes (MB)")
+             .help("Set the maximum amount of memory to use to cache writes and, with --cache memory, the sequences (MB); beyond it, they are set aside to a temporary file")
              .default_value("500")
              .takes_value(true))
+        .arg(Arg::with_name("undo-budget")
+             .long("undo-budget")
+             .help("Set the maximum amount of memory to use to keep the previous states of the edited sequences, for ctl/undo (MB); 0 disables ctl/undo")
+             .default_value("100")
+             .takes_value(true))
+        .arg(Arg::with_name("gff")
+             .long("gff")
+             .help("A GFF3 file annotating the FASTA file; the translations of its CDS are exposed in proteins/")
+             .value_name("GFF")
+             .takes_value(true))
+        .arg(Arg::with_name("genetic-code")
+             .long("genetic-code")
+             .help("The NCBI genetic code to use to translate the CDS of the annotation")
+             .default_value("1")
+             .takes_value(true))
+        .arg(Arg::with_name("max-amplicon")
+             .long("max-amplicon")
+             .help("Set the length of the longest amplicons reported in pcr/ (bp)")
+             .default_value("4000")
+             .takes_value(true))
+        .arg(Arg::with_name("telomere-motifs")
+             .long("telomere-motifs")
+             .help("A comma-separated list of the telomeric motifs looked for at the ends of the sequences in repeats.txt")
+             .default_value("TTAGGG")
+             .takes_value(true))
+        .arg(Arg::with_name("end-length")
+             .long("end-length")
+             .help("Set how many bases of the ends of the sequences are exposed in ends/")
+             .default_value("1000")
+             .takes_value(true))
+        .arg(Arg::with_name("taxa-delimiter")
+             .long("taxa-delimiter")
+             .help("Set what separates the ranks of the taxonomies in the descriptions, e.g. `Bacteria;Firmicutes;Bacilli`, by which taxa/ groups the sequences")
+             .default_value(";")
+             .takes_value(true))
         .arg(Arg::with_name("cache")
              .long("cache")
-             .help("Use either mmap, fseek(2) or memory-backed cache to extract sequences from FASTA files. WARNING: memory caching use as much RAM as the size of the FASTA file should be available.")
+             .help("Use either mmap, fseek(2) or memory-backed cache to extract sequences from FASTA files. With memory caching, the least recently used sequences beyond --max-cache are spilled to a temporary file, and read back from there when accessed.")
              .possible_values(["file", "mmap", "memory"])
              .default_value("mmap"))
+        .arg(Arg::with_name("headers-only")
+             .long("headers-only")
+             .help("Only read the headers and the sizes of the sequences, mounting the file read-only: the sequences can not be read, but infos.* and labels.txt are available right away; useful to inspect very large files")
+             .conflicts_with_all(&["prefetch", "verify", "gff"]))
+        .arg(Arg::with_name("noatime")
+             .long("noatime")
+             .help("Do not update the access times of the sequences when they are read; their modification times are updated nonetheless"))
+        .arg(Arg::with_name("no-index")
+             .long("no-index")
+             .help("Do not write FASTA.fusta-idx after scanning FASTA, nor update it when writing the changes back"))
+        .arg(Arg::with_name("seed")
+             .long("seed")
+             .help("Seed the randomized features, i.e. the faults injected by --chaos, so that a run is reproduced by passing it the seed recorded in .fusta/manifest.json; drawn from the clock by default")
+             .value_name("N")
+             .takes_value(true))
+        .arg(Arg::with_name("prefetch")
+             .long("prefetch")
+             .help("Once mounted, compute the statistics of all the sequences in the background (index), after reading the whole FASTA file to warm the page cache (all); useful on cold network storage")
+             .possible_values(["all", "index", "none"])
+             .default_value("none"))
+        .arg(Arg::with_name("retries")
+             .long("retries")
+             .help("How many times to retry the reads of the FASTA file failing with a transient error, e.g. EIO or ESTALE on NFS or Lustre, before returning EIO; only with --cache file, as the failures of mmap can not be recovered from")
+             .value_name("N")
+             .default_value("3"))
+        .arg(Arg::with_name("retry-delay")
+             .long("retry-delay")
+             .help("How long to wait before retrying a failed read, the delay doubling at every retry, e.g. 100ms or 2s")
+             .value_name("DURATION")
+             .default_value("100ms"))
 
     // Other options
         .arg(Arg::with_name("csv-separator")
@@ -76,11 +448,264 @@ fn main() -> Result<()> {
              .help("Set the separator to use in CSV files")
              .default_value(",")
              .takes_value(true))
+        .arg(Arg::with_name("verify")
+             .long("verify")
+             .help("Before mounting, check the digests of the sequences against the ones recorded in the previous session, or against the ones listed in the given file (one `MD5 ID` per line)")
+             .takes_value(true)
+             .min_values(0)
+             .max_values(1)
+             .require_equals(true)
+             .value_name("CHECKSUMS"))
+        .arg(Arg::with_name("on-mismatch")
+             .long("on-mismatch")
+             .help("Whether to refuse to mount or just to warn when a sequence does not match its reference digest")
+             .possible_values(["refuse", "warn"])
+             .default_value("refuse"))
+        .arg(Arg::with_name("allow-other")
+             .long("allow-other")
+             .help("Let other users access the mounted file; requires `user_allow_other` to be set in /etc/fuse.conf"))
+        .arg(Arg::with_name("writer-uid")
+             .long("writer-uid")
+             .help("With --allow-other, only let the given user (besides the one running FUSTA) modify the mounted file; may be repeated")
+             .takes_value(true)
+             .multiple_occurrences(true)
+             .value_name("UID")
+             .requires("allow-other"))
+        .arg(Arg::with_name("writer-group")
+             .long("writer-group")
+             .help("With --allow-other, only let the members of the given group (name or GID) modify the mounted file; may be repeated")
+             .takes_value(true)
+             .multiple_occurrences(true)
+             .value_name("GROUP")
+             .requires("allow-other"))
+        .arg(Arg::with_name("on-collision")
+             .long("on-collision")
+             .help("What to do when appending a sequence whose ID already exists: skip it, overwrite the existing one, or suffix the new ID (`ID__2`, `ID__3`, ...). Defaults to overwrite with --allow-overwrite, skip otherwise")
+             .possible_values(["skip", "overwrite", "suffix"])
+             .takes_value(true))
+        .arg(Arg::with_name("on-edit-conflict")
+             .long("on-edit-conflict")
+             .help("What to do when an appended sequence would overwrite one whose edits have not been written back yet: refuse it, reporting the conflict, overwrite the edits anyway, or suffix the new ID")
+             .possible_values(["refuse", "overwrite", "suffix"])
+             .default_value("refuse"))
+        .arg(Arg::with_name("append-id-template")
+             .long("append-id-template")
+             .help("How to name the sequences appended without a header; `{filename}` is replaced by the name of the file (without extension) and `{count}` by the number of such sequences appended so far")
+             .default_value("{filename}")
+             .takes_value(true))
         .arg(Arg::with_name("overwrite")
              .short('W')
              .long("allow-overwrite")
-            .help("allow FUSTA to overwrite existing sequences, when (i) appending new sequences conflicting with an existing ID, (ii) renaming sequences"))
-        .get_matches();
+            .help("allow FUSTA to overwrite existing sequences, when (i) appending new sequences conflicting with an existing ID, (ii) renaming sequences")
+             .overrides_with("no-overwrite"))
+        .arg(Arg::with_name("no-overwrite")
+             .long("no-overwrite")
+             .help("forbid FUSTA to overwrite existing sequences; this is the default, but it overrides a previous --allow-overwrite, e.g. from an alias")
+             .overrides_with("overwrite"))
+        .arg(Arg::with_name("defer-deletions")
+             .long("defer-deletions")
+             .help("do not rewrite the FASTA file after every deletion, but only at the next write-back, e.g. through ctl/commit or when unmounting; speeds up the removal of many sequences with --cache memory"))
+        .arg(Arg::with_name("writable-fasta")
+             .long("writable-fasta")
+             .help("make the files in fasta/ writable; edits to their header rename the sequence or change its description"))
+        .arg(Arg::with_name("allow-empty")
+             .long("allow-empty")
+             .help("write the FASTA file back whatever the share of its content lost, even if all its sequences have been deleted"))
+        .arg(Arg::with_name("max-loss")
+             .long("max-loss")
+             .help("hold back the write-backs that would lose more than FRACTION of the FASTA file content since it was mounted, until confirmed through ctl/commit")
+             .value_name("FRACTION")
+             .default_value("0.5")
+             .takes_value(true))
+        .arg(Arg::with_name("max-shrink")
+             .long("max-shrink")
+             .help("hold back the write-backs that would shrink an edited sequence by more than FRACTION of its length, until confirmed through ctl/commit; see .fusta/commit-report.txt")
+             .value_name("FRACTION")
+             .takes_value(true))
+        .arg(Arg::with_name("output")
+             .long("output")
+             .help("write the sequences back to FILE rather than to the mounted FASTA file, which is left untouched")
+             .value_name("FILE")
+             .takes_value(true))
+        .arg(Arg::with_name("merge")
+             .long("merge")
+             .help("also mount the sequences of FASTA alongside those of the mounted file; each of them is written back to the file it comes from")
+             .value_name("FASTA")
+             .multiple_occurrences(true)
+             .conflicts_with("output")
+             .takes_value(true))
+        .arg(Arg::with_name("on-merge-collision")
+             .long("on-merge-collision")
+             .help("what to do when a file given to --merge contains an ID already mounted: refuse to mount, suffix it (`ID__2`, `ID__3`, ...), or prefix it with the name of its file (`FILE__ID`); the headers are written back unchanged")
+             .possible_values(["refuse", "suffix", "prefix"])
+             .default_value("refuse")
+             .takes_value(true))
+        .arg(Arg::with_name("aliases")
+             .long("aliases")
+             .help("a UCSC-style chromAlias.txt, listing on each line the tab-separated names of a sequence; its aliases are then resolved in fasta/, seqs/ and get/, and listed in aliases/")
+             .value_name("FILE")
+             .takes_value(true))
+        .arg(Arg::with_name("write-filter")
+             .long("write-filter")
+             .help("only write back the sequences whose tags, as set in tags/, fulfill all the comma-separated conditions among key=value, key!=value, key, and !key")
+             .value_name("CONDITIONS")
+             .takes_value(true))
+        .arg(Arg::with_name("write-transform")
+             .long("write-transform")
+             .help("normalize the sequences written to seqs/, fasta/ and append/ with the comma-separated transformations, e.g. strip-whitespace,uppercase,dna to clean up sequences pasted from a web page")
+             .value_name("TRANSFORMATIONS")
+             .takes_value(true))
+        .arg(Arg::with_name("normalize-ids")
+             .long("normalize-ids")
+             .help("derive the file names from the sequence IDs through a comma-separated list of rules among strip-prefix=PREFIX, strip-suffix=SUFFIX, truncate-at-space, truncate-at=CHARS, sanitize, and max-len=N; the headers are left untouched in the FASTA file")
+             .value_name("RULES")
+             .takes_value(true))
+        .arg(Arg::with_name("filename-codec")
+             .long("filename-codec")
+             .help("how to turn the sequence IDs into file names: as they are (plain), percent-encoding the characters that can not appear in a file name (percent), or further escaping upper-case letters for case-insensitive filesystems (folded)")
+             .possible_values(["plain", "percent", "folded"])
+             .default_value("plain"))
+        .arg(Arg::with_name("config")
+             .long("config")
+             .help("read the settings from FILE rather than from $XDG_CONFIG_HOME/fusta/config, e.g. the size of the operations worth a desktop notification, or the directories exposing the transformed sequences")
+             .value_name("FILE")
+             .takes_value(true));
+    #[cfg(feature = "browse")]
+    let app = app.subcommand(
+        App::new("browse")
+            .about("Look through the sequences of a FASTA file in a terminal interface, and rename, delete or extract them without mounting it")
+            .arg(
+                Arg::with_name("FASTA")
+                    .help("The (multi)FASTA file to browse")
+                    .required(true)
+                    .index(1),
+            ),
+    );
+    #[cfg(feature = "catalog")]
+    let app = app.arg(
+        Arg::with_name("catalog")
+            .long("catalog")
+            .help("Keep the records of FASTA, and the inodes, digests and tags of its sequences, in an SQLite database next to the session rather than in FASTA.fusta-idx and the session; meant for files of millions of sequences, e.g. nt or SILVA, whose recorded digests then stay in the database rather than being held in memory"),
+    );
+    #[cfg(feature = "tracing")]
+    let app = app.arg(
+        Arg::with_name("trace-chrome")
+            .long("trace-chrome")
+            .help("record the timing of all the filesystem operations in FILE, to be opened with chrome://tracing or Perfetto")
+            .value_name("FILE")
+            .takes_value(true),
+    );
+    #[cfg(debug_assertions)]
+    let app = app.arg(
+        Arg::with_name("chaos")
+            .long("chaos")
+            .help("inject faults in the accesses to the FASTA file, e.g. `read-delay=50ms,write-error=0.01`; available keys are read-delay, write-delay, read-error and write-error")
+            .value_name("FAULTS")
+            .takes_value(true),
+    );
+    let args = app.get_matches();
+
+    match args.subcommand() {
+        Some(("doctor", sub_args)) => {
+            return doctor::run(
+                sub_args.value_of("MOUNTPOINT").unwrap(),
+                Output::from_args(sub_args),
+            )
+        }
+        Some(("umount", sub_args)) => {
+            return umount::run(
+                sub_args.value_of("MOUNTPOINT").unwrap(),
+                if sub_args.is_present("if-clean") {
+                    umount::Policy::IfClean
+                } else if sub_args.is_present("force-discard") {
+                    umount::Policy::Discard
+                } else {
+                    umount::Policy::Ask
+                },
+            )
+        }
+        Some(("derive", sub_args)) => {
+            let selected = [
+                ("fai", derive::Companion::Fai),
+                ("dict", derive::Companion::Dict),
+                ("chromsizes", derive::Companion::ChromSizes),
+                ("checksums", derive::Companion::Checksums),
+            ]
+            .iter()
+            .filter(|(flag, _)| sub_args.is_present(flag))
+            .map(|(_, companion)| *companion)
+            .collect::<Vec<_>>();
+            return derive::run(
+                sub_args.value_of("FASTA").unwrap(),
+                if selected.is_empty() {
+                    &derive::Companion::ALL
+                } else {
+                    &selected
+                },
+                Output::from_args(sub_args),
+            );
+        }
+        Some(("index", sub_args)) => {
+            let fasta = sub_args.value_of("FASTA").unwrap();
+            let file = backing::Backing::open(fasta)
+                .with_context(|| format!("Failed to open `{}`", fasta))?;
+            let index = fusta::index::Index::build(file.file(), file.reader())
+                .with_context(|| format!("Unable to index `{}`", fasta))?;
+            let path = fusta::index::path_for(std::path::Path::new(fasta));
+            index
+                .write(&path)
+                .with_context(|| format!("Unable to write `{}`", path.display()))?;
+            let report = serde_json::json!({
+                "file": fasta,
+                "sequences": index.fragments.len(),
+                "index": path,
+                "version": fusta::index::VERSION,
+            });
+            return Output::from_args(sub_args).emit(&report, |_| {
+                println!(
+                    "{} sequences indexed in {}",
+                    index.fragments.len(),
+                    path.display()
+                )
+            });
+        }
+        Some(("checksum", sub_args)) => {
+            let fasta = sub_args.value_of("FASTA").unwrap();
+            let threads = match sub_args.value_of("threads") {
+                Some(n) => n
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .context("--threads should be a positive number")?,
+                None => std::thread::available_parallelism()
+                    .map(|n| n.get())
+                    .unwrap_or(1),
+            };
+            return checksum::run(
+                fasta,
+                threads,
+                &sub_args
+                    .value_of("cache")
+                    .map(std::path::PathBuf::from)
+                    .unwrap_or_else(|| checksum::DigestCache::path_for(fasta)),
+                Output::from_args(sub_args),
+            );
+        }
+        #[cfg(feature = "browse")]
+        Some(("browse", sub_args)) => {
+            let fasta = sub_args.value_of("FASTA").unwrap();
+            check_sanity(fasta, None)?;
+            return browse::run(fasta);
+        }
+        Some(("stats", sub_args)) => {
+            return stats::run(
+                sub_args.value_of("FASTA").unwrap(),
+                Output::from_args(sub_args),
+            )
+        }
+        _ => {}
+    }
+    let output = Output::from_args(&args);
 
     let log_level = match args.get_one::<u8>("verbose").copied().unwrap_or_default() {
         0 => LevelFilter::Info,
@@ -88,60 +713,362 @@ fn main() -> Result<()> {
         2 => LevelFilter::Trace,
         _ => LevelFilter::Trace,
     };
+    let container = args.is_present("container");
+    let scratch = args.is_present("session-scratch");
+    let daemonize = !(args.is_present("nodaemon") || container);
+    let json_logs = container || args.value_of("log-format") == Some("json");
+    if container || args.is_present("no-notify") {
+        NOTIFICATIONS.store(false, std::sync::atomic::Ordering::Relaxed);
+    }
+    // Keep the standard output clean for scripts; otherwise, the loggers let
+    // everything through, and the level is set globally, so that it can be
+    // changed through ctl/config
+    let term_level = if output.is_human() {
+        LevelFilter::Trace
+    } else {
+        LevelFilter::Error
+    };
     let log_config = ConfigBuilder::new().build();
-    let mut loggers: Vec<Box<dyn SharedLogger>> = vec![TermLogger::new(
-        log_level,
-        log_config.clone(),
-        TerminalMode::Mixed,
-        simplelog::ColorChoice::Auto,
-    )];
-    if !args.is_present("nodaemon") {
+    let mut loggers: Vec<Box<dyn SharedLogger>> = vec![if json_logs {
+        logging::JsonLogger::new(term_level, std::io::stderr())
+    } else {
+        TermLogger::new(
+            term_level,
+            log_config.clone(),
+            TerminalMode::Mixed,
+            simplelog::ColorChoice::Auto,
+        )
+    }];
+    let mut log_file = None;
+    if daemonize {
         let log_file_path = tempfile::Builder::new()
             .prefix("fusta-")
             .suffix(".log")
             .tempfile()
             .context("Unable to create a temporary file")?;
 
-        println!(
-            "Logs ({:?}) available in {}",
-            log_level,
-            log_file_path.path().display()
-        );
-        loggers.push(WriteLogger::new(log_level, log_config, log_file_path));
+        if output.is_human() {
+            println!(
+                "Logs ({:?}) available in {}",
+                log_level,
+                log_file_path.path().display()
+            );
+        }
+        log_file = Some(log_file_path.path().to_owned());
+        if json_logs {
+            loggers.push(logging::JsonLogger::new(LevelFilter::Trace, log_file_path));
+        } else {
+            loggers.push(WriteLogger::new(
+                LevelFilter::Trace,
+                log_config,
+                log_file_path,
+            ));
+        }
     }
     CombinedLogger::init(loggers).context("Unable to init logger")?;
+    log::set_max_level(log_level);
 
     let fasta_file = value_t!(args, "FASTA", String)?;
-    let mountpoint = value_t!(args, "mountpoint", String).unwrap_or(format!(
-        "fusta-{}",
-        std::path::Path::new(&fasta_file)
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .context(format!("{:?} is not a valid path", &fasta_file))?
-    ));
-    let fuse_options: Vec<fuser::MountOption> = vec![
-        fuser::MountOption::FSName("FUSTA".to_string()),
-        // fuser::MountOption::AutoUnmount,
-        fuser::MountOption::DefaultPermissions,
-    ];
+    let mountpoint = match value_t!(args, "mountpoint", String) {
+        Ok(mountpoint) => mountpoint,
+        Err(_) => {
+            let name = format!(
+                "fusta-{}",
+                std::path::Path::new(&fasta_file)
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .context(format!("{:?} is not a valid path", &fasta_file))?
+            );
+            let cwd = std::env::current_dir().context("Unable to read current directory")?;
+            // HPC jobs are typically started on a parallel filesystem, but
+            // given a node-local $TMPDIR
+            if scratch {
+                std::env::temp_dir()
+                    .join(format!("{}-{}", name, job_id()))
+                    .to_string_lossy()
+                    .to_string()
+            } else if args.is_present("in-tmpdir")
+                || (placement::refuses_fuse(&cwd) && std::env::var_os("TMPDIR").is_some())
+            {
+                let mountpoint = std::env::temp_dir().join(name);
+                info!("Mounting in {:?}", mountpoint);
+                mountpoint.to_string_lossy().to_string()
+            } else {
+                name
+            }
+        }
+    };
+    let writers = fs::Writers {
+        uids: args
+            .values_of("writer-uid")
+            .map(|uids| {
+                uids.map(|uid| {
+                    uid.parse::<u32>()
+                        .context(format!("`{}` is not a valid UID", uid))
+                })
+                .collect::<Result<Vec<_>>>()
+            })
+            .transpose()?
+            .unwrap_or_default(),
+        gids: args
+            .values_of("writer-group")
+            .map(|groups| groups.map(resolve_group).collect::<Result<Vec<_>>>())
+            .transpose()?
+            .unwrap_or_default(),
+    };
+    let mut fuse_options: Vec<fuser::MountOption> =
+        vec![fuser::MountOption::FSName("FUSTA".to_string())];
+    if args.is_present("auto-unmount") {
+        fuse_options.push(fuser::MountOption::AutoUnmount);
+    } else if container {
+        if has_fusermount() {
+            fuse_options.push(fuser::MountOption::AutoUnmount);
+        } else {
+            info!("fusermount is not available; the mount point will be left behind if FUSTA is killed");
+        }
+    }
+    if args.is_present("allow-other") {
+        fuse_options.push(fuser::MountOption::AllowOther);
+    }
+    let headers_only = args.is_present("headers-only");
+    // The changes can not be written back to a bgzip-compressed file
+    let compressed = |f: &str| bgzf::is_bgzf_file(std::path::Path::new(f));
+    let read_only = (compressed(&fasta_file) && args.value_of("output").is_none())
+        || args
+            .values_of("merge")
+            .into_iter()
+            .flatten()
+            .any(compressed);
+    if read_only {
+        info!("The changes can not be written back to bgzip-compressed files; mounting read-only, unless --output is given for the mounted file");
+    }
+    if headers_only || read_only {
+        fuse_options.push(fuser::MountOption::RO);
+    }
+    let noatime = args.is_present("noatime");
+    if noatime {
+        fuse_options.push(fuser::MountOption::NoAtime);
+    }
+    // When writers are restricted, permissions are checked by FUSTA itself
+    // rather than by the kernel from the file modes.
+    if writers.is_empty() {
+        fuse_options.push(fuser::MountOption::DefaultPermissions);
+    }
+    let seed = args
+        .value_of("seed")
+        .map(|s| s.parse::<u64>())
+        .transpose()
+        .context("--seed should be a positive integer")?
+        .unwrap_or_else(chaos::random_seed);
+    #[cfg(debug_assertions)]
+    let chaos = args
+        .value_of("chaos")
+        .map(|spec| chaos::Chaos::parse(spec, seed))
+        .transpose()
+        .context("Invalid --chaos specification")?
+        .unwrap_or_default();
+    #[cfg(not(debug_assertions))]
+    let chaos = chaos::Chaos::default();
+    if chaos.is_active() {
+        warn!("Injecting faults: {}, with --seed {}", chaos, seed);
+    }
+    let id_rules = args
+        .value_of("normalize-ids")
+        .map(IdRules::parse)
+        .transpose()
+        .context("Invalid --normalize-ids rules")?
+        .unwrap_or_default();
+    let codec = Codec::parse(args.value_of("filename-codec").unwrap())?;
+    let config = config::Config::load(args.value_of("config").map(std::path::Path::new))?;
+    let max_loss = value_t!(args, "max-loss", f64)
+        .ok()
+        .filter(|x| (0. ..=1.).contains(x))
+        .context("--max-loss should be a number between 0 and 1")?;
+    let max_shrink = args
+        .value_of("max-shrink")
+        .map(|x| x.parse::<f64>().ok().filter(|x| (0. ..=1.).contains(x)))
+        .map(|x| x.context("--max-shrink should be a number between 0 and 1"))
+        .transpose()?;
+    let no_overwrite = !args.is_present("overwrite");
     let settings = FustaSettings {
+        // Without the sequences, the file is only read through its handle
         cache: match args.value_of("cache").unwrap() {
+            _ if headers_only => fs::Cache::File,
             "mmap" => fs::Cache::Mmap,
             "file" => fs::Cache::File,
             "memory" => fs::Cache::RAM,
             _ => unreachable!(),
         },
         concretize_threshold: value_t!(args, "max-cache", usize).unwrap() * 1024 * 1024,
+        memory_budget: value_t!(args, "max-cache", usize).unwrap() * 1024 * 1024,
         csv_separator: value_t!(args, "csv-separator", String).unwrap(),
-        no_overwrite: args.is_present("overwrite"),
+        no_overwrite,
+        on_append_collision: match args.value_of("on-collision") {
+            Some("skip") => fs::Collision::Skip,
+            Some("overwrite") => fs::Collision::Overwrite,
+            Some("suffix") => fs::Collision::Suffix,
+            None if no_overwrite => fs::Collision::Skip,
+            None => fs::Collision::Overwrite,
+            _ => unreachable!(),
+        },
+        on_edit_conflict: match args.value_of("on-edit-conflict").unwrap() {
+            "refuse" => fs::Collision::Skip,
+            "overwrite" => fs::Collision::Overwrite,
+            "suffix" => fs::Collision::Suffix,
+            _ => unreachable!(),
+        },
+        verify: match (args.is_present("verify"), args.value_of("verify")) {
+            (false, _) => fs::Verify::Off,
+            (true, None) => fs::Verify::AgainstSession,
+            (true, Some(checksums)) => fs::Verify::AgainstFile(checksums.to_owned()),
+        },
+        refuse_mismatches: args.value_of("on-mismatch").unwrap() == "refuse",
+        writers,
+        append_id_template: value_t!(args, "append-id-template", String).unwrap(),
+        writable_fasta: args.is_present("writable-fasta"),
+        defer_deletions: args.is_present("defer-deletions"),
+        headers_only,
+        noatime,
+        no_index: args.is_present("no-index"),
+        catalog: cfg!(feature = "catalog") && args.is_present("catalog"),
+        seed,
+        chaos,
+        id_rules,
+        codec,
+        allow_empty: args.is_present("allow-empty"),
+        max_loss,
+        max_shrink,
+        write_filter: args
+            .value_of("write-filter")
+            .map(TagFilter::parse)
+            .transpose()
+            .context("Invalid --write-filter")?,
+        output: args.value_of("output").map(str::to_owned),
+        merge: args
+            .values_of("merge")
+            .map(|files| files.map(str::to_owned).collect())
+            .unwrap_or_default(),
+        aliases: args.value_of("aliases").map(Aliases::load).transpose()?,
+        on_merge_collision: match args.value_of("on-merge-collision") {
+            Some("suffix") => fs::MergeCollision::Suffix,
+            Some("prefix") => fs::MergeCollision::Prefix,
+            _ => fs::MergeCollision::Refuse,
+        },
+        notifications: config.notifications,
+        views: config.views,
+        session_dir: session::Session::default_dir(),
+        reads: config.reads,
+        write_transform: args
+            .value_of("write-transform")
+            .map(Pipeline::parse)
+            .transpose()
+            .context("Invalid --write-transform")?
+            .unwrap_or_default(),
+        retry: backing::Retry {
+            attempts: value_t!(args, "retries", usize)
+                .context("--retries should be a number of retries")?,
+            delay: chaos::parse_duration(args.value_of("retry-delay").unwrap())
+                .context("Invalid --retry-delay")?,
+        },
+        prefetch: match args.value_of("prefetch").unwrap() {
+            "all" => fs::Prefetch::All,
+            "index" => fs::Prefetch::Index,
+            "none" => fs::Prefetch::None,
+            _ => unreachable!(),
+        },
+        undo_budget: value_t!(args, "undo-budget", usize)
+            .context("--undo-budget should be a number of MB")?
+            * 1024
+            * 1024,
+        annotation: args.value_of("gff").map(str::to_owned),
+        max_amplicon: value_t!(args, "max-amplicon", usize)
+            .context("--max-amplicon should be a number of bp")?,
+        end_length: value_t!(args, "end-length", usize)
+            .context("--end-length should be a number of bases")?,
+        taxa_delimiter: value_t!(args, "taxa-delimiter", String).unwrap(),
+        telomere_motifs: TelomereMotifs::new(
+            &args
+                .value_of("telomere-motifs")
+                .unwrap()
+                .split(',')
+                .collect::<Vec<_>>(),
+        )?,
+        genetic_code: GeneticCode::new(
+            value_t!(args, "genetic-code", u8).context("--genetic-code should be a number")?,
+        )?,
     };
     info!("Caching method:  {:#?}", settings.cache);
+    check_nesting(
+        std::path::Path::new(&fasta_file),
+        std::path::Path::new(&mountpoint),
+    )?;
+    for other in settings.output.iter().chain(settings.merge.iter()) {
+        check_nesting(
+            std::path::Path::new(other),
+            std::path::Path::new(&mountpoint),
+        )?;
+    }
 
-    let fs = FustaFS::new(settings, &fasta_file)?;
+    let expected = match args.value_of("type") {
+        Some("dna") => Some(Alphabet::Nucleotides),
+        Some("protein") => Some(Alphabet::Proteins),
+        _ => None,
+    };
+    // A directory is mounted as a collection, every FASTA file it holds in
+    // its own subtree of the mount point; the files are read right away, but
+    // only mounted once daemonized
+    let mut filesystems = Vec::new();
+    let collection = if std::path::Path::new(&fasta_file).is_dir() {
+        for option in ["output", "merge", "create", "gff"] {
+            if args.is_present(option) {
+                bail!(
+                    "--{} applies to a single FASTA file, and can not be used to mount the directory `{}`",
+                    option,
+                    fasta_file
+                );
+            }
+        }
+        let collection = Collection::new(
+            std::path::Path::new(&fasta_file),
+            std::path::Path::new(&mountpoint),
+            settings,
+            fuse_options,
+            expected,
+        );
+        for (name, file) in collection::files(&fasta_file)? {
+            match collection.prepare(&file) {
+                Ok((fs, options)) => filesystems.push((name, file, fs, options)),
+                Err(e) => warn!("{:#}; skipping it", e),
+            }
+        }
+        if filesystems.is_empty() {
+            bail!("`{}` holds no FASTA file that can be mounted", fasta_file);
+        }
+        Some(collection)
+    } else {
+        if args.is_present("watch") {
+            bail!("--watch only applies to a directory of FASTA files");
+        }
+        if args.is_present("create") {
+            create_fasta(&fasta_file)?;
+        } else if !std::path::Path::new(&fasta_file).exists() {
+            bail!(
+                "`{}` does not exist; use --create to start a new FASTA file from scratch",
+                fasta_file
+            );
+        }
+        for file in std::iter::once(&fasta_file).chain(settings.merge.iter()) {
+            check_sanity(file, expected)?;
+        }
+        let fs = FustaFS::new(settings, &fasta_file)?;
+        filesystems.push((String::new(), fasta_file.clone(), fs, fuse_options));
+        None
+    };
 
     let mut env = RunEnvironment {
         mountpoint: std::path::PathBuf::from(mountpoint),
         created_mountpoint: false,
+        scratch,
     };
     if !env.mountpoint.exists() {
         std::fs::create_dir(&env.mountpoint)?;
@@ -153,8 +1080,26 @@ fn main() -> Result<()> {
     if std::fs::read_dir(&env.mountpoint)?.take(1).count() != 0 {
         bail!("mount point {:?} is not empty.", env.mountpoint);
     }
-
-    let umount_msg = if cfg!(target_os = "freebsd") || cfg!(target_os = "macos") {
+    for warning in placement::check(&env.mountpoint.canonicalize()?) {
+        warn!("{}", warning);
+    }
+    for (_, file, _, _) in filesystems.iter() {
+        if args.value_of("cache") == Some("mmap")
+            && !headers_only
+            && std::fs::canonicalize(file).is_ok_and(|p| placement::on_network_fs(&p))
+        {
+            warn!(
+                "{} lies on a network filesystem, where the reads failing with --cache mmap can not be retried; --cache file is safer",
+                file
+            );
+        }
+    }
+    let umount_msg = if collection.is_some() {
+        format!(
+            "Please unmount every directory of {0:?}, e.g. with `umount {0:?}/*`, to exit.",
+            &env.mountpoint.canonicalize().unwrap()
+        )
+    } else if cfg!(target_os = "freebsd") || cfg!(target_os = "macos") {
         format!(
             "Please use `umount {:?}` to exit.",
             &env.mountpoint.canonicalize().unwrap()
@@ -167,13 +1112,16 @@ fn main() -> Result<()> {
     };
 
     info!("{}", &umount_msg);
-    {
-        ctrlc::set_handler(move || {
-            error!("{}", umount_msg);
-        })?;
-    }
+    output.emit(
+        &MountReport {
+            fasta: std::fs::canonicalize(&fasta_file)?,
+            mountpoint: env.mountpoint.canonicalize()?,
+            log: log_file,
+        },
+        |_| {},
+    )?;
 
-    if !args.is_present("nodaemon") {
+    if daemonize {
         let pid_file = tempfile::Builder::new()
             .prefix("fusta-")
             .suffix(".pid")
@@ -186,16 +1134,65 @@ fn main() -> Result<()> {
             .start()?;
     }
 
+    // The signal handler runs in its own thread, and must thus be set up
+    // after daemonization
+    {
+        let root = env.mountpoint.canonicalize()?;
+        let subtrees = collection.is_some();
+        ctrlc::set_handler(move || {
+            if container || scratch {
+                let mountpoints = if subtrees {
+                    collection::mountpoints(&root)
+                } else {
+                    vec![root.clone()]
+                };
+                for mountpoint in mountpoints.iter() {
+                    info!("Unmounting {:?}", mountpoint);
+                    if let Err(e) = umount::detach(mountpoint) {
+                        error!("Unable to unmount {:?}: {:?}", mountpoint, e);
+                    }
+                }
+            } else {
+                error!("{}", umount_msg);
+            }
+        })?;
+    }
+
     notify(format!(
         "{} is now available in {:#?}",
         &fasta_file, &env.mountpoint
     ));
 
-    match fuser::mount2(fs, &env.mountpoint, &fuse_options) {
-        Ok(()) => {}
-        _ => {
-            error!("Unable to mount the FUSE filesystem");
-            std::process::exit(1);
+    // The trace writer runs in its own thread, and must thus be started
+    // after daemonization
+    #[cfg(feature = "tracing")]
+    let _trace_guard = args
+        .value_of("trace-chrome")
+        .map(trace::chrome)
+        .transpose()
+        .context("Unable to set up tracing")?;
+
+    if let Some(mut collection) = collection {
+        // Set up before mounting, so that no file added meanwhile is missed
+        let watcher = if args.is_present("watch") {
+            Some(collection::Watcher::new(std::path::Path::new(&fasta_file))?)
+        } else {
+            None
+        };
+        for (name, file, fs, options) in filesystems {
+            if let Err(e) = collection.mount(&name, &file, fs, &options) {
+                error!("{:#}", e);
+            }
+        }
+        collection.run(watcher);
+    } else {
+        let (_, _, fs, options) = filesystems.pop().unwrap();
+        match fuser::mount2(fs, &env.mountpoint, &options) {
+            Ok(()) => {}
+            Err(e) => {
+                error!("Unable to mount the FUSE filesystem: {}", e);
+                std::process::exit(1);
+            }
         }
     }
     cleanup(&env)?;
@@ -206,7 +1203,11 @@ fn main() -> Result<()> {
 fn cleanup(env: &RunEnvironment) -> Result<()> {
     notify("Successfully unmounted");
 
-    if env.created_mountpoint {
+    if env.scratch && env.created_mountpoint {
+        std::fs::remove_dir(&env.mountpoint)
+            .with_context(|| format!("Unable to remove {:?}", env.mountpoint))?;
+        info!("Removed {:?}", env.mountpoint);
+    } else if env.created_mountpoint {
         notify(format!(
             "You can now safely remove the {:?} directory",
             env.mountpoint
@@ -0,0 +1,147 @@
+use std::time::{Duration, Instant};
+
+/// How large an operation should be to be notified as soon as it happens;
+/// the smaller ones are only summed up periodically.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Thresholds {
+    pub rewrite_size: u64,          // In bytes
+    pub append_count: usize,        // In sequences
+    pub summary_interval: Duration, // How often the smaller operations are summed up
+}
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            rewrite_size: 1 << 30,
+            append_count: 1000,
+            summary_interval: Duration::from_secs(600),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Operation {
+    Rewrite(u64),  // The FASTA file has been rewritten, with that many bytes
+    Append(usize), // That many sequences have been appended
+}
+
+/// Decides which operations are worth a desktop notification, and batches
+/// the other ones
+#[derive(Debug)]
+pub struct Notifier {
+    pub thresholds: Thresholds,
+    since: Instant, // When the last summary was emitted
+    rewrites: usize,
+    rewritten: u64,
+    appends: usize,
+    appended: usize,
+}
+impl Notifier {
+    pub fn new(thresholds: Thresholds) -> Notifier {
+        Notifier {
+            thresholds,
+            since: Instant::now(),
+            rewrites: 0,
+            rewritten: 0,
+            appends: 0,
+            appended: 0,
+        }
+    }
+
+    pub fn is_large(&self, operation: Operation) -> bool {
+        match operation {
+            Operation::Rewrite(size) => size >= self.thresholds.rewrite_size,
+            Operation::Append(count) => count >= self.thresholds.append_count,
+        }
+    }
+
+    /// Account for `operation`; returns whether it should be notified right
+    /// away, as it will otherwise be part of the next summary
+    pub fn record(&mut self, operation: Operation) -> bool {
+        if self.is_large(operation) {
+            return true;
+        }
+        match operation {
+            Operation::Rewrite(size) => {
+                self.rewrites += 1;
+                self.rewritten += size;
+            }
+            Operation::Append(count) => {
+                self.appends += 1;
+                self.appended += count;
+            }
+        }
+        false
+    }
+
+    /// The summary of the operations batched since the last one, if it is
+    /// due at `now` or `force`d, e.g. when unmounting
+    pub fn summary(&mut self, now: Instant, force: bool) -> Option<String> {
+        if self.rewrites + self.appends == 0
+            || (!force && now.duration_since(self.since) < self.thresholds.summary_interval)
+        {
+            return None;
+        }
+        let mut parts = Vec::new();
+        if self.rewrites > 0 {
+            parts.push(format!(
+                "{} rewrites ({} MB written)",
+                self.rewrites,
+                self.rewritten / (1024 * 1024)
+            ));
+        }
+        if self.appends > 0 {
+            parts.push(format!(
+                "{} sequences appended from {} files",
+                self.appended, self.appends
+            ));
+        }
+        let minutes = now.duration_since(self.since).as_secs() / 60;
+        *self = Notifier::new(self.thresholds.clone());
+        self.since = now;
+        Some(format!(
+            "In the last {} minutes: {}",
+            minutes,
+            parts.join(", ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batched_notifications() {
+        let mut notifier = Notifier::new(Thresholds {
+            rewrite_size: 1000,
+            append_count: 10,
+            summary_interval: Duration::from_secs(60),
+        });
+        let start = notifier.since;
+        assert!(notifier.record(Operation::Rewrite(1000)));
+        assert!(notifier.record(Operation::Append(12)));
+        assert_eq!(
+            notifier.summary(start + Duration::from_secs(3600), true),
+            None
+        );
+
+        assert!(!notifier.record(Operation::Rewrite(999)));
+        assert!(!notifier.record(Operation::Append(3)));
+        assert!(!notifier.record(Operation::Append(4)));
+        assert_eq!(
+            notifier.summary(start + Duration::from_secs(30), false),
+            None
+        );
+        assert_eq!(
+            notifier
+                .summary(start + Duration::from_secs(120), false)
+                .unwrap(),
+            "In the last 2 minutes: 1 rewrites (0 MB written), 7 sequences appended from 2 files"
+        );
+        // Everything has been summed up
+        assert_eq!(
+            notifier.summary(start + Duration::from_secs(900), true),
+            None
+        );
+    }
+}
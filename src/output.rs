@@ -0,0 +1,40 @@
+use anyhow::Result;
+use clap::ArgMatches;
+use serde::Serialize;
+
+/// How the commands report their results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// Tables and messages, meant to be read by humans
+    Human,
+    /// A single JSON document on stdout, meant to be consumed by scripts
+    Json,
+    /// Nothing besides errors; the outcome is conveyed by the exit status
+    Quiet,
+}
+impl Output {
+    pub fn from_args(args: &ArgMatches) -> Output {
+        if args.is_present("json") {
+            Output::Json
+        } else if args.is_present("quiet") {
+            Output::Quiet
+        } else {
+            Output::Human
+        }
+    }
+
+    /// Whether the messages usually printed along the way should be shown
+    pub fn is_human(&self) -> bool {
+        *self == Output::Human
+    }
+
+    /// Report `value`, using `human` to format it for humans
+    pub fn emit<T: Serialize>(&self, value: &T, human: impl FnOnce(&T)) -> Result<()> {
+        match self {
+            Output::Human => human(value),
+            Output::Json => println!("{}", serde_json::to_string_pretty(value)?),
+            Output::Quiet => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+//! The simulation of PCRs: the amplicons a pair of primers would produce
+//! from the sequences.
+use crate::fasta::IupacPattern;
+use anyhow::{anyhow, Result};
+
+/// A pair of primers, both written 5' to 3', e.g. as ordered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Primers {
+    pub name: String,
+    forward: IupacPattern,
+    reverse: IupacPattern,
+}
+impl Primers {
+    /// Parse a pair of primers written as `FORWARD__REVERSE`, in IUPAC codes
+    pub fn parse(name: &str) -> Result<Primers> {
+        let (forward, reverse) = name
+            .split_once("__")
+            .ok_or_else(|| anyhow!("`{}` should be of the form FORWARD__REVERSE", name))?;
+        let primer = |p: &str| {
+            IupacPattern::new(p.as_bytes())
+                .ok_or_else(|| anyhow!("`{}` is not a primer made of IUPAC codes", p))
+        };
+        Ok(Primers {
+            name: name.to_owned(),
+            forward: primer(forward)?,
+            reverse: primer(reverse)?,
+        })
+    }
+
+    /// The amplicons of `seq` at most `max_size` long, in increasing order;
+    /// those on the reverse strand start with the forward primer on the
+    /// reverse strand
+    pub fn amplicons(&self, seq: &[u8], max_size: usize) -> Vec<Amplicon> {
+        let mut r = Vec::new();
+        // On each strand, an amplicon spans from the forward primer to the
+        // reverse complement of the reverse primer
+        for (left, right, reverse) in [
+            (&self.forward, self.reverse.reverse_complement(), false),
+            (&self.reverse, self.forward.reverse_complement(), true),
+        ] {
+            let ends = right
+                .find_all(seq)
+                .into_iter()
+                .map(|start| start + right.len())
+                .collect::<Vec<_>>();
+            for start in left.find_all(seq) {
+                let first = ends.partition_point(|&end| end < start + left.len().max(right.len()));
+                for &end in ends[first..]
+                    .iter()
+                    .take_while(|&&end| end - start <= max_size)
+                {
+                    r.push(Amplicon {
+                        start,
+                        end,
+                        reverse,
+                    });
+                }
+            }
+        }
+        r.sort_by_key(|a| (a.start, a.end));
+        r
+    }
+}
+
+/// The product of a PCR; positions are 0-based, and the range half-open
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Amplicon {
+    pub start: usize,
+    pub end: usize,
+    /// Whether it is amplified from the reverse strand
+    pub reverse: bool,
+}
+impl Amplicon {
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end == self.start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amplicons() {
+        for invalid in ["ACGT", "ACGT__", "__ACGT", "ACGT__ACXT"] {
+            assert!(Primers::parse(invalid).is_err(), "{}", invalid);
+        }
+
+        // The reverse primer is the reverse complement of the end of the
+        // amplicon
+        let primers = Primers::parse("ACGR__CCAA").unwrap();
+        let seq = b"TTACGATTTTTTGGCCTTACGGTTGGNN";
+        let summary = |max_size| {
+            primers
+                .amplicons(seq, max_size)
+                .iter()
+                .map(|a| (a.start, a.end, a.reverse))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            summary(100),
+            [(2, 14, false), (2, 26, false), (18, 26, false)]
+        );
+        assert_eq!(summary(10), [(18, 26, false)]);
+
+        // ...and on the reverse strand
+        let primers = Primers::parse("CCAA__ACGR").unwrap();
+        assert_eq!(
+            primers
+                .amplicons(seq, 100)
+                .iter()
+                .map(|a| (a.start, a.end, a.reverse))
+                .collect::<Vec<_>>(),
+            [(2, 14, true), (2, 26, true), (18, 26, true)]
+        );
+    }
+}
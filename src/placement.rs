@@ -0,0 +1,188 @@
+//! Where the mount point lies, as described by /proc/self/mountinfo, to warn
+//! about the places that can not host a FUSE mount or from where the mount
+//! would not be seen, e.g. outside of a container.
+use std::path::{Path, PathBuf};
+
+/// The parallel filesystems of HPC clusters, on which fusermount refuses to
+/// mount for unprivileged users
+const UNHOSTING_FS: [&str; 5] = ["lustre", "gpfs", "beegfs", "panfs", "pvfs2"];
+
+/// The filesystems whose reads may transiently fail, e.g. with ESTALE
+const NETWORK_FS: [&str; 10] = [
+    "nfs", "nfs4", "cifs", "smb3", "ceph", "lustre", "gpfs", "beegfs", "panfs", "pvfs2",
+];
+
+/// /proc/*/mount* escape spaces & co. as octal sequences
+pub fn unescape(s: &str) -> String {
+    let mut r = Vec::new();
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 3 < bytes.len() {
+            if let Ok(c) = u8::from_str_radix(&s[i + 1..i + 4], 8) {
+                r.push(c);
+                i += 4;
+                continue;
+            }
+        }
+        r.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&r).to_string()
+}
+
+/// Whether the mounts and unmounts under a mount point are seen elsewhere
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Propagation {
+    Shared,  // Both ways
+    Slave,   // Only from its master, not from here
+    Private, // Not at all
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Mount {
+    path: PathBuf,
+    fs_type: String,
+    propagation: Propagation,
+}
+
+/// Parse the content of /proc/self/mountinfo, e.g.
+/// `36 35 98:0 /mnt1 /mnt/parent rw,noatime master:1 - ext3 /dev/root rw`
+fn parse_mountinfo(content: &str) -> Vec<Mount> {
+    content
+        .lines()
+        .filter_map(|l| {
+            let fields = l.split(' ').collect::<Vec<_>>();
+            let separator = fields.iter().position(|f| *f == "-")?;
+            if separator < 6 || fields.len() <= separator + 1 {
+                return None;
+            }
+            let tags = &fields[6..separator];
+            let propagation = if tags.iter().any(|t| t.starts_with("shared:")) {
+                Propagation::Shared
+            } else if tags.iter().any(|t| t.starts_with("master:")) {
+                Propagation::Slave
+            } else {
+                Propagation::Private
+            };
+            Some(Mount {
+                path: PathBuf::from(unescape(fields[4])),
+                fs_type: fields[separator + 1].to_owned(),
+                propagation,
+            })
+        })
+        .collect()
+}
+
+/// The mount `path` lies in, i.e. the last one mounted on its longest
+/// prefix, as max_by_key keeps the last of the maxima
+fn containing<'a>(mounts: &'a [Mount], path: &Path) -> Option<&'a Mount> {
+    mounts
+        .iter()
+        .filter(|m| path.starts_with(&m.path))
+        .max_by_key(|m| m.path.components().count())
+}
+
+fn mounts() -> Vec<Mount> {
+    std::fs::read_to_string("/proc/self/mountinfo")
+        .map(|content| parse_mountinfo(&content))
+        .unwrap_or_default()
+}
+
+/// Whether FUSTA runs in a Docker, Podman or Singularity/Apptainer container
+pub fn in_container() -> bool {
+    Path::new("/.dockerenv").exists()
+        || Path::new("/run/.containerenv").exists()
+        || std::env::var_os("SINGULARITY_CONTAINER").is_some()
+        || std::env::var_os("APPTAINER_CONTAINER").is_some()
+}
+
+/// Whether a FUSE mount in `dir` is likely to be refused, as it lies on a
+/// parallel filesystem and FUSTA is not run by root
+pub fn refuses_fuse(dir: &Path) -> bool {
+    let privileged = unsafe { libc::geteuid() } == 0;
+    !privileged
+        && containing(&mounts(), dir).is_some_and(|m| UNHOSTING_FS.contains(&m.fs_type.as_str()))
+}
+
+/// Whether `path`, an absolute path, lies on a network filesystem
+pub fn on_network_fs(path: &Path) -> bool {
+    containing(&mounts(), path).is_some_and(|m| NETWORK_FS.contains(&m.fs_type.as_str()))
+}
+
+/// What may go wrong when mounting on `mountpoint`, an absolute path, among
+/// `mounts`; `privileged` if run by root, `contained` if in a container
+fn diagnose(mounts: &[Mount], mountpoint: &Path, privileged: bool, contained: bool) -> Vec<String> {
+    let mut r = Vec::new();
+    let mount = match containing(mounts, mountpoint) {
+        Some(mount) => mount,
+        None => return r,
+    };
+    if !privileged && UNHOSTING_FS.contains(&mount.fs_type.as_str()) {
+        r.push(format!(
+            "{:?} lies on a {} filesystem, where fusermount usually refuses to mount; please use -o to mount under a local directory, e.g. in $TMPDIR or /tmp",
+            mountpoint, mount.fs_type
+        ));
+    }
+    if contained && mount.propagation != Propagation::Shared {
+        r.push(if mount.path == Path::new("/") {
+            format!(
+                "{:?} lies in the filesystem of the container, so that the mount will only be visible from within it; to access it from the host, please use -o to mount under a directory bind-mounted with shared propagation, e.g. `docker run -v /host/dir:/data:rshared ...`",
+                mountpoint
+            )
+        } else {
+            format!(
+                "{:?} lies in {:?}, which does not propagate its mounts, so that the mount will only be visible from within the container; to access it from the host, please bind-mount {:?} with shared propagation, e.g. `-v /host/dir:{}:rshared`",
+                mountpoint,
+                mount.path,
+                mount.path,
+                mount.path.display()
+            )
+        });
+    }
+    r
+}
+
+/// Warnings about mounting on `mountpoint`, an absolute path, in the
+/// current environment
+pub fn check(mountpoint: &Path) -> Vec<String> {
+    let privileged = unsafe { libc::geteuid() } == 0;
+    diagnose(&mounts(), mountpoint, privileged, in_container())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placements() {
+        let mounts = parse_mountinfo(
+            "21 1 0:19 / / rw,relatime - overlay overlay rw,lowerdir=/l\n\
+             22 21 0:20 / /proc rw - proc proc rw\n\
+             23 21 8:1 /home/me/data /data rw shared:4 - ext4 /dev/sda1 rw\n\
+             24 21 8:1 /scratch /scr\\040atch rw master:7 - lustre 10.0.0.1@tcp:/fs rw\n\
+             garbage\n",
+        );
+        assert_eq!(mounts.len(), 4);
+        assert_eq!(mounts[3].path, Path::new("/scr atch"));
+        assert_eq!(mounts[3].propagation, Propagation::Slave);
+        assert_eq!(
+            containing(&mounts, Path::new("/data/mnt")).unwrap().fs_type,
+            "ext4"
+        );
+        assert_eq!(
+            containing(&mounts, Path::new("/database")).unwrap().path,
+            Path::new("/")
+        );
+
+        let diagnose = |path: &str, privileged, contained| {
+            diagnose(&mounts, Path::new(path), privileged, contained).len()
+        };
+        assert_eq!(diagnose("/data/mnt", false, true), 0);
+        assert_eq!(diagnose("/tmp/mnt", false, false), 0);
+        assert_eq!(diagnose("/tmp/mnt", false, true), 1);
+        assert_eq!(diagnose("/scr atch/mnt", true, false), 0);
+        assert_eq!(diagnose("/scr atch/mnt", false, false), 1);
+        assert_eq!(diagnose("/scr atch/mnt", false, true), 2);
+    }
+}
@@ -0,0 +1,102 @@
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::time::Duration;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// A fixed set of worker threads computing the values derived from the
+/// sequences, so that the filesystem keeps answering in the meantime. The
+/// threads are only started with the first job, as they would not survive
+/// the daemonization.
+pub struct Pool {
+    threads: usize,
+    sender: Option<mpsc::Sender<Task>>,
+}
+impl Pool {
+    pub fn new(threads: usize) -> Pool {
+        Pool {
+            threads: threads.max(1),
+            sender: None,
+        }
+    }
+
+    fn start(&self) -> mpsc::Sender<Task> {
+        let (sender, receiver) = mpsc::channel::<Task>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..self.threads {
+            let receiver = receiver.clone();
+            std::thread::Builder::new()
+                .name(format!("fusta-worker-{}", i))
+                .spawn(move || loop {
+                    // The lock is only held while waiting for a task
+                    let task = receiver.lock().unwrap().recv();
+                    match task {
+                        Ok(task) => task(),
+                        // The pool has been dropped
+                        Err(_) => break,
+                    }
+                })
+                .expect("unable to spawn a worker thread");
+        }
+        sender
+    }
+
+    /// Start computing `f` on one of the workers
+    pub fn compute<V: Send + 'static>(
+        &mut self,
+        f: impl FnOnce() -> V + Send + 'static,
+    ) -> Pending<V> {
+        let pending = Pending {
+            slot: Arc::new((Mutex::new(None), Condvar::new())),
+        };
+        let slot = pending.slot.clone();
+        let sender = match self.sender.take() {
+            Some(sender) => sender,
+            None => self.start(),
+        };
+        let _ = sender.send(Box::new(move || {
+            let value = f();
+            *slot.0.lock().unwrap() = Some(value);
+            slot.1.notify_all();
+        }));
+        self.sender = Some(sender);
+        pending
+    }
+}
+
+/// A value being computed by a `Pool`
+pub struct Pending<V> {
+    slot: Arc<(Mutex<Option<V>>, Condvar)>,
+}
+impl<V: Clone> Pending<V> {
+    /// The value, if it is available within `timeout`
+    pub fn wait(&self, timeout: Duration) -> Option<V> {
+        let (lock, ready) = &*self.slot;
+        let (value, _) = ready
+            .wait_timeout_while(lock.lock().unwrap(), timeout, |v| v.is_none())
+            .unwrap();
+        value.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounded_waits() {
+        let mut pool = Pool::new(2);
+        let (unblock, blocked) = mpsc::channel::<()>();
+        let slow = pool.compute(move || {
+            blocked.recv().unwrap();
+            1
+        });
+        let fast = pool.compute(|| 2);
+
+        // A slow job does not hold back the other ones
+        assert_eq!(fast.wait(Duration::from_secs(10)), Some(2));
+        assert_eq!(slow.wait(Duration::from_millis(10)), None);
+        unblock.send(()).unwrap();
+        assert_eq!(slow.wait(Duration::from_secs(10)), Some(1));
+        assert_eq!(slow.wait(Duration::ZERO), Some(1));
+    }
+}
@@ -0,0 +1,226 @@
+//! The screening of assemblies for telomeric ends and simple repeats.
+use crate::fasta::reverse_complement;
+use anyhow::{bail, Result};
+use std::collections::VecDeque;
+
+/// How many bases are scanned for telomeric repeats at each end of a
+/// sequence
+pub const TELOMERE_WINDOW: usize = 1000;
+/// Which fraction of its end should be covered by a motif for it to be
+/// considered telomeric
+pub const TELOMERE_DENSITY: f64 = 0.5;
+/// The longest period of the tandem repeats considered as simple repeats
+const MAX_PERIOD: usize = 6;
+/// The shortest span of a simple repeat
+const MIN_SPAN: usize = 12;
+
+/// The telomeric motifs to look for, e.g. TTAGGG for vertebrates
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TelomereMotifs(Vec<Vec<u8>>);
+impl TelomereMotifs {
+    /// Each motif is looked for in both orientations
+    pub fn new<S: AsRef<str>>(motifs: &[S]) -> Result<TelomereMotifs> {
+        let mut r = Vec::new();
+        for motif in motifs {
+            let motif = motif.as_ref().to_ascii_uppercase().into_bytes();
+            if motif.is_empty() || motif.iter().any(|b| !b"ACGT".contains(b)) {
+                bail!(
+                    "`{}` is not a telomeric motif made of A, C, G and T",
+                    String::from_utf8_lossy(&motif)
+                )
+            }
+            let mut rc = motif.clone();
+            reverse_complement(&mut rc);
+            r.push(motif);
+            if !r.contains(&rc) {
+                r.push(rc);
+            }
+        }
+        Ok(TelomereMotifs(r))
+    }
+
+    /// The motif covering the largest fraction of `end`, the end of a
+    /// sequence, and this fraction, if it is at least `TELOMERE_DENSITY`
+    pub fn find(&self, end: &[u8]) -> Option<(&str, f64)> {
+        self.0
+            .iter()
+            .map(|motif| (motif, coverage(end, motif) as f64 / end.len() as f64))
+            .filter(|(_, density)| *density >= TELOMERE_DENSITY)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(motif, density)| (std::str::from_utf8(motif).unwrap(), density))
+    }
+}
+
+/// How many bases of `seq` are covered by non-overlapping copies of `motif`,
+/// case-insensitively
+fn coverage(seq: &[u8], motif: &[u8]) -> usize {
+    let mut r = 0;
+    let mut i = 0;
+    while i + motif.len() <= seq.len() {
+        if seq[i..i + motif.len()].eq_ignore_ascii_case(motif) {
+            r += motif.len();
+            i += motif.len();
+        } else {
+            i += 1;
+        }
+    }
+    r
+}
+
+/// How many bases of `seq` belong to simple repeats, i.e. to tandem repeats
+/// of a unit of at most `MAX_PERIOD` bases, such as homopolymers or
+/// microsatellites, spanning at least `MIN_SPAN` bases; N are never part of
+/// a repeat
+pub fn simple_repeats(seq: &[u8]) -> usize {
+    let mut repeats = SimpleRepeats::default();
+    seq.iter().for_each(|&base| repeats.push(base));
+    repeats.finish()
+}
+
+/// Counts the bases of a sequence fed base by base that belong to simple
+/// repeats, as `simple_repeats` does, keeping only the last `MAX_PERIOD`
+/// bases and the repeats that may still be extended
+#[derive(Debug, Default)]
+pub struct SimpleRepeats {
+    recent: [u8; MAX_PERIOD], // The last bases, the one at `i` in `i % MAX_PERIOD`
+    len: usize,               // How many bases have been pushed
+    starts: [usize; MAX_PERIOD], // Where the current run of every period started
+    spans: VecDeque<(usize, usize)>, // The repeats found that a later one may overlap
+    covered: usize,           // How many bases the repeats found cover
+}
+impl SimpleRepeats {
+    pub fn push(&mut self, base: u8) {
+        let i = self.len;
+        for period in 1..=MAX_PERIOD.min(i) {
+            let before = self.recent[(i - period) % MAX_PERIOD];
+            if !base.eq_ignore_ascii_case(&before) || base.eq_ignore_ascii_case(&b'N') {
+                self.end_run(period, i);
+            }
+        }
+        self.recent[i % MAX_PERIOD] = base;
+        self.len += 1;
+    }
+
+    /// How many of the bases pushed belong to simple repeats
+    pub fn finish(mut self) -> usize {
+        for period in 1..=MAX_PERIOD.min(self.len) {
+            self.end_run(period, self.len);
+        }
+        self.covered
+    }
+
+    /// Record the repeat of `period` ending at `end`, if long enough
+    fn end_run(&mut self, period: usize, end: usize) {
+        let start = self.starts[period - 1].max(period) - period;
+        if end - start >= MIN_SPAN {
+            self.cover(start, end);
+        }
+        self.starts[period - 1] = end + 1;
+    }
+
+    /// Add the span [`start`, `end`) to the bases covered; as the runs are
+    /// ended in order, `end` is never before the end of a previous span
+    fn cover(&mut self, mut start: usize, end: usize) {
+        while let Some(&(s, e)) = self.spans.back() {
+            if e < start {
+                break;
+            }
+            start = start.min(s);
+            self.covered -= e - s;
+            self.spans.pop_back();
+        }
+        self.spans.push_back((start, end));
+        self.covered += end - start;
+        // The spans ending before the start of every current run can not
+        // be overlapped anymore
+        let earliest = (1..=MAX_PERIOD)
+            .map(|period| self.starts[period - 1].max(period) - period)
+            .min()
+            .unwrap();
+        while self.spans.front().is_some_and(|&(_, e)| e < earliest) {
+            self.spans.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn telomeres() {
+        let motifs = TelomereMotifs::new(&["TTAGGG"]).unwrap();
+        assert!(TelomereMotifs::new(&["TTAGGN"]).is_err());
+        assert!(TelomereMotifs::new(&[""]).is_err());
+
+        let head = b"CCCTAACCCTAAcccTAACCCTAAACGT";
+        let (motif, density) = motifs.find(head).unwrap();
+        assert_eq!(motif, "CCCTAA");
+        assert!((density - 24. / 28.).abs() < 1e-9);
+        assert_eq!(motifs.find(b"TTAGGGACGTACGTACGTACGT"), None);
+    }
+
+    #[test]
+    fn simple_repeats() {
+        assert_eq!(super::simple_repeats(b""), 0);
+        assert_eq!(super::simple_repeats(b"ACGTTGCA"), 0);
+        // A homopolymer, a dinucleotide repeat, and a run of N
+        assert_eq!(super::simple_repeats(b"GCAAAAAAAAAAAAGC"), 12);
+        // ...including the leading C, as the repeats need not start
+        // with a full unit
+        assert_eq!(super::simple_repeats(b"GCacacacacacacGC"), 13);
+        assert_eq!(super::simple_repeats(b"GCNNNNNNNNNNNNGC"), 0);
+        assert_eq!(super::simple_repeats(b"AAAAAAAAAAA"), 0);
+        assert_eq!(super::simple_repeats(b"CAGCAGCAGCAG"), 12);
+    }
+
+    /// The repeats of `seq`, flagging every base
+    fn reference(seq: &[u8]) -> usize {
+        let mut repeated = vec![false; seq.len()];
+        for period in 1..=MAX_PERIOD {
+            let mut start = 0;
+            for i in period..=seq.len() {
+                let extends = i < seq.len()
+                    && seq[i].eq_ignore_ascii_case(&seq[i - period])
+                    && !seq[i].eq_ignore_ascii_case(&b'N');
+                if !extends {
+                    let span_start = start.max(period) - period;
+                    if i - span_start >= MIN_SPAN {
+                        repeated[span_start..i].iter_mut().for_each(|r| *r = true);
+                    }
+                    start = i + 1;
+                }
+            }
+        }
+        repeated.iter().filter(|&&r| r).count()
+    }
+
+    #[test]
+    fn streamed_repeats() {
+        // Overlapping repeats of several periods, among random bases
+        let mut state = 42u64;
+        let mut seq = Vec::new();
+        for _ in 0..2000 {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            let unit = &b"ACGTNacgt"[..1 + (state >> 60) as usize % 6];
+            let copies = 1 + (state >> 40) as usize % 8;
+            seq.extend(unit.iter().cycle().take(unit.len() * copies));
+            seq.push(b"ACGT"[(state >> 20) as usize % 4]);
+        }
+        for len in [0, 5, 13, 100, 1000, seq.len()] {
+            assert_eq!(super::simple_repeats(&seq[..len]), reference(&seq[..len]));
+        }
+
+        // Only the repeats that may still be extended are kept
+        let mut repeats = SimpleRepeats::default();
+        for &base in b"AAAAAAAAAAAAAAACGTACACACACACACACGT"
+            .iter()
+            .cycle()
+            .take(34 * 1000)
+        {
+            repeats.push(base);
+            assert!(repeats.spans.len() <= 2);
+        }
+        assert_eq!(repeats.finish(), 1000 * (15 + 14));
+    }
+}
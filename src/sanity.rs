@@ -0,0 +1,127 @@
+//! The detection of files that are obviously not what FUSTA expects, e.g. a
+//! compressed file, a FASTQ file, or proteins where nucleotides are
+//! expected, from a sample of their beginning.
+use anyhow::{bail, Result};
+
+/// How many bytes of a file are sampled to check it
+pub const SAMPLE_SIZE: usize = 4 * 1024 * 1024;
+/// Which fraction of the letters of nucleotide sequences may be something
+/// else than A, C, G, T, U or N
+const MAX_NON_NUCLEOTIDES: f64 = 0.1;
+/// Which fraction of the bytes of a text file may be something else than
+/// printable ASCII
+const MAX_NON_TEXT: f64 = 0.05;
+
+/// What a FASTA file is made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alphabet {
+    Nucleotides,
+    Proteins,
+}
+impl std::fmt::Display for Alphabet {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Alphabet::Nucleotides => write!(f, "nucleotides"),
+            Alphabet::Proteins => write!(f, "proteins"),
+        }
+    }
+}
+
+/// The alphabet of the sequence lines of `sample`, if any
+fn guess_alphabet(sample: &[u8]) -> Option<Alphabet> {
+    let (mut letters, mut nucleotides) = (0usize, 0usize);
+    for line in sample
+        .split(|&c| c == b'\n')
+        .skip_while(|l| !l.starts_with(b">"))
+        .filter(|l| !l.starts_with(b">"))
+    {
+        for c in line.iter().filter(|c| c.is_ascii_alphabetic()) {
+            letters += 1;
+            nucleotides += usize::from(b"ACGTUN".contains(&c.to_ascii_uppercase()));
+        }
+    }
+    if letters == 0 {
+        None
+    } else if (letters - nucleotides) as f64 > letters as f64 * MAX_NON_NUCLEOTIDES {
+        Some(Alphabet::Proteins)
+    } else {
+        Some(Alphabet::Nucleotides)
+    }
+}
+
+/// Check that `sample`, the beginning of a file, looks like a FASTA file
+/// whose sequences are made of `expected`, if set. Fail if it can not be
+/// one, and return warnings about what looks suspicious otherwise.
+pub fn check(sample: &[u8], expected: Option<Alphabet>) -> Result<Vec<String>> {
+    let mut warnings = Vec::new();
+    if sample.starts_with(&[0x1f, 0x8b]) {
+        bail!("it is compressed; please decompress it first, or compress it with `bgzip` instead")
+    }
+    let non_text = sample
+        .iter()
+        .filter(|&&c| !(c.is_ascii_graphic() || c.is_ascii_whitespace()))
+        .count();
+    if sample.contains(&0) || non_text as f64 > sample.len() as f64 * MAX_NON_TEXT {
+        bail!("it looks like a binary file")
+    }
+
+    let mut lines = sample
+        .split(|&c| c == b'\n')
+        .filter(|l| !l.iter().all(u8::is_ascii_whitespace));
+    let first = match lines.next() {
+        Some(first) => first,
+        None => return Ok(warnings),
+    };
+    if first.starts_with(b"@") && matches!(lines.nth(1), Some(l) if l.starts_with(b"+")) {
+        bail!("it looks like a FASTQ file; please convert it to FASTA first")
+    }
+    if !sample.split(|&c| c == b'\n').any(|l| l.starts_with(b">")) {
+        bail!(
+            "no FASTA header was found in its first {} MB",
+            SAMPLE_SIZE / (1024 * 1024)
+        )
+    }
+    if !first.starts_with(b">") {
+        warnings.push("whatever precedes its first FASTA header will be ignored".to_owned());
+    }
+
+    match (guess_alphabet(sample), expected) {
+        (Some(found), Some(expected)) if found != expected => {
+            bail!("it looks like {} rather than {}", found, expected)
+        }
+        (Some(Alphabet::Proteins), None) => warnings.push(
+            "it looks like proteins; the files assuming nucleotides, such as pcr/ or proteins/, will be meaningless"
+                .to_owned(),
+        ),
+        _ => {}
+    }
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanity() {
+        let dna = b">chr1 desc\nACGTNNacgt\nACGTRY\n>chr2\nUUUU\n";
+        let protein = b">sp|P69905|HBA_HUMAN\nMVLSPADKTNVKAAWGKVGAHAGEYGAEALERMF\n";
+        assert!(check(dna, None).unwrap().is_empty());
+        assert!(check(dna, Some(Alphabet::Nucleotides)).unwrap().is_empty());
+        assert!(check(dna, Some(Alphabet::Proteins)).is_err());
+        assert_eq!(check(protein, None).unwrap().len(), 1);
+        assert!(check(protein, Some(Alphabet::Proteins)).unwrap().is_empty());
+        assert!(check(protein, Some(Alphabet::Nucleotides)).is_err());
+        assert!(check(b"", None).unwrap().is_empty());
+
+        // Headers without sequences tell nothing of the alphabet
+        assert!(check(b">a\n>b\n", Some(Alphabet::Proteins)).is_ok());
+        assert_eq!(check(b"# comment\n>a\nACGT\n", None).unwrap().len(), 1);
+
+        assert!(check(b"@read1\nACGT\n+\nIIII\n", None).is_err());
+        assert!(check(b"LOCUS       chr1\nORIGIN\n", None).is_err());
+        assert!(check(&[0x1f, 0x8b, 0x08, 0x00], None).is_err());
+        assert!(check(b">a\nAC\0GT\n", None).is_err());
+        assert!(check(&[b'>', 0xc3, 0xa9, 0xff, 0xfe, b'\n'], None).is_err());
+    }
+}
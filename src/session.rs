@@ -0,0 +1,114 @@
+use anyhow::{Context, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const SESSION_EXT: &str = "fusta-session";
+
+/// The state persisted across successive mounts of the same FASTA file,
+/// stored in the state directory of the user, so that the directories
+/// holding the FASTA files are left untouched.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Session {
+    /// Fragment ID -> [FASTA file ino, sequence file ino]
+    #[serde(default)]
+    pub inodes: BTreeMap<String, [u64; 2]>,
+    /// Fragment ID -> MD5 digest of its sequence
+    #[serde(default)]
+    pub digests: BTreeMap<String, String>,
+    /// How many times the file has been mounted
+    #[serde(default)]
+    pub mounts: u64,
+    /// Fragment ID -> generation of its inodes
+    #[serde(default)]
+    pub generations: BTreeMap<String, u64>,
+    /// Fragment ID -> its tags, as set in tags/
+    #[serde(default)]
+    pub tags: BTreeMap<String, BTreeMap<String, String>>,
+    /// Name -> region, as bookmarked in get/
+    #[serde(default)]
+    pub bookmarks: BTreeMap<String, String>,
+    /// Directory ino -> its mode and owner, as set by chmod or chown
+    #[serde(default)]
+    pub directories: BTreeMap<u64, DirOwnership>,
+}
+
+/// The permissions of a virtual directory, when changed from the defaults
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirOwnership {
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+}
+impl Session {
+    /// Where the sessions are stored: $XDG_STATE_HOME/fusta, by default
+    /// ~/.local/state/fusta
+    pub fn default_dir() -> Option<PathBuf> {
+        std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("state"))
+            })
+            .map(|dir| dir.join("fusta"))
+    }
+
+    /// Where the session of `fasta_file` is stored in `dir`; it is named
+    /// after the file and the digest of its absolute path, so that files of
+    /// the same name in different directories do not share their sessions.
+    pub fn path_for(fasta_file: &str, dir: &Path) -> PathBuf {
+        let path = Path::new(fasta_file);
+        let absolute = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        let digest = md5::compute(absolute.to_string_lossy().as_bytes());
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        dir.join(format!(
+            "{}-{}.{}",
+            name,
+            &format!("{:x}", digest)[..12],
+            SESSION_EXT
+        ))
+    }
+
+    /// Where the sessions were stored by the previous versions, next to the
+    /// FASTA file
+    pub fn legacy_path_for(fasta_file: &str) -> PathBuf {
+        PathBuf::from(format!("{}.{}", fasta_file, SESSION_EXT))
+    }
+
+    /// Load the session of `fasta_file` stored in `path`, falling back to
+    /// the one the previous versions stored next to it
+    pub fn load_for(fasta_file: &str, path: Option<&Path>) -> Session {
+        match path {
+            Some(path) if path.exists() => Session::load(path),
+            _ => Session::load(&Session::legacy_path_for(fasta_file)),
+        }
+    }
+
+    /// Load the session stored in `path`, or start a new one if there is none
+    /// or if it can not be used.
+    pub fn load(path: &Path) -> Session {
+        if !path.exists() {
+            return Session::default();
+        }
+        std::fs::read_to_string(path)
+            .context("unable to read file")
+            .and_then(|content| serde_json::from_str(&content).context("unable to parse file"))
+            .unwrap_or_else(|e| {
+                warn!("Ignoring session file {:?}: {:?}", path, e);
+                Session::default()
+            })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).context(format!("while creating {:?}", dir))?;
+        }
+        let tmp_path = path.with_extension(format!("{}#fusta#", SESSION_EXT));
+        std::fs::write(&tmp_path, serde_json::to_string(self)?)
+            .context(format!("while writing {:?}", tmp_path))?;
+        std::fs::rename(&tmp_path, path).context(format!("while writing {:?}", path))
+    }
+}
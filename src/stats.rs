@@ -0,0 +1,128 @@
+use crate::output::Output;
+use anyhow::{Context, Result};
+use fusta::assembly::*;
+use fusta::fasta::*;
+use serde::Serialize;
+use std::fs;
+
+/// The ID, description and composition of every sequence in `filename`
+fn scan(filename: &str) -> Result<Vec<(Fragment, Composition)>> {
+    let file =
+        fs::File::open(filename).with_context(|| format!("Failed to open `{}`", filename))?;
+    let fragments = FastaReader::new(&file, false).collect::<Vec<_>>();
+    if fragments.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mmap = unsafe { memmap2::Mmap::map(&file) }
+        .with_context(|| format!("Failed to map `{}`", filename))?;
+    Ok(fragments
+        .into_iter()
+        .map(|f| {
+            let mut composition = Composition::default();
+            composition.consume(&mmap[f.pos.0..f.pos.1]);
+            (f, composition)
+        })
+        .collect())
+}
+
+#[derive(Serialize)]
+struct SequenceReport {
+    id: String,
+    description: Option<String>,
+    length: usize,
+    gc_content: f64,
+    n_content: f64,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    file: &'a str,
+    count: usize,
+    total_length: usize,
+    shortest: usize,
+    longest: usize,
+    mean_length: f64,
+    n50: usize,
+    l50: usize,
+    n90: usize,
+    l90: usize,
+    gc_content: f64,
+    n_content: f64,
+    sequences: Vec<SequenceReport>,
+}
+
+pub fn run(filename: &str, output: Output) -> Result<()> {
+    let sequences = scan(filename)?;
+    let stats = AssemblyStats::new(&sequences.iter().map(|s| s.1).collect::<Vec<_>>());
+    let report = Report {
+        file: filename,
+        count: stats.count,
+        total_length: stats.total,
+        shortest: stats.shortest,
+        longest: stats.longest,
+        mean_length: stats.mean,
+        n50: stats.n50,
+        l50: stats.l50,
+        n90: stats.n90,
+        l90: stats.l90,
+        gc_content: stats.composition.gc_content(),
+        n_content: stats.composition.n_content(),
+        sequences: sequences
+            .into_iter()
+            .map(|(f, c)| SequenceReport {
+                id: f.id.to_string(),
+                description: f.name,
+                length: c.length,
+                gc_content: c.gc_content(),
+                n_content: c.n_content(),
+            })
+            .collect(),
+    };
+    output.emit(&report, print_report)
+}
+
+fn print_report(report: &Report) {
+    use ascii_table::*;
+    use num_format::*;
+
+    let bp = |x: usize| format!("{} bp", x.to_formatted_string(&Locale::en));
+    println!("{}", report.file);
+    println!("{}", "=".repeat(report.file.len()));
+    for (name, value) in [
+        ("Sequences", report.count.to_formatted_string(&Locale::en)),
+        ("Total length", bp(report.total_length)),
+        ("Shortest", bp(report.shortest)),
+        ("Longest", bp(report.longest)),
+        ("Mean length", format!("{:.1} bp", report.mean_length)),
+        ("N50", bp(report.n50)),
+        ("L50", report.l50.to_formatted_string(&Locale::en)),
+        ("N90", bp(report.n90)),
+        ("L90", report.l90.to_formatted_string(&Locale::en)),
+        ("GC", format!("{:.2}%", 100. * report.gc_content)),
+        ("N", format!("{:.2}%", 100. * report.n_content)),
+    ] {
+        println!("{:<14}{}", name, value);
+    }
+
+    if !report.sequences.is_empty() {
+        let mut table = AsciiTable::default();
+        table.column(0).set_header("ID");
+        table.column(1).set_header("Length (bp)");
+        table.column(2).set_header("GC (%)");
+        table.column(3).set_header("N (%)");
+        let rows = report
+            .sequences
+            .iter()
+            .map(|s| {
+                vec![
+                    s.id.clone(),
+                    s.length.to_formatted_string(&Locale::en),
+                    format!("{:.2}", 100. * s.gc_content),
+                    format!("{:.2}", 100. * s.n_content),
+                ]
+            })
+            .collect::<Vec<_>>();
+        println!();
+        print!("{}", table.format(&rows));
+    }
+}
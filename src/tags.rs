@@ -0,0 +1,92 @@
+//! Filters selecting the fragments by the `key=value` tags set on them, e.g.
+//! to only write back the ones kept while reviewing an assembly.
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Condition {
+    Equals(String, String),
+    Differs(String, String),
+    Present(String),
+    Absent(String),
+}
+impl Condition {
+    fn matches(&self, tags: &BTreeMap<String, String>) -> bool {
+        match self {
+            Condition::Equals(key, value) => tags.get(key) == Some(value),
+            Condition::Differs(key, value) => tags.get(key) != Some(value),
+            Condition::Present(key) => tags.contains_key(key),
+            Condition::Absent(key) => !tags.contains_key(key),
+        }
+    }
+}
+
+/// A conjunction of conditions on the tags of a fragment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagFilter(Vec<Condition>);
+impl TagFilter {
+    /// Parse a comma-separated list of conditions, each of them either
+    /// `key=value`, `key!=value` (the tag is not set to `value`, or not set
+    /// at all), `key` (the tag is set) or `!key` (it is not), e.g.
+    /// `keep=true,!suspect`
+    pub fn parse(filter: &str) -> Result<TagFilter> {
+        let key = |key: &str| {
+            let key = key.trim();
+            if key.is_empty() || key.contains(|c: char| c.is_whitespace() || "=!".contains(c)) {
+                bail!("`{}` is not a valid tag name", key)
+            }
+            Ok(key.to_owned())
+        };
+        let mut r = Vec::new();
+        for condition in filter.split(',') {
+            let condition = condition.trim();
+            r.push(if let Some((k, value)) = condition.split_once("!=") {
+                Condition::Differs(key(k)?, value.trim().to_owned())
+            } else if let Some((k, value)) = condition.split_once('=') {
+                Condition::Equals(key(k)?, value.trim().to_owned())
+            } else if let Some(k) = condition.strip_prefix('!') {
+                Condition::Absent(key(k)?)
+            } else {
+                Condition::Present(key(condition)?)
+            });
+        }
+        Ok(TagFilter(r))
+    }
+
+    /// Whether a fragment tagged with `tags` fulfills all the conditions
+    pub fn matches(&self, tags: &BTreeMap<String, String>) -> bool {
+        self.0.iter().all(|c| c.matches(tags))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters() {
+        let tags = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let kept = tags(&[("keep", "true"), ("reviewer", "me")]);
+        let suspect = tags(&[("keep", "true"), ("suspect", "")]);
+        let untagged = tags(&[]);
+
+        let filter = TagFilter::parse("keep=true").unwrap();
+        assert!(filter.matches(&kept) && filter.matches(&suspect));
+        assert!(!filter.matches(&untagged));
+        let filter = TagFilter::parse(" keep = true , !suspect").unwrap();
+        assert!(filter.matches(&kept) && !filter.matches(&suspect));
+        let filter = TagFilter::parse("keep!=true").unwrap();
+        assert!(!filter.matches(&kept) && filter.matches(&untagged));
+        let filter = TagFilter::parse("reviewer").unwrap();
+        assert!(filter.matches(&kept) && !filter.matches(&suspect));
+
+        for invalid in ["", "keep,", "=true", "!", "a b=c", "!!keep"] {
+            assert!(TagFilter::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
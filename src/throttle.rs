@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+type Reply = Box<dyn FnOnce() + Send>;
+
+/// Who the read rate is shared by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Client {
+    Pid, // Every process has its own budget
+    Uid, // All the processes of a user share the same budget
+}
+
+/// How fast every client may read from the mount point, so that a process
+/// streaming whole chromosomes does not delay the interactive ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    pub rate: u64,  // In bytes per second; 0 does not limit the reads
+    pub burst: u64, // How much a client that has been idle may read at once (in B)
+    pub per: Client,
+}
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            rate: 0,
+            burst: 16 << 20,
+            per: Client::Pid,
+        }
+    }
+}
+
+/// The token bucket of a client: how much it may read right away, as of
+/// `at`; negative once it has read more than its burst
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    at: Instant,
+}
+
+/// Delays the answers to the clients reading faster than their rate; the
+/// delayed answers are sent from a thread of their own, so that the other
+/// clients keep being answered in the meantime. As in the pool, the thread
+/// is only started with the first delayed answer, as it would not survive
+/// the daemonization.
+pub struct Throttle {
+    pub limits: Limits,
+    buckets: HashMap<u32, Bucket>,
+    sender: Option<mpsc::SyncSender<(Instant, Reply)>>,
+}
+impl Throttle {
+    /// How many idle clients are remembered before being forgotten
+    const MAX_BUCKETS: usize = 1024;
+    /// How many answers may be delayed at once; past that, the filesystem
+    /// waits for the earliest of them before answering anything else
+    const MAX_PENDING: usize = 1024;
+
+    pub fn new(limits: Limits) -> Throttle {
+        Throttle {
+            limits,
+            buckets: HashMap::new(),
+            sender: None,
+        }
+    }
+
+    /// Which client issued a request by `pid` on behalf of `uid`
+    pub fn client(&self, pid: u32, uid: u32) -> u32 {
+        match self.limits.per {
+            Client::Pid => pid,
+            Client::Uid => uid,
+        }
+    }
+
+    /// When `client`, reading `size` bytes at `now`, should be answered
+    pub fn admit(&mut self, client: u32, size: usize, now: Instant) -> Instant {
+        let rate = self.limits.rate as f64;
+        let burst = self.limits.burst as f64;
+        let refilled = |b: &Bucket| {
+            (b.tokens + now.saturating_duration_since(b.at).as_secs_f64() * rate).min(burst)
+        };
+        if self.buckets.len() >= Throttle::MAX_BUCKETS {
+            self.buckets.retain(|_, b| refilled(b) < burst);
+        }
+        let bucket = self.buckets.entry(client).or_insert(Bucket {
+            tokens: burst,
+            at: now,
+        });
+        bucket.tokens = refilled(bucket) - size as f64;
+        bucket.at = now;
+        if bucket.tokens >= 0. {
+            now
+        } else {
+            now + Duration::from_secs_f64(-bucket.tokens / rate)
+        }
+    }
+
+    fn start() -> mpsc::SyncSender<(Instant, Reply)> {
+        let (sender, receiver) = mpsc::sync_channel::<(Instant, Reply)>(0);
+        std::thread::Builder::new()
+            .name("fusta-throttle".into())
+            .spawn(move || {
+                let mut pending: Vec<(Instant, Reply)> = Vec::new();
+                loop {
+                    let next = pending.iter().map(|p| p.0).min();
+                    let received = match next {
+                        // Nothing more is received until a slot is freed,
+                        // which holds up the sender
+                        Some(next) if pending.len() >= Throttle::MAX_PENDING => {
+                            std::thread::sleep(next.saturating_duration_since(Instant::now()));
+                            Err(mpsc::RecvTimeoutError::Timeout)
+                        }
+                        Some(next) => {
+                            receiver.recv_timeout(next.saturating_duration_since(Instant::now()))
+                        }
+                        None => receiver
+                            .recv()
+                            .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+                    };
+                    match received {
+                        Ok(reply) => pending.push(reply),
+                        Err(mpsc::RecvTimeoutError::Timeout) => {}
+                        // The filesystem is gone; so are the ones waiting
+                        // for the answers
+                        Err(mpsc::RecvTimeoutError::Disconnected) => {
+                            pending.into_iter().for_each(|(_, reply)| reply());
+                            break;
+                        }
+                    }
+                    let now = Instant::now();
+                    let (due, later) = pending.into_iter().partition(|(when, _)| *when <= now);
+                    pending = later;
+                    due.into_iter()
+                        .for_each(|(_, reply): (Instant, Reply)| reply());
+                }
+            })
+            .expect("unable to spawn the throttling thread");
+        sender
+    }
+
+    /// Send `reply` at `when`; blocks while too many answers are already
+    /// delayed
+    pub fn defer(&mut self, when: Instant, reply: impl FnOnce() + Send + 'static) {
+        let sender = self.sender.get_or_insert_with(Throttle::start);
+        let _ = sender.send((when, Box::new(reply)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn buckets() {
+        let mut throttle = Throttle::new(Limits {
+            rate: 1 << 20,
+            burst: 4 << 20,
+            per: Client::Uid,
+        });
+        assert_eq!(throttle.client(1234, 1000), 1000);
+        let t0 = Instant::now();
+        let second = Duration::from_secs(1);
+
+        // The burst is answered right away, then one MB per second...
+        assert_eq!(throttle.admit(1, 4 << 20, t0), t0);
+        assert_eq!(throttle.admit(1, 1 << 20, t0), t0 + second);
+        assert_eq!(throttle.admit(1, 1 << 20, t0), t0 + 2 * second);
+        // ...while the other clients have their own budget
+        assert_eq!(throttle.admit(2, 128 << 10, t0), t0);
+        // The debt is paid back over time...
+        assert_eq!(throttle.admit(1, 1 << 20, t0 + second), t0 + 3 * second);
+        // ...and an idle client recovers its burst, but no more
+        let later = t0 + 60 * second;
+        assert_eq!(throttle.admit(1, 4 << 20, later), later);
+        assert_eq!(throttle.admit(1, 1 << 20, later), later + second);
+    }
+
+    #[test]
+    fn deferred() {
+        let mut throttle = Throttle::new(Limits::default());
+        let replies = Arc::new(Mutex::new(Vec::new()));
+        let now = Instant::now();
+        for (i, delay) in [(1, 50), (2, 0), (3, 20)] {
+            let replies = replies.clone();
+            throttle.defer(now + Duration::from_millis(delay), move || {
+                replies.lock().unwrap().push(i)
+            });
+        }
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(*replies.lock().unwrap(), [2, 3, 1]);
+    }
+
+    #[test]
+    fn bounded() {
+        let mut throttle = Throttle::new(Limits::default());
+        let replies = Arc::new(Mutex::new(0));
+        let now = Instant::now();
+        let delay = Duration::from_millis(100);
+        for _ in 0..Throttle::MAX_PENDING {
+            let replies = replies.clone();
+            throttle.defer(now + delay, move || *replies.lock().unwrap() += 1);
+        }
+        assert!(now.elapsed() < delay);
+        assert_eq!(*replies.lock().unwrap(), 0);
+
+        // One more waits for the pending ones to be answered
+        throttle.defer(now, || {});
+        assert!(now.elapsed() >= delay);
+        assert_eq!(*replies.lock().unwrap(), Throttle::MAX_PENDING);
+    }
+}
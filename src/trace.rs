@@ -0,0 +1,74 @@
+use log::*;
+use std::time::Instant;
+
+/// A FUSE request being served; its start and its end are logged at the trace
+/// level, along with its latency. When built with the `tracing` feature, it
+/// is also recorded as a span, that can be exported with `--trace-chrome`.
+pub struct Op {
+    id: u64,
+    name: &'static str,
+    start: Instant,
+    #[cfg(feature = "tracing")]
+    _span: tracing::span::EnteredSpan,
+}
+impl Op {
+    pub fn new(
+        id: u64,
+        name: &'static str,
+        ino: u64,
+        #[cfg(feature = "tracing")] span: tracing::Span,
+    ) -> Op {
+        trace!("#{} {} {}", id, name, ino);
+        Op {
+            id,
+            name,
+            start: Instant::now(),
+            #[cfg(feature = "tracing")]
+            _span: span.entered(),
+        }
+    }
+}
+impl Drop for Op {
+    fn drop(&mut self) {
+        trace!(
+            "#{} {} done in {:?}",
+            self.id,
+            self.name,
+            self.start.elapsed()
+        );
+    }
+}
+
+/// Start tracing the request `$req`, named `$name`, targeting the inode `$ino`
+#[cfg(feature = "tracing")]
+macro_rules! op {
+    ($req:expr, $name:literal, $ino:expr) => {
+        $crate::trace::Op::new(
+            $req.unique(),
+            $name,
+            $ino,
+            tracing::trace_span!($name, id = $req.unique(), ino = $ino),
+        )
+    };
+}
+#[cfg(not(feature = "tracing"))]
+macro_rules! op {
+    ($req:expr, $name:literal, $ino:expr) => {
+        $crate::trace::Op::new($req.unique(), $name, $ino)
+    };
+}
+pub(crate) use op;
+
+/// Record the spans of all the FUSE requests to `filename`, in the Chrome
+/// trace format; they are flushed when the returned guard is dropped.
+#[cfg(feature = "tracing")]
+pub fn chrome(filename: &str) -> anyhow::Result<tracing_chrome::FlushGuard> {
+    use tracing_subscriber::prelude::*;
+
+    let (layer, guard) = tracing_chrome::ChromeLayerBuilder::new()
+        .file(filename)
+        .include_args(true)
+        .build();
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))?;
+    Ok(guard)
+}
@@ -0,0 +1,188 @@
+//! Transformations of the sequences, e.g. reverse-complementing or
+//! hard-masking them, that can be chained to expose the fragments under
+//! another light.
+use crate::fasta::complement;
+use anyhow::{bail, Result};
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    Uppercase,       // Upcase all the bases, dropping the soft-masking
+    Lowercase,       // Downcase all the bases
+    Revcomp,         // Reverse-complement the sequence
+    Rna,             // Replace T by U
+    Dna,             // Replace U by T
+    Mask,            // Hard-mask the soft-masked, i.e. lowercase, bases with N
+    StripNewlines,   // Put the whole sequence on a single line
+    StripWhitespace, // Remove the blanks and newlines within the sequence
+}
+impl Transform {
+    const NAMES: [(&'static str, Transform); 8] = [
+        ("uppercase", Transform::Uppercase),
+        ("lowercase", Transform::Lowercase),
+        ("revcomp", Transform::Revcomp),
+        ("rna", Transform::Rna),
+        ("dna", Transform::Dna),
+        ("mask", Transform::Mask),
+        ("strip-newlines", Transform::StripNewlines),
+        ("strip-whitespace", Transform::StripWhitespace),
+    ];
+
+    /// What becomes of a base; the reversal of Revcomp is left to the caller
+    fn map(&self, b: u8) -> u8 {
+        match self {
+            Transform::Uppercase => b.to_ascii_uppercase(),
+            Transform::Lowercase => b.to_ascii_lowercase(),
+            Transform::Revcomp => complement(b),
+            Transform::Rna => match b {
+                b'T' => b'U',
+                b't' => b'u',
+                b => b,
+            },
+            Transform::Dna => match b {
+                b'U' => b'T',
+                b'u' => b't',
+                b => b,
+            },
+            Transform::Mask if b.is_ascii_lowercase() => b'N',
+            Transform::Mask | Transform::StripNewlines | Transform::StripWhitespace => b,
+        }
+    }
+}
+impl std::fmt::Display for Transform {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = Transform::NAMES.iter().find(|(_, t)| t == self).unwrap().0;
+        write!(f, "{}", name)
+    }
+}
+
+/// A chain of transformations, applied in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pipeline(Vec<Transform>);
+impl Pipeline {
+    /// Parse a comma-separated list of transformations, e.g.
+    /// `revcomp,uppercase`
+    pub fn parse(pipeline: &str) -> Result<Pipeline> {
+        let mut r = Vec::new();
+        for name in pipeline.split(',').map(str::trim) {
+            match Transform::NAMES.iter().find(|(n, _)| *n == name) {
+                Some((_, transform)) => r.push(*transform),
+                None => bail!(
+                    "unknown transformation `{}`; expected one of {}",
+                    name,
+                    Transform::NAMES
+                        .iter()
+                        .map(|(n, _)| *n)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            }
+        }
+        Ok(Pipeline(r))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether the sequences end up reversed
+    pub fn reverses(&self) -> bool {
+        self.0.iter().filter(|t| **t == Transform::Revcomp).count() % 2 == 1
+    }
+
+    /// Whether the sequences should be written on a single line
+    pub fn single_line(&self) -> bool {
+        self.0.contains(&Transform::StripNewlines)
+    }
+
+    /// Transform `bases` in place, but for their reversal
+    pub fn map(&self, bases: &mut [u8]) {
+        for b in bases.iter_mut() {
+            *b = self.0.iter().fold(*b, |b, t| t.map(b));
+        }
+    }
+
+    /// Transform the whole `seq` in place, e.g. as written by a user; it
+    /// may contain newlines, that are removed by strip-newlines, and other
+    /// blanks, that are removed along with them by strip-whitespace
+    pub fn apply(&self, seq: &mut Vec<u8>) {
+        if self.0.contains(&Transform::StripWhitespace) {
+            seq.retain(|b| !b.is_ascii_whitespace());
+        } else if self.single_line() {
+            seq.retain(|b| *b != b'\n' && *b != b'\r');
+        }
+        if self.reverses() {
+            seq.reverse();
+        }
+        self.map(seq);
+    }
+
+    /// The bases `range` of the transformed version of a sequence of length
+    /// `len`, whose bases are given by `fetch(offset, size)`; only the bases
+    /// required are fetched, so that large sequences can be read piecewise
+    pub fn extract<E>(
+        &self,
+        len: usize,
+        range: Range<usize>,
+        fetch: impl FnOnce(usize, usize) -> Result<Vec<u8>, E>,
+    ) -> Result<Vec<u8>, E> {
+        let mut r = if self.reverses() {
+            let mut r = fetch(len - range.end, range.len())?;
+            r.reverse();
+            r
+        } else {
+            fetch(range.start, range.len())?
+        };
+        self.map(&mut r);
+        Ok(r)
+    }
+}
+impl std::fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let names = self.0.iter().map(Transform::to_string).collect::<Vec<_>>();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pipelines() {
+        let seq = b"ACGTacgtNU";
+        let apply = |pipeline: &str, range: Range<usize>| {
+            let pipeline = Pipeline::parse(pipeline).unwrap();
+            let r = pipeline.extract(seq.len(), range, |o, s| Ok::<_, ()>(seq[o..o + s].to_vec()));
+            String::from_utf8(r.unwrap()).unwrap()
+        };
+        assert_eq!(apply("uppercase", 0..10), "ACGTACGTNU");
+        assert_eq!(apply("revcomp", 0..10), "ANacgtACGT");
+        assert_eq!(apply("revcomp", 0..3), "ANa");
+        assert_eq!(apply("revcomp", 2..5), "acg");
+        assert_eq!(apply("revcomp, revcomp", 2..5), "GTa");
+        assert_eq!(apply("rna,mask", 4..10), "NNNNNU");
+        assert_eq!(apply("mask, rna", 4..10), "NNNNNU");
+        assert_eq!(apply("lowercase,dna", 6..10), "gtnt");
+        assert_eq!(apply("uppercase,mask", 4..8), "ACGT");
+
+        let pipeline = Pipeline::parse("strip-newlines,revcomp").unwrap();
+        assert!(pipeline.single_line() && pipeline.reverses());
+        assert_eq!(pipeline.to_string(), "strip-newlines,revcomp");
+        assert!(Pipeline::parse("reverse").is_err());
+
+        let apply = |pipeline: &str, seq: &str| {
+            let mut seq = seq.as_bytes().to_vec();
+            Pipeline::parse(pipeline).unwrap().apply(&mut seq);
+            String::from_utf8(seq).unwrap()
+        };
+        assert_eq!(apply("uppercase", "ac gu\nua"), "AC GU\nUA");
+        assert_eq!(apply("strip-newlines", "ac gu\r\nua\n"), "ac guua");
+        assert_eq!(
+            apply("strip-whitespace,uppercase,dna", " ac\tgu\r\n ua\n"),
+            "ACGTTA"
+        );
+        assert_eq!(apply("revcomp,strip-whitespace", "AAC G"), "CGTT");
+        assert!(Pipeline::parse("").is_err());
+    }
+}
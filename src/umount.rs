@@ -0,0 +1,128 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::process::Command;
+
+/// What to do with the changes not written back yet when unmounting
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Policy {
+    Ask,     // Let the user choose if there is a terminal, write them back otherwise
+    IfClean, // Refuse to unmount
+    Discard, // Drop them
+}
+
+/// The content of `.fusta/status`: `clean` or `dirty`, then the reasons why
+/// as `- REASON`, then the policies in effect as `KEY: VALUE`
+fn status(mountpoint: &Path) -> Result<Vec<String>> {
+    let status =
+        fs::read_to_string(mountpoint.join(".fusta").join("status")).with_context(|| {
+            format!(
+                "{} does not look like a FUSTA mount point",
+                mountpoint.display()
+            )
+        })?;
+    Ok(status.lines().map(str::to_owned).collect())
+}
+
+fn is_clean(status: &[String]) -> bool {
+    status.first().map(|s| s == "clean").unwrap_or(false)
+}
+
+/// Why the mount is not clean, one reason per line
+fn reasons(status: &[String]) -> String {
+    status
+        .iter()
+        .filter(|l| l.starts_with("- "))
+        .cloned()
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Write `mode` to ctl/commit, then check that nothing remains to write back
+fn commit(mountpoint: &Path, mode: &str) -> Result<()> {
+    fs::write(mountpoint.join("ctl").join("commit"), mode)
+        .context("Unable to write to ctl/commit")?;
+    let status = status(mountpoint)?;
+    if !is_clean(&status) {
+        let last_error =
+            fs::read_to_string(mountpoint.join(".fusta").join("last_error")).unwrap_or_default();
+        bail!(
+            "some changes are still pending:\n{}\n{}",
+            reasons(&status),
+            last_error.trim_end()
+        )
+    }
+    Ok(())
+}
+
+fn unmount(mountpoint: &Path) -> Result<()> {
+    let helpers: &[(&str, &[&str])] = if cfg!(target_os = "linux") {
+        &[
+            ("fusermount", &["-u"]),
+            ("fusermount3", &["-u"]),
+            ("umount", &[]),
+        ]
+    } else {
+        &[("umount", &[])]
+    };
+    for (helper, args) in helpers {
+        match Command::new(helper).args(*args).arg(mountpoint).status() {
+            Ok(status) if status.success() => return Ok(()),
+            Ok(status) => bail!("`{}` failed with {}", helper, status),
+            // Try the next one
+            Err(_) => continue,
+        }
+    }
+    bail!("neither fusermount nor umount could be found in $PATH")
+}
+
+/// Unmount `mountpoint` right away, even if it is busy, through umount2(2)
+/// if FUSTA is privileged enough, e.g. as root in a container, or through the
+/// usual helpers otherwise; the changes are then written back as usual.
+pub fn detach(mountpoint: &Path) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let path = std::ffi::CString::new(mountpoint.as_os_str().as_bytes())?;
+        if unsafe { libc::umount2(path.as_ptr(), libc::MNT_DETACH) } == 0 {
+            return Ok(());
+        }
+    }
+    unmount(mountpoint)
+}
+
+pub fn run(mountpoint: &str, policy: Policy) -> Result<()> {
+    let mountpoint = Path::new(mountpoint);
+    let status = status(mountpoint)?;
+    if !is_clean(&status) {
+        let reasons = reasons(&status);
+        match policy {
+            Policy::IfClean => bail!(
+                "Not unmounting {}, as some changes have not been written back:\n{}\nWrite them back with `echo > {}/ctl/commit`, or drop them with --force-discard",
+                mountpoint.display(),
+                reasons,
+                mountpoint.display()
+            ),
+            Policy::Discard => commit(mountpoint, "discard")?,
+            Policy::Ask if std::io::stdin().is_terminal() => {
+                println!(
+                    "Some changes have not been written back:\n{}",
+                    reasons
+                );
+                print!("[c]ommit them, [d]iscard them, or [a]bort? ");
+                std::io::stdout().flush()?;
+                let mut answer = String::new();
+                std::io::stdin().lock().read_line(&mut answer)?;
+                match answer.trim() {
+                    "c" | "commit" => commit(mountpoint, "")?,
+                    "d" | "discard" => commit(mountpoint, "discard")?,
+                    _ => bail!("Aborted"),
+                }
+            }
+            // As with a plain umount, the changes are written back
+            Policy::Ask => {}
+        }
+    }
+    unmount(mountpoint)
+}